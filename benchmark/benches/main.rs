@@ -1,7 +1,7 @@
 use std::fs;
 
 use criterion::{
-    criterion_group, criterion_main, Criterion,
+    criterion_group, criterion_main, Criterion, Throughput,
 };
 use rand::{self, seq::SliceRandom};
 
@@ -29,6 +29,12 @@ fn bench_1_put_and_99_reads(c: &mut Criterion) {
                 b.bench_rocksdb(&mut group, "baseline_rocksdb", size, div);
             }
 
+            // Test for sled.
+            b.bench_sled(&mut group, "baseline_sled", size, div);
+
+            // Test for LMDB.
+            b.bench_lmdb(&mut group, "baseline_lmdb", size, div);
+
             // Test for fs.
             b.bench_fs(&mut group, "baseline_fs", size, div);
 
@@ -37,6 +43,35 @@ fn bench_1_put_and_99_reads(c: &mut Criterion) {
     }
 }
 
+/// Bench test about little write but a lot of read, by many threads sharing
+/// one database. The throughput is of all threads.
+fn bench_concurrent_1_put_and_99_reads(c: &mut Criterion) {
+    let mut b = Bencher::new();
+
+    let size = 1000;
+    for (value_size, div) in [("3K", 100), ("30K", 10)] {
+        let group_name = format!(
+            "concurrent_1_put_and_99_reads__size={}__content={}",
+            size, value_size,
+        );
+        let mut group = c.benchmark_group(group_name);
+        group.sample_size(10);
+        group.throughput(Throughput::Elements(size as u64 * 100));
+
+        for threads in [1, 2, 4, 8] {
+            // Test for WasteIsland database.
+            let path = format!("waste_island_database__threads={}", threads);
+            b.bench_waste_island_concurrent(&mut group, &path, size, div, threads);
+
+            // Test for RocksDB.
+            let path = format!("baseline_rocksdb__threads={}", threads);
+            b.bench_rocksdb_concurrent(&mut group, &path, size, div, threads);
+        }
+
+        group.finish();
+    }
+}
+
 /// Bench test to make sure it can boost very quickly.
 fn bench_boost_quickly_for_pictures(c: &mut Criterion) {
     let size = 100;
@@ -70,9 +105,40 @@ fn bench_boost_quickly_for_pictures(c: &mut Criterion) {
     group.finish();
 }
 
+/// Bench test to bulk-load a lot of small records, by `put` one by one and by
+/// `put_batch`.
+fn bench_bulk_load_small_records(c: &mut Criterion) {
+    let contents: Vec<Vec<u8>> = (0..10000u32)
+        .map(|i| format!("record {}", i).into_bytes())
+        .collect();
+    let items: Vec<&[u8]> = contents.iter().map(|c| c.as_slice()).collect();
+
+    let mut group = c.benchmark_group("bulk_load_small_records__size=10000");
+    group.sample_size(10);
+    group.bench_function("waste_island_database_put", |b| {
+        b.iter(|| {
+            let database_path = benchmark_path("bulk_load_small_records_put");
+            let mut database = Database::new(&database_path).unwrap();
+            for item in &items {
+                database.put(item).unwrap();
+            }
+        });
+    });
+    group.bench_function("waste_island_database_put_batch", |b| {
+        b.iter(|| {
+            let database_path = benchmark_path("bulk_load_small_records_put_batch");
+            let mut database = Database::new(&database_path).unwrap();
+            database.put_batch(&items).unwrap();
+        });
+    });
+
+    group.finish();
+}
+
 criterion_group!(
     name = benches;
     config = Criterion::default();
-    targets = bench_1_put_and_99_reads, bench_boost_quickly_for_pictures,
+    targets = bench_1_put_and_99_reads, bench_concurrent_1_put_and_99_reads,
+        bench_boost_quickly_for_pictures, bench_bulk_load_small_records,
 );
 criterion_main!(benches);
@@ -2,9 +2,12 @@ use std::{
     fs::{self, create_dir_all, File},
     io::Read,
     path::PathBuf,
+    sync::{Arc, RwLock},
+    thread,
 };
 
 use criterion::{measurement::Measurement, BenchmarkGroup};
+use heed::{types::{ByteSlice, Str}, EnvOpenOptions};
 use rand::{rngs::ThreadRng, Rng, seq::SliceRandom};
 use rocksdb::DB;
 use sqlite::Value;
@@ -44,6 +47,34 @@ pub fn get_data(file_path: &PathBuf, div: u64) -> Vec<u8> {
     content
 }
 
+/// Run the mix of 1 put and 99 reads over the files in `pathes`, split among
+/// `threads` threads. Each thread reads the wastes it put only, and it picks
+/// the new ones more often as the others do.
+fn run_concurrently<P, G>(threads: usize, pathes: &[PathBuf], div: u64, put: P, get: G)
+where
+    P: Fn(&[u8]) -> String + Sync,
+    G: Fn(&str) + Sync,
+{
+    let chunk_size = pathes.len().div_ceil(threads);
+    thread::scope(|s| {
+        for chunk in pathes.chunks(chunk_size.max(1)) {
+            let (put, get) = (&put, &get);
+            s.spawn(move || {
+                let mut rng = rand::thread_rng();
+                let mut hashes = vec![];
+                for p in chunk {
+                    let content = get_data(p, div);
+                    hashes.push(put(&content));
+                    for _ in 0..99 {
+                        let hidx = (rng.gen::<f64>().sqrt() * hashes.len() as f64).floor() as usize;
+                        get(&hashes[hidx]);
+                    }
+                }
+            });
+        }
+    });
+}
+
 pub struct Bencher {
     cache: PictureCache,
     rng: ThreadRng,
@@ -71,6 +102,7 @@ impl Bencher {
 
             b.iter(|| {
                 let mut hashes = vec![];
+                let mut buf = vec![];
                 for p in &self.cache.data_pathes[0..size] {
                     // Put the data.
                     let content = get_data(p, div);
@@ -82,13 +114,42 @@ impl Bencher {
                         let hidx =
                             (self.rng.gen::<f64>().sqrt() * hashes.len() as f64).floor() as usize;
                         let hash = &hashes[hidx];
-                        database.get(hash).unwrap();
+                        database.get_into(hash, &mut buf).unwrap();
                     }
                 }
             })
         });
     }
 
+    /// Bench as `bench_waste_island` does, but by `threads` threads sharing
+    /// one database. The database is locked for writing to put, and for
+    /// reading to get - as the web server does.
+    pub fn bench_waste_island_concurrent<T: Measurement>(
+        &mut self,
+        g: &mut BenchmarkGroup<'_, T>,
+        path: &str,
+        size: usize,
+        div: u64,
+        threads: usize,
+    ) {
+        g.bench_function(path, |b| {
+            let database_path = benchmark_path("1_put_and_99_reads_concurrent");
+            let database = Arc::new(RwLock::new(Database::new(&database_path).unwrap()));
+
+            b.iter(|| {
+                run_concurrently(
+                    threads,
+                    &self.cache.data_pathes[0..size],
+                    div,
+                    |content| database.write().unwrap().put(content).unwrap(),
+                    |hash| {
+                        database.read().unwrap().get(hash).unwrap();
+                    },
+                );
+            })
+        });
+    }
+
     pub fn bench_sqlite<T: Measurement>(
         &mut self,
         g: &mut BenchmarkGroup<'_, T>,
@@ -161,6 +222,103 @@ impl Bencher {
         });
     }
 
+    /// Bench as `bench_rocksdb` does, but by `threads` threads sharing one
+    /// database. RocksDB needs no lock of ours.
+    pub fn bench_rocksdb_concurrent<T: Measurement>(
+        &mut self,
+        g: &mut BenchmarkGroup<'_, T>,
+        path: &str,
+        size: usize,
+        div: u64,
+        threads: usize,
+    ) {
+        g.bench_function(path, |b| {
+            let baseline_path = benchmark_path("1_put_and_99_reads_concurrent_baseline_rocksdb");
+            let db = Arc::new(DB::open_default(baseline_path).unwrap());
+
+            b.iter(|| {
+                run_concurrently(
+                    threads,
+                    &self.cache.data_pathes[0..size],
+                    div,
+                    |content| {
+                        let hash = Database::gen_waste_hash(content);
+                        db.put(&hash, content).unwrap();
+                        hash
+                    },
+                    |hash| {
+                        db.get(hash).unwrap();
+                    },
+                );
+            })
+        });
+    }
+
+    pub fn bench_sled<T: Measurement>(
+        &mut self,
+        g: &mut BenchmarkGroup<'_, T>,
+        path: &str,
+        size: usize,
+        div: u64,
+    ) {
+        g.bench_function(path, |b| {
+            let baseline_path = benchmark_path("1_put_and_99_reads_baseline_sled");
+            let db = sled::open(baseline_path).unwrap();
+
+            b.iter(|| {
+                let mut hashes = vec![];
+                for p in &self.cache.data_pathes[0..size] {
+                    let content = get_data(p, div);
+                    let hash = Database::gen_waste_hash(&content);
+                    db.insert(&hash, content).unwrap();
+                    hashes.push(hash);
+                    for _ in 0..99 {
+                        let hidx = (self.rng.gen::<f64>().sqrt() * hashes.len() as f64).floor() as usize;
+                        let hash = &hashes[hidx];
+                        db.get(hash).unwrap();
+                    }
+                }
+            })
+        });
+    }
+
+    /// Bench LMDB by heed. Each put is committed in its own transaction, as
+    /// each put of the others is durable on its own.
+    pub fn bench_lmdb<T: Measurement>(
+        &mut self,
+        g: &mut BenchmarkGroup<'_, T>,
+        path: &str,
+        size: usize,
+        div: u64,
+    ) {
+        g.bench_function(path, |b| {
+            let baseline_path = benchmark_path("1_put_and_99_reads_baseline_lmdb");
+            let env = EnvOpenOptions::new()
+                .map_size(64 * 1024 * 1024 * 1024) // 64GB.
+                .open(baseline_path)
+                .unwrap();
+            let db = env.create_database::<Str, ByteSlice>(None).unwrap();
+
+            b.iter(|| {
+                let mut hashes = vec![];
+                for p in &self.cache.data_pathes[0..size] {
+                    let content = get_data(p, div);
+                    let hash = Database::gen_waste_hash(&content);
+                    let mut wtxn = env.write_txn().unwrap();
+                    db.put(&mut wtxn, &hash, &content).unwrap();
+                    wtxn.commit().unwrap();
+                    hashes.push(hash);
+                    for _ in 0..99 {
+                        let hidx = (self.rng.gen::<f64>().sqrt() * hashes.len() as f64).floor() as usize;
+                        let hash = &hashes[hidx];
+                        let rtxn = env.read_txn().unwrap();
+                        db.get(&rtxn, hash).unwrap();
+                    }
+                }
+            })
+        });
+    }
+
     pub fn bench_fs<T: Measurement>(
         &mut self,
         g: &mut BenchmarkGroup<'_, T>,
@@ -1,8 +1,22 @@
-use std::{fs, path::PathBuf, ffi::OsString};
+use std::{
+    env, error::Error, fs, path::{Path, PathBuf}, ffi::OsString, time::Duration,
+};
 
 use home::home_dir;
+use reqwest::blocking::Client;
 use waste_island::Database;
 
+/// Where the pictures are downloaded from by default. Each request of it gives
+/// a picture - and maybe another one each time.
+const DEFAULT_SOURCE: &str = "https://cataas.com/cat";
+
+/// The environment variable to download the pictures from another URL.
+const SOURCE_VAR: &str = "WASTE_LAND_PICTURE_SOURCE";
+
+/// The environment variable of the directory to copy the pictures from if they
+/// can not be downloaded - for example, in an offline CI.
+const FIXTURES_VAR: &str = "WASTE_LAND_PICTURE_FIXTURES";
+
 pub struct PictureCache {
     /// Cache data (yes, it is a picture) files' hashes.
     pub data_hashes: Vec<String>,
@@ -12,33 +26,27 @@ pub struct PictureCache {
 }
 
 impl PictureCache {
+    /// Get `size` pictures from the cache. The missing ones are downloaded -
+    /// or copied from the fixtures if they can not be - and kept in the cache,
+    /// so that they are never fetched again by the next runs.
     pub fn new(size: usize) -> PictureCache {
         let picture_cache_path = home_dir().unwrap().join("tmp/waste_land_picture_cache");
         fs::create_dir_all(&picture_cache_path).unwrap();
 
-        if !picture_cache_path.join("SUCCESS").exists() {
-            eprintln!("Can't find file named 'SUCCESS' in the {}", picture_cache_path.display());
-            eprintln!("Put images into the directory, and touch a file named 'SUCCESS'");
-            eprintln!();
-            eprintln!("Run downloader.sh maybe can help you");
-            panic!();
+        let mut cached_pathes = Self::cached_pathes(&picture_cache_path);
+        if cached_pathes.len() < size {
+            Self::fetch(&picture_cache_path, cached_pathes.len(), size);
+            cached_pathes = Self::cached_pathes(&picture_cache_path);
         }
+        fs::write(picture_cache_path.join("SUCCESS"), b"").unwrap();
 
         let mut data_hashes = vec![];
         let mut data_pathes = vec![];
-        for f in fs::read_dir(&picture_cache_path).unwrap() {
-            let f_path = f.unwrap().path();
-            if f_path.file_name() == Some(&OsString::from("SUCCESS")) {
-                continue;
-            }
+        for f_path in cached_pathes.into_iter().take(size) {
             let content = fs::read(&f_path).unwrap();
             let hash = Database::gen_waste_hash(&content);
             data_pathes.push(f_path);
             data_hashes.push(hash);
-
-            if data_hashes.len() == size {
-                break;
-            }
         }
 
         assert!(size == data_hashes.len());
@@ -47,4 +55,72 @@ impl PictureCache {
             data_pathes,
         }
     }
-}
\ No newline at end of file
+
+    /// List the pictures in the cache, in order of their names.
+    fn cached_pathes(picture_cache_path: &Path) -> Vec<PathBuf> {
+        let mut pathes: Vec<PathBuf> = fs::read_dir(picture_cache_path)
+            .unwrap()
+            .map(|f| f.unwrap().path())
+            .filter(|p| p.file_name() != Some(&OsString::from("SUCCESS")))
+            .filter(|p| p.extension() != Some(&OsString::from("part")))
+            .collect();
+        pathes.sort();
+        pathes
+    }
+
+    /// Fill the cache from `cached` pictures up to `size` pictures. They are
+    /// downloaded from `$WASTE_LAND_PICTURE_SOURCE`, and once a download fails
+    /// the rest are copied from `$WASTE_LAND_PICTURE_FIXTURES`.
+    fn fetch(picture_cache_path: &Path, cached: usize, size: usize) {
+        let source = env::var(SOURCE_VAR).unwrap_or_else(|_| DEFAULT_SOURCE.to_string());
+        let client = Client::builder().timeout(Duration::from_secs(30)).build().unwrap();
+        eprintln!("Downloading {} pictures from {} ...", size - cached, source);
+
+        let mut fixtures: Option<Vec<PathBuf>> = None;
+        for i in cached..size {
+            let dest = picture_cache_path.join(format!("picture_{:06}", i));
+            if fixtures.is_none() {
+                match Self::download(&client, &source, &dest) {
+                    Ok(()) => continue,
+                    Err(e) => {
+                        eprintln!("Can't download pictures from {}: {}", source, e);
+                        fixtures = Some(Self::fixture_pathes());
+                    }
+                }
+            }
+
+            let fixtures = fixtures.as_ref().unwrap();
+            let fixture = fixtures.get(i - cached).unwrap_or_else(|| {
+                panic!(
+                    "Can't find {} pictures in ${}, which has only {}",
+                    size - cached, FIXTURES_VAR, fixtures.len(),
+                )
+            });
+            fs::copy(fixture, &dest).unwrap();
+        }
+    }
+
+    /// Download a picture from `source` to `dest`. It is written to a `.part`
+    /// file first, so that a broken download is never taken as a picture.
+    fn download(client: &Client, source: &str, dest: &Path) -> Result<(), Box<dyn Error>> {
+        let content = client.get(source).send()?.error_for_status()?.bytes()?;
+        let part = dest.with_extension("part");
+        fs::write(&part, &content)?;
+        fs::rename(&part, dest)?;
+        Ok(())
+    }
+
+    /// List the pictures in the fixtures, in order of their names.
+    fn fixture_pathes() -> Vec<PathBuf> {
+        let fixtures_path = env::var(FIXTURES_VAR).unwrap_or_else(|_| {
+            panic!("Set ${} to a directory of pictures to use them instead", FIXTURES_VAR)
+        });
+        let mut pathes: Vec<PathBuf> = fs::read_dir(fixtures_path)
+            .unwrap()
+            .map(|f| f.unwrap().path())
+            .filter(|p| p.is_file())
+            .collect();
+        pathes.sort();
+        pathes
+    }
+}
@@ -49,6 +49,9 @@ def show_raw(c):
 def show_head(c):
     print('    TYPE: HEAD')
     print(f'    ROOT_NODE_PAGE_ID: {int.from_bytes(c[64 : 64 + 4], "little")}')
+    print(f'    RECORDS_LEN: {int.from_bytes(c[72 : 72 + 8], "little")}')
+    print(f'    HASH_ALGORITHM: {c[80 : 80 + 16].rstrip(bytes(1)).decode()}')
+    print(f'    PAGE_SIZE: {int.from_bytes(c[96 : 96 + 4], "little")}')
     show_raw(c)
 
 def show_leaf(c):
@@ -58,9 +61,13 @@ def show_internal(c):
     print('    TYPE: INTERNAL')
 
 with open('index', 'rb') as f:
+    # The page size is recorded in the head node, which is the first page.
+    page_size = int.from_bytes(f.read(100)[96 : 96 + 4], 'little')
+    f.seek(0)
+
     block_id = 0
     while True:
-        c = f.read(4 * 1024) # 4KB
+        c = f.read(page_size)
         if len(c) == 0: # EOF
             break
 
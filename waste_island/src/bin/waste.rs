@@ -0,0 +1,166 @@
+//! A command-line tool to poke at a database.
+//!
+//! ```shell
+//! $ waste --db ./data put ./picture.png
+//! $ cat ./picture.png | waste --db ./data put -
+//! $ waste --db ./data get <hash> --out ./picture.png
+//! $ waste --db ./data list
+//! $ waste --db ./data stat <hash>
+//! $ waste --db ./data delete <hash>
+//! ```
+
+use std::{
+    env,
+    fs::File,
+    io::{self, Write},
+    process::ExitCode,
+};
+
+use waste_island::{Database, Error, ErrorKind};
+
+const USAGE: &str = "\
+Usage: waste --db <dir> <command>
+
+Commands:
+    put <path>                Put the file - or stdin if it is `-` - and print its hash
+    get <hash> [--out <file>] Write the waste to the file, or to stdout
+    list                      Print the hashes of all wastes
+    stat <hash>               Print where and how the waste is stored
+    delete <hash>             Delete the waste once";
+
+/// The command parsed from the arguments.
+enum Command {
+    Put { path: String },
+    Get { hash: String, out: Option<String> },
+    List,
+    Stat { hash: String },
+    Delete { hash: String },
+}
+
+fn main() -> ExitCode {
+    let (db, command) = match parse_args(env::args().skip(1).collect()) {
+        Ok(parsed) => parsed,
+        Err(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(Some(msg)) => {
+            eprintln!("waste: {}\n\n{}", msg, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(&db, command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("waste: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parse the arguments into the path of the database and the command. The
+/// error is `None` if the help is asked for.
+fn parse_args(args: Vec<String>) -> Result<(String, Command), Option<String>> {
+    let mut db = None;
+    let mut out = None;
+    let mut positional = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--db" => db = Some(args.next().ok_or_else(|| usage_error("--db needs a directory"))?),
+            "--out" => out = Some(args.next().ok_or_else(|| usage_error("--out needs a file"))?),
+            "-h" | "--help" => return Err(None),
+            _ => positional.push(arg),
+        }
+    }
+    let db = db.ok_or_else(|| usage_error("--db is missing"))?;
+
+    let mut positional = positional.into_iter();
+    let command = positional.next().ok_or_else(|| usage_error("the command is missing"))?;
+    let mut operand = |what: &str| {
+        positional.next().ok_or_else(|| Some(format!("{} needs {}", command, what)))
+    };
+    let command = match command.as_str() {
+        "put" => Command::Put { path: operand("a path")? },
+        "get" => Command::Get { hash: operand("a hash")?, out: out.take() },
+        "list" => Command::List,
+        "stat" => Command::Stat { hash: operand("a hash")? },
+        "delete" => Command::Delete { hash: operand("a hash")? },
+        _ => return Err(Some(format!("unknown command {}", command))),
+    };
+    if let Some(extra) = positional.next() {
+        return Err(Some(format!("unexpected argument {}", extra)));
+    }
+    if out.is_some() {
+        return Err(usage_error("--out is only for get"));
+    }
+    Ok((db, command))
+}
+
+/// The error of `parse_args` other than asking for the help.
+fn usage_error(msg: &str) -> Option<String> {
+    Some(msg.to_string())
+}
+
+fn run(db: &str, command: Command) -> Result<(), Error> {
+    let mut stdout = io::stdout().lock();
+    match command {
+        Command::Put { path } => {
+            let mut database = Database::new(db)?;
+            // Both of them are streamed, so they are never held in memory.
+            let hash = if path == "-" {
+                database.put_reader(io::stdin().lock())?
+            } else {
+                let file = File::open(&path)
+                    .map_err(|e| Error::new(format!("can't open {}: {}", path, e)))?;
+                database.put_reader(file)?
+            };
+            writeln!(stdout, "{}", hash).map_err(output_error)?;
+        }
+        Command::Get { hash, out } => {
+            let database = Database::open_read_only(db)?;
+            let mut reader = database.get_reader(&hash)?;
+            match out {
+                Some(out) => {
+                    let mut file = File::create(&out)
+                        .map_err(|e| Error::new(format!("can't create {}: {}", out, e)))?;
+                    io::copy(&mut reader, &mut file).map_err(copy_error)?;
+                }
+                None => {
+                    io::copy(&mut reader, &mut stdout).map_err(copy_error)?;
+                }
+            }
+        }
+        Command::List => {
+            let database = Database::open_read_only(db)?;
+            for hash in database.list()? {
+                writeln!(stdout, "{}", hash).map_err(output_error)?;
+            }
+        }
+        Command::Stat { hash } => {
+            let database = Database::open_read_only(db)?;
+            let stat = database.stat(&hash)?;
+            writeln!(
+                stdout,
+                "shard: {}\noffset: {}\nlen: {}\ncompression: {:?}",
+                stat.shard, stat.offset, stat.len, stat.compression,
+            )
+            .map_err(output_error)?;
+        }
+        Command::Delete { hash } => {
+            let mut database = Database::new(db)?;
+            if !database.delete(&hash)? {
+                return Err(Error::with_kind(ErrorKind::NotFound, "waste not found"));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn copy_error(e: io::Error) -> Error {
+    Error::new(format!("can't copy the waste: {}", e))
+}
+
+fn output_error(e: io::Error) -> Error {
+    Error::new(format!("can't write the output: {}", e))
+}
@@ -0,0 +1,87 @@
+use crate::hash::Hash;
+
+/// How many bits are there for each key - about 1% false positives.
+const BITS_PER_KEY: usize = 10;
+
+/// How many bits are set for each key.
+const HASHES: u64 = 7;
+
+/// The Bloom filter of hashes: it tells that a hash is definitely absent, or
+/// maybe present - so a positive still needs to be checked.
+///
+/// The hashes are already uniformly distributed, so the bits are chosen by
+/// their bytes directly instead of hashing them again.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    capacity: usize,
+    len: usize,
+}
+
+impl BloomFilter {
+    /// Create an empty filter for at most `capacity` keys. More keys can still
+    /// be inserted, but there will be more false positives.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let words = (capacity.max(1) * BITS_PER_KEY).div_ceil(64);
+        Self { bits: vec![0; words], capacity, len: 0 }
+    }
+
+    /// Is the filter holding as many keys as its capacity?
+    pub fn is_full(&self) -> bool {
+        self.len >= self.capacity
+    }
+
+    pub fn insert(&mut self, hash: &Hash) {
+        for i in self.bit_indexes(hash) {
+            self.bits[i / 64] |= 1 << (i % 64);
+        }
+        self.len += 1;
+    }
+
+    /// Return `false` if the hash is definitely absent.
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.bit_indexes(hash).all(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+    }
+
+    /// Choose the bits of the hash by double hashing.
+    fn bit_indexes(&self, hash: &Hash) -> impl Iterator<Item = usize> {
+        let bytes = hash.to_bytes();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) | 1;
+        let bits_len = self.bits.len() as u64 * 64;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits_len) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::HASH_SIZE;
+
+    use super::*;
+
+    #[test]
+    fn it_never_misses_inserted_keys() {
+        let keys: Vec<Hash> = (0..10000)
+            .map(|_| Hash::from_bytes(rand::random::<[u8; HASH_SIZE]>()))
+            .collect();
+        let mut bloom = BloomFilter::with_capacity(keys.len());
+        for key in &keys {
+            assert!(!bloom.is_full());
+            bloom.insert(key);
+        }
+        assert!(bloom.is_full());
+        assert!(keys.iter().all(|key| bloom.contains(key)));
+
+        // About 1% false positives.
+        let false_positives = (0..10000)
+            .filter(|_| bloom.contains(&Hash::from_bytes(rand::random::<[u8; HASH_SIZE]>())))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn it_works_when_empty() {
+        let bloom = BloomFilter::with_capacity(0);
+        assert!(bloom.is_full());
+        assert!(!bloom.contains(&Hash::from_bytes([1; HASH_SIZE])));
+    }
+}
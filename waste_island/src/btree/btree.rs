@@ -1,34 +1,93 @@
 use std::{
-    collections::HashMap, fs::File, path::Path,
+    collections::{HashMap, HashSet}, fs::File, hash::Hash as StdHash, path::{Path, PathBuf},
 };
 
 use crate::{
     btree::{
-        node::{get_node_type, HeadNode, LeafNode},
-        page::Page,
+        node::{
+            get_node_type, now_millis, HeadNode, LeafNode, LeafValue, HASH_ALGORITHM_MAX_LEN,
+            HEAD_NODE_VERSION, NEVER_EXPIRES,
+        },
+        page::{is_valid_page_size, Page, MIN_PAGE_SIZE},
     },
-    error::{Error, ToInnerResult},
+    error::{Error, ErrorKind, ToInnerResult},
     hash::Hash,
     offset::Offset,
+    store::BlockStore,
+    uuid::UUID_SIZE,
 };
 
 use super::{
     node::{InternalNode, NodeType},
+    pod::Pod,
     page::PageId,
-    pager::Pager,
+    pager::{Pager, PagerStats},
 };
 
-pub struct BTree {
+/// The B+ tree in a file - or any store - whose keys are `K` and whose values
+/// are `V`. Both of them are stored as their bytes in pages, see `Pod`.
+///
+/// Each record has a reference count and an expiry time beside its value, see
+/// `put` and `put_with_expiry`.
+pub struct BPlusTree<K, V>
+where
+    K: Pod + Ord + StdHash,
+    V: Pod,
+{
     pager: Pager,
-    cache: HashMap<Hash, Offset>,
+    cache: HashMap<K, V>,
     head_node: HeadNode,
 }
 
-impl BTree {
+/// The B+ tree of the index: from the hashes of wastes to their offsets in the
+/// data file.
+pub type BTree = BPlusTree<Hash, Offset>;
+
+/// The node in a page as it is. See `BPlusTree::dump_page`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeDump<K, V> {
+    /// The head node, with the page ID of the root.
+    Head { root: PageId },
+    /// A leaf, with its records in key order and the page ID of the next leaf,
+    /// which is `None` for the last leaf.
+    Leaf { records: Vec<(K, LeafValue<V>)>, next_leaf: Option<PageId> },
+    /// An internal node, with its separators and their children in key order,
+    /// and the child for the keys greater than all separators.
+    Internal { records: Vec<(K, PageId)>, rightest_child: PageId },
+}
+
+/// The iterator through the chain of leaves. See `BPlusTree::leaves`.
+pub struct Leaves<'a, K, V>
+where
+    K: Pod + Ord + StdHash,
+    V: Pod,
+{
+    btree: &'a BPlusTree<K, V>,
+    next_page_id: PageId,
+}
+
+impl<K, V> BPlusTree<K, V>
+where
+    K: Pod + Ord + StdHash,
+    V: Pod,
+{
     const HEAD_PAGE_ID: PageId = PageId::new(0);
 
-    /// Open or create a new BTree file.
-    pub fn new<P>(file_name: P) -> Result<BTree, Error>
+    /// How many leaves are filled in a transaction by `bulk_load`, so that
+    /// not too many dirty pages are kept in the cache.
+    const BULK_LOAD_BATCH_LEAVES: usize = 64;
+
+    /// Open or create a new BTree file, whose keys are hashed by
+    /// `hash_algorithm`. It is recorded when the file is created, and an
+    /// error will be raised if we open the file by another one.
+    ///
+    /// The file is created with pages of `page_size` bytes. An existing file
+    /// is always opened with the page size recorded in it.
+    ///
+    /// Each change is written to the write-ahead log `<file_name>.wal` first,
+    /// and the changes left in it by a crash are written to the file again
+    /// here - so the file is never left half-changed.
+    pub fn new<P>(file_name: P, hash_algorithm: &str, page_size: usize) -> Result<Self, Error>
     where
         P: AsRef<Path>,
     {
@@ -36,13 +95,65 @@ impl BTree {
             .write(true)
             .read(true)
             .create(true)
-            .open(file_name)
+            .open(&file_name)
             .to_inner_result("open or create index data file in read-write mode")?;
+        let wal = File::options()
+            .write(true)
+            .read(true)
+            .create(true)
+            .truncate(false)
+            .open(Self::wal_path(file_name.as_ref()))
+            .to_inner_result("open or create index WAL in read-write mode")?;
+
+        let page_size = Self::check_store(&file, hash_algorithm, page_size)?;
+        let pager = Pager::with_wal(file, wal, page_size).to_inner_result("create pager")?;
+        Self::init(pager, hash_algorithm, page_size)
+    }
+
+    /// Open or create a new BTree in the store instead of a file. See `new` as
+    /// well - but there is no WAL, so the store maybe is left half-changed by
+    /// a crash.
+    pub fn new_in<S>(store: S, hash_algorithm: &str, page_size: usize) -> Result<Self, Error>
+    where
+        S: BlockStore + 'static,
+    {
+        let page_size = Self::check_store(&store, hash_algorithm, page_size)?;
+        let pager = Pager::new_with_store(store, page_size).to_inner_result("create pager")?;
+        Self::init(pager, hash_algorithm, page_size)
+    }
 
-        let mut pager = Pager::new(file).to_inner_result("create pager")?;
+    /// Check the head node of the store, or the options to create it if it is
+    /// empty. Return the page size of the BTree.
+    fn check_store(
+        store: &dyn BlockStore,
+        hash_algorithm: &str,
+        page_size: usize,
+    ) -> Result<usize, Error> {
+        if hash_algorithm.len() > HASH_ALGORITHM_MAX_LEN {
+            return Err(Error::with_kind(
+                ErrorKind::Unsupported,
+                "the name of the hash algorithm is too long",
+            ));
+        }
+
+        if !store.is_empty().to_inner_result("get length of store")? {
+            return Self::check_head(store, hash_algorithm);
+        }
+        if !is_valid_page_size(page_size) {
+            return Err(Error::with_kind(
+                ErrorKind::Unsupported,
+                format!("the page size {} is not supported", page_size),
+            ));
+        }
+        Ok(page_size)
+    }
 
+    /// Init the head node and the root node if the store is empty, and create
+    /// the BTree by the pager.
+    fn init(mut pager: Pager, hash_algorithm: &str, page_size: usize) -> Result<Self, Error> {
         if pager.len() == 0 {
             // Look like the paper need to be inited.
+            pager.begin();
 
             let head_page = pager.append_empty_uninited_page()?;
             debug_assert_eq!(head_page.id(), Self::HEAD_PAGE_ID);
@@ -51,27 +162,62 @@ impl BTree {
             let mut head_node = unsafe { HeadNode::new_unchecked(head_page) };
             head_node.make_dirty();
             unsafe {
-                head_node.init(root_page.id());
+                head_node.init(root_page.id(), hash_algorithm, page_size);
             }
             pager.sync_page(unsafe { head_node.mut_page() })?;
 
-            let mut root_node = unsafe { LeafNode::new_unchecked(root_page) };
+            let mut root_node = unsafe { LeafNode::<K, V>::new_unchecked(root_page) };
             root_node.make_dirty();
             unsafe {
                 root_node.init();
             }
             pager.sync_page(unsafe { root_node.mut_page() })?;
             debug_assert_eq!(
-                unsafe { get_node_type(root_node.mut_page()) },
-                NodeType::Leaf
+                unsafe { get_node_type(root_node.mut_page()) }.ok(),
+                Some(NodeType::Leaf)
             );
+            pager.commit()?;
+        }
+
+        Self::with_pager(pager)
+    }
+
+    /// Open an existing BTree file in read-only mode, whose keys are hashed by
+    /// `hash_algorithm`. The file is never written - so `put` and `delete`
+    /// will fail.
+    ///
+    /// The changes left in the WAL by a crash cannot be written to the file in
+    /// this mode, so an error is raised then: open it by `new` once to recover.
+    pub fn open_read_only<P>(file_name: P, hash_algorithm: &str) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let wal_len = Self::wal_path(file_name.as_ref()).metadata().map_or(0, |m| m.len());
+        if wal_len != 0 {
+            return Err(Error::new("the index WAL needs to be recovered in read-write mode"));
         }
+        let file = File::options()
+            .read(true)
+            .open(file_name)
+            .to_inner_result("open index data file in read-only mode")?;
+        let page_size = Self::check_head(&file, hash_algorithm)?;
+
+        let pager = Pager::read_only(file, page_size).to_inner_result("create pager")?;
+        Self::with_pager(pager)
+    }
+
+    /// The path of the WAL of the BTree file - `.wal` is appended to it.
+    fn wal_path(file_name: &Path) -> PathBuf {
+        let mut wal_path = file_name.as_os_str().to_owned();
+        wal_path.push(".wal");
+        PathBuf::from(wal_path)
+    }
 
+    /// Create the BTree by the pager of a checked file.
+    fn with_pager(pager: Pager) -> Result<Self, Error> {
         let head_page = pager.get_page(Self::HEAD_PAGE_ID)?;
         let head_node = unsafe { HeadNode::new_unchecked(head_page) };
-        if !head_node.check() {
-            return Err(Error::new("the head node is not valid"));
-        }
+        debug_assert!(head_node.check());
 
         Ok(Self {
             pager,
@@ -80,265 +226,1888 @@ impl BTree {
         })
     }
 
-    /// List all records' keys.
-    pub fn list(&mut self) -> Result<Vec<Hash>, Error> {
-        fn inner_list(slf: &mut BTree, res: &mut Vec<Hash>, root_page: &Page) -> Result<(), Error> {
-            match get_node_type(root_page) {
-                NodeType::Leaf => {
-                    let leaf_node = unsafe { LeafNode::new_unchecked(root_page.clone()) };
-                    for r in leaf_node.into_iter() {
-                        res.push(r.key);
-                    }
-                }
+    /// Check the head node of an existing file before we know its page size,
+    /// and return the page size recorded in it.
+    ///
+    /// The head node is at the very beginning of the file, and it is shorter
+    /// than `MIN_PAGE_SIZE` - so read it into a page of that size.
+    fn check_head(store: &dyn BlockStore, hash_algorithm: &str) -> Result<usize, Error> {
+        let mut head_page = Page::new_zeroed(Self::HEAD_PAGE_ID, MIN_PAGE_SIZE);
+        store.read_exact_at(unsafe { head_page.mut_buf() }, 0)
+            .map_err(|e| Error::with_kind(ErrorKind::Corrupt, format!("read head node: {}", e)))?;
+
+        let head_node = unsafe { HeadNode::new_unchecked(head_page) };
+        if head_node.version() != HEAD_NODE_VERSION {
+            return Err(Error::with_kind(ErrorKind::Unsupported, format!(
+                "the version of the index file is {}, but only {} is supported",
+                head_node.version(),
+                HEAD_NODE_VERSION,
+            )));
+        }
+        if !head_node.check() {
+            return Err(Error::with_kind(ErrorKind::Corrupt, "the head node is not valid"));
+        }
+        if head_node.hash_algorithm() != hash_algorithm {
+            return Err(Error::with_kind(ErrorKind::Unsupported, format!(
+                "the index file is hashed by {}, but not {}",
+                head_node.hash_algorithm(),
+                hash_algorithm,
+            )));
+        }
+        if !is_valid_page_size(head_node.page_size()) {
+            return Err(Error::with_kind(ErrorKind::Unsupported, format!(
+                "the page size {} of the index file is not supported",
+                head_node.page_size(),
+            )));
+        }
+
+        Ok(head_node.page_size())
+    }
+
+    /// The height of the tree - a tree of only a leaf has height 1. All leaves
+    /// are at the same depth, so it is found by only one descent to a leaf.
+    pub fn height(&self) -> Result<usize, Error> {
+        let mut height = 1;
+        let mut page_id = self.head_node.hdr().root_node_page_id;
+        loop {
+            let page = self.pager.get_page(page_id)?;
+            match get_node_type(&page)? {
+                NodeType::Leaf => return Ok(height),
                 NodeType::Internal => {
-                    let mut internal_node = unsafe { InternalNode::new_unchecked(root_page.clone()) };
-                    for r in internal_node.into_iter() {
-                        let page = &slf.pager.get_page(r.value)?;
-                        inner_list(slf, res, page)?;
-                    }
-                    let rightest_page_id = unsafe { internal_node.hdr_mut().rightest_page_id };
-                    let page = &slf.pager.get_page(rightest_page_id)?;
-                    inner_list(slf, res, page)?;
+                    let mut node = unsafe { InternalNode::<K>::new_unchecked(page) };
+                    page_id = unsafe { node.hdr_mut().rightest_page_id };
+                    height += 1;
                 }
-                _ => panic!("unexpected node type")
-            };
-            Ok(())
+                typ => return Err(unexpected_node_type(typ)),
+            }
         }
+    }
 
-        let root_page_id = self.head_node.hdr().root_node_page_id;
-        let root_page = self.pager.get_page(root_page_id)?;
-        let mut res: Vec<Hash> = vec![];
-        inner_list(self, &mut res, &root_page)?;
-        Ok(res)
+    /// The count of pages in the file, including the head node and the pages
+    /// not referenced any more.
+    pub fn page_count(&self) -> usize {
+        self.pager.len()
     }
 
-    /// Put a new record (key, value).
-    pub fn put(&mut self, key: &Hash, value: &Offset) -> Result<(), Error> {
-        let root_page_id = self.head_node.hdr().root_node_page_id;
-        let root_page = self.pager.get_page(root_page_id)?;
+    /// The length of the file in bytes.
+    pub fn file_len(&self) -> Result<u64, Error> {
+        self.pager.store_len()
+    }
 
-        enum InnerPut {
-            SplitMe(Hash, PageId),
-            Alright,
-        }
-        fn inner_put(
-            slf: &mut BTree,
-            page: Page,
-            key: &Hash,
-            value: &Offset,
-        ) -> Result<InnerPut, Error> {
-            match get_node_type(&page) {
-                NodeType::Leaf => {
-                    let mut node = unsafe { LeafNode::new_unchecked(page) };
-
-                    if node.is_full() {
-                        // Split root_node into new_node.
-                        let new_page = slf.pager.append_empty_uninited_page()?;
-                        let mut new_node = unsafe { LeafNode::new_unchecked(new_page) };
-                        unsafe { new_node.init() };
-                        unsafe { node.split(&mut new_node) };
-                        new_node.make_dirty();
-                        node.make_dirty();
-                        slf.pager.sync_page(unsafe { new_node.mut_page() })?;
-                        slf.pager.sync_page(unsafe { node.mut_page() })?;
+    /// Another handle of the pager, sharing its cache of pages.
+    pub fn pager(&self) -> Pager {
+        self.pager.clone()
+    }
 
-                        return Ok(InnerPut::SplitMe(
-                            unsafe { *node.rightest_key() },
-                            new_node.page_id(),
-                        ));
-                    }
+    /// The counters of the pager's cache of pages.
+    pub fn pager_stats(&self) -> PagerStats {
+        self.pager.stats()
+    }
 
-                    unsafe { node.put(key, value) };
-                    node.make_dirty();
-                    slf.pager.sync_page(unsafe { node.mut_page() })?;
-                    Ok(InnerPut::Alright)
-                }
-                NodeType::Internal => {
-                    let mut node = unsafe { InternalNode::new_unchecked(page) };
+    /// The size of pages recorded in the head node.
+    pub fn page_size(&self) -> usize {
+        self.head_node.page_size()
+    }
 
-                    if node.is_full() {
-                        // Split me into two new node.
-                        let new_page = slf.pager.append_empty_uninited_page()?;
-                        let mut new_node = unsafe { InternalNode::new_unchecked(new_page) };
-                        unsafe { new_node.init(node.hdr_mut().rightest_page_id) };
-                        unsafe { node.split(&mut new_node) };
+    /// The count of records.
+    pub fn len(&self) -> usize {
+        self.head_node.hdr().records_len as usize
+    }
 
-                        // Change inner struct...
-                        let mid_record = unsafe { node.pop_rightest_record() };
-                        unsafe { node.hdr_mut().rightest_page_id = mid_record.value };
+    /// Whether there is no record.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-                        slf.pager.sync_page(unsafe { new_node.mut_page() })?;
-                        slf.pager.sync_page(unsafe { node.mut_page() })?;
+    /// How many bytes the owner of the B-tree counts as garbage, see
+    /// `add_garbage_bytes`.
+    pub fn garbage_bytes(&self) -> u64 {
+        self.head_node.hdr().garbage_bytes
+    }
 
-                        return Ok(InnerPut::SplitMe(mid_record.key, new_node.page_id()));
-                    }
+    /// Add to the count of bytes of garbage in the head node - for example,
+    /// the bytes of the data file not referenced by the records any more. It
+    /// is only kept for the owner, and it is 0 in a new B-tree.
+    pub fn add_garbage_bytes(&mut self, bytes: u64) -> Result<(), Error> {
+        self.transaction(|slf| {
+            let hdr = unsafe { slf.head_node.mut_hdr() };
+            hdr.garbage_bytes = hdr.garbage_bytes.saturating_add(bytes);
+            slf.head_node.make_dirty();
+            slf.pager.sync_page(unsafe { slf.head_node.mut_page() })
+        })
+    }
 
-                    let (origin_key, next_page_id) = node.get(key);
-                    let next_page = slf.pager.get_page(next_page_id)?;
-                    match inner_put(slf, next_page, key, value)? {
-                        InnerPut::Alright => {
-                            return Ok(InnerPut::Alright);
-                        }
-                        InnerPut::SplitMe(new_key, new_value) => {
-                            match origin_key {
-                                Some(ori_k) => {
-                                    unsafe { node.put(&ori_k, &new_value) };
-                                    unsafe { node.put(&new_key, &next_page_id) };
-                                }
-                                None => {
-                                    unsafe { node.hdr_mut().rightest_page_id = new_value };
-                                    unsafe { node.put(&new_key, &next_page_id) };
-                                }
-                            };
-                            node.make_dirty();
-                            let node_page = unsafe { node.mut_page() };
-                            slf.pager.sync_page(&mut node_page.clone())?;
-                            inner_put(slf, node_page.clone(), key, value)
-                        }
-                    }
+    /// The UUID of the owner of the B-tree, see `set_uuid`.
+    pub fn uuid(&self) -> [u8; UUID_SIZE] {
+        self.head_node.hdr().uuid
+    }
+
+    /// Set the UUID of the owner of the B-tree in the head node - for example,
+    /// the database holding it. It is only kept for the owner, and it is all
+    /// zeros in a new B-tree.
+    pub fn set_uuid(&mut self, uuid: [u8; UUID_SIZE]) -> Result<(), Error> {
+        self.transaction(|slf| {
+            unsafe { slf.head_node.mut_hdr().uuid = uuid };
+            slf.head_node.make_dirty();
+            slf.pager.sync_page(unsafe { slf.head_node.mut_page() })
+        })
+    }
+
+    /// Set the count of records stored in the head node, and sync it.
+    fn set_len(&mut self, len: usize) -> Result<(), Error> {
+        unsafe { self.head_node.mut_hdr().records_len = len as u64 };
+        self.head_node.make_dirty();
+        self.pager.sync_page(unsafe { self.head_node.mut_page() })
+    }
+
+    /// List all records' keys.
+    pub fn list(&self) -> Result<Vec<K>, Error> {
+        let mut res: Vec<K> = vec![];
+        for leaf_node in self.leaves()? {
+            for r in leaf_node?.into_iter() {
+                res.push(r.key);
+            }
+        }
+        Ok(res)
+    }
+
+    /// List up to `limit` keys strictly greater than `after` - or from the
+    /// smallest key if it is `None`, in key order.
+    pub fn list_after(&self, after: Option<&K>, limit: usize) -> Result<Vec<K>, Error> {
+        let mut res = vec![];
+        if limit == 0 {
+            return Ok(res);
+        }
+        let start_page_id = match after {
+            Some(after) => self.find_leaf(after)?.page_id(),
+            None => self.first_leaf()?.page_id(),
+        };
+        let leaves = Leaves { btree: self, next_page_id: start_page_id };
+        for leaf_node in leaves {
+            for r in leaf_node?.into_iter() {
+                if matches!(after, Some(after) if &r.key <= after) {
+                    continue;
+                }
+                res.push(r.key);
+                if res.len() == limit {
+                    return Ok(res);
                 }
-                typ => panic!("unexcepted node type: {:?}", typ),
             }
         }
+        Ok(res)
+    }
 
-        match inner_put(self, root_page, key, value)? {
-            InnerPut::Alright => {}
-            InnerPut::SplitMe(new_key, new_value) => {
-                let parent_page = self.pager.append_empty_uninited_page()?;
-                let mut parent_node = unsafe { InternalNode::new_unchecked(parent_page.clone()) };
-                unsafe { parent_node.init(new_value) };
-                unsafe { parent_node.put(&new_key, &root_page_id) }
-                unsafe {
-                    self.head_node.mut_hdr().root_node_page_id = parent_node.page_id();
+    /// Get the leftest leaf - which holds the smallest keys.
+    pub fn first_leaf(&self) -> Result<LeafNode<K, V>, Error> {
+        let mut page_id = self.head_node.hdr().root_node_page_id;
+        loop {
+            let page = self.pager.get_page(page_id)?;
+            match get_node_type(&page)? {
+                NodeType::Leaf => return Ok(unsafe { LeafNode::<K, V>::new_unchecked(page) }),
+                NodeType::Internal => {
+                    let mut node = unsafe { InternalNode::<K>::new_unchecked(page) };
+                    page_id = match node.into_iter().next() {
+                        Some(r) => r.value,
+                        None => unsafe { node.hdr_mut().rightest_page_id },
+                    };
                 }
+                typ => return Err(unexpected_node_type(typ)),
+            }
+        }
+    }
 
-                self.head_node.make_dirty();
-                parent_node.make_dirty();
-                unsafe {
-                    self.pager.sync_page(self.head_node.mut_page()).unwrap();
-                    self.pager.sync_page(parent_node.mut_page()).unwrap();
+    /// Get the leaf which should hold the key.
+    fn find_leaf(&self, key: &K) -> Result<LeafNode<K, V>, Error> {
+        let mut page_id = self.head_node.hdr().root_node_page_id;
+        loop {
+            let page = self.pager.get_page(page_id)?;
+            match get_node_type(&page)? {
+                NodeType::Leaf => return Ok(unsafe { LeafNode::<K, V>::new_unchecked(page) }),
+                NodeType::Internal => {
+                    let node = unsafe { InternalNode::<K>::new_unchecked(page) };
+                    (_, page_id) = node.get(key);
                 }
-                inner_put(self, parent_page, key, value)?;
+                typ => return Err(unexpected_node_type(typ)),
             }
-        };
+        }
+    }
 
-        self.cache.insert(*key, *value);
-        Ok(())
+    /// Walk through all leaves in key order.
+    pub fn leaves(&self) -> Result<Leaves<'_, K, V>, Error> {
+        let first_leaf_page_id = self.first_leaf()?.page_id();
+        Ok(Leaves { btree: self, next_page_id: first_leaf_page_id })
     }
 
-    /// Get record's value by the record's key.
-    pub fn get(&mut self, key: &Hash) -> Result<Option<Offset>, Error> {
-        if let Some(v) = self.cache.get(key) {
-            return Ok(Some(*v));
+    /// Read the node in the page as it is, without walking the tree from its
+    /// root - so the pages not referenced any more are read as well. It is for
+    /// debugging: to report a corrupt page, or to check the invariants of the
+    /// tree in tests.
+    pub fn dump_page(&self, page_id: PageId) -> Result<NodeDump<K, V>, Error> {
+        if page_id.raw() as usize >= self.page_count() {
+            return Err(Error::with_kind(ErrorKind::NotFound, "page not found"));
+        }
+        let page = self.pager.get_page(page_id)?;
+        match get_node_type(&page)? {
+            NodeType::Head => Ok(NodeDump::Head { root: self.head_node.hdr().root_node_page_id }),
+            NodeType::Leaf => {
+                let node = unsafe { LeafNode::<K, V>::new_unchecked(page) };
+                let next_leaf = Some(node.next_leaf()).filter(|id| *id != PageId::invalid());
+                let records = node.into_iter().map(|r| (r.key, r.value)).collect();
+                Ok(NodeDump::Leaf { records, next_leaf })
+            }
+            NodeType::Internal => {
+                let mut node = unsafe { InternalNode::<K>::new_unchecked(page) };
+                let records = node.into_iter().map(|r| (r.key, r.value)).collect();
+                let rightest_child = unsafe { node.hdr_mut().rightest_page_id };
+                Ok(NodeDump::Internal { records, rightest_child })
+            }
         }
+    }
+
+    /// Get all records with their reference counts, in key order.
+    pub fn records(&self) -> Result<Vec<(K, LeafValue<V>)>, Error> {
+        let mut res = vec![];
+        for leaf_node in self.leaves()? {
+            for r in leaf_node?.into_iter() {
+                res.push((r.key, r.value));
+            }
+        }
+        Ok(res)
+    }
 
+    /// Walk through the tree from the root for all records, in key order.
+    /// Unlike `records`, an unreadable page never stops the walk: its error is
+    /// pushed to `errors` with its page ID, and the records under it are
+    /// skipped.
+    pub fn scan(&self, errors: &mut Vec<(PageId, Error)>) -> Vec<(K, LeafValue<V>)> {
+        let mut records = vec![];
+        let mut visited = HashSet::new();
         let root_page_id = self.head_node.hdr().root_node_page_id;
-        let root_page = self.pager.get_page(root_page_id)?;
+        self.scan_page(root_page_id, &mut visited, &mut records, errors);
+        records
+    }
 
-        fn inner_get(slf: &mut BTree, page: Page, key: &Hash) -> Result<Option<Offset>, Error> {
-            match get_node_type(&page) {
+    /// Collect the records under the page for `scan`. `visited` keeps the
+    /// corrupt pages refering to each other from looping forever.
+    fn scan_page(
+        &self,
+        page_id: PageId,
+        visited: &mut HashSet<PageId>,
+        records: &mut Vec<(K, LeafValue<V>)>,
+        errors: &mut Vec<(PageId, Error)>,
+    ) {
+        if !visited.insert(page_id) {
+            let err = Error::with_kind(ErrorKind::Corrupt, "the page is refered to twice");
+            errors.push((page_id, err));
+            return;
+        }
+        let page = match self.pager.get_page(page_id) {
+            Ok(page) => page,
+            Err(err) => return errors.push((page_id, err)),
+        };
+        match get_node_type(&page) {
+            Ok(NodeType::Leaf) => {
+                let node = unsafe { LeafNode::<K, V>::new_unchecked(page) };
+                records.extend(node.into_iter().map(|r| (r.key, r.value)));
+            }
+            Ok(NodeType::Internal) => {
+                let mut node = unsafe { InternalNode::<K>::new_unchecked(page) };
+                let mut children: Vec<PageId> = node.into_iter().map(|r| r.value).collect();
+                children.push(unsafe { node.hdr_mut().rightest_page_id });
+                for child in children {
+                    self.scan_page(child, visited, records, errors);
+                }
+            }
+            Ok(typ) => errors.push((page_id, unexpected_node_type(typ))),
+            Err(err) => errors.push((page_id, err)),
+        }
+    }
+
+    /// Check the structure of the whole tree, and raise an error of
+    /// `ErrorKind::Corrupt` for the first broken rule found. It is for tests:
+    /// a broken split or merge maybe still gets the right values by luck.
+    ///
+    /// The keys in every node are strictly ascending. The keys under a child
+    /// are at most its separator, and greater than the separator before it.
+    /// Every child - the rightest one as well - is a page of the tree which is
+    /// referenced only once. All leaves are at the same depth, and the chain
+    /// of leaves links them in key order. The count of records is `len`.
+    pub fn check_invariants(&self) -> Result<(), Error> {
+        /// What is collected while walking down the tree.
+        #[derive(Default)]
+        struct Checked {
+            visited: HashSet<PageId>,
+            leaves: Vec<PageId>,
+            leaf_depth: Option<usize>,
+            records: usize,
+        }
+
+        fn check<K: Pod + Ord + StdHash, V: Pod>(
+            slf: &BPlusTree<K, V>,
+            page_id: PageId,
+            (lower, upper): (Option<K>, Option<K>),
+            depth: usize,
+            checked: &mut Checked,
+        ) -> Result<(), Error> {
+            let corrupt = |msg: &str| {
+                Error::with_kind(ErrorKind::Corrupt, format!("{}: page {}", msg, page_id.raw()))
+            };
+            if page_id.raw() as usize >= slf.page_count() {
+                return Err(corrupt("the child is not a page of the tree"));
+            }
+            if !checked.visited.insert(page_id) {
+                return Err(corrupt("the page is refered to twice"));
+            }
+            let page = slf.pager.get_page(page_id)?;
+            let in_bounds = |key: &K| {
+                lower.is_none_or(|lower| *key > lower) && upper.is_none_or(|upper| *key <= upper)
+            };
+            let check_keys = |keys: &[K]| {
+                if !keys.windows(2).all(|w| w[0] < w[1]) {
+                    return Err(corrupt("the keys are not ascending"));
+                }
+                if !keys.iter().all(in_bounds) {
+                    return Err(corrupt("the key is out of the bounds of its parent"));
+                }
+                Ok(())
+            };
+            match get_node_type(&page)? {
                 NodeType::Leaf => {
-                    let node = unsafe { LeafNode::new_unchecked(page) };
-                    let result = node.get(key);
-                    Ok(result)
+                    let node = unsafe { LeafNode::<K, V>::new_unchecked(page) };
+                    let keys: Vec<K> = node.into_iter().map(|r| r.key).collect();
+                    check_keys(&keys)?;
+                    if *checked.leaf_depth.get_or_insert(depth) != depth {
+                        return Err(corrupt("the leaves are not at the same depth"));
+                    }
+                    checked.leaves.push(page_id);
+                    checked.records += keys.len();
                 }
                 NodeType::Internal => {
-                    let node = unsafe { InternalNode::new_unchecked(page) };
-                    let (_, next_page_id) = node.get(key);
-                    let page = slf.pager.get_page(next_page_id)?;
-                    inner_get(slf, page, key)
+                    let mut node = unsafe { InternalNode::<K>::new_unchecked(page) };
+                    let records: Vec<(K, PageId)> =
+                        node.into_iter().map(|r| (r.key, r.value)).collect();
+                    let keys: Vec<K> = records.iter().map(|(key, _)| *key).collect();
+                    check_keys(&keys)?;
+                    let mut lower = lower;
+                    for (separator, child) in records {
+                        check(slf, child, (lower, Some(separator)), depth + 1, checked)?;
+                        lower = Some(separator);
+                    }
+                    let rightest_page_id = unsafe { node.hdr_mut().rightest_page_id };
+                    check(slf, rightest_page_id, (lower, upper), depth + 1, checked)?;
                 }
-                _ => panic!("unsupported node"),
+                typ => return Err(unexpected_node_type(typ)),
             }
+            Ok(())
         }
 
-        inner_get(self, root_page, key)
+        let mut checked = Checked::default();
+        let root_page_id = self.head_node.hdr().root_node_page_id;
+        check(self, root_page_id, (None, None), 0, &mut checked)?;
+
+        // A broken chain maybe loops forever, so it is cut after all leaves.
+        let chained = self
+            .leaves()?
+            .take(checked.leaves.len() + 1)
+            .map(|leaf| Ok(leaf?.page_id()))
+            .collect::<Result<Vec<PageId>, Error>>()?;
+        if chained != checked.leaves {
+            return Err(Error::with_kind(ErrorKind::Corrupt, "the chain of leaves is broken"));
+        }
+        if checked.records != self.len() {
+            return Err(Error::with_kind(
+                ErrorKind::Corrupt,
+                format!("{} records are found, but `len` is {}", checked.records, self.len()),
+            ));
+        }
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::{collections::HashMap, fs, path::PathBuf};
+    /// Get all records whose keys are in `[start, end)`, in key order.
+    pub fn range(&self, start: &K, end: &K) -> Result<Vec<(K, V)>, Error> {
+        if end <= start {
+            return Ok(vec![]);
+        }
+        self.walk_from(start, |key| key >= end)
+    }
 
-    use crate::hash::HASH_SIZE;
+    /// Get all records whose keys are in `[start, last]`, in key order.
+    pub fn range_inclusive(&self, start: &K, last: &K) -> Result<Vec<(K, V)>, Error> {
+        if last < start {
+            return Ok(vec![]);
+        }
+        self.walk_from(start, |key| key > last)
+    }
 
-    use super::*;
+    /// Get all records from `start` in key order, until `is_end` is true.
+    fn walk_from<F>(&self, start: &K, is_end: F) -> Result<Vec<(K, V)>, Error>
+    where
+        F: Fn(&K) -> bool,
+    {
+        let mut res = vec![];
 
-    fn cleanup_and_create_new_btree_file(btree_filename: &str) -> PathBuf {
-        let directory_path = Path::new("/tmp/waste-land/");
-        if !directory_path.exists() {
-            fs::create_dir(directory_path).unwrap();
+        // The `start` may fall between two leaves, so the leaf found maybe has
+        // no record in the range - then just walk to the next one.
+        let start_page_id = self.find_leaf(start)?.page_id();
+        let leaves = Leaves { btree: self, next_page_id: start_page_id };
+        for leaf_node in leaves {
+            for r in leaf_node?.into_iter() {
+                if is_end(&r.key) {
+                    return Ok(res);
+                }
+                if &r.key >= start {
+                    res.push((r.key, r.value.value));
+                }
+            }
         }
-        let btree_path = directory_path.join(btree_filename);
-        if btree_path.exists() {
-            fs::remove_file(&btree_path).unwrap();
+        Ok(res)
+    }
+
+    /// Run `f` in a transaction of the pager: the pages it syncs are written
+    /// all at once by the WAL if it succeeds, or they are all rolled back if it
+    /// fails.
+    ///
+    /// The generation in the head node is bumped in the same transaction if
+    /// anything is changed, see `refresh`.
+    fn transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        self.pager.begin();
+        match f(self).and_then(|res| self.bump_generation().map(|()| res)) {
+            Ok(res) => {
+                self.pager.commit()?;
+                Ok(res)
+            }
+            Err(err) => {
+                self.cache.clear();
+                self.pager.abort()?;
+                Err(err)
+            }
         }
-        File::create(&btree_path).unwrap();
-        btree_path
     }
 
-    #[test]
-    fn it_works() {
-        let btree_path = cleanup_and_create_new_btree_file("it-works.btree");
+    /// Bump the generation in the head node if any page is synced in the
+    /// transaction.
+    fn bump_generation(&mut self) -> Result<(), Error> {
+        if !self.pager.has_transaction_pages() {
+            return Ok(());
+        }
+        unsafe { self.head_node.mut_hdr().generation += 1 };
+        self.head_node.make_dirty();
+        self.pager.sync_page(unsafe { self.head_node.mut_page() })
+    }
 
-        let mut btree = BTree::new(btree_path).unwrap();
-        let (key1, value1) = (&Hash::from_bytes([14u8; HASH_SIZE]), Offset::new(114514));
-        let (key2, value2) = (&Hash::from_bytes([21u8; HASH_SIZE]), Offset::new(63));
-        btree.put(&key1, &value2).unwrap();
-        btree.put(&key2, &value2).unwrap();
-        btree.put(&key1, &value1).unwrap();
-        assert_eq!(btree.get(key1).unwrap(), Some(value1));
-        assert_eq!(btree.get(key2).unwrap(), Some(value2));
-        assert_eq!(
-            btree.get(&Hash::from_bytes([33u8; HASH_SIZE])).unwrap(),
-            None
-        );
+    /// Pick up the changes by another writer of the file - for example, if it
+    /// is opened by `open_read_only` in another process. Return `true` if it
+    /// is changed since it is opened or refreshed last time.
+    ///
+    /// Only the head node is read if nothing is changed. Otherwise all cached
+    /// pages are dropped, as any of them maybe is rewritten.
+    ///
+    /// The writer writes the pages of a transaction one by one, so a read
+    /// racing with it maybe sees some of them only - then refresh again.
+    pub fn refresh(&mut self) -> Result<bool, Error> {
+        let head_page = self.pager.read_page_uncached(Self::HEAD_PAGE_ID)?;
+        let head_node = unsafe { HeadNode::new_unchecked(head_page) };
+        if !head_node.check() {
+            return Err(Error::with_kind(ErrorKind::Corrupt, "the head node is broken"));
+        }
+        if head_node.generation() == self.head_node.generation() {
+            return Ok(false);
+        }
+
+        self.pager.refresh()?;
+        self.cache.clear();
+        let head_page = self.pager.get_page(Self::HEAD_PAGE_ID)?;
+        self.head_node = unsafe { HeadNode::new_unchecked(head_page) };
+        Ok(true)
     }
 
-    #[test]
-    fn it_works_even_after_reopen() {
-        let btree_path = cleanup_and_create_new_btree_file("it-works-even-after-reopen.btree");
+    /// Put many records at once. The pages are synced only once at the end,
+    /// instead of once for each record - and all records are put, or none of
+    /// them.
+    pub fn put_batch(&mut self, records: &[(K, V)]) -> Result<(), Error> {
+        self.transaction(|slf| {
+            records
+                .iter()
+                .try_for_each(|(key, value)| slf.put_record(key, &LeafValue::new(*value, 1, NEVER_EXPIRES)))
+        })
+    }
 
-        {
-            let mut btree = BTree::new(&btree_path).unwrap();
-            for i in 0..0xff {
-                btree.put(&Hash::from_bytes([i as u8; HASH_SIZE]), &Offset::new(i)).unwrap();
-            }
+    /// Put many records at once as `put_batch` does, but each of them adds its
+    /// own count of references instead of one, and has its own expiry time.
+    pub fn put_batch_with_refcounts(&mut self, records: &[(K, LeafValue<V>)]) -> Result<(), Error> {
+        self.transaction(|slf| {
+            records.iter().try_for_each(|(key, value)| slf.put_record(key, value))
+        })
+    }
+
+    /// Load the records into the empty BTree - much faster than putting them
+    /// one by one. The records must be in strictly ascending order of keys:
+    /// the leaves are filled one by one from the left, and the internal nodes
+    /// are built bottom-up over them.
+    ///
+    /// The new nodes are referenced by the head node only once all of them are
+    /// written, so the BTree is still empty if it fails.
+    pub fn bulk_load(&mut self, records: &[(K, LeafValue<V>)]) -> Result<(), Error> {
+        if !self.is_empty() {
+            return Err(Error::with_kind(
+                ErrorKind::Unsupported,
+                "only an empty BTree can be bulk loaded",
+            ));
         }
-        for i in 0..0xff {
-            let mut btree = BTree::new(&btree_path).unwrap();
-            btree.get(&Hash::from_bytes([i as u8; HASH_SIZE])).unwrap();
+        if records.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return Err(Error::new("the records are not in strictly ascending order"));
         }
+        self.transaction(|slf| slf.bulk_load_records(records))
     }
 
-    #[test]
-    fn a_simple_tree_with_internal_node() {
-        let btree_path =
-            cleanup_and_create_new_btree_file("a-simple-tree-with-internal-node.btree");
+    /// Load the records into the empty BTree - in a transaction begun by the
+    /// caller, which is committed and begun again once in a while.
+    fn bulk_load_records(&mut self, records: &[(K, LeafValue<V>)]) -> Result<(), Error> {
+        // The rightest internal node of each level from the bottom, and the
+        // greatest key under it. They are only added to the level above once
+        // they are full - or all records are loaded.
+        let mut levels: Vec<(InternalNode<K>, K)> = vec![];
+        let mut leaf = self.new_leaf()?;
+        let mut leaves = 1;
+        for (key, value) in records {
+            if leaf.is_full() {
+                let mut next_leaf = self.new_leaf()?;
+                unsafe { leaf.set_next_leaf(next_leaf.page_id()) };
+                leaf.make_dirty();
+                self.pager.sync_page(unsafe { leaf.mut_page() })?;
+                let greatest_key = unsafe { *leaf.rightest_key() };
+                self.push_child(&mut levels, 0, greatest_key, leaf.page_id())?;
+                std::mem::swap(&mut leaf, &mut next_leaf);
 
-        let mut btree = BTree::new(btree_path).unwrap();
-        let mut mem_map = HashMap::new();
-        let mut keys = vec![];
-        for i in 0..0xff {
-            let key = Hash::from_bytes([i; HASH_SIZE]);
-            keys.push(key);
-            let value = Offset::new(i as u64);
-            btree.put(&key, &value).unwrap();
-            mem_map.insert(key, value);
+                leaves += 1;
+                if leaves % Self::BULK_LOAD_BATCH_LEAVES == 0 {
+                    self.pager.commit()?;
+                    self.pager.begin();
+                }
+            }
+            unsafe { leaf.put(key, value) };
         }
+        leaf.make_dirty();
+        self.pager.sync_page(unsafe { leaf.mut_page() })?;
 
-        for k in mem_map.keys() {
-            assert_eq!(&btree.get(k).unwrap().unwrap(), mem_map.get(k).unwrap());
+        // The rightest nodes are complete now, so add each of them to the
+        // level above - the top one is the root.
+        let mut root_page_id = leaf.page_id();
+        if !levels.is_empty() {
+            let greatest_key = unsafe { *leaf.rightest_key() };
+            self.push_child(&mut levels, 0, greatest_key, leaf.page_id())?;
+            let mut level = 0;
+            while level + 1 < levels.len() {
+                let (key, page_id) = (levels[level].1, levels[level].0.page_id());
+                self.push_child(&mut levels, level + 1, key, page_id)?;
+                level += 1;
+            }
+            root_page_id = levels[levels.len() - 1].0.page_id();
         }
 
-        assert_eq!(btree.list().unwrap(), keys);
+        unsafe { self.head_node.mut_hdr().root_node_page_id = root_page_id };
+        self.set_len(records.len())
+    }
+
+    /// Append a new empty leaf.
+    fn new_leaf(&mut self) -> Result<LeafNode<K, V>, Error> {
+        let page = self.pager.append_empty_uninited_page()?;
+        let mut leaf = unsafe { LeafNode::<K, V>::new_unchecked(page) };
+        unsafe { leaf.init() };
+        Ok(leaf)
+    }
+
+    /// Add the child - whose greatest key is `key` - as the rightest child of
+    /// the rightest node of the level, while bulk loading. If the node is
+    /// full, a new node takes its place, and the full one is added to the
+    /// level above.
+    fn push_child(
+        &mut self,
+        levels: &mut Vec<(InternalNode<K>, K)>,
+        mut level: usize,
+        mut key: K,
+        mut page_id: PageId,
+    ) -> Result<(), Error> {
+        loop {
+            if level == levels.len() {
+                let node = self.new_internal(page_id)?;
+                levels.push((node, key));
+                return Ok(());
+            }
+
+            let (node, greatest_key) = &mut levels[level];
+            if !node.is_full() {
+                let rightest_page_id = unsafe { node.hdr_mut().rightest_page_id };
+                unsafe { node.put(greatest_key, &rightest_page_id) };
+                unsafe { node.hdr_mut().rightest_page_id = page_id };
+                *greatest_key = key;
+                node.make_dirty();
+                return self.pager.sync_page(unsafe { node.mut_page() });
+            }
+
+            let full = (*greatest_key, node.page_id());
+            levels[level] = (self.new_internal(page_id)?, key);
+            (key, page_id) = full;
+            level += 1;
+        }
+    }
+
+    /// Append a new internal node, whose only child is the page.
+    fn new_internal(&mut self, rightest_page_id: PageId) -> Result<InternalNode<K>, Error> {
+        let page = self.pager.append_empty_uninited_page()?;
+        let mut node = unsafe { InternalNode::<K>::new_unchecked(page) };
+        unsafe { node.init(rightest_page_id) };
+        node.make_dirty();
+        self.pager.sync_page(unsafe { node.mut_page() })?;
+        Ok(node)
+    }
+
+    /// Put a new record (key, value). If the key is already existing, its
+    /// value is updated and its reference count is increased - so it needs to
+    /// be deleted as many times as it is put.
+    ///
+    /// Return `true` if the key is new, or `false` if it is already existing.
+    pub fn put(&mut self, key: &K, value: &V) -> Result<bool, Error> {
+        self.put_with_expiry(key, value, NEVER_EXPIRES)
+    }
+
+    /// Put a new record as `put` does, which expires at `expires_at` - in
+    /// milliseconds since the Unix epoch. An expired record is never got, and
+    /// it is removed by `purge_expired`.
+    ///
+    /// If the key is already existing and not expired yet, the later expiry
+    /// time of both wins.
+    pub fn put_with_expiry(
+        &mut self,
+        key: &K,
+        value: &V,
+        expires_at: u64,
+    ) -> Result<bool, Error> {
+        let len = self.len();
+        self.transaction(|slf| slf.put_record(key, &LeafValue::new(*value, 1, expires_at)))?;
+        Ok(self.len() > len)
+    }
+
+    /// Put a new record (key, value) - in a transaction begun by the caller.
+    /// The reference count in `value` is added to the existing one, unless the
+    /// existing one is expired - then it is replaced.
+    ///
+    /// Full nodes are split on the way down, so the key is put into the half
+    /// which should hold it, and the parent only takes the new separator - each
+    /// node is visited once.
+    fn put_record(&mut self, key: &K, value: &LeafValue<V>) -> Result<(), Error> {
+        let root_page_id = self.head_node.hdr().root_node_page_id;
+        let root_page = self.pager.get_page(root_page_id)?;
+
+        enum InnerPut<K> {
+            /// The node is split: the left half keeps its page and the keys up
+            /// to the separator, and the right half is in the new page.
+            SplitMe(K, PageId),
+            Alright,
+        }
+
+        /// Put the record into the leaf, and return `true` if the key is new.
+        fn put_into_leaf<K: Pod + Ord, V: Pod>(
+            node: &mut LeafNode<K, V>,
+            key: &K,
+            value: &LeafValue<V>,
+        ) -> bool {
+            let origin_value = unsafe { node.put(key, value) };
+            if let Some(origin) = origin_value {
+                if origin.expires_at > now_millis() {
+                    let value = LeafValue::new(
+                        value.value,
+                        origin.refcount + value.refcount,
+                        origin.expires_at.max(value.expires_at),
+                    );
+                    unsafe { node.put(key, &value) };
+                }
+            }
+            node.make_dirty();
+            origin_value.is_none()
+        }
+
+        /// Put the record under the internal node, which must not be full - so
+        /// that it can take the separator if its child is split.
+        fn put_into_internal<K: Pod + Ord + StdHash, V: Pod>(
+            slf: &mut BPlusTree<K, V>,
+            node: &mut InternalNode<K>,
+            key: &K,
+            value: &LeafValue<V>,
+        ) -> Result<(), Error> {
+            let (origin_key, next_page_id) = node.get(key);
+            let next_page = slf.pager.get_page(next_page_id)?;
+            if let InnerPut::SplitMe(new_key, new_value) = inner_put(slf, next_page, key, value)? {
+                match origin_key {
+                    Some(ori_k) => {
+                        unsafe { node.put(&ori_k, &new_value) };
+                        unsafe { node.put(&new_key, &next_page_id) };
+                    }
+                    None => {
+                        unsafe { node.hdr_mut().rightest_page_id = new_value };
+                        unsafe { node.put(&new_key, &next_page_id) };
+                    }
+                };
+                node.make_dirty();
+            }
+            Ok(())
+        }
+
+        #[cfg_attr(feature = "tracing", tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(page_id = ?page.id(), node_type = ?get_node_type(&page).ok()),
+        ))]
+        fn inner_put<K: Pod + Ord + StdHash, V: Pod>(
+            slf: &mut BPlusTree<K, V>,
+            page: Page,
+            key: &K,
+            value: &LeafValue<V>,
+        ) -> Result<InnerPut<K>, Error> {
+            match get_node_type(&page)? {
+                NodeType::Leaf => {
+                    let mut node = unsafe { LeafNode::<K, V>::new_unchecked(page) };
+
+                    if !node.is_full() {
+                        let inserted = put_into_leaf(&mut node, key, value);
+                        slf.pager.sync_page(unsafe { node.mut_page() })?;
+                        if inserted {
+                            slf.set_len(slf.len() + 1)?;
+                        }
+                        return Ok(InnerPut::Alright);
+                    }
+
+                    // Split me into new_node, and put the record into the half
+                    // holding its key.
+                    let new_page = slf.pager.append_empty_uninited_page()?;
+                    let mut new_node = unsafe { LeafNode::<K, V>::new_unchecked(new_page) };
+                    unsafe { new_node.init() };
+                    unsafe { node.split(&mut new_node) };
+                    let separator = unsafe { *node.rightest_key() };
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(new_page_id = ?new_node.page_id(), "split leaf");
+
+                    let inserted = match key <= &separator {
+                        true => put_into_leaf(&mut node, key, value),
+                        false => put_into_leaf(&mut new_node, key, value),
+                    };
+                    new_node.make_dirty();
+                    node.make_dirty();
+                    slf.pager.sync_page(unsafe { new_node.mut_page() })?;
+                    slf.pager.sync_page(unsafe { node.mut_page() })?;
+                    if inserted {
+                        slf.set_len(slf.len() + 1)?;
+                    }
+                    Ok(InnerPut::SplitMe(separator, new_node.page_id()))
+                }
+                NodeType::Internal => {
+                    let mut node = unsafe { InternalNode::<K>::new_unchecked(page) };
+
+                    if !node.is_full() {
+                        put_into_internal(slf, &mut node, key, value)?;
+                        slf.pager.sync_page(unsafe { node.mut_page() })?;
+                        return Ok(InnerPut::Alright);
+                    }
+
+                    // Split me into two new node.
+                    let new_page = slf.pager.append_empty_uninited_page()?;
+                    let mut new_node = unsafe { InternalNode::<K>::new_unchecked(new_page) };
+                    unsafe { new_node.init(node.hdr_mut().rightest_page_id) };
+                    unsafe { node.split(&mut new_node) };
+
+                    // Change inner struct...
+                    let mid_record = unsafe { node.pop_rightest_record() };
+                    unsafe { node.hdr_mut().rightest_page_id = mid_record.value };
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(new_page_id = ?new_node.page_id(), "split internal node");
+
+                    match key <= &mid_record.key {
+                        true => put_into_internal(slf, &mut node, key, value)?,
+                        false => put_into_internal(slf, &mut new_node, key, value)?,
+                    };
+                    new_node.make_dirty();
+                    node.make_dirty();
+                    slf.pager.sync_page(unsafe { new_node.mut_page() })?;
+                    slf.pager.sync_page(unsafe { node.mut_page() })?;
+                    Ok(InnerPut::SplitMe(mid_record.key, new_node.page_id()))
+                }
+                typ => Err(unexpected_node_type(typ)),
+            }
+        }
+
+        if let InnerPut::SplitMe(new_key, new_value) = inner_put(self, root_page, key, value)? {
+            let parent_page = self.pager.append_empty_uninited_page()?;
+            let mut parent_node = unsafe { InternalNode::<K>::new_unchecked(parent_page) };
+            unsafe { parent_node.init(new_value) };
+            unsafe { parent_node.put(&new_key, &root_page_id) }
+            unsafe {
+                self.head_node.mut_hdr().root_node_page_id = parent_node.page_id();
+            }
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(root_page_id = ?parent_node.page_id(), "grow root");
+            self.head_node.make_dirty();
+            parent_node.make_dirty();
+            unsafe {
+                self.pager.sync_page(self.head_node.mut_page())?;
+                self.pager.sync_page(parent_node.mut_page())?;
+            }
+        }
+
+        // Only the records never expiring are cached, so that the cache never
+        // needs to check the time.
+        match value.expires_at {
+            NEVER_EXPIRES => self.cache.insert(*key, value.value),
+            _ => self.cache.remove(key),
+        };
+        Ok(())
+    }
+
+    /// Remove all expired records, however many references they have. Return
+    /// the count of records removed.
+    pub fn purge_expired(&mut self) -> Result<usize, Error> {
+        let now = now_millis();
+        let expired: Vec<K> = self
+            .records()?
+            .into_iter()
+            .filter(|(_, value)| value.expires_at <= now)
+            .map(|(key, _)| key)
+            .collect();
+        self.transaction(|slf| {
+            for key in &expired {
+                slf.remove_record(key)?;
+            }
+            Ok(expired.len())
+        })
+    }
+
+    /// Delete the record by its key. Return `true` if the record was existing.
+    ///
+    /// The reference count of the record is decreased, and the record is only
+    /// removed once it hits zero.
+    ///
+    /// The node having less than `cap() / 2` records will be merged with its
+    /// sibling if they can fit in one node. The page of the merged right
+    /// sibling will not be reused for now.
+    pub fn delete(&mut self, key: &K) -> Result<bool, Error> {
+        self.transaction(|slf| slf.delete_record(key))
+    }
+
+    /// Delete the record by its key - in a transaction begun by the caller.
+    fn delete_record(&mut self, key: &K) -> Result<bool, Error> {
+        let mut leaf_node = self.find_leaf(key)?;
+        match leaf_node.get(key) {
+            None => return Ok(false),
+            Some(value) if value.refcount > 1 => {
+                let value = LeafValue::new(value.value, value.refcount - 1, value.expires_at);
+                unsafe { leaf_node.put(key, &value) };
+                leaf_node.make_dirty();
+                self.pager.sync_page(unsafe { leaf_node.mut_page() })?;
+                return Ok(true);
+            }
+            Some(_) => {}
+        }
+        drop(leaf_node);
+        self.remove_record(key)
+    }
+
+    /// Remove the record by its key, however many references it has - in a
+    /// transaction begun by the caller. Return `true` if the record was
+    /// existing.
+    fn remove_record(&mut self, key: &K) -> Result<bool, Error> {
+        let root_page_id = self.head_node.hdr().root_node_page_id;
+        let root_page = self.pager.get_page(root_page_id)?;
+
+        fn inner_delete<K: Pod + Ord + StdHash, V: Pod>(
+            slf: &mut BPlusTree<K, V>,
+            page: Page,
+            key: &K,
+        ) -> Result<bool, Error> {
+            match get_node_type(&page)? {
+                NodeType::Leaf => {
+                    let mut node = unsafe { LeafNode::<K, V>::new_unchecked(page) };
+                    if unsafe { node.remove(key) }.is_none() {
+                        return Ok(false);
+                    }
+                    node.make_dirty();
+                    slf.pager.sync_page(unsafe { node.mut_page() })?;
+                    Ok(true)
+                }
+                NodeType::Internal => {
+                    let mut node = unsafe { InternalNode::<K>::new_unchecked(page) };
+                    let (origin_key, next_page_id) = node.get(key);
+                    let next_page = slf.pager.get_page(next_page_id)?;
+                    if !inner_delete(slf, next_page.clone(), key)? {
+                        return Ok(false);
+                    }
+                    if BPlusTree::<K, V>::is_underfull(&next_page)? {
+                        slf.merge_child(&mut node, origin_key)?;
+                    }
+                    Ok(true)
+                }
+                typ => Err(unexpected_node_type(typ)),
+            }
+        }
+
+        self.cache.remove(key);
+        if !inner_delete(self, root_page, key)? {
+            return Ok(false);
+        }
+        self.set_len(self.len() - 1)?;
+
+        // Merging down to the single root: the root without any record just
+        // has one child, so let the child be the new root.
+        loop {
+            let root_page_id = self.head_node.hdr().root_node_page_id;
+            let root_page = self.pager.get_page(root_page_id)?;
+            if get_node_type(&root_page)? != NodeType::Internal {
+                break;
+            }
+            let mut root_node = unsafe { InternalNode::<K>::new_unchecked(root_page) };
+            if !root_node.is_empty() {
+                break;
+            }
+
+            unsafe {
+                self.head_node.mut_hdr().root_node_page_id = root_node.hdr_mut().rightest_page_id;
+            }
+            self.head_node.make_dirty();
+            self.pager.sync_page(unsafe { self.head_node.mut_page() })?;
+        }
+        Ok(true)
+    }
+
+    /// Is the node of the page less than half full?
+    fn is_underfull(page: &Page) -> Result<bool, Error> {
+        match get_node_type(page)? {
+            NodeType::Leaf => {
+                let node = unsafe { LeafNode::<K, V>::new_unchecked(page.clone()) };
+                Ok(node.len() < node.cap() / 2)
+            }
+            NodeType::Internal => {
+                let node = unsafe { InternalNode::<K>::new_unchecked(page.clone()) };
+                Ok(node.len() < node.cap() / 2)
+            }
+            typ => Err(unexpected_node_type(typ)),
+        }
+    }
+
+    /// Merge the child of `node` with its sibling if they can fit in one node.
+    /// The child is pointed by the record of `child_key`, or by the
+    /// `rightest_page_id` if `child_key` is `None`.
+    fn merge_child(
+        &mut self,
+        node: &mut InternalNode<K>,
+        child_key: Option<K>,
+    ) -> Result<(), Error> {
+        let records: Vec<(K, PageId)> = node.into_iter().map(|r| (r.key, r.value)).collect();
+        let rightest_page_id = unsafe { node.hdr_mut().rightest_page_id };
+
+        // The left one is pointed by the record of `separator`, and the right
+        // one is pointed by the record of `right_key` - or `rightest_page_id`
+        // if there is no such record.
+        let left_idx = match child_key {
+            Some(k) => records.iter().position(|(rk, _)| *rk == k).unwrap(),
+            None if records.is_empty() => return Ok(()),
+            None => records.len() - 1,
+        };
+        let (separator, left_page_id) = records[left_idx];
+        let (right_key, right_page_id) = match records.get(left_idx + 1) {
+            Some((k, v)) => (Some(*k), *v),
+            None => (None, rightest_page_id),
+        };
+
+        let left_page = self.pager.get_page(left_page_id)?;
+        let right_page = self.pager.get_page(right_page_id)?;
+        match get_node_type(&left_page)? {
+            NodeType::Leaf => {
+                let mut left_node = unsafe { LeafNode::<K, V>::new_unchecked(left_page) };
+                let right_node = unsafe { LeafNode::<K, V>::new_unchecked(right_page) };
+                if left_node.len() + right_node.len() > left_node.cap() {
+                    return Ok(());
+                }
+                unsafe { left_node.merge(&right_node) };
+                left_node.make_dirty();
+                self.pager.sync_page(unsafe { left_node.mut_page() })?;
+            }
+            NodeType::Internal => {
+                let mut left_node = unsafe { InternalNode::<K>::new_unchecked(left_page) };
+                let right_node = unsafe { InternalNode::<K>::new_unchecked(right_page) };
+                if left_node.len() + right_node.len() + 1 > left_node.cap() {
+                    return Ok(());
+                }
+                unsafe { left_node.merge(&separator, &right_node) };
+                left_node.make_dirty();
+                self.pager.sync_page(unsafe { left_node.mut_page() })?;
+            }
+            typ => return Err(unexpected_node_type(typ)),
+        }
+
+        // Now the left one takes the place of the right one.
+        unsafe { node.remove(&separator) };
+        match right_key {
+            Some(k) => unsafe { node.put(&k, &left_page_id) },
+            None => unsafe { node.hdr_mut().rightest_page_id = left_page_id },
+        }
+        node.make_dirty();
+        self.pager.sync_page(unsafe { node.mut_page() })?;
+        Ok(())
+    }
+
+    /// Sync all dirty pages to the index file.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.pager.flush_all()
+    }
+
+    /// Sync all dirty pages to the index file, and `fsync` it.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.pager.sync_all()
+    }
+
+    /// Get record's value by the record's key.
+    pub fn get(&self, key: &K) -> Result<Option<V>, Error> {
+        if let Some(v) = self.cache.get(key) {
+            return Ok(Some(*v));
+        }
+
+        let root_page_id = self.head_node.hdr().root_node_page_id;
+        let root_page = self.pager.get_page(root_page_id)?;
+
+        #[cfg_attr(feature = "tracing", tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(page_id = ?page.id(), node_type = ?get_node_type(&page).ok()),
+        ))]
+        fn inner_get<K: Pod + Ord + StdHash, V: Pod>(
+            slf: &BPlusTree<K, V>,
+            page: Page,
+            key: &K,
+        ) -> Result<Option<V>, Error> {
+            match get_node_type(&page)? {
+                NodeType::Leaf => {
+                    let node = unsafe { LeafNode::<K, V>::new_unchecked(page) };
+                    let result = node.get(key).filter(|value| value.expires_at > now_millis());
+                    Ok(result.map(|value| value.value))
+                }
+                NodeType::Internal => {
+                    let node = unsafe { InternalNode::<K>::new_unchecked(page) };
+                    let (_, next_page_id) = node.get(key);
+                    let page = slf.pager.get_page(next_page_id)?;
+                    inner_get(slf, page, key)
+                }
+                typ => Err(unexpected_node_type(typ)),
+            }
+        }
+
+        inner_get(self, root_page, key)
+    }
+
+    /// Get the whole record's value by the record's key - with its reference
+    /// count and expiry time, even if it is expired.
+    pub fn get_record(&self, key: &K) -> Result<Option<LeafValue<V>>, Error> {
+        Ok(self.find_leaf(key)?.get(key))
+    }
+
+    /// Get the values of many keys, which must be in ascending order. The keys
+    /// in the same leaf are found by only one descent to it.
+    pub fn get_many(&self, keys: &[K]) -> Result<Vec<Option<V>>, Error> {
+        debug_assert!(keys.windows(2).all(|w| w[0] <= w[1]), "keys are not sorted");
+        let mut res = Vec::with_capacity(keys.len());
+        let mut leaf: Option<LeafNode<K, V>> = None;
+        let now = now_millis();
+        for key in keys {
+            if let Some(v) = self.cache.get(key) {
+                res.push(Some(*v));
+                continue;
+            }
+            // The leaf found for a smaller key holds this key as well, unless
+            // this key is greater than all keys in it.
+            let holds = |leaf: &LeafNode<K, V>| {
+                leaf.len() > 0 && key <= unsafe { leaf.rightest_key() }
+            };
+            let leaf = match &mut leaf {
+                Some(leaf) if holds(leaf) => leaf,
+                _ => leaf.insert(self.find_leaf(key)?),
+            };
+            let value = leaf.get(key).filter(|value| value.expires_at > now);
+            res.push(value.map(|value| value.value));
+        }
+        Ok(res)
+    }
+}
+
+impl<'a, K, V> Iterator for Leaves<'a, K, V>
+where
+    K: Pod + Ord + StdHash,
+    V: Pod,
+{
+    type Item = Result<LeafNode<K, V>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_page_id == PageId::invalid() {
+            return None;
+        }
+
+        let page = match self.btree.pager.get_page(self.next_page_id) {
+            Ok(page) => page,
+            Err(e) => {
+                self.next_page_id = PageId::invalid();
+                return Some(Err(e));
+            }
+        };
+        let node = unsafe { LeafNode::<K, V>::new_unchecked(page) };
+        self.next_page_id = node.next_leaf();
+        Some(Ok(node))
+    }
+}
+
+/// The error of a node found where it should not be - for example, the head
+/// node in place of a child. The index file must be corrupt.
+fn unexpected_node_type(typ: NodeType) -> Error {
+    Error::with_kind(ErrorKind::Corrupt, format!("unexcepted node type: {:?}", typ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs, path::PathBuf};
+
+    use rand::seq::SliceRandom;
+
+    use crate::{btree::page::DEFAULT_PAGE_SIZE, hash::HASH_SIZE, store::MemStore};
+
+    use super::*;
+
+    const HASH_ALGORITHM: &str = "sha256";
+
+    fn cleanup_and_create_new_btree_file(btree_filename: &str) -> PathBuf {
+        let directory_path = Path::new("/tmp/waste-land/");
+        if !directory_path.exists() {
+            fs::create_dir(directory_path).unwrap();
+        }
+        let btree_path = directory_path.join(btree_filename);
+        if btree_path.exists() {
+            fs::remove_file(&btree_path).unwrap();
+        }
+        let wal_path = BTree::wal_path(&btree_path);
+        if wal_path.exists() {
+            fs::remove_file(&wal_path).unwrap();
+        }
+        File::create(&btree_path).unwrap();
+        btree_path
+    }
+
+    /// The key of the `i`-th record of a test. The keys are scattered, so they
+    /// are not put in order.
+    fn test_key(i: u64) -> Hash {
+        let mut bytes = [0u8; HASH_SIZE];
+        bytes[..8].copy_from_slice(&i.wrapping_mul(0x9e3779b97f4a7c15).to_be_bytes());
+        Hash::from_bytes(bytes)
+    }
+
+    #[test]
+    fn it_works() {
+        let btree_path = cleanup_and_create_new_btree_file("it-works.btree");
+
+        let mut btree = BTree::new(btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        let (key1, value1) = (&Hash::from_bytes([14u8; HASH_SIZE]), Offset::new(114514));
+        let (key2, value2) = (&Hash::from_bytes([21u8; HASH_SIZE]), Offset::new(63));
+        btree.put(&key1, &value2).unwrap();
+        btree.put(&key2, &value2).unwrap();
+        btree.put(&key1, &value1).unwrap();
+        assert_eq!(btree.get(key1).unwrap(), Some(value1));
+        assert_eq!(btree.get(key2).unwrap(), Some(value2));
+        assert_eq!(
+            btree.get(&Hash::from_bytes([33u8; HASH_SIZE])).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn it_works_even_after_reopen() {
+        let btree_path = cleanup_and_create_new_btree_file("it-works-even-after-reopen.btree");
+
+        {
+            let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+            for i in 0..0xff {
+                btree.put(&Hash::from_bytes([i as u8; HASH_SIZE]), &Offset::new(i)).unwrap();
+            }
+        }
+        for i in 0..0xff {
+            let btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+            btree.get(&Hash::from_bytes([i as u8; HASH_SIZE])).unwrap();
+        }
+    }
+
+    #[test]
+    fn a_simple_tree_with_internal_node() {
+        let btree_path =
+            cleanup_and_create_new_btree_file("a-simple-tree-with-internal-node.btree");
+
+        let mut btree = BTree::new(btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        let mut mem_map = HashMap::new();
+        let mut keys = vec![];
+        for i in 0..0xff {
+            let key = Hash::from_bytes([i; HASH_SIZE]);
+            keys.push(key);
+            let value = Offset::new(i as u64);
+            btree.put(&key, &value).unwrap();
+            mem_map.insert(key, value);
+        }
+
+        for k in mem_map.keys() {
+            assert_eq!(&btree.get(k).unwrap().unwrap(), mem_map.get(k).unwrap());
+        }
+
+        assert_eq!(btree.list().unwrap(), keys);
+        btree.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn it_checks_invariants_of_the_tree() {
+        let mut btree = BTree::new_in(MemStore::new(), HASH_ALGORITHM, MIN_PAGE_SIZE).unwrap();
+        btree.check_invariants().unwrap();
+        for i in 0..0xff {
+            btree.put(&Hash::from_bytes([i; HASH_SIZE]), &Offset::new(i as u64)).unwrap();
+        }
+        btree.check_invariants().unwrap();
+
+        // Move the greatest key into the first leaf, out of its bounds.
+        let key = Hash::from_bytes([0xfe; HASH_SIZE]);
+        btree.delete(&key).unwrap();
+        let mut leaf = btree.first_leaf().unwrap();
+        unsafe { leaf.put(&key, &LeafValue::new(Offset::new(0xfe), 1, NEVER_EXPIRES)) };
+        leaf.make_dirty();
+        btree.pager.sync_page(unsafe { leaf.mut_page() }).unwrap();
+        btree.set_len(btree.len() + 1).unwrap();
+        let err = btree.check_invariants().unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::Corrupt);
+    }
+
+    #[test]
+    fn we_can_delete_records() {
+        let btree_path = cleanup_and_create_new_btree_file("we-can-delete-records.btree");
+
+        let mut btree = BTree::new(btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        let keys: Vec<Hash> = (0..0xff).map(|i| Hash::from_bytes([i; HASH_SIZE])).collect();
+        for (i, k) in keys.iter().enumerate() {
+            btree.put(k, &Offset::new(i as u64)).unwrap();
+        }
+
+        // Delete the rightest key first, and then every even key.
+        assert!(btree.delete(&keys[0xfe]).unwrap());
+        assert!(!btree.delete(&keys[0xfe]).unwrap());
+        for k in keys[..0xfe].iter().step_by(2) {
+            assert!(btree.delete(k).unwrap());
+        }
+
+        for (i, k) in keys.iter().enumerate() {
+            let expected = if i % 2 == 0 || i == 0xfe { None } else { Some(Offset::new(i as u64)) };
+            assert_eq!(btree.get(k).unwrap(), expected);
+        }
+        let rest: Vec<Hash> = keys.iter().enumerate()
+            .filter(|(i, _)| i % 2 == 1 && *i != 0xfe)
+            .map(|(_, k)| *k)
+            .collect();
+        assert_eq!(btree.list().unwrap(), rest);
+    }
+
+    #[test]
+    fn we_can_delete_all_records_and_put_again() {
+        let btree_path =
+            cleanup_and_create_new_btree_file("we-can-delete-all-records-and-put-again.btree");
+
+        let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        for i in 0..0xff {
+            btree.put(&Hash::from_bytes([i; HASH_SIZE]), &Offset::new(i as u64)).unwrap();
+        }
+        for i in (0..0xff).rev() {
+            assert!(btree.delete(&Hash::from_bytes([i; HASH_SIZE])).unwrap());
+        }
+        assert_eq!(btree.list().unwrap(), vec![]);
+
+        let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        for i in 0..0xff {
+            let key = Hash::from_bytes([i; HASH_SIZE]);
+            assert_eq!(btree.get(&key).unwrap(), None);
+            btree.put(&key, &Offset::new(i as u64)).unwrap();
+            assert_eq!(btree.get(&key).unwrap(), Some(Offset::new(i as u64)));
+        }
+    }
+
+    #[test]
+    fn it_merges_down_to_a_single_root() {
+        let btree_path = cleanup_and_create_new_btree_file("it-merges-down-to-a-single-root.btree");
+
+        let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        for i in 0..0xff {
+            btree.put(&Hash::from_bytes([i; HASH_SIZE]), &Offset::new(i as u64)).unwrap();
+        }
+        let root_page_id = btree.head_node.hdr().root_node_page_id;
+        let root_page = btree.pager.get_page(root_page_id).unwrap();
+        assert_eq!(get_node_type(&root_page).unwrap(), NodeType::Internal);
+
+        for i in 1..0xff {
+            assert!(btree.delete(&Hash::from_bytes([i; HASH_SIZE])).unwrap());
+        }
+        let root_page_id = btree.head_node.hdr().root_node_page_id;
+        let root_page = btree.pager.get_page(root_page_id).unwrap();
+        assert_eq!(get_node_type(&root_page).unwrap(), NodeType::Leaf);
+
+        let btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(btree.list().unwrap(), vec![Hash::from_bytes([0; HASH_SIZE])]);
+    }
+
+    #[test]
+    fn it_works_with_random_puts_and_deletes() {
+        let btree_path =
+            cleanup_and_create_new_btree_file("it-works-with-random-puts-and-deletes.btree");
+
+        let mut btree = BTree::new(btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        let mut mem_map = HashMap::new();
+        let mut keys = vec![];
+        for _ in 0..2e4 as usize {
+            let key = Hash::from_bytes(rand::random::<[u8; HASH_SIZE]>());
+            let value = Offset::new(rand::random::<u64>());
+            btree.put(&key, &value).unwrap();
+            mem_map.insert(key, value);
+            keys.push(key);
+        }
+
+        keys.shuffle(&mut rand::thread_rng());
+        let (to_delete, to_keep) = keys.split_at(keys.len() * 9 / 10);
+        for k in to_delete {
+            assert!(btree.delete(k).unwrap());
+            mem_map.remove(k);
+        }
+
+        for k in to_delete {
+            assert_eq!(btree.get(k).unwrap(), None);
+        }
+        for k in to_keep {
+            assert_eq!(&btree.get(k).unwrap().unwrap(), mem_map.get(k).unwrap());
+        }
+        let mut expected_keys = to_keep.to_vec();
+        expected_keys.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(btree.list().unwrap(), expected_keys);
+        btree.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn it_works_with_other_keys_and_values() {
+        let mut btree: BPlusTree<u64, u32> =
+            BPlusTree::new_in(MemStore::new(), HASH_ALGORITHM, MIN_PAGE_SIZE).unwrap();
+        let mut keys: Vec<u64> = (0..2000).map(|i| i * 7919 % 10007).collect();
+        keys.shuffle(&mut rand::thread_rng());
+        for &k in &keys {
+            btree.put(&k, &(k as u32 * 2)).unwrap();
+        }
+        for &k in &keys[..1000] {
+            assert!(btree.delete(&k).unwrap());
+        }
+
+        for &k in &keys[..1000] {
+            assert_eq!(btree.get(&k).unwrap(), None);
+        }
+        for &k in &keys[1000..] {
+            assert_eq!(btree.get(&k).unwrap(), Some(k as u32 * 2));
+        }
+        let mut expected_keys = keys[1000..].to_vec();
+        expected_keys.sort();
+        assert_eq!(btree.list().unwrap(), expected_keys);
+    }
+
+    #[test]
+    fn it_counts_records() {
+        let btree_path = cleanup_and_create_new_btree_file("it-counts-records.btree");
+
+        {
+            let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+            assert_eq!(btree.len(), 0);
+            for i in 0..0xff {
+                btree.put(&Hash::from_bytes([i; HASH_SIZE]), &Offset::new(i as u64)).unwrap();
+            }
+            // Update the existing records.
+            for i in 0..0x10 {
+                btree.put(&Hash::from_bytes([i; HASH_SIZE]), &Offset::new(0)).unwrap();
+            }
+            assert_eq!(btree.len(), 0xff);
+        }
+
+        // The records put twice need to be deleted twice.
+        let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(btree.len(), 0xff);
+        for i in 0..0x10 {
+            assert!(btree.delete(&Hash::from_bytes([i; HASH_SIZE])).unwrap());
+            assert_eq!(btree.len(), 0xff - i as usize);
+            assert!(btree.delete(&Hash::from_bytes([i; HASH_SIZE])).unwrap());
+            assert!(!btree.delete(&Hash::from_bytes([i; HASH_SIZE])).unwrap());
+        }
+        assert_eq!(btree.len(), 0xff - 0x10);
+        assert_eq!(btree.list().unwrap().len(), btree.len());
+    }
+
+    #[test]
+    fn it_walks_through_leaves_in_key_order() {
+        let btree_path =
+            cleanup_and_create_new_btree_file("it-walks-through-leaves-in-key-order.btree");
+
+        let mut btree = BTree::new(btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        for i in (0..0xff).rev() {
+            btree.put(&Hash::from_bytes([i; HASH_SIZE]), &Offset::new(i as u64)).unwrap();
+        }
+
+        let leaves: Vec<LeafNode> = btree.leaves().unwrap().map(|l| l.unwrap()).collect();
+        assert!(leaves.len() > 1);
+        assert_eq!(leaves[0].page_id(), btree.first_leaf().unwrap().page_id());
+        let keys: Vec<Hash> = leaves.iter().flat_map(|l| l.into_iter().map(|r| r.key)).collect();
+        let expected: Vec<Hash> = (0..0xff).map(|i| Hash::from_bytes([i; HASH_SIZE])).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn it_lists_keys_after_cursors() {
+        let btree_path = cleanup_and_create_new_btree_file("it-lists-keys-after-cursors.btree");
+
+        let mut btree = BTree::new(btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        for i in (0..0xff).step_by(2) {
+            btree.put(&Hash::from_bytes([i; HASH_SIZE]), &Offset::new(i as u64)).unwrap();
+        }
+        let keys = btree.list().unwrap();
+
+        // Walk through all keys page by page, from the cursor either stored
+        // or absent.
+        let mut pages = vec![];
+        let mut after = None;
+        loop {
+            let page = btree.list_after(after.as_ref(), 10).unwrap();
+            if page.is_empty() {
+                break;
+            }
+            after = page.last().copied();
+            pages.extend(page);
+        }
+        assert_eq!(pages, keys);
+        let absent = Hash::from_bytes([0x11; HASH_SIZE]);
+        assert_eq!(btree.list_after(Some(&absent), 3).unwrap(), keys[9..12]);
+        assert_eq!(btree.list_after(None, 0).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn it_gets_many_records() {
+        let btree_path = cleanup_and_create_new_btree_file("it-gets-many-records.btree");
+
+        let mut btree = BTree::new(btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        for i in (0..0xff).step_by(2) {
+            btree.put(&Hash::from_bytes([i; HASH_SIZE]), &Offset::new(i as u64)).unwrap();
+        }
+
+        let keys: Vec<Hash> = (0..=0xff).map(|i| Hash::from_bytes([i; HASH_SIZE])).collect();
+        let expected: Vec<Option<Offset>> = keys.iter().map(|key| btree.get(key).unwrap()).collect();
+        assert_eq!(btree.get_many(&keys).unwrap(), expected);
+        assert_eq!(btree.get_many(&[]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn it_gets_records_in_range() {
+        let btree_path = cleanup_and_create_new_btree_file("it-gets-records-in-range.btree");
+
+        let mut btree = BTree::new(btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        for i in (0..0xff).step_by(2) {
+            btree.put(&Hash::from_bytes([i; HASH_SIZE]), &Offset::new(i as u64)).unwrap();
+        }
+
+        let hash = |i: u8| Hash::from_bytes([i; HASH_SIZE]);
+        let expected: Vec<(Hash, Offset)> = (0x11..0xe0)
+            .filter(|i| i % 2 == 0)
+            .map(|i| (hash(i), Offset::new(i as u64)))
+            .collect();
+        assert_eq!(btree.range(&hash(0x11), &hash(0xe0)).unwrap(), expected);
+        assert_eq!(
+            btree.range(&hash(0x10), &hash(0x11)).unwrap(),
+            vec![(hash(0x10), Offset::new(0x10))]
+        );
+        assert_eq!(btree.range(&hash(0x11), &hash(0x12)).unwrap(), vec![]);
+        assert_eq!(btree.range(&hash(0xe0), &hash(0x11)).unwrap(), vec![]);
+        assert_eq!(btree.range(&hash(0x00), &hash(0xff)).unwrap().len(), btree.len());
+
+        assert_eq!(
+            btree.range_inclusive(&hash(0x10), &hash(0x12)).unwrap(),
+            vec![(hash(0x10), Offset::new(0x10)), (hash(0x12), Offset::new(0x12))]
+        );
+        assert_eq!(
+            btree.range_inclusive(&hash(0xfe), &hash(0xff)).unwrap(),
+            vec![(hash(0xfe), Offset::new(0xfe))]
+        );
+        assert_eq!(btree.range_inclusive(&hash(0x12), &hash(0x10)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn it_puts_records_in_batch() {
+        let btree_path = cleanup_and_create_new_btree_file("it-puts-records-in-batch.btree");
+
+        let records: Vec<(Hash, Offset)> = (0..0xffff)
+            .map(|i: u64| (test_key(i), Offset::new(i)))
+            .collect();
+        {
+            let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+            btree.put_batch(&records).unwrap();
+        }
+
+        let btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(btree.len(), records.len());
+        for (key, value) in &records {
+            assert_eq!(btree.get(key).unwrap(), Some(*value));
+        }
+    }
+
+    #[test]
+    fn it_bulk_loads_records() {
+        let key = |i: u64| {
+            let mut bytes = [0u8; HASH_SIZE];
+            bytes[..8].copy_from_slice(&i.to_be_bytes());
+            Hash::from_bytes(bytes)
+        };
+        let cap = {
+            let btree = BTree::new_in(MemStore::new(), HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+            btree.first_leaf().unwrap().cap() as u64
+        };
+
+        for len in [0, 1, cap, cap + 1, 0x10000] {
+            let btree_path = cleanup_and_create_new_btree_file("it-bulk-loads-records.btree");
+            let records: Vec<(Hash, LeafValue)> = (0..len)
+                .map(|i| {
+                    let refcount = (i % 3 + 1) as u32;
+                    (key(i * 2), LeafValue::new(Offset::new(i), refcount, NEVER_EXPIRES))
+                })
+                .collect();
+            {
+                let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+                btree.bulk_load(&records).unwrap();
+                if len > 0 {
+                    assert!(btree.bulk_load(&records[..1]).is_err());
+                }
+            }
+
+            let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+            assert_eq!(btree.len(), records.len());
+            assert_eq!(btree.records().unwrap(), records);
+            for (key, record) in &records {
+                assert_eq!(btree.get(key).unwrap(), Some(record.value));
+            }
+            if len == 0x10000 {
+                assert_eq!(btree.height().unwrap(), 3);
+            }
+
+            // It is still a valid BTree to be put and deleted.
+            for i in 0..len.min(1000) {
+                btree.put(&key(i * 2 + 1), &Offset::new(i)).unwrap();
+                assert!(btree.delete(&key(i * 2)).unwrap());
+            }
+            for i in 0..len.min(1000) {
+                assert_eq!(btree.get(&key(i * 2 + 1)).unwrap(), Some(Offset::new(i)));
+                assert_eq!(btree.get(&key(i * 2)).unwrap().is_some(), i % 3 != 0);
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_unordered_records_to_bulk_load() {
+        let mut btree = BTree::new_in(MemStore::new(), HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        let record = |i: u8| {
+            (Hash::from_bytes([i; HASH_SIZE]), LeafValue::new(Offset::new(i as u64), 1, NEVER_EXPIRES))
+        };
+        assert!(btree.bulk_load(&[record(2), record(1)]).is_err());
+        assert!(btree.bulk_load(&[record(1), record(1)]).is_err());
+        assert_eq!(btree.len(), 0);
+        btree.bulk_load(&[record(1), record(2)]).unwrap();
+        assert_eq!(btree.records().unwrap(), [record(1), record(2)]);
+    }
+
+    #[test]
+    fn it_knows_its_height() {
+        let btree_path = cleanup_and_create_new_btree_file("it-knows-its-height.btree");
+
+        let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(btree.height().unwrap(), 1);
+        assert_eq!(btree.page_count(), 2);
+        let mut heights = vec![];
+        for i in 0..0x10000u64 {
+            btree.put(&test_key(i), &Offset::new(i)).unwrap();
+            let height = btree.height().unwrap();
+            if heights.last() != Some(&height) {
+                heights.push(height);
+            }
+        }
+        assert_eq!(heights, [1, 2, 3]);
+        assert!(btree.page_count() > 0x10000 / btree.first_leaf().unwrap().cap());
+    }
+
+    #[test]
+    fn it_counts_references_of_records() {
+        let btree_path = cleanup_and_create_new_btree_file("it-counts-references-of-records.btree");
+        let refcount = |btree: &BTree, key: &Hash| {
+            btree.find_leaf(key).unwrap().get(key).map_or(0, |record| record.refcount)
+        };
+        let (key1, key2) = (&Hash::from_bytes([14u8; HASH_SIZE]), &Hash::from_bytes([21u8; HASH_SIZE]));
+
+        {
+            let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+            btree.put(key1, &Offset::new(1)).unwrap();
+            btree.put(key1, &Offset::new(2)).unwrap();
+            btree.put(key2, &Offset::new(3)).unwrap();
+            assert_eq!(refcount(&btree, key1), 2);
+            assert_eq!(refcount(&btree, key2), 1);
+
+            // Put, put, delete: the record is still there, with the last value.
+            assert!(btree.delete(key1).unwrap());
+            assert_eq!(btree.get(key1).unwrap(), Some(Offset::new(2)));
+            assert_eq!(refcount(&btree, key1), 1);
+            assert_eq!(btree.len(), 2);
+        }
+
+        // The reference counts are persistent.
+        let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(refcount(&btree, key1), 1);
+        assert!(btree.delete(key1).unwrap());
+        assert_eq!(btree.get(key1).unwrap(), None);
+        assert!(!btree.delete(key1).unwrap());
+        assert_eq!(btree.get(key2).unwrap(), Some(Offset::new(3)));
+        assert_eq!(btree.len(), 1);
+
+        // Put again after it is gone.
+        btree.put(key1, &Offset::new(4)).unwrap();
+        assert_eq!(refcount(&btree, key1), 1);
+        assert_eq!(btree.get(key1).unwrap(), Some(Offset::new(4)));
+    }
+
+    #[test]
+    fn it_purges_expired_records() {
+        let mut btree = BTree::new_in(MemStore::new(), HASH_ALGORITHM, MIN_PAGE_SIZE).unwrap();
+        let now = now_millis();
+        for i in 0..1000 {
+            let expires_at = if i % 2 == 0 { now - 1 } else { NEVER_EXPIRES };
+            btree.put_with_expiry(&test_key(i), &Offset::new(i), expires_at).unwrap();
+        }
+        assert_eq!(btree.len(), 1000);
+        assert_eq!(btree.get(&test_key(0)).unwrap(), None);
+        assert_eq!(btree.get(&test_key(1)).unwrap(), Some(Offset::new(1)));
+
+        // Putting over the expired record starts it again.
+        btree.put_with_expiry(&test_key(0), &Offset::new(10000), NEVER_EXPIRES).unwrap();
+        assert_eq!(btree.get(&test_key(0)).unwrap(), Some(Offset::new(10000)));
+        // The later expiry time wins.
+        btree.put_with_expiry(&test_key(1), &Offset::new(1), now - 1).unwrap();
+        assert_eq!(btree.get(&test_key(1)).unwrap(), Some(Offset::new(1)));
+
+        assert_eq!(btree.purge_expired().unwrap(), 499);
+        assert_eq!(btree.purge_expired().unwrap(), 0);
+        assert_eq!(btree.len(), 501);
+        for i in 0..1000 {
+            assert_eq!(btree.get(&test_key(i)).unwrap().is_some(), i % 2 == 1 || i == 0);
+        }
+    }
+
+    #[test]
+    fn it_drops_unfinished_transactions_after_a_crash() {
+        let btree_path =
+            cleanup_and_create_new_btree_file("it-drops-unfinished-transactions-after-a-crash.btree");
+        let record = |i: u64| (test_key(i), Offset::new(i));
+
+        {
+            let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+            for i in 0..100 {
+                let (key, value) = record(i);
+                btree.put(&key, &value).unwrap();
+            }
+
+            // Crash in the middle of a transaction splitting the root.
+            btree.pager.begin();
+            for i in 100..1000 {
+                let (key, value) = record(i);
+                btree.put_record(&key, &LeafValue::new(value, 1, NEVER_EXPIRES)).unwrap();
+            }
+        }
+
+        let btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(btree.len(), 100);
+        assert_eq!(btree.list().unwrap().len(), 100);
+        for i in 0..1000 {
+            let (key, value) = record(i);
+            assert_eq!(btree.get(&key).unwrap(), (i < 100).then_some(value));
+        }
+    }
+
+    #[test]
+    fn it_refreshes_changes_by_another_writer() {
+        let btree_path =
+            cleanup_and_create_new_btree_file("it-refreshes-changes-by-another-writer.btree");
+
+        let mut writer = BTree::new(&btree_path, HASH_ALGORITHM, MIN_PAGE_SIZE).unwrap();
+        writer.put(&test_key(0), &Offset::new(0)).unwrap();
+        let mut reader = BTree::open_read_only(&btree_path, HASH_ALGORITHM).unwrap();
+        assert_eq!(reader.get(&test_key(0)).unwrap(), Some(Offset::new(0)));
+        assert!(!reader.refresh().unwrap());
+        // Nothing is changed by deleting an absent key.
+        assert!(!writer.delete(&test_key(1)).unwrap());
+        assert!(!reader.refresh().unwrap());
+
+        // The root is split many times, and the leaf cached is rewritten.
+        for i in 1..2000 {
+            writer.put(&test_key(i), &Offset::new(i)).unwrap();
+        }
+        assert!(reader.refresh().unwrap());
+        assert!(!reader.refresh().unwrap());
+        assert_eq!(reader.len(), 2000);
+        assert!(reader.height().unwrap() > 1);
+        for i in 0..2000 {
+            assert_eq!(reader.get(&test_key(i)).unwrap(), Some(Offset::new(i)));
+        }
+
+        for i in 0..1000 {
+            assert!(writer.delete(&test_key(i)).unwrap());
+        }
+        assert!(reader.refresh().unwrap());
+        assert_eq!(reader.len(), 1000);
+        for i in 0..2000 {
+            assert_eq!(reader.get(&test_key(i)).unwrap().is_some(), i >= 1000);
+        }
+    }
+
+    #[test]
+    fn it_rejects_index_files_of_other_versions() {
+        let btree_path =
+            cleanup_and_create_new_btree_file("it-rejects-index-files-of-other-versions.btree");
+
+        BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+
+        // The version is the second byte of the head node.
+        let mut content = fs::read(&btree_path).unwrap();
+        content[1] = HEAD_NODE_VERSION - 1;
+        fs::write(&btree_path, content).unwrap();
+        assert!(BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).is_err());
+    }
+
+    #[test]
+    fn it_reports_corrupt_nodes_as_errors() {
+        let btree_path =
+            cleanup_and_create_new_btree_file("it-reports-corrupt-nodes-as-errors.btree");
+
+        let key = Hash::from_bytes([14u8; HASH_SIZE]);
+        {
+            let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+            btree.put(&key, &Offset::new(114514)).unwrap();
+        }
+
+        // The root leaf is the second page, and the node type is its first
+        // byte: try an unknown type, and the type of the head node.
+        for typ in [0xff, NodeType::Head as u8] {
+            let mut content = fs::read(&btree_path).unwrap();
+            content[DEFAULT_PAGE_SIZE] = typ;
+            fs::write(&btree_path, content).unwrap();
+            let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+            assert_eq!(btree.get(&key).err().unwrap().kind(), crate::ErrorKind::Corrupt);
+            let other_key = Hash::from_bytes([21u8; HASH_SIZE]);
+            let err = btree.put(&other_key, &Offset::new(63)).err().unwrap();
+            assert_eq!(err.kind(), crate::ErrorKind::Corrupt);
+        }
+    }
+
+    #[test]
+    fn it_rejects_index_files_of_other_hash_algorithms() {
+        let btree_path = cleanup_and_create_new_btree_file(
+            "it-rejects-index-files-of-other-hash-algorithms.btree",
+        );
+
+        BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        assert!(BTree::new(&btree_path, "blake3", DEFAULT_PAGE_SIZE).is_err());
+        assert!(BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).is_ok());
+    }
+
+    #[test]
+    fn it_works_with_other_page_sizes() {
+        let btree_path = cleanup_and_create_new_btree_file("it-works-with-other-page-sizes.btree");
+        let key = |i: u64| {
+            let mut bytes = [0u8; HASH_SIZE];
+            bytes[..8].copy_from_slice(&i.to_be_bytes());
+            Hash::from_bytes(bytes)
+        };
+
+        {
+            let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, 16 << 10).unwrap();
+            for i in 0..2000 {
+                btree.put(&key(i), &Offset::new(i)).unwrap();
+            }
+        }
+        assert_eq!(fs::metadata(&btree_path).unwrap().len() % (16 << 10), 0);
+
+        // The page size recorded in the file wins.
+        let btree = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(btree.len(), 2000);
+        for i in 0..2000 {
+            assert_eq!(btree.get(&key(i)).unwrap(), Some(Offset::new(i)));
+        }
+
+        assert!(BTree::new(&btree_path, HASH_ALGORITHM, 3000).is_ok());
+    }
+
+    #[test]
+    fn it_works_with_sequential_puts() {
+        let key = |i: u64| {
+            let mut bytes = [0u8; HASH_SIZE];
+            bytes[..8].copy_from_slice(&i.to_be_bytes());
+            Hash::from_bytes(bytes)
+        };
+
+        // Sequential keys always hit the rightest or the leftest nodes, so they
+        // split on every level - the small pages make the tree deeper.
+        for reversed in [false, true] {
+            let btree_path = cleanup_and_create_new_btree_file(&format!(
+                "it-works-with-sequential-puts-{}.btree",
+                reversed,
+            ));
+            let mut btree = BTree::new(&btree_path, HASH_ALGORITHM, MIN_PAGE_SIZE).unwrap();
+            let count = 20000;
+            for i in 0..count {
+                let i = if reversed { count - 1 - i } else { i };
+                assert!(btree.put(&key(i), &Offset::new(i)).unwrap());
+            }
+            assert!(btree.height().unwrap() > 3);
+            assert_eq!(btree.len(), count as usize);
+            for i in 0..count {
+                assert_eq!(btree.get(&key(i)).unwrap(), Some(Offset::new(i)));
+            }
+            let keys: Vec<Hash> = (0..count).map(key).collect();
+            assert_eq!(btree.list().unwrap(), keys);
+
+            let btree = BTree::new(&btree_path, HASH_ALGORITHM, MIN_PAGE_SIZE).unwrap();
+            assert_eq!(btree.list().unwrap(), keys);
+        }
+    }
+
+    #[test]
+    fn it_rejects_unsupported_page_sizes() {
+        let btree_path =
+            cleanup_and_create_new_btree_file("it-rejects-unsupported-page-sizes.btree");
+
+        for page_size in [3000, 512, 128 << 10] {
+            let err = BTree::new(&btree_path, HASH_ALGORITHM, page_size).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+        }
+
+        // The page size is the 4 bytes after the name of the hash algorithm.
+        BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
+        let mut content = fs::read(&btree_path).unwrap();
+        content[96..100].copy_from_slice(&3000u32.to_le_bytes());
+        fs::write(&btree_path, content).unwrap();
+        let err = BTree::new(&btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
     }
 
     #[test]
     fn how_about_1e5_key_values_aha() {
         let btree_path = cleanup_and_create_new_btree_file("how-about-1e5-key-values-aha.btree");
 
-        let mut btree = BTree::new(btree_path).unwrap();
+        let mut btree = BTree::new(btree_path, HASH_ALGORITHM, DEFAULT_PAGE_SIZE).unwrap();
         let mut mem_map = HashMap::new();
         for i in 0..(1e5 as usize) {
             dbg!(i);
@@ -352,5 +2121,6 @@ mod tests {
             dbg!(i);
             assert_eq!(&btree.get(k).unwrap().unwrap(), mem_map.get(k).unwrap());
         }
+        btree.check_invariants().unwrap();
     }
 }
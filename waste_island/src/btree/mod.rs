@@ -6,5 +6,10 @@ mod btree;
 // mod internal_node;
 mod node;
 mod page;
+mod pod;
 
-pub use btree::BTree;
\ No newline at end of file
+pub use btree::{BPlusTree, BTree, Leaves, NodeDump};
+pub use node::{now_millis, LeafValue, HEAD_NODE_VERSION, NEVER_EXPIRES};
+pub use page::{is_valid_page_size, PageId, DEFAULT_PAGE_SIZE};
+pub use pager::{Pager, PagerStats};
+pub use pod::Pod;
\ No newline at end of file
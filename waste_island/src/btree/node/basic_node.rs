@@ -1,6 +1,6 @@
-use std::{cmp::{min, max}, marker::PhantomData, mem::size_of, fmt::Debug};
+use std::{cmp::{min, max}, marker::PhantomData, mem::size_of, fmt::Debug, ptr};
 
-use crate::{btree::page::{PAGE_SIZE, Page, PageId}, debug};
+use crate::{btree::page::{Page, PageId}, debug};
 
 #[derive(Clone)]
 pub struct BasicNode<H, K, V>
@@ -32,13 +32,14 @@ where
     record_id_offset: Offset,
 }
 
+/// The view of the page's head. The records and their IDs follow it in the
+/// page, whose size is only known at runtime.
 #[derive(Clone, Copy)]
 #[repr(C)]
-pub union BasicNodePageWrapper<H>
+pub struct BasicNodePageWrapper<H>
 where
     H: Copy,
 {
-    buf: [u8; PAGE_SIZE],
     hdr: BasicNodeHdr<H>,
 }
 
@@ -50,24 +51,25 @@ where
 {
     org_hdr: H,
 
-    records_length: u8,
+    records_length: u16,
     first_free_record_id: RecordId,
 }
 
 /// The ID of the record. It is started from 0.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct RecordId(u8);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RecordId(u16);
 
 /// The offset in the buffer.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
 pub struct Offset(usize);
 
-/// A linked list to hold all free space for record.
+/// A linked list to hold all free space for record. Each node of the list is a
+/// run of free records: the IDs in `[self_id, self_id + length)`.
 ///
 /// If it do not have next list node, the `next` should be `RecordId::invalid()`.
 #[derive(Clone, Copy)]
 pub struct FreeRecord {
-    pub length: u8,
+    pub length: u16,
     pub next: RecordId,
 }
 
@@ -83,17 +85,21 @@ where
     /// The size of the record.
     const RECORD_SIZE: usize = size_of::<Record<K, V>>();
 
+    /// The size of the record ID.
+    const RECORD_ID_SIZE: usize = size_of::<RecordId>();
+
     /// Get the node view of the page.
     ///
     /// # Safety
     ///
     /// - The page maybe is not valid. If the page is not even inited, call
     ///   `init` to init the inner page.
-    /// - It can only at most store 255 records. So make sure the K and V's
+    /// - It can only at most store 65535 records. So make sure the K and V's
     ///   sizes are not too small to avoid watsing space. Don't your mom tell
     ///   you do not waste anything?
-    /// - Make sure that `size_of::<Record<K, V>> >= size_of::<FreeRecord>(2 bytes)`.
+    /// - Make sure that `size_of::<Record<K, V>> >= size_of::<FreeRecord>(4 bytes)`.
     pub unsafe fn new_unchecked(page: Page) -> Self {
+        debug_assert!(Self::RECORD_SIZE >= size_of::<FreeRecord>());
         Self {
             page,
             _extra_hdr: PhantomData,
@@ -113,7 +119,7 @@ where
         page_hdr.first_free_record_id = first_free_record_id;
         page_hdr.records_length = 0;
 
-        let cap = self.cap() as u8;
+        let cap = self.cap() as u16;
         let free_record = self.mut_free_record(first_free_record_id);
         free_record.next = RecordId::invalid();
         free_record.length = cap;
@@ -123,13 +129,14 @@ where
         self.page.id()
     }
 
-    /// Put a new record.
+    /// Put a new record. Return the origin value if the key is already
+    /// existing - then it is just updated.
     ///
     /// # Safety
     ///
     /// - It is your duty to make sure it is not full: maybe `is_full()` can help you.
     /// - Remember to use `make_dirty` and sync.
-    pub unsafe fn put(&mut self, key: &K, value: &V) {
+    pub unsafe fn put(&mut self, key: &K, value: &V) -> Option<V> {
         let new_record_id_offset = self.lower_bound(key);
         if new_record_id_offset != self.record_id_offset_right() {
             // If the record we need to put is not the biggest element, then we
@@ -140,8 +147,9 @@ where
             if &record.key == key {
                 // And it is already existing... So we just update it and no
                 // more insert!
+                let origin_value = record.value;
                 record.value = *value;
-                return;
+                return Some(origin_value);
             }
         }
 
@@ -151,6 +159,30 @@ where
         new_record.value = *value;
         self.insert_new_record_id(new_record_id, new_record_id_offset);
         self.mut_page_wrapper().hdr.records_length += 1;
+        None
+    }
+
+    /// Remove the record by key and return its value. Return `None` if there
+    /// is no such record.
+    ///
+    /// # Safety
+    ///
+    /// - Remember to use `make_dirty` and sync.
+    pub unsafe fn remove(&mut self, key: &K) -> Option<V> {
+        let record_id_offset = self.lower_bound(key);
+        if record_id_offset == self.record_id_offset_right() {
+            return None;
+        }
+
+        let record_id = self.record_id_by_offset(record_id_offset);
+        let record = self.record(*record_id);
+        if &record.key != key {
+            return None;
+        }
+
+        let value = record.value;
+        self.dealloc_record(record_id_offset);
+        Some(value)
     }
 
     /// Get the value by key.
@@ -165,14 +197,13 @@ where
         if &record.key == key {
             Some(record.value)
         } else {
-            #[cfg(test)]
-            {
-                eprintln!("BEGIN");
-                for r in self.into_iter() {
-                    eprintln!("    {:?}", r);
-                }
-                eprintln!("END");
-            }
+            #[cfg(feature = "tracing")]
+            tracing::trace!(
+                page_id = ?self.page_id(),
+                ?key,
+                records = ?self.into_iter().collect::<Vec<_>>(),
+                "key not found in node",
+            );
 
             None
         }
@@ -218,6 +249,22 @@ where
         }
     }
 
+    /// Append all records of `rhs` into `self`.
+    ///
+    /// # Safety
+    ///
+    /// - It is your duty to make sure `self` can hold all records of `rhs`:
+    ///   `self.len() + rhs.len() <= self.cap()`.
+    /// - All keys in `self` should be less than the keys in `rhs`.
+    /// - Remember to use `make_dirty` and sync.
+    pub unsafe fn merge(&mut self, rhs: &BasicNode<H, K, V>) {
+        debug_assert!(self.len() + rhs.len() <= self.cap());
+
+        for record in rhs.into_iter() {
+            self.put(&record.key, &record.value);
+        }
+    }
+
     /// Get the rightest record.
     /// 
     /// # Safety
@@ -226,7 +273,7 @@ where
     pub unsafe fn rightest_record(&self) -> &Record<K, V> {
         debug_assert!(!self.is_empty());
 
-        let record_id = self.record_id_by_offset(self.record_id_offset_right().offset(-1));
+        let record_id = self.record_id_by_offset(self.record_id_offset(self.len() - 1));
         self.record(*record_id)
     }
 
@@ -238,7 +285,7 @@ where
     pub unsafe fn pop_righest_record(&mut self) -> Record<K, V> {
         debug_assert!(!self.is_empty());
 
-        let rightest_record_id_offset = self.record_id_offset_right().offset(-1);
+        let rightest_record_id_offset = self.record_id_offset(self.len() - 1);
         let record_id = self.record_id_by_offset(rightest_record_id_offset);
         let record = (*self.record(*record_id)).clone();
 
@@ -259,7 +306,7 @@ where
         assert!(!self.is_empty());
         assert!(!rhs.is_full());
 
-        let rightest_record_id_offset = self.record_id_offset_right().offset(-1);
+        let rightest_record_id_offset = self.record_id_offset(self.len() - 1);
         let record_id = self.record_id_by_offset(rightest_record_id_offset);
         let record = self.record(*record_id);
 
@@ -268,16 +315,17 @@ where
     }
 
     /// The length of the node - or how many records in the node. Tht length is
-    /// less than `u8::MAX`(255) because it only use 1 byte to store the length.
+    /// less than `u16::MAX`(65535) because it only use 2 bytes to store the
+    /// length.
     pub fn len(&self) -> usize {
-        unsafe { self.page_wrapper().hdr }.records_length as usize
+        self.page_wrapper().hdr.records_length as usize
     }
 
-    /// The capacity of the node. Tht capacity is less than `u8::MAX`(255) because
-    /// it only use 1 byte to store the length.
+    /// The capacity of the node. Tht capacity is less than `u16::MAX`(65535)
+    /// because it only use 2 bytes to store the length.
     pub fn cap(&self) -> usize {
-        let cap = (PAGE_SIZE - Self::PAGE_HEAD_SIZE) / (Self::RECORD_SIZE + size_of::<RecordId>());
-        min(cap, u8::MAX as usize)
+        let cap = (self.page_size() - Self::PAGE_HEAD_SIZE) / (Self::RECORD_SIZE + Self::RECORD_ID_SIZE);
+        min(cap, u16::MAX as usize)
     }
 
     /// Is it full? Is it `len() == cap()`?
@@ -294,7 +342,7 @@ where
     ///
     /// It will:
     ///
-    /// - shift the bytes located in `offset .. self.record_id_offset_right()`.
+    /// - shift the record IDs located in `offset .. self.record_id_offset_right()`.
     /// - Insert the new record at the location which the offset point to. ❤
     ///
     /// # Safety
@@ -303,44 +351,47 @@ where
     /// - Make sure you will call `make_dirty` and sync.
     unsafe fn insert_new_record_id(&mut self, record_id: RecordId, offset: Offset) {
         let record_id_offset_right = self.record_id_offset_right();
-        let page = self.mut_page_wrapper();
 
-        // Step 1: Shift those right bytes.
-        for i in (offset.raw()..record_id_offset_right.raw()).rev() {
-            let i = Offset::new(i);
-            *page.mut_ptr_by_offset(i.offset(1)) = *page.mut_ptr_by_offset(i);
-        }
+        // Step 1: Shift those right record IDs.
+        let src = self.mut_ptr_by_offset(offset);
+        let dst = self.mut_ptr_by_offset(offset.offset(Self::RECORD_ID_SIZE as isize));
+        ptr::copy(src, dst, record_id_offset_right.raw() - offset.raw());
 
         // Step 2: Insert the record ID.
-        *page.mut_ptr_by_offset(offset) = record_id.raw();
+        *(self.mut_ptr_by_offset(offset) as *mut RecordId) = record_id;
+    }
+
+    /// Point to the record ID at the index.
+    fn record_id_offset(&self, index: usize) -> Offset {
+        Offset::new(Self::PAGE_HEAD_SIZE + index * Self::RECORD_ID_SIZE)
     }
 
     /// Point to the first of the record IDs.
     fn record_id_offset_left(&self) -> Offset {
-        Offset::new(Self::PAGE_HEAD_SIZE)
+        self.record_id_offset(0)
     }
 
     /// Point to the next element after the last of the record IDs.
     fn record_id_offset_right(&self) -> Offset {
-        Offset::new(Self::PAGE_HEAD_SIZE + unsafe { self.page_wrapper().hdr }.records_length as usize)
+        self.record_id_offset(self.len())
     }
 
     /// Return a offset, which pointer to the record ID of lower bound by key.
     fn lower_bound(&self, key: &K) -> Offset {
-        let mut left = self.record_id_offset_left();
-        let mut right = self.record_id_offset_right();
+        let mut left = 0;
+        let mut right = self.len();
         while left != right {
-            let mid = Offset::mid(left, right);
-            let mid_record_id = unsafe { self.record_id_by_offset(mid) };
+            let mid = (left + right) / 2;
+            let mid_record_id = unsafe { self.record_id_by_offset(self.record_id_offset(mid)) };
             let mid_record = unsafe { self.record(*mid_record_id) };
             // debug!(left, right, mid_record, key);
             if key <= &mid_record.key {
                 right = mid;
             } else {
-                left = mid.offset(1);
+                left = mid + 1;
             }
         }
-        left
+        self.record_id_offset(left)
     }
 
     /// Alloc a new space to contain a record. It will return the ID of the
@@ -352,18 +403,17 @@ where
     /// - Remember to use `make_dirty` and sync.
     /// - Use `insert_record_id` to store the ID of new record.
     unsafe fn alloc_new_record(&mut self) -> (RecordId, &mut Record<K, V>) {
-        let page = self.mut_page_wrapper();
-        let first_free_record_id = page.hdr.first_free_record_id;
+        let first_free_record_id = self.page_wrapper().hdr.first_free_record_id;
         debug_assert_ne!(first_free_record_id, RecordId::invalid());
         let free_record = {
-            let ptr = page.mut_ptr_by_offset(Self::record_page_offset(first_free_record_id));
+            let ptr = self.mut_ptr_by_offset(self.record_page_offset(first_free_record_id));
             &mut *(ptr as *mut FreeRecord)
         };
 
         if free_record.length == 1 {
             // Let the free record point to the next free record.
             let origin_first_free_record_id = first_free_record_id;
-            page.hdr.first_free_record_id = free_record.next;
+            self.mut_page_wrapper().hdr.first_free_record_id = free_record.next;
             let record = &mut *(free_record as *mut FreeRecord as *mut Record<K, V>);
             (origin_first_free_record_id, record)
         } else {
@@ -382,28 +432,59 @@ where
         debug_assert!(record_id_offset >= self.record_id_offset_left());
         debug_assert!(record_id_offset < self.record_id_offset_right());
 
-        // Put the record into free record linked list.
         let record_id = *self.record_id_by_offset(record_id_offset);
-        let first_free_record_id = self.page_wrapper().hdr.first_free_record_id;
-        let free_record = self.mut_free_record(record_id);
-        free_record.length = 1;
-        free_record.next = first_free_record_id;
-        self.mut_page_wrapper().hdr.first_free_record_id = record_id;
+        self.free_record_id(record_id);
 
         // Shift the righter record IDs.
-        let right = self.record_id_offset_right().raw();
-        let page = self.mut_page_wrapper();
-        for i in record_id_offset.raw() .. right {
-            let i = Offset::new(i);
-            *page.mut_ptr_by_offset(i) = *page.mut_ptr_by_offset(i.offset(1));
+        let right = self.record_id_offset_right();
+        let src = self.mut_ptr_by_offset(record_id_offset.offset(Self::RECORD_ID_SIZE as isize));
+        let dst = self.mut_ptr_by_offset(record_id_offset);
+        ptr::copy(src, dst, right.raw() - record_id_offset.raw() - Self::RECORD_ID_SIZE);
+        self.mut_page_wrapper().hdr.records_length -= 1;
+    }
+
+    /// Put the record into free record linked list, and merge it with the free
+    /// runs next to it - so that the free space is not fragmented.
+    ///
+    /// # Safety
+    ///
+    /// - Make sure the record is not free yet.
+    unsafe fn free_record_id(&mut self, record_id: RecordId) {
+        let mut start = record_id;
+        let mut length = 1u16;
+
+        // Take all adjacent runs out of the list, and merge them.
+        let mut prev = RecordId::invalid();
+        let mut cur = self.page_wrapper().hdr.first_free_record_id;
+        while cur != RecordId::invalid() {
+            let FreeRecord { length: cur_length, next } = *self.mut_free_record(cur);
+            let adjacent = cur.offset(cur_length as isize) == start
+                || start.offset(length as isize) == cur;
+            if adjacent {
+                if prev == RecordId::invalid() {
+                    self.mut_page_wrapper().hdr.first_free_record_id = next;
+                } else {
+                    self.mut_free_record(prev).next = next;
+                }
+                start = min(start, cur);
+                length += cur_length;
+            } else {
+                prev = cur;
+            }
+            cur = next;
         }
-        page.hdr.records_length -= 1;
+
+        let first_free_record_id = self.page_wrapper().hdr.first_free_record_id;
+        let free_record = self.mut_free_record(start);
+        free_record.length = length;
+        free_record.next = first_free_record_id;
+        self.mut_page_wrapper().hdr.first_free_record_id = start;
     }
 
     /// Get the page wrapper.
     pub fn page_wrapper(&self) -> &BasicNodePageWrapper<H> {
         let page_buf = self.page.buf();
-        unsafe { &*(page_buf as *const [u8; PAGE_SIZE] as *const BasicNodePageWrapper<H>) }
+        unsafe { &*(page_buf.as_ptr() as *const BasicNodePageWrapper<H>) }
     }
 
     /// Get the mutable reference of the page wrapper.
@@ -427,12 +508,36 @@ where
     /// If the inner page is changed, remember to use `make_dirty` and sync.
     pub unsafe fn mut_page_wrapper(&mut self) -> &mut BasicNodePageWrapper<H> {
         let page_buf = self.page.mut_buf();
-        &mut *(page_buf as *mut [u8; PAGE_SIZE] as *mut BasicNodePageWrapper<H>)
+        &mut *(page_buf.as_mut_ptr() as *mut BasicNodePageWrapper<H>)
+    }
+
+    /// The size of the page - so that we know where the records are.
+    fn page_size(&self) -> usize {
+        self.page.buf().len()
+    }
+
+    /// Get the raw pointer that point to byte at the offset in the page.
+    ///
+    /// # Safety
+    ///
+    /// - If you change the buffer, remember to use `make_dirty`.
+    /// - The offset may is not valid or it does not point to a valid value.
+    unsafe fn mut_ptr_by_offset(&mut self, offset: Offset) -> *mut u8 {
+        unsafe { self.page.mut_buf().as_mut_ptr().add(offset.raw()) }
+    }
+
+    /// Get the raw pointer that point to byte at the offset in the page.
+    ///
+    /// # Safety
+    ///
+    /// - The offset may is not valid or it does not point to a valid value.
+    unsafe fn ptr_by_offset(&self, offset: Offset) -> *const u8 {
+        unsafe { self.page.buf().as_ptr().add(offset.raw()) }
     }
 
     /// Get the record's offset in page - just by its ID.
-    fn record_page_offset(id: RecordId) -> Offset {
-        Offset::new(PAGE_SIZE - Self::RECORD_SIZE * (id.raw() as usize + 1))
+    fn record_page_offset(&self, id: RecordId) -> Offset {
+        Offset::new(self.page_size() - Self::RECORD_SIZE * (id.raw() as usize + 1))
     }
 
     /// Get the free record by its ID.
@@ -442,8 +547,8 @@ where
     /// - If you change the free record, remember to use `make_dirty` and sync.
     /// - The ID may is not point to the free record - so be careful.
     unsafe fn mut_free_record(&mut self, id: RecordId) -> &mut FreeRecord {
-        let offset = Self::record_page_offset(id);
-        &mut *(self.mut_page_wrapper().mut_ptr_by_offset(offset) as *mut FreeRecord)
+        let offset = self.record_page_offset(id);
+        &mut *(self.mut_ptr_by_offset(offset) as *mut FreeRecord)
     }
 
     /// Get the record by its ID.
@@ -453,7 +558,7 @@ where
     /// - If you change the free record, remember to use `make_dirty` and sync.
     /// - The ID may is not point to the valid record - so be careful.
     unsafe fn mut_record(&mut self, id: RecordId) -> &mut Record<K, V> {
-        let offset = Self::record_page_offset(id);
+        let offset = self.record_page_offset(id);
         self.mut_record_by_offset(offset)
     }
 
@@ -463,7 +568,7 @@ where
     ///
     /// - The ID may is not point to the valid record - so be careful.
     unsafe fn record(&self, id: RecordId) -> &Record<K, V> {
-        let offset = Self::record_page_offset(id);
+        let offset = self.record_page_offset(id);
         self.record_by_offset(offset)
     }
 
@@ -473,7 +578,7 @@ where
     ///
     /// - The offset may is not point to the valid record - so be careful.
     unsafe fn record_by_offset(&self, offset: Offset) -> &Record<K, V> {
-        &*(self.page_wrapper().ptr_by_offset(offset) as *const Record<K, V>)
+        &*(self.ptr_by_offset(offset) as *const Record<K, V>)
     }
 
     /// Get the record by its offset.
@@ -483,7 +588,7 @@ where
     /// - If you change the free record, remember to use `make_dirty` and sync.
     /// - The offset may is not point to the valid record - so be careful.
     unsafe fn mut_record_by_offset(&mut self, offset: Offset) -> &mut Record<K, V> {
-        &mut *(self.mut_page_wrapper().mut_ptr_by_offset(offset) as *mut Record<K, V>)
+        &mut *(self.mut_ptr_by_offset(offset) as *mut Record<K, V>)
     }
 
     /// Get the record ID by its offset in the page.
@@ -496,7 +601,7 @@ where
         debug_assert!(
             offset >= self.record_id_offset_left() && offset < self.record_id_offset_right()
         );
-        &*(self.page_wrapper().ptr_by_offset(offset) as *const RecordId)
+        &*(self.ptr_by_offset(offset) as *const RecordId)
     }
 }
 
@@ -542,7 +647,7 @@ where
         }
         let record_id = unsafe { self.node.record_id_by_offset(self.record_id_offset) };
         let record = unsafe { self.node.record(*record_id) };
-        self.record_id_offset = self.record_id_offset.offset(1);
+        self.record_id_offset = self.record_id_offset.offset(size_of::<RecordId>() as isize);
         Some(record)
     }
 }
@@ -551,51 +656,32 @@ impl<H> BasicNodePageWrapper<H>
 where
     H: Copy,
 {
-    /// Get the raw pointer that point to byte at the offset in the buffer.
-    ///
-    /// # Safety
-    ///
-    /// - If you change the buffer, remember to use `make_dirty`.
-    /// - The offset may is not valid or it does not point to a valid value.
-    unsafe fn mut_ptr_by_offset(&mut self, offset: Offset) -> *mut u8 {
-        unsafe { self.buf.as_mut_ptr().add(offset.raw()) }
-    }
-
-    /// Get the raw pointer that point to byte at the offset in the buffer.
-    ///
-    /// # Safety
-    ///
-    /// - The offset may is not valid or it does not point to a valid value.
-    unsafe fn ptr_by_offset(&self, offset: Offset) -> *const u8 {
-        unsafe { self.buf.as_ptr().add(offset.raw()) }
-    }
-
     // Get the mutable reference point to the `H`.
     pub fn mut_hdr(&mut self) -> &mut H {
-        unsafe { &mut self.hdr.org_hdr }
+        &mut self.hdr.org_hdr
     }
 
     // Get the reference point to the `H`.
     pub fn hdr(&self) -> &H {
-        unsafe { &self.hdr.org_hdr }
+        &self.hdr.org_hdr
     }
 }
 
 impl RecordId {
-    pub fn new(value: u8) -> Self {
+    pub fn new(value: u16) -> Self {
         Self(value)
     }
 
-    pub fn raw(&self) -> u8 {
+    pub fn raw(&self) -> u16 {
         self.0
     }
 
     pub fn offset(self, offset: isize) -> Self {
-        Self::new((self.raw() as isize + offset) as u8)
+        Self::new((self.raw() as isize + offset) as u16)
     }
 
     pub fn invalid() -> Self {
-        RecordId(u8::MAX)
+        RecordId(u16::MAX)
     }
 }
 
@@ -604,11 +690,6 @@ impl Offset {
         Self(value)
     }
 
-    /// Self((l.raw() + r.raw()) / 2)
-    pub fn mid(l: Self, r: Self) -> Self {
-        Self((l.raw() + r.raw()) / 2)
-    }
-
     pub fn offset(self, offset: isize) -> Self {
         Self::new((self.raw() as isize + offset) as usize)
     }
@@ -620,16 +701,16 @@ impl Offset {
 
 #[cfg(test)]
 mod tests {
-    use crate::btree::page::{Page, PageId};
+    use crate::btree::page::{Page, PageId, DEFAULT_PAGE_SIZE};
 
     use super::*;
 
     #[test]
     fn it_works() {
-        let page = unsafe { Page::new_uninited(PageId::new(114)) };
+        let page = Page::new_zeroed(PageId::new(114), DEFAULT_PAGE_SIZE);
 
         // Init the node.
-        let mut node: BasicNode<(), u8, u8> = unsafe { BasicNode::new_unchecked(page) };
+        let mut node: BasicNode<(), u16, u16> = unsafe { BasicNode::new_unchecked(page) };
         unsafe {
             node.init();
         }
@@ -637,7 +718,7 @@ mod tests {
         // Put and get! Cool, alright.
         println!("{}", node.cap());
         for i in 0..node.cap() {
-            let i = i as u8;
+            let i = i as u16;
             assert_eq!(node.get(&i), None);
             unsafe {
                 node.put(&i, &i);
@@ -645,13 +726,15 @@ mod tests {
             assert_eq!(node.get(&i), Some(i));
         }
 
-        assert_eq!(node.len(), node.cap())
+        assert_eq!(node.len(), node.cap());
+        // Small records are not limited by 255.
+        assert!(node.cap() > u8::MAX as usize);
     }
 
     #[test]
     fn we_can_split_node() {
-        let page1 = unsafe { Page::new_uninited(PageId::new(114)) };
-        let page2 = unsafe { Page::new_uninited(PageId::new(514)) };
+        let page1 = Page::new_zeroed(PageId::new(114), DEFAULT_PAGE_SIZE);
+        let page2 = Page::new_zeroed(PageId::new(514), DEFAULT_PAGE_SIZE);
 
         let mut node1: BasicNode<(), u64, u64> = unsafe { BasicNode::new_unchecked(page1) };
         let mut node2: BasicNode<(), u64, u64> = unsafe { BasicNode::new_unchecked(page2) };
@@ -674,4 +757,65 @@ mod tests {
         let node2_min_key = (&node2).into_iter().fold(u64::MAX, |a, b| max(a, b.key));
         assert!(node1_max_key < node2_min_key);
     }
+
+    #[test]
+    fn it_removes_records() {
+        let page = Page::new_zeroed(PageId::new(114), DEFAULT_PAGE_SIZE);
+        let mut node: BasicNode<(), u64, u64> = unsafe { BasicNode::new_unchecked(page) };
+        unsafe { node.init(); }
+        for i in 0..10u64 {
+            unsafe { node.put(&(i * 10), &i) };
+        }
+
+        // The first, the middle and the last one.
+        assert_eq!(unsafe { node.remove(&0) }, Some(0));
+        assert_eq!(unsafe { node.remove(&50) }, Some(5));
+        assert_eq!(unsafe { node.remove(&90) }, Some(9));
+        assert_eq!(node.len(), 7);
+        let keys: Vec<u64> = node.into_iter().map(|r| r.key).collect();
+        assert_eq!(keys, [10, 20, 30, 40, 60, 70, 80]);
+
+        // Neither the keys removed nor the keys between records are present.
+        assert_eq!(unsafe { node.remove(&50) }, None);
+        assert_eq!(unsafe { node.remove(&55) }, None);
+        assert_eq!(unsafe { node.remove(&100) }, None);
+        assert_eq!(node.len(), 7);
+        assert_eq!(node.get(&50), None);
+        assert_eq!(node.get(&60), Some(6));
+    }
+
+    #[test]
+    fn it_coalesces_free_records() {
+        let page = Page::new_zeroed(PageId::new(114), DEFAULT_PAGE_SIZE);
+        let mut node: BasicNode<(), u64, u64> = unsafe { BasicNode::new_unchecked(page) };
+        unsafe { node.init(); }
+
+        let cap = node.cap() as u64;
+        for i in 0..cap {
+            unsafe { node.put(&i, &i) };
+        }
+
+        // Delete alternating keys, then put them again: no capacity is lost.
+        for i in (0..cap).step_by(2) {
+            assert_eq!(unsafe { node.remove(&i) }, Some(i));
+        }
+        for i in (0..cap).step_by(2) {
+            unsafe { node.put(&i, &(i + 1)) };
+        }
+        assert!(node.is_full());
+        for i in 0..cap {
+            assert_eq!(node.get(&i), Some(if i % 2 == 0 { i + 1 } else { i }));
+        }
+
+        // Delete all in a mixed order: the free records are a single run.
+        for i in (1..cap).step_by(2).chain((0..cap).step_by(2)) {
+            unsafe { node.remove(&i) };
+        }
+        assert!(node.is_empty());
+        let first_free_record_id = node.page_wrapper().hdr.first_free_record_id;
+        let free_record = *unsafe { node.mut_free_record(first_free_record_id) };
+        assert_eq!(first_free_record_id, RecordId::new(0));
+        assert_eq!(free_record.length as u64, cap);
+        assert_eq!(free_record.next, RecordId::invalid());
+    }
 }
@@ -1,4 +1,4 @@
-use crate::btree::page::{Page, PageId};
+use crate::{btree::page::{Page, PageId}, uuid::UUID_SIZE};
 
 use super::NodeType;
 
@@ -8,6 +8,32 @@ pub struct HeadNode(Page);
 
 const HEAD_NODE_MAGIC: &'static str = "skogkatt.org/WasteIsland/B-Plus-Tree";
 
+/// The version of the layout of the database - both the B-tree file and the
+/// data file:
+///
+/// - 0: The initial version.
+/// - 1: `HeadNodeHdr` has `records_len`.
+/// - 2: `LeafNodeHdr` has `next_leaf`.
+/// - 3: `HeadNodeHdr` has `hash_algorithm`.
+/// - 4: Records in the data file have a one-byte compression flag.
+/// - 5: Records in the data file have a CRC32 checksum after the content.
+/// - 6: Records in the data file have metadata after the checksum.
+/// - 7: `BasicNodeHdr` has `u16` records length and record IDs.
+/// - 8: `HeadNodeHdr` has `page_size`.
+/// - 9: Records in the data file have varint lengths.
+/// - 10: Records in leaves have a reference count beside the offset.
+/// - 11: Offsets in leaves have the shard of the data file.
+/// - 12: Records in leaves have an expiry time.
+/// - 13: `HeadNodeHdr` has `generation`.
+/// - 14: `HeadNodeHdr` has `garbage_bytes`.
+/// - 15: `HeadNodeHdr` has `uuid`.
+/// - 16: Records in leaves are laid out by `repr(C)`, see `LeafValue`.
+/// - 17: Offsets are laid out by `repr(C)` with a zeroed padding, see `Offset`.
+pub const HEAD_NODE_VERSION: u8 = 17;
+
+/// The max length of the name of the hash algorithm.
+pub const HASH_ALGORITHM_MAX_LEN: usize = 16;
+
 #[repr(C)]
 pub struct HeadNodeHdr {
     // node_type + version + magic = 64 bytes
@@ -17,6 +43,27 @@ pub struct HeadNodeHdr {
 
     // 4 bytes
     pub root_node_page_id: PageId,
+
+    // 8 bytes: how many records in the B-tree.
+    pub records_len: u64,
+
+    // 16 bytes: the name of the hash algorithm of keys, padded with zeros.
+    hash_algorithm: [u8; HASH_ALGORITHM_MAX_LEN],
+
+    // 4 bytes: the size of each page in the B-tree file.
+    page_size: u32,
+
+    // 8 bytes: how many transactions changed the B-tree, so that a reader
+    // knows if the pages it cached are still fresh.
+    pub generation: u64,
+
+    // 8 bytes: how many bytes of the data file are not referenced by the
+    // records any more. The B-tree never reads it itself.
+    pub garbage_bytes: u64,
+
+    // 16 bytes: the UUID of the database owning the B-tree, or zeros if it is
+    // not owned yet. The B-tree never reads it itself.
+    pub uuid: [u8; UUID_SIZE],
 }
 
 impl HeadNode {
@@ -54,16 +101,47 @@ impl HeadNode {
     /// 
     /// # Safety
     /// 
-    /// Remember to use `make_dirty` and sync.
-    pub unsafe fn init(&mut self, root_node_page_id: PageId) {
+    /// - Remember to use `make_dirty` and sync.
+    /// - The `hash_algorithm` should not be longer than
+    ///   `HASH_ALGORITHM_MAX_LEN`.
+    pub unsafe fn init(&mut self, root_node_page_id: PageId, hash_algorithm: &str, page_size: usize) {
         self.0.make_dirty();
         let hdr = self.mut_hdr();
         hdr.node_type = NodeType::Head;
-        hdr.version = 0;
+        hdr.version = HEAD_NODE_VERSION;
         let mut magic = vec![0u8; 62];
         magic[0..HEAD_NODE_MAGIC.len()].copy_from_slice(HEAD_NODE_MAGIC.as_bytes());
         hdr.magic = magic.as_slice().try_into().unwrap();
         hdr.root_node_page_id = root_node_page_id;
+        hdr.records_len = 0;
+        hdr.hash_algorithm = [0u8; HASH_ALGORITHM_MAX_LEN];
+        hdr.hash_algorithm[0..hash_algorithm.len()].copy_from_slice(hash_algorithm.as_bytes());
+        hdr.page_size = page_size as u32;
+        hdr.generation = 0;
+        hdr.garbage_bytes = 0;
+        hdr.uuid = [0u8; UUID_SIZE];
+    }
+
+    /// The version of the layout of the database.
+    pub fn version(&self) -> u8 {
+        self.hdr().version
+    }
+
+    /// The name of the hash algorithm of keys.
+    pub fn hash_algorithm(&self) -> &str {
+        let name = &self.hdr().hash_algorithm;
+        let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        std::str::from_utf8(&name[..len]).unwrap_or("")
+    }
+
+    /// The size of each page in the B-tree file.
+    pub fn page_size(&self) -> usize {
+        self.hdr().page_size as usize
+    }
+
+    /// How many transactions changed the B-tree since it is created.
+    pub fn generation(&self) -> u64 {
+        self.hdr().generation
     }
 
     /// Check to make sure this page is really a `HeadNode`: by check its magic
@@ -86,7 +164,7 @@ impl HeadNode {
         })();
 
         hdr.node_type == NodeType::Head
-            && hdr.version == 0
+            && hdr.version == HEAD_NODE_VERSION
             && magic_matched
     }
 
@@ -95,3 +173,31 @@ impl HeadNode {
         self.0.make_dirty()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::btree::page::DEFAULT_PAGE_SIZE;
+
+    use super::*;
+
+    #[test]
+    fn it_round_trips_root_page_id() {
+        let page = Page::new_zeroed(PageId::new(0), DEFAULT_PAGE_SIZE);
+        let mut node = unsafe { HeadNode::new_unchecked(page) };
+        unsafe { node.init(PageId::new(0x0102), "sha256", DEFAULT_PAGE_SIZE) };
+        assert!(node.check());
+        assert_eq!(node.hash_algorithm(), "sha256");
+        assert_eq!(node.hdr().root_node_page_id, PageId::new(0x0102));
+        assert_eq!(node.page_size(), DEFAULT_PAGE_SIZE);
+        assert_eq!(node.generation(), 0);
+
+        // The root page ID is stored in little-endian just after the magic.
+        let buf = unsafe { node.mut_page() }.buf();
+        assert_eq!(buf[64..68], [0x02, 0x01, 0x00, 0x00]);
+        assert_eq!(buf[80..96], *b"sha256\0\0\0\0\0\0\0\0\0\0");
+        assert_eq!(buf[96..100], [0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(buf[104..112], [0u8; 8]);
+        assert_eq!(buf[112..120], [0u8; 8]);
+        assert_eq!(buf[120..136], [0u8; 16]);
+    }
+}
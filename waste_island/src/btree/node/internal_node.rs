@@ -1,4 +1,4 @@
-use crate::{btree::page::{PageId, Page}, hash::Hash, offset::Offset};
+use crate::{btree::{page::{PageId, Page}, pod::Pod}, hash::Hash, offset::Offset};
 
 use super::{NodeType, basic_node::{BasicNode, BasicNodeIter, Record}};
 
@@ -10,11 +10,17 @@ pub struct InternalNodeHdr {
 }
 
 #[derive(Clone)]
-pub struct InternalNode {
-    node: BasicNode<InternalNodeHdr, Hash, PageId>,
+pub struct InternalNode<K = Hash>
+where
+    K: Pod + Ord,
+{
+    node: BasicNode<InternalNodeHdr, K, PageId>,
 }
 
-impl InternalNode {
+impl<K> InternalNode<K>
+where
+    K: Pod + Ord,
+{
     /// Create a new node by the page.
     /// 
     /// # Safety
@@ -41,12 +47,26 @@ impl InternalNode {
         self.node.page_id()
     }
 
+    pub fn len(&self) -> usize {
+        self.node.len()
+    }
+
+    pub fn cap(&self) -> usize {
+        self.node.cap()
+    }
+
     pub fn is_full(&self) -> bool {
         self.node.is_full()
     }
 
+    /// Is there no record? The node still has its `rightest_page_id` even if
+    /// it is empty.
+    pub fn is_empty(&self) -> bool {
+        self.node.is_empty()
+    }
+
     /// Get the page ID of the next page.
-    pub fn get(&self, key: &Hash) -> (Option<Hash>, PageId) {
+    pub fn get(&self, key: &K) -> (Option<K>, PageId) {
         match self.node.get_lower_bound_record(key) {
             Some(r) => (Some(r.key), r.value),
             None => (None, self.node.page_wrapper().hdr().rightest_page_id)
@@ -66,10 +86,25 @@ impl InternalNode {
         self.node.split(&mut rhs.node);
     }
 
+    /// Take all records of `rhs` - the right sibling of `self`. The
+    /// `separator` is the key between them in the parent, and it is pulled
+    /// down as the record pointing to the origin `rightest_page_id` of `self`.
+    ///
+    /// # Safety
+    ///
+    /// - It is your duty to make sure `self.len() + rhs.len() + 1 <= self.cap()`.
+    /// - Remember to use `make_dirty` and sync.
+    pub unsafe fn merge(&mut self, separator: &K, rhs: &Self) {
+        let rightest_page_id = self.hdr_mut().rightest_page_id;
+        self.node.put(separator, &rightest_page_id);
+        self.node.merge(&rhs.node);
+        self.hdr_mut().rightest_page_id = rhs.node.page_wrapper().hdr().rightest_page_id;
+    }
+
     /// # Safety
     /// 
     /// - You should make sure that it is not empty.
-    pub unsafe fn pop_rightest_record(&mut self) -> Record<Hash, PageId> {
+    pub unsafe fn pop_rightest_record(&mut self) -> Record<K, PageId> {
         self.node.pop_righest_record()
     }
 
@@ -87,8 +122,18 @@ impl InternalNode {
     /// 
     /// - Remember to use `make_dirty` and sync.
     /// - Make sure it has more space to store.
-    pub unsafe fn put(&mut self, key: &Hash, left_page_id: &PageId) {
-        self.node.put(key, left_page_id)
+    pub unsafe fn put(&mut self, key: &K, left_page_id: &PageId) {
+        self.node.put(key, left_page_id);
+    }
+
+    /// Remove the record (key, left_page_id) by its key and return the
+    /// `left_page_id`.
+    ///
+    /// # Safety
+    ///
+    /// - Remember to use `make_dirty` and sync.
+    pub unsafe fn remove(&mut self, key: &K) -> Option<PageId> {
+        self.node.remove(key)
     }
 
     pub fn make_dirty(&mut self) {
@@ -102,19 +147,21 @@ impl InternalNode {
         unsafe { self.node.mut_page() }
     }
 
-    pub fn into_iter<'a>(&'a self) -> BasicNodeIter<'a, InternalNodeHdr, Hash, PageId> {
+    pub fn into_iter<'a>(&'a self) -> BasicNodeIter<'a, InternalNodeHdr, K, PageId> {
         self.node.into_iter()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::btree::page::DEFAULT_PAGE_SIZE;
+
     use super::*;
 
     #[test]
     fn it_works() {
-        let page = unsafe { Page::new_uninited(PageId::new(114)) };
-        let mut node = unsafe { InternalNode::new_unchecked(page) };
+        let page = Page::new_zeroed(PageId::new(114), DEFAULT_PAGE_SIZE);
+        let mut node: InternalNode = unsafe { InternalNode::new_unchecked(page) };
         unsafe { node.init(PageId::new(514)) };
     }
 }
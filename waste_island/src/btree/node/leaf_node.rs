@@ -1,4 +1,6 @@
-use crate::{btree::page::{PageId, Page}, hash::Hash, offset::Offset};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{btree::{page::{PageId, Page}, pod::Pod}, hash::Hash, offset::Offset};
 
 use super::{NodeType, basic_node::{BasicNode, Record, self}};
 
@@ -6,13 +8,61 @@ use super::{NodeType, basic_node::{BasicNode, Record, self}};
 #[repr(C)]
 pub struct LeafNodeHdr {
     node_type: NodeType,
+    /// The next leaf in key order, or `PageId::invalid()` if it is the
+    /// rightest leaf.
+    pub next_leaf: PageId,
+}
+
+/// The value of a record in a leaf: the value put - the offset by default, how
+/// many times the same key has been put - the count of references to it - and
+/// when it expires, in milliseconds since the Unix epoch. See `NEVER_EXPIRES`.
+///
+/// It is stored in the pages just as its bytes, so its layout is fixed by
+/// `repr(C)`: `_pad` is always zero, and the value put follows the first 16
+/// bytes - aligned for any `Pod`. There is no padding only if the size of the
+/// value is a multiple of 8, as `Offset` is - a smaller one, like `u32`, is
+/// followed by the trailing padding of the struct.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct LeafValue<V = Offset> {
+    pub expires_at: u64,
+    pub refcount: u32,
+    _pad: u32,
+    pub value: V,
+}
+
+// The values of the index are stored without any padding.
+const _: () = assert!(std::mem::size_of::<LeafValue>() == 16 + std::mem::size_of::<Offset>());
+
+impl<V> LeafValue<V> {
+    pub fn new(value: V, refcount: u32, expires_at: u64) -> Self {
+        Self { expires_at, refcount, _pad: 0, value }
+    }
+}
+
+/// The expiry time of the records which never expire.
+pub const NEVER_EXPIRES: u64 = u64::MAX;
+
+/// The current time in milliseconds since the Unix epoch, to compare with the
+/// expiry time of records.
+pub fn now_millis() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    u64::try_from(now.as_millis()).unwrap_or(NEVER_EXPIRES - 1)
 }
 
-pub struct LeafNode {
-    node: BasicNode<LeafNodeHdr, Hash, Offset>,
+pub struct LeafNode<K = Hash, V = Offset>
+where
+    K: Pod + Ord,
+    V: Pod,
+{
+    node: BasicNode<LeafNodeHdr, K, LeafValue<V>>,
 }
 
-impl LeafNode {
+impl<K, V> LeafNode<K, V>
+where
+    K: Pod + Ord,
+    V: Pod,
+{
     /// Create a new node by the page.
     /// 
     /// # Safety
@@ -32,39 +82,75 @@ impl LeafNode {
         self.node.init();
         let hdr = self.node.mut_page_wrapper().mut_hdr();
         hdr.node_type = NodeType::Leaf;
+        hdr.next_leaf = PageId::invalid();
     }
 
     pub fn page_id(&self) -> PageId {
         self.node.page_id()
     }
 
-    /// Get the offset by hash key.
-    pub fn get(&self, key: &Hash) -> Option<Offset> {
+    /// Get the page ID of the next leaf, or `PageId::invalid()` if there is no
+    /// next leaf.
+    pub fn next_leaf(&self) -> PageId {
+        self.node.page_wrapper().hdr().next_leaf
+    }
+
+    /// Link the page as the next leaf of me.
+    ///
+    /// # Safety
+    ///
+    /// Remember to use `make_dirty` and sync.
+    pub unsafe fn set_next_leaf(&mut self, next_leaf: PageId) {
+        self.node.mut_page_wrapper().mut_hdr().next_leaf = next_leaf;
+    }
+
+    /// Get the value, the reference count and the expiry time by key.
+    pub fn get(&self, key: &K) -> Option<LeafValue<V>> {
         self.node.get(key)
     }
 
-    /// Put a new record. Cool?
+    /// Put a new record. Cool? Return the origin value if the key is already
+    /// existing.
     /// 
     /// # Safety
     /// 
     /// - Are you sure there is more space to hold a new record? Use `is_full`
     ///   to check it.
     /// - Remember to use `make_dirty` and sync.
-    pub unsafe fn put(&mut self, key: &Hash, value: &Offset) {
+    pub unsafe fn put(&mut self, key: &K, value: &LeafValue<V>) -> Option<LeafValue<V>> {
         self.node.put(key, value)
     }
 
+    /// Remove the record by key and return its value.
+    ///
+    /// # Safety
+    ///
+    /// - Remember to use `make_dirty` and sync.
+    pub unsafe fn remove(&mut self, key: &K) -> Option<LeafValue<V>> {
+        self.node.remove(key)
+    }
+
+    /// How many records in me?
+    pub fn len(&self) -> usize {
+        self.node.len()
+    }
+
+    /// How many records can I hold?
+    pub fn cap(&self) -> usize {
+        self.node.cap()
+    }
+
     /// Is me full?
     pub fn is_full(&self) -> bool {
         self.node.is_full()
     }
-
     /// Make the inner page dirty.
     pub fn make_dirty(&mut self) {
         self.node.make_dirty()
     }
 
-    /// Shift half of records from `self` to `rhs`.
+    /// Shift half of records from `self` to `rhs`, and link `rhs` as the next
+    /// leaf of `self`.
     ///
     /// # Safety
     ///
@@ -75,6 +161,20 @@ impl LeafNode {
     /// - Remember to use `make_dirty` and sync - both `self` and `rhs`.
     pub unsafe fn split(&mut self, rhs: &mut Self) {
         self.node.split(&mut rhs.node);
+        rhs.node.mut_page_wrapper().mut_hdr().next_leaf = self.next_leaf();
+        self.node.mut_page_wrapper().mut_hdr().next_leaf = rhs.page_id();
+    }
+
+    /// Take all records of `rhs` - the right sibling of `self`, and its place
+    /// in the chain of leaves.
+    ///
+    /// # Safety
+    ///
+    /// - It is your duty to make sure `self.len() + rhs.len() <= self.cap()`.
+    /// - Remember to use `make_dirty` and sync.
+    pub unsafe fn merge(&mut self, rhs: &Self) {
+        self.node.merge(&rhs.node);
+        self.node.mut_page_wrapper().mut_hdr().next_leaf = rhs.next_leaf();
     }
 
     /// # Safety
@@ -87,11 +187,28 @@ impl LeafNode {
     /// # Safety
     /// 
     /// Make sure self is not empty node.
-    pub unsafe fn rightest_key(&self) -> &Hash {
+    pub unsafe fn rightest_key(&self) -> &K {
         &self.node.rightest_record().key
     }
 
-    pub fn into_iter<'a>(&'a self) -> basic_node::BasicNodeIter<'a, LeafNodeHdr, Hash, Offset> {
+    pub fn into_iter<'a>(&'a self) -> basic_node::BasicNodeIter<'a, LeafNodeHdr, K, LeafValue<V>> {
         self.node.into_iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{offset_of, size_of};
+
+    use super::*;
+
+    #[test]
+    fn it_pins_the_layout_of_leaf_values() {
+        assert_eq!(offset_of!(LeafValue, expires_at), 0);
+        assert_eq!(offset_of!(LeafValue, refcount), 8);
+        assert_eq!(offset_of!(LeafValue, _pad), 12);
+        assert_eq!(offset_of!(LeafValue, value), 16);
+        assert_eq!(size_of::<LeafValue>(), 16 + size_of::<Offset>());
+        assert_eq!(size_of::<Record<Hash, LeafValue>>(), 32 + size_of::<LeafValue>());
+    }
+}
@@ -1,3 +1,5 @@
+use crate::error::{Error, ErrorKind};
+
 use super::page::Page;
 
 mod basic_node;
@@ -5,8 +7,8 @@ mod internal_node;
 mod leaf_node;
 mod head_node;
 
-pub use head_node::HeadNode;
-pub use leaf_node::LeafNode;
+pub use head_node::{HeadNode, HASH_ALGORITHM_MAX_LEN, HEAD_NODE_VERSION};
+pub use leaf_node::{now_millis, LeafNode, LeafValue, NEVER_EXPIRES};
 pub use internal_node::InternalNode;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -16,12 +18,17 @@ pub enum NodeType {
     Internal = 3,
 }
 
-pub fn get_node_type(page: &Page) -> NodeType {
+/// Get the type of the node in the page. A page appended but not inited yet -
+/// all zeroed - or any other unknown byte is corrupt.
+pub fn get_node_type(page: &Page) -> Result<NodeType, Error> {
     match page.buf()[0] {
-        1 => NodeType::Head,
-        2 => NodeType::Leaf,
-        3 => NodeType::Internal,
-        _ => panic!("unexcepted type: {}", page.buf()[0])
+        1 => Ok(NodeType::Head),
+        2 => Ok(NodeType::Leaf),
+        3 => Ok(NodeType::Internal),
+        typ => Err(Error::with_kind(
+            ErrorKind::Corrupt,
+            format!("unexcepted node type {} of page {:?}", typ, page.id()),
+        )),
     }
 }
 
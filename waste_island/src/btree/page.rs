@@ -1,7 +1,23 @@
-use std::{fmt::Debug, alloc::{alloc, Layout, dealloc}, sync::{RwLock, Arc}};
+use std::{
+    cell::UnsafeCell, fmt::Debug, slice,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+};
 
-/// The size of page in the b-tree file.
-pub const PAGE_SIZE: usize = 4usize << 10; // 4 KB
+/// The default size of page in the b-tree file.
+pub const DEFAULT_PAGE_SIZE: usize = 4usize << 10; // 4 KB
+
+/// The minimum size of page supported.
+pub const MIN_PAGE_SIZE: usize = 1usize << 10; // 1 KB
+
+/// The maximum size of page supported. The offsets in the page should fit in
+/// `u16`.
+pub const MAX_PAGE_SIZE: usize = 64usize << 10; // 64 KB
+
+/// Check if the page size is supported - it should be a power of two between
+/// `MIN_PAGE_SIZE` and `MAX_PAGE_SIZE`.
+pub fn is_valid_page_size(page_size: usize) -> bool {
+    page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+}
 
 /// The ID refered to a page. It should be unikey in the B-Tree. It need
 /// `PAGE_ID_LENGTH` bytes to hold data.
@@ -10,20 +26,34 @@ pub struct PageId(u32);
 
 struct PageInner {
     id: PageId,
-    ref_cnt: usize,
-    is_dirty: bool,
-    buf: [u8; PAGE_SIZE],
+    /// Atomic - the page may be marked dirty through any of its clones.
+    is_dirty: AtomicBool,
+    /// The buffer, in `u64` so that it is aligned for the records.
+    buf: UnsafeCell<Box<[u64]>>,
 }
 
+// SAFETY: The buffer is only written through `Page::mut_buf`, whose callers
+// make sure no one else reads or writes it at the same time: the pages of a
+// B-Tree are only written while the B-Tree is borrowed mutably, and the pages
+// read by its pager are written before they are shared. So other threads only
+// read the pages while the B-Tree is borrowed immutably - for example, by the
+// `RwLock` of its database. The thread of `SyncPolicy::Background` never reads
+// the pages at all, but the bytes copied by the pager once they are synced.
+unsafe impl Send for PageInner {}
+unsafe impl Sync for PageInner {}
+
 /// The cached page. It will have a data in the heap.
-/// 
+///
 /// There are maybe more than one want to access the page --- and it is safe
-/// if you use `clone`. Because it contains a reference counter in the heap.
-/// 
-/// The implement don't use `Rc` because we need to avoid cycle. And I have no
-/// idea how to alloc a uninited memory and turn it into `Weak` as well.
+/// if you use `clone`, because the buffer is shared by an `Arc`: it is freed
+/// once the last clone is dropped, from whichever thread.
+///
+/// The `Arc` can never be part of a cycle: pages refer to each other by their
+/// `PageId`s in the buffer, not by pointers, so the pages form a graph of IDs
+/// and a `Page` only ever holds its own buffer.
+#[derive(Clone)]
 pub struct Page {
-    inner: Arc<RwLock<*mut PageInner>>,
+    inner: Arc<PageInner>,
 }
 
 impl PageId {
@@ -47,94 +77,59 @@ impl Debug for PageId {
 }
 
 impl Page {
-    /// Get a `Page` containing an uninited buffer in it. The buffer of the
-    /// uninited `Page` is not all zeroed.
-    /// 
-    /// # Safety
-    /// 
-    /// Make sure the `Page`'s buffer will be as same as the disk content...
-    pub unsafe fn new_uninited(id: PageId) -> Self {
-        let inner = {
-            let ptr = unsafe {
-                let ptr = alloc(Layout::new::<PageInner>()) as *mut PageInner;
-                &mut *ptr
-            };
-            ptr.id = id;
-            ptr.is_dirty = false;
-            ptr.ref_cnt = 1;
-            ptr as *mut PageInner
+    /// Get a `Page` of the all zeroed buffer. It is no valid node yet - its
+    /// node type is 0 - until the node is inited.
+    pub fn new_zeroed(id: PageId, page_size: usize) -> Self {
+        debug_assert!(is_valid_page_size(page_size));
+        let inner = PageInner {
+            id,
+            is_dirty: AtomicBool::new(false),
+            buf: UnsafeCell::new(vec![0; page_size / 8].into_boxed_slice()),
         };
-        Self { inner: Arc::new(RwLock::new(inner)) }
+        Self { inner: Arc::new(inner) }
     }
 
     /// Get the mutable reference to the inner buffer.
-    /// 
+    ///
     /// # Safety
-    /// 
-    /// If you changed the content in the buffer, involve `make_dirty`.
-    pub unsafe fn mut_buf(&mut self) -> &mut [u8; PAGE_SIZE] {
-        &mut self.mut_inner().buf
+    ///
+    /// If you changed the content in the buffer, involve `make_dirty`. The
+    /// buffer is shared by all clones of the page, so make sure no one else
+    /// is reading or writing it at the same time - the B-Tree only writes
+    /// pages while it is borrowed mutably.
+    pub unsafe fn mut_buf(&mut self) -> &mut [u8] {
+        let buf = unsafe { &mut *self.inner.buf.get() };
+        unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 8) }
     }
 
     /// Get the unmutable reference to the inner buffer.
-    pub fn buf(&self) -> &[u8; PAGE_SIZE] {
-        &self.inner().buf
+    pub fn buf(&self) -> &[u8] {
+        let buf = unsafe { &*self.inner.buf.get() };
+        unsafe { slice::from_raw_parts(buf.as_ptr() as *const u8, buf.len() * 8) }
     }
 
     /// Get the ID of this page.
     pub fn id(&self) -> PageId {
-        self.inner().id
+        self.inner.id
+    }
+
+    /// Get how many `Page`s are referring to the same inner struct.
+    pub fn ref_cnt(&self) -> usize {
+        Arc::strong_count(&self.inner)
     }
 
     /// Set the `is_dirty` flag is true.
     pub fn make_dirty(&mut self) {
-        unsafe { self.mut_inner().is_dirty = true; }
+        self.inner.is_dirty.store(true, Ordering::Release);
     }
 
     /// Get the `is_dirty` flag.
     pub fn is_dirty(&self) -> bool {
-        self.inner().is_dirty
+        self.inner.is_dirty.load(Ordering::Acquire)
     }
 
     /// Clear the `is_dirty` flag.
     pub fn clear(&mut self) {
-        unsafe { self.mut_inner().is_dirty = false; }
-    }
-
-    /// Get the mutable reference to the inner struct.
-    /// 
-    /// # Safety
-    /// 
-    /// Make sure those fields in the inner struct will not be dirty.
-    unsafe fn mut_inner(&mut self) -> &mut PageInner {
-        let mut inner = self.inner.write().unwrap();
-        &mut **inner
-    }
-
-    /// Get the unmutable reference to the inner struct.
-    fn inner(&self) -> &PageInner {
-        let inner = self.inner.read().unwrap();
-        unsafe { &**inner }
-    }
-}
-
-impl Clone for Page {
-    fn clone(&self) -> Self {
-        let mut result = Self { inner: self.inner.clone() };
-        unsafe { result.mut_inner().ref_cnt += 1; }
-        result
+        self.inner.is_dirty.store(false, Ordering::Release);
     }
 }
-
-impl Drop for Page {
-    fn drop(&mut self) {
-        let inner = unsafe { self.mut_inner() };
-        inner.ref_cnt -= 1;
-        if inner.ref_cnt == 0 {
-            unsafe { dealloc(*self.inner.write().unwrap() as *mut u8, Layout::new::<PageInner>()); }
-        }
-    }
-}
-
-unsafe impl Send for Page {}
-unsafe impl Sync for Page {}
\ No newline at end of file
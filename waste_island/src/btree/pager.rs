@@ -1,19 +1,63 @@
 use std::{
     collections::HashMap,
     fs::File,
-    io::{Read, Seek, SeekFrom, Write},
-    sync::{RwLock, Arc}
+    mem,
+    sync::{atomic::{AtomicU64, Ordering}, RwLock, Arc}
 };
 
-use crate::error::{Error, ToInnerResult};
+use lru::LruCache;
 
-use super::page::{PageId, Page, PAGE_SIZE};
+use crate::{error::{Error, ToInnerResult}, store::BlockStore};
+
+use super::page::{PageId, Page};
+
+/// The default max count of pages cached by the pager.
+pub const DEFAULT_MAX_PAGES: usize = 1024; // 4 MB by the default page size
+
+/// The size of the trailer of a WAL: the count of pages as `u64` and the CRC32
+/// of all bytes before it.
+const WAL_TRAILER_SIZE: usize = 8 + 4;
 
 pub struct PagerInner {
-    file: File,
+    file: Box<dyn BlockStore>,
+    page_size: usize,
+    /// Appending pages and syncing dirty pages fail if it is true.
+    read_only: bool,
+    /// The write-ahead log, where the dirty pages of a transaction are written
+    /// before they are written to the file. See `Pager::with_wal`.
+    wal: Option<Box<dyn BlockStore>>,
+    /// Is there anything in the WAL to be cleared?
+    wal_written: bool,
+    /// The depth of nested transactions. Synced pages are kept dirty in
+    /// `transaction_pages` until the outermost transaction is committed.
+    transaction_depth: usize,
+    transaction_pages: HashMap<PageId, Page>,
+    /// Are the writes of pages deferred? See `Pager::set_deferred`.
+    deferred: bool,
+    /// The bytes of the pages written while deferred, which are not written to
+    /// the file yet - and the ones staged by `Pager::stage_deferred`, which
+    /// are older. Reads find them before the file.
+    deferred_pages: HashMap<PageId, Box<[u8]>>,
+    staged_pages: HashMap<PageId, Box<[u8]>>,
     pages_len: usize,
-    /// The cache of pages.
-    page_map: HashMap<PageId, Page>,
+    /// The cache of pages. The least-recently-used page will be evicted once
+    /// its length exceeds `max_pages`.
+    page_map: LruCache<PageId, Page>,
+    max_pages: usize,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// The counters of the pager's cache of pages. See `Pager::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PagerStats {
+    /// How many times `get_page` found the page in the cache.
+    pub cache_hits: u64,
+    /// How many times `get_page` read the page from the file.
+    pub cache_misses: u64,
+    /// How many pages were evicted from the cache.
+    pub evictions: u64,
 }
 
 #[derive(Clone)]
@@ -21,90 +65,759 @@ pub struct Pager {
     inner: Arc<RwLock<PagerInner>>,
 }
 
-/// From page ID to its file seek.
-fn page_id_to_file_seek(page_id: PageId) -> SeekFrom {
-    let offset = page_id.raw() as u64 * PAGE_SIZE as u64;
-    SeekFrom::Start(offset)
+/// From page ID to its offset in the file.
+fn page_id_to_offset(page_id: PageId, page_size: usize) -> u64 {
+    page_id.raw() as u64 * page_size as u64
+}
+
+impl PagerInner {
+    /// Write the page to the file if the page is dirty - or keep its bytes in
+    /// `deferred_pages` if the writes are deferred.
+    fn write_page(&mut self, page: &mut Page) -> Result<(), Error> {
+        if page.is_dirty() {
+            if self.read_only {
+                return Err(Error::new("pager is read-only"));
+            }
+            page.clear();
+            if self.deferred {
+                self.deferred_pages.insert(page.id(), page.buf().into());
+                return Ok(());
+            }
+            self.file
+                .write_all_at(page.buf(), page_id_to_offset(page.id(), self.page_size))
+                .to_inner_result("write page to sync")?;
+        }
+
+        Ok(())
+    }
+
+    /// Write all dirty pages in the cache to the file. Nothing is written in
+    /// a transaction - the pages are written once it is committed.
+    fn flush_all(&mut self) -> Result<(), Error> {
+        if self.transaction_depth > 0 {
+            return Ok(());
+        }
+        let pages = self.page_map
+            .iter()
+            .filter(|(_, p)| p.is_dirty())
+            .map(|(_, p)| p.clone())
+            .collect();
+        self.write_pages(pages)
+    }
+
+    /// Write the pages to the file, by the WAL if there is one. The WAL is
+    /// skipped if the writes are deferred, as nothing is written to the file.
+    fn write_pages(&mut self, mut pages: Vec<Page>) -> Result<(), Error> {
+        if !self.deferred {
+            let bufs: Vec<_> = pages.iter().map(|p| (p.id(), p.buf())).collect();
+            self.write_wal(&bufs)?;
+        }
+        for page in pages.iter_mut() {
+            self.write_page(page)?;
+        }
+        self.clear_wal()
+    }
+
+    /// Move the deferred pages to the staged ones. The pages deferred later
+    /// win, as they are newer.
+    fn stage_deferred(&mut self) {
+        let deferred: Vec<_> = self.deferred_pages.drain().collect();
+        self.staged_pages.extend(deferred);
+    }
+
+    /// Write the staged pages to the file at once, by the WAL if there is one.
+    /// They are kept staged if it fails, so that they are written again.
+    fn write_staged(&mut self) -> Result<(), Error> {
+        if self.staged_pages.is_empty() {
+            return Ok(());
+        }
+        let staged = mem::take(&mut self.staged_pages);
+        let result = self.write_bufs(&staged);
+        if result.is_err() {
+            self.staged_pages = staged;
+        }
+        result
+    }
+
+    /// Write the bytes of the pages to the file, by the WAL if there is one.
+    fn write_bufs(&mut self, pages: &HashMap<PageId, Box<[u8]>>) -> Result<(), Error> {
+        let pages: Vec<(PageId, &[u8])> = pages.iter().map(|(id, buf)| (*id, &buf[..])).collect();
+        self.write_wal(&pages)?;
+        for (id, buf) in pages {
+            self.file
+                .write_all_at(buf, page_id_to_offset(id, self.page_size))
+                .to_inner_result("write page to sync")?;
+        }
+        self.clear_wal()
+    }
+
+    /// Read the page into `buf` - from the deferred pages if it is not written
+    /// to the file yet.
+    fn read_page(&self, id: PageId, buf: &mut [u8]) -> Result<(), Error> {
+        match self.deferred_pages.get(&id).or_else(|| self.staged_pages.get(&id)) {
+            Some(deferred) => buf.copy_from_slice(deferred),
+            None => self.file
+                .read_exact_at(buf, page_id_to_offset(id, self.page_size))
+                .to_inner_result("read to buffer")?,
+        }
+        Ok(())
+    }
+
+    /// `fsync` the file, and the WAL if there is one.
+    fn sync_files(&self) -> Result<(), Error> {
+        self.file.sync().to_inner_result("fsync pager file")?;
+        if let Some(wal) = &self.wal {
+            wal.sync().to_inner_result("fsync WAL")?;
+        }
+        Ok(())
+    }
+
+    /// Write the pages to the WAL, if there is one and there are at least two
+    /// pages - writing only one page is atomic enough.
+    ///
+    /// The WAL is the pages, each of which is its page ID as `u64` and its
+    /// bytes, followed by the trailer: the count of pages as `u64` and the
+    /// CRC32 of all bytes before it.
+    fn write_wal(&mut self, pages: &[(PageId, &[u8])]) -> Result<(), Error> {
+        if self.wal.is_none() || pages.len() < 2 {
+            return Ok(());
+        }
+        let mut buf = Vec::with_capacity(pages.len() * (8 + self.page_size) + WAL_TRAILER_SIZE);
+        for (id, page_buf) in pages {
+            buf.extend_from_slice(&(id.raw() as u64).to_le_bytes());
+            buf.extend_from_slice(page_buf);
+        }
+        buf.extend_from_slice(&(pages.len() as u64).to_le_bytes());
+        let checksum = crc32fast::hash(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+
+        let wal = self.wal.as_ref().unwrap();
+        wal.write_all_at(&buf, 0).to_inner_result("write WAL")?;
+        self.wal_written = true;
+        Ok(())
+    }
+
+    /// Clear the WAL once all pages in it are written to the file.
+    fn clear_wal(&mut self) -> Result<(), Error> {
+        if let (Some(wal), true) = (&self.wal, self.wal_written) {
+            wal.set_len(0).to_inner_result("truncate WAL")?;
+            self.wal_written = false;
+        }
+        Ok(())
+    }
+
+    /// Put the page into the cache, and evict the least-recently-used pages if
+    /// there are too many pages.
+    ///
+    /// The page referenced elsewhere will never be evicted, and the dirty page
+    /// will be synced before being evicted.
+    fn cache_page(&mut self, page: Page) -> Result<(), Error> {
+        self.page_map.put(page.id(), page);
+
+        while self.page_map.len() > self.max_pages {
+            let id = self.page_map
+                .iter()
+                .rev()
+                .find(|(_, p)| p.ref_cnt() == 1)
+                .map(|(id, _)| *id);
+            let id = match id {
+                Some(id) => id,
+                None => break, // All pages are in use.
+            };
+
+            let mut page = self.page_map.pop(&id).unwrap();
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+            #[cfg(feature = "tracing")]
+            tracing::trace!(page_id = ?id, dirty = page.is_dirty(), "evict page");
+            self.write_page(&mut page)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PagerInner {
+    fn drop(&mut self) {
+        // Best-effort: there is no way to report the error here. Call
+        // `Pager::flush_all` if you care about it. The pages of an unfinished
+        // transaction are dropped, as if the process crashed.
+        let _ = self.flush_all();
+        self.stage_deferred();
+        let _ = self.write_staged();
+    }
 }
 
 impl Pager {
-    /// Create a new pager by a file.
-    pub fn new(file: File) -> Result<Self, Error> {
-        let metadata = file.metadata().to_inner_result("get metadata")?;
+    /// Create a new pager by a file, whose pages are `page_size` bytes. It
+    /// caches at most `DEFAULT_MAX_PAGES` pages.
+    pub fn new(file: File, page_size: usize) -> Result<Self, Error> {
+        Self::new_with_store(file, page_size)
+    }
+
+    /// Create a new pager by any store, whose pages are `page_size` bytes. It
+    /// caches at most `DEFAULT_MAX_PAGES` pages.
+    pub fn new_with_store<S>(store: S, page_size: usize) -> Result<Self, Error>
+    where
+        S: BlockStore + 'static,
+    {
+        Self::with_capacity(store, page_size, DEFAULT_MAX_PAGES)
+    }
+
+    /// Create a new pager by a store, whose pages are `page_size` bytes. It
+    /// caches at most `max_pages` pages - unless they are all referenced
+    /// elsewhere.
+    pub fn with_capacity<S>(store: S, page_size: usize, max_pages: usize) -> Result<Self, Error>
+    where
+        S: BlockStore + 'static,
+    {
+        let len = store.len().to_inner_result("get length of store")?;
         let inner = PagerInner {
-            file,
-            pages_len: (metadata.len() as usize / PAGE_SIZE),
-            page_map: HashMap::new(),
+            file: Box::new(store),
+            page_size,
+            read_only: false,
+            wal: None,
+            wal_written: false,
+            transaction_depth: 0,
+            transaction_pages: HashMap::new(),
+            deferred: false,
+            deferred_pages: HashMap::new(),
+            staged_pages: HashMap::new(),
+            pages_len: (len as usize / page_size),
+            page_map: LruCache::unbounded(),
+            max_pages,
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
         };
         Ok(Pager { inner: Arc::new(RwLock::new(inner)) })
     }
 
+    /// Create a new pager by a store, whose pages are `page_size` bytes, with
+    /// the write-ahead log `wal`. The pages of a transaction are written to the
+    /// WAL before they are written to the file, so a crash in the middle of
+    /// writing them cannot leave the file half-written: the pages left in the
+    /// WAL are written to the file again here.
+    ///
+    /// It makes the file safe from a killed process. It is not synced to the
+    /// disk by itself, so call `sync_all` to be safe from a power loss.
+    pub fn with_wal<S, W>(store: S, wal: W, page_size: usize) -> Result<Self, Error>
+    where
+        S: BlockStore + 'static,
+        W: BlockStore + 'static,
+    {
+        Self::replay_wal(&store, &wal, page_size)?;
+
+        let pager = Self::new_with_store(store, page_size)?;
+        pager.inner.write().unwrap().wal = Some(Box::new(wal));
+        Ok(pager)
+    }
+
+    /// Write the pages left in the WAL to the file, and then clear the WAL.
+    /// A WAL without a valid trailer was not written completely, so the file
+    /// was never touched - just ignore it.
+    fn replay_wal(file: &dyn BlockStore, wal: &dyn BlockStore, page_size: usize) -> Result<(), Error> {
+        let wal_len = wal.len().to_inner_result("get length of WAL")?;
+        if wal_len == 0 {
+            return Ok(());
+        }
+        let mut buf = vec![0u8; wal_len as usize];
+        wal.read_exact_at(&mut buf, 0).to_inner_result("read WAL")?;
+
+        if let Some(records) = Self::parse_wal(&buf, page_size) {
+            for (id, page_buf) in records {
+                file.write_all_at(page_buf, page_id_to_offset(id, page_size))
+                    .to_inner_result("write page to replay")?;
+            }
+        }
+        wal.set_len(0).to_inner_result("truncate WAL")?;
+        Ok(())
+    }
+
+    /// Parse the pages in the WAL. Return `None` if its trailer is broken.
+    fn parse_wal(buf: &[u8], page_size: usize) -> Option<Vec<(PageId, &[u8])>> {
+        let body_len = buf.len().checked_sub(WAL_TRAILER_SIZE)?;
+        let (body, checksum) = buf.split_at(body_len + 8);
+        if u32::from_le_bytes(checksum.try_into().unwrap()) != crc32fast::hash(body) {
+            return None;
+        }
+        let (records, count) = body.split_at(body_len);
+        let count = u64::from_le_bytes(count.try_into().unwrap()) as usize;
+        if records.len() != count.checked_mul(8 + page_size)? {
+            return None;
+        }
+
+        Some(records
+            .chunks_exact(8 + page_size)
+            .map(|record| {
+                let (id, page_buf) = record.split_at(8);
+                let id = u64::from_le_bytes(id.try_into().unwrap()) as usize;
+                (PageId::new(id), page_buf)
+            })
+            .collect())
+    }
+
+    /// Create a read-only pager by a file, whose pages are `page_size` bytes.
+    /// The file could be opened in read-only mode, as it is never written.
+    pub fn read_only(file: File, page_size: usize) -> Result<Self, Error> {
+        let pager = Self::new(file, page_size)?;
+        pager.inner.write().unwrap().read_only = true;
+        Ok(pager)
+    }
+
     /// Get the length of the pages.
     pub fn len(&self) -> usize {
         let pager = self.inner.read().unwrap();
         pager.pages_len
     }
 
-    /// Append a new empty page and return it.
+    /// Get the length of the store in bytes.
+    pub fn store_len(&self) -> Result<u64, Error> {
+        let pager = self.inner.read().unwrap();
+        pager.file.len().to_inner_result("get length of store")
+    }
+
+    /// Get the counters of the cache of pages since the pager is created.
+    pub fn stats(&self) -> PagerStats {
+        let pager = self.inner.read().unwrap();
+        PagerStats {
+            cache_hits: pager.cache_hits.load(Ordering::Relaxed),
+            cache_misses: pager.cache_misses.load(Ordering::Relaxed),
+            evictions: pager.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Append a new empty page and return it. The page is all zeroed - both in
+    /// the memory and on the disk - until its node is inited.
     pub fn append_empty_uninited_page(&mut self) -> Result<Page, Error> {
         let mut pager = self.inner.write().unwrap();
+        if pager.read_only {
+            return Err(Error::new("pager is read-only"));
+        }
+        let page = Page::new_zeroed(PageId::new(pager.pages_len), pager.page_size);
         pager.file
-            .seek(SeekFrom::End(0))
-            .to_inner_result("seek to offset")?;
-
-        let page = unsafe {
-            Page::new_uninited(PageId::new(pager.pages_len))
-        };
-        pager.file
-            .write_all(page.buf())
+            .write_all_at(page.buf(), page_id_to_offset(page.id(), pager.page_size))
             .to_inner_result("write to file")?;
 
-        pager.page_map.insert(page.id(), page.clone());
+        pager.cache_page(page.clone())?;
         pager.pages_len += 1;
 
         Ok(page)
     }
 
-    /// Get the page by its page ID.
-    pub fn get_page(&mut self, id: PageId) -> Result<Page, Error> {
+    /// Get the page by its page ID. It only needs `&self`, so many readers can
+    /// share one pager.
+    pub fn get_page(&self, id: PageId) -> Result<Page, Error> {
         let mut pager = self.inner.write().unwrap();
         match pager.page_map.get(&id) {
             Some(p) => {
                 let page = p.clone();
+                pager.cache_hits.fetch_add(1, Ordering::Relaxed);
                 Ok(page)
             }
             None => {
-                pager.file
-                    .seek(page_id_to_file_seek(id))
-                    .to_inner_result("seek to offset")?;
+                pager.cache_misses.fetch_add(1, Ordering::Relaxed);
+                let mut page = Page::new_zeroed(id, pager.page_size);
+                pager.read_page(id, unsafe { page.mut_buf() })?;
 
-                let mut page = unsafe {
-                    Page::new_uninited(id)
-                };
-                pager.file
-                    .read_exact(unsafe { page.mut_buf() })
-                    .to_inner_result("read to buffer")?;
-
-                pager.page_map.insert(id, page.clone());
+                pager.cache_page(page.clone())?;
 
                 Ok(page)
             }
         }
     }
 
+    /// Read the page from the store by its page ID, by-passing the cache: the
+    /// page cached is never returned, and the page read is never cached. The
+    /// deferred pages are still read before the store.
+    pub fn read_page_uncached(&self, id: PageId) -> Result<Page, Error> {
+        let pager = self.inner.read().unwrap();
+        let mut page = Page::new_zeroed(id, pager.page_size);
+        pager.read_page(id, unsafe { page.mut_buf() })?;
+        Ok(page)
+    }
+
+    /// Drop all cached pages - the dirty ones are synced first - and count the
+    /// pages of the store again, so that the pages changed by another writer
+    /// of the store are read again.
+    ///
+    /// The pages got before are not dropped, so they must not be used any
+    /// more. It fails in a transaction.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let mut pager = self.inner.write().unwrap();
+        if pager.transaction_depth > 0 {
+            return Err(Error::new("can not refresh the pager in a transaction"));
+        }
+        pager.flush_all()?;
+        pager.page_map.clear();
+        let len = pager.file.len().to_inner_result("get length of store")?;
+        pager.pages_len = len as usize / pager.page_size;
+        Ok(())
+    }
+
     /// Sync the page if the page is dirty (if `page.isDirty` is ture)
+    ///
+    /// In a transaction, the page is just kept dirty in the cache - it is
+    /// written once the transaction is committed.
     pub fn sync_page(&mut self, page: &mut Page) -> Result<(), Error> {
-        if page.is_dirty() {
+        let mut pager = self.inner.write().unwrap();
+        pager.page_map.promote(&page.id());
+        if pager.transaction_depth > 0 {
+            pager.transaction_pages.entry(page.id()).or_insert_with(|| page.clone());
+            return Ok(());
+        }
+        pager.write_page(page)
+    }
+
+    /// Begin a transaction: the pages synced are kept dirty in the cache until
+    /// it is committed, and then they are written all at once - by the WAL if
+    /// there is one. It also saves the writes of pages synced again and again.
+    ///
+    /// Transactions can be nested, and only the outermost one writes pages.
+    pub fn begin(&mut self) {
+        let mut pager = self.inner.write().unwrap();
+        pager.transaction_depth += 1;
+    }
+
+    /// Are there pages synced in the transaction, to be written once it is
+    /// committed?
+    pub fn has_transaction_pages(&self) -> bool {
+        let pager = self.inner.read().unwrap();
+        !pager.transaction_pages.is_empty()
+    }
+
+    /// Commit the transaction begun by `begin`.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        let mut pager = self.inner.write().unwrap();
+        debug_assert!(pager.transaction_depth > 0, "commit without a transaction");
+        pager.transaction_depth -= 1;
+        if pager.transaction_depth > 0 {
+            return Ok(());
+        }
+        let pages = pager.transaction_pages.drain().map(|(_, p)| p).collect();
+        pager.write_pages(pages)
+    }
+
+    /// Abort all transactions: the dirty pages are read from the file - or
+    /// the deferred pages - again, so all changes since the outermost `begin`
+    /// are gone. The pages appended are kept, but they are not referenced by
+    /// any page.
+    pub fn abort(&mut self) -> Result<(), Error> {
+        let mut pager = self.inner.write().unwrap();
+        pager.transaction_depth = 0;
+        let pages: Vec<Page> = pager.transaction_pages.drain().map(|(_, p)| p).collect();
+        for mut page in pages {
+            pager.read_page(page.id(), unsafe { page.mut_buf() })?;
             page.clear();
-            let mut pager = self.inner.write().unwrap();
-            pager.file
-                .seek(page_id_to_file_seek(page.id()))
-                .to_inner_result("seek to page to sync")?;
-            pager.file
-                .write_all(page.buf())
-                .to_inner_result("write page to sync")?;
         }
+        Ok(())
+    }
+
+    /// Sync all dirty pages cached by the pager.
+    pub fn flush_all(&mut self) -> Result<(), Error> {
+        let mut pager = self.inner.write().unwrap();
+        pager.flush_all()
+    }
 
+    /// Sync all dirty pages cached by the pager - and the deferred pages as
+    /// well - and then make sure they are really on the disk by `fsync`.
+    pub fn sync_all(&mut self) -> Result<(), Error> {
+        let mut pager = self.inner.write().unwrap();
+        pager.flush_all()?;
+        pager.stage_deferred();
+        pager.write_staged()?;
+        pager.sync_files()
+    }
+
+    /// Defer the writes of pages: once it is deferred, the pages synced - or
+    /// evicted - are kept in memory as they are then, instead of written to
+    /// the file, until `sync_all` or `stage_deferred` and `sync_staged`. So
+    /// whatever the pages reference can be synced to the disk before them,
+    /// without holding the pager. It is not deferred by default.
+    ///
+    /// The deferred pages are written at once if it is not deferred any more.
+    pub fn set_deferred(&mut self, deferred: bool) -> Result<(), Error> {
+        let mut pager = self.inner.write().unwrap();
+        if !deferred {
+            pager.stage_deferred();
+            pager.write_staged()?;
+        }
+        pager.deferred = deferred;
         Ok(())
     }
+
+    /// Stage the pages deferred so far, to be written by `sync_staged`. The
+    /// pages deferred after it are left for the next time.
+    pub fn stage_deferred(&mut self) {
+        let mut pager = self.inner.write().unwrap();
+        pager.stage_deferred();
+    }
+
+    /// Write the pages staged by `stage_deferred` to the file at once - by the
+    /// WAL if there is one - and then `fsync` them.
+    pub fn sync_staged(&mut self) -> Result<(), Error> {
+        let mut pager = self.inner.write().unwrap();
+        pager.write_staged()?;
+        pager.sync_files()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::{btree::page::DEFAULT_PAGE_SIZE, store::MemStore};
+
+    use super::*;
+
+    #[test]
+    fn it_evicts_least_recently_used_pages() {
+        let store = MemStore::new();
+        let mut pager = Pager::with_capacity(store.clone(), DEFAULT_PAGE_SIZE, 2).unwrap();
+        let held_page = pager.append_empty_uninited_page().unwrap();
+        for i in 1..5 {
+            let mut page = pager.append_empty_uninited_page().unwrap();
+            unsafe { page.mut_buf()[0] = i as u8 };
+            page.make_dirty();
+        }
+
+        // The held page is never evicted, and the evicted pages were synced.
+        {
+            let pager = pager.inner.read().unwrap();
+            assert_eq!(pager.page_map.len(), 2);
+            assert!(pager.page_map.contains(&held_page.id()));
+        }
+        assert_eq!(pager.stats(), PagerStats { cache_hits: 0, cache_misses: 0, evictions: 3 });
+        for i in 1..5 {
+            assert_eq!(pager.get_page(PageId::new(i)).unwrap().buf()[0], i as u8);
+        }
+        assert_eq!(pager.stats(), PagerStats { cache_hits: 0, cache_misses: 4, evictions: 7 });
+        pager.get_page(PageId::new(4)).unwrap();
+        assert_eq!(pager.stats(), PagerStats { cache_hits: 1, cache_misses: 4, evictions: 7 });
+        assert_eq!(store.len().unwrap(), 5 * DEFAULT_PAGE_SIZE as u64);
+        assert_eq!(pager.store_len().unwrap(), 5 * DEFAULT_PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn it_appends_zeroed_pages() {
+        let store = MemStore::new();
+        let mut pager = Pager::with_capacity(store.clone(), DEFAULT_PAGE_SIZE, 1).unwrap();
+        let page_id = pager.append_empty_uninited_page().unwrap().id();
+        // Evict the page, so that it is read back from the store.
+        pager.append_empty_uninited_page().unwrap();
+
+        let page = pager.get_page(page_id).unwrap();
+        assert!(page.buf().iter().all(|b| *b == 0));
+        let err = crate::btree::node::get_node_type(&page).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Corrupt);
+    }
+
+    #[test]
+    fn it_never_appends_pages_in_read_only_mode() {
+        let directory_path = Path::new("/tmp/waste-land/");
+        fs::create_dir_all(directory_path).unwrap();
+        let file_path = directory_path.join("it-never-appends-pages-in-read-only-mode.pager");
+        {
+            let file = File::create(&file_path).unwrap();
+            let mut pager = Pager::new(file, DEFAULT_PAGE_SIZE).unwrap();
+            pager.append_empty_uninited_page().unwrap();
+        }
+
+        let mut pager = Pager::read_only(File::open(&file_path).unwrap(), DEFAULT_PAGE_SIZE).unwrap();
+        assert!(pager.append_empty_uninited_page().is_err());
+        let mut page = pager.get_page(PageId::new(0)).unwrap();
+        page.make_dirty();
+        assert!(pager.sync_page(&mut page).is_err());
+        assert_eq!(pager.len(), 1);
+    }
+
+    #[test]
+    fn it_keeps_synced_pages_dirty_in_transactions() {
+        let directory_path = Path::new("/tmp/waste-land/");
+        fs::create_dir_all(directory_path).unwrap();
+        let file_path = directory_path.join("it-keeps-synced-pages-dirty-in-transactions.pager");
+        let file = File::options()
+            .write(true)
+            .read(true)
+            .create(true)
+            .truncate(true)
+            .open(&file_path)
+            .unwrap();
+
+        let mut pager = Pager::new(file, DEFAULT_PAGE_SIZE).unwrap();
+        let mut page = pager.append_empty_uninited_page().unwrap();
+        pager.begin();
+        pager.begin();
+        unsafe { page.mut_buf()[0] = 42 };
+        page.make_dirty();
+        pager.sync_page(&mut page).unwrap();
+        pager.commit().unwrap();
+        assert!(page.is_dirty());
+        assert_eq!(fs::read(&file_path).unwrap()[0], 0);
+
+        pager.commit().unwrap();
+        assert!(!page.is_dirty());
+        assert_eq!(fs::read(&file_path).unwrap()[0], 42);
+
+        // The aborted changes are read from the file again.
+        pager.begin();
+        unsafe { page.mut_buf()[0] = 43 };
+        page.make_dirty();
+        pager.sync_page(&mut page).unwrap();
+        pager.abort().unwrap();
+        assert!(!page.is_dirty());
+        assert_eq!(page.buf()[0], 42);
+        assert_eq!(fs::read(&file_path).unwrap()[0], 42);
+    }
+
+    #[test]
+    fn it_defers_writes_of_pages() {
+        let store = MemStore::new();
+        let read_store = |id: usize| {
+            let mut buf = [0u8];
+            let offset = page_id_to_offset(PageId::new(id), DEFAULT_PAGE_SIZE);
+            store.read_exact_at(&mut buf, offset).unwrap();
+            buf[0]
+        };
+        let write_page = |pager: &mut Pager, id: usize, value: u8| {
+            let mut page = pager.get_page(PageId::new(id)).unwrap();
+            unsafe { page.mut_buf()[0] = value };
+            page.make_dirty();
+            pager.sync_page(&mut page).unwrap();
+        };
+
+        let mut pager = Pager::with_capacity(store.clone(), DEFAULT_PAGE_SIZE, 1).unwrap();
+        pager.set_deferred(true).unwrap();
+        pager.append_empty_uninited_page().unwrap();
+        write_page(&mut pager, 0, 42);
+        // Evict the page, so that it is read back from the deferred pages.
+        pager.append_empty_uninited_page().unwrap();
+        assert_eq!(read_store(0), 0);
+        assert_eq!(pager.get_page(PageId::new(0)).unwrap().buf()[0], 42);
+
+        // Only the pages staged are written.
+        pager.stage_deferred();
+        write_page(&mut pager, 1, 43);
+        pager.sync_staged().unwrap();
+        assert_eq!((read_store(0), read_store(1)), (42, 0));
+        assert_eq!(pager.get_page(PageId::new(1)).unwrap().buf()[0], 43);
+
+        pager.set_deferred(false).unwrap();
+        assert_eq!(read_store(1), 43);
+    }
+
+    #[test]
+    fn it_replays_the_wal_after_a_crash() {
+        let directory_path = Path::new("/tmp/waste-land/");
+        fs::create_dir_all(directory_path).unwrap();
+        let file_path = directory_path.join("it-replays-the-wal-after-a-crash.pager");
+        let wal_path = directory_path.join("it-replays-the-wal-after-a-crash.pager.wal");
+        let open_file = |path: &Path, truncate: bool| {
+            File::options()
+                .write(true)
+                .read(true)
+                .create(true)
+                .truncate(truncate)
+                .open(path)
+                .unwrap()
+        };
+        let open_pager = |truncate: bool| {
+            let file = open_file(&file_path, truncate);
+            Pager::with_wal(file, open_file(&wal_path, truncate), DEFAULT_PAGE_SIZE).unwrap()
+        };
+        let write_pages = |pager: &mut Pager, value: u8| {
+            pager.begin();
+            for i in 0..3 {
+                let mut page = pager.get_page(PageId::new(i)).unwrap();
+                unsafe { page.mut_buf()[0] = value };
+                page.make_dirty();
+                pager.sync_page(&mut page).unwrap();
+            }
+        };
+        let read_pages = |pager: &Pager| -> Vec<u8> {
+            (0..3).map(|i| pager.get_page(PageId::new(i)).unwrap().buf()[0]).collect()
+        };
+
+        {
+            let mut pager = open_pager(true);
+            for _ in 0..3 {
+                pager.append_empty_uninited_page().unwrap();
+            }
+            write_pages(&mut pager, 1);
+            pager.commit().unwrap();
+        }
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), 0);
+
+        // Crash before the WAL is written: nothing is changed.
+        {
+            let mut pager = open_pager(false);
+            assert_eq!(read_pages(&pager), [1, 1, 1]);
+            write_pages(&mut pager, 2);
+        }
+        assert_eq!(read_pages(&open_pager(false)), [1, 1, 1]);
+
+        // Crash after the WAL is written, while only a page is written to the
+        // file: the pages in the WAL are written again.
+        {
+            let mut pager = open_pager(false);
+            write_pages(&mut pager, 3);
+            let mut inner = pager.inner.write().unwrap();
+            let mut pages: Vec<Page> = inner.transaction_pages.values().cloned().collect();
+            let bufs: Vec<_> = pages.iter().map(|p| (p.id(), p.buf())).collect();
+            inner.write_wal(&bufs).unwrap();
+            inner.write_page(&mut pages[0]).unwrap();
+        }
+        assert!(fs::metadata(&wal_path).unwrap().len() > 0);
+        let pager = open_pager(false);
+        assert_eq!(read_pages(&pager), [3, 3, 3]);
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), 0);
+        drop(pager);
+
+        // A half-written WAL is ignored.
+        {
+            let mut pager = open_pager(false);
+            write_pages(&mut pager, 4);
+            let mut inner = pager.inner.write().unwrap();
+            let pages: Vec<Page> = inner.transaction_pages.values().cloned().collect();
+            let bufs: Vec<_> = pages.iter().map(|p| (p.id(), p.buf())).collect();
+            inner.write_wal(&bufs).unwrap();
+            let wal = inner.wal.as_ref().unwrap();
+            wal.set_len(wal.len().unwrap() - 1).unwrap();
+        }
+        assert_eq!(read_pages(&open_pager(false)), [3, 3, 3]);
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn it_flushes_dirty_pages_when_dropped() {
+        let directory_path = Path::new("/tmp/waste-land/");
+        fs::create_dir_all(directory_path).unwrap();
+        let file_path = directory_path.join("it-flushes-dirty-pages-when-dropped.pager");
+        let open_file = || {
+            File::options()
+                .write(true)
+                .read(true)
+                .create(true)
+                .truncate(false)
+                .open(&file_path)
+                .unwrap()
+        };
+        if file_path.exists() {
+            fs::remove_file(&file_path).unwrap();
+        }
+
+        {
+            let mut pager = Pager::new(open_file(), DEFAULT_PAGE_SIZE).unwrap();
+            for i in 0..4 {
+                let mut page = pager.append_empty_uninited_page().unwrap();
+                unsafe { page.mut_buf()[0] = i as u8 };
+                page.make_dirty();
+            }
+        }
+
+        let pager = Pager::new(open_file(), DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(pager.len(), 4);
+        for i in 0..4 {
+            assert_eq!(pager.get_page(PageId::new(i)).unwrap().buf()[0], i as u8);
+        }
+    }
 }
@@ -0,0 +1,30 @@
+use std::fmt::Debug;
+
+use crate::{hash::Hash, offset::Offset};
+
+use super::page::PageId;
+
+/// The plain old data which can be a key or a value of `BPlusTree`. It is
+/// stored in the pages just as its bytes in memory, and read back in place.
+///
+/// # Safety
+///
+/// - Any bytes written by a value of the type must be a valid value of it
+///   when they are read back - maybe by another process. So there must be no
+///   pointer, reference or anything owning memory in it.
+/// - Its alignment must be at most 8, the alignment of the pages.
+pub unsafe trait Pod: Copy + Debug + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+unsafe impl Pod for Hash {}
+unsafe impl Pod for Offset {}
+unsafe impl Pod for PageId {}
@@ -0,0 +1,90 @@
+use std::io::{self, ErrorKind, Read, Write};
+
+/// The size of the CRC32 checksum stored after each record.
+pub const CHECKSUM_SIZE: u64 = 4;
+
+/// A writer which computes the checksum of all bytes written through it.
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, hasher: crc32fast::Hasher::new() }
+    }
+
+    /// The checksum of all bytes written.
+    pub fn checksum(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader which yields exactly `len` bytes from the inner reader, and then
+/// checks them against the checksum just after them. An error of
+/// `ErrorKind::InvalidData` is returned instead of EOF if they mismatch.
+pub struct ChecksumReader<R: Read> {
+    inner: io::Take<R>,
+    hasher: crc32fast::Hasher,
+    checked: bool,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub fn new(inner: R, len: u64) -> Self {
+        Self { inner: inner.take(len), hasher: crc32fast::Hasher::new(), checked: false }
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        if n == 0 && !buf.is_empty() && !self.checked {
+            if self.inner.limit() != 0 {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "waste is truncated"));
+            }
+            let mut checksum = [0u8; CHECKSUM_SIZE as usize];
+            self.inner.get_mut().read_exact(&mut checksum)?;
+            if u32::from_le_bytes(checksum) != self.hasher.clone().finalize() {
+                return Err(io::Error::new(ErrorKind::InvalidData, "checksum mismatch"));
+            }
+            self.checked = true;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let mut writer = ChecksumWriter::new(vec![]);
+        writer.write_all(b"hello world").unwrap();
+        let checksum = writer.checksum();
+        assert_eq!(checksum, crc32fast::hash(b"hello world"));
+
+        let mut stored = writer.inner.clone();
+        stored.extend_from_slice(&checksum.to_le_bytes());
+        let mut content = vec![];
+        ChecksumReader::new(stored.as_slice(), 11).read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"hello world");
+
+        stored[0] ^= 1;
+        let err = ChecksumReader::new(stored.as_slice(), 11).read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,36 @@
+use crate::error::{Error, ErrorKind};
+
+/// How the wastes are compressed in the data file. Each record has a one-byte
+/// flag of its own mode, so records of different modes can coexist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionMode {
+    /// Store the raw bytes.
+    #[default]
+    None,
+    /// Compress the bytes by Zstandard.
+    Zstd,
+}
+
+impl CompressionMode {
+    /// The level of Zstandard - 0 means the default level of zstd.
+    pub(crate) const ZSTD_LEVEL: i32 = 0;
+
+    /// The flag stored before the record.
+    pub(crate) fn to_flag(self) -> u8 {
+        match self {
+            CompressionMode::None => 0,
+            CompressionMode::Zstd => 1,
+        }
+    }
+
+    pub(crate) fn from_flag(flag: u8) -> Result<Self, Error> {
+        match flag {
+            0 => Ok(CompressionMode::None),
+            1 => Ok(CompressionMode::Zstd),
+            _ => Err(Error::with_kind(
+                ErrorKind::Corrupt,
+                format!("unknown compression flag {}", flag),
+            )),
+        }
+    }
+}
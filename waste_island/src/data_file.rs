@@ -0,0 +1,191 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::store::BlockStore;
+
+/// A shard of the data file, which is maybe preallocated: grown by a chunk of
+/// bytes at once when it runs out, instead of a write at a time. See
+/// `Database::with_preallocation`.
+///
+/// The preallocated file is longer than the data in it, so the end of the
+/// data is kept in a file of its own - `data.end` for `data` - which is
+/// updated once a record is written, see `BlockStore::publish`. Then `len` is
+/// the end of the data, and nothing beyond it is read.
+///
+/// The end and the length of the file are kept in memory once it is opened
+/// in write-read mode, so a write costs no more syscalls than the write
+/// itself unless it runs out of the bytes preallocated.
+pub struct DataFile {
+    file: File,
+    /// The file of the end of the data. It is `None` if the data file has
+    /// never been preallocated, and then its end is just its length.
+    end_file: Option<File>,
+    /// How many bytes the file grows by at least when it runs out, if it is
+    /// preallocated now.
+    chunk: Option<u64>,
+    /// The end of the data. It is `None` in read-only mode, where the data
+    /// file is maybe appended to by its writer, so the end is read from its
+    /// file each time.
+    end: Option<AtomicU64>,
+    /// The length of the file, which is beyond the end if it is preallocated.
+    file_len: AtomicU64,
+}
+
+impl DataFile {
+    /// Open or create the data file in write-read mode. It is preallocated by
+    /// `chunk` bytes at once from now on if it is given.
+    pub fn open(path: &Path, chunk: Option<u64>) -> io::Result<Self> {
+        let file = File::options()
+            .write(true)
+            .read(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let end_path = Self::end_path(path);
+        let end_file = match File::options().write(true).read(true).open(&end_path) {
+            Ok(end_file) => Some(end_file),
+            Err(e) if e.kind() == ErrorKind::NotFound && chunk.is_some() => {
+                // Nothing is preallocated yet, so all bytes of it are data.
+                let end_file = File::options()
+                    .write(true)
+                    .read(true)
+                    .create_new(true)
+                    .open(&end_path)?;
+                write_end(&end_file, file.metadata()?.len())?;
+                Some(end_file)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let file_len = file.metadata()?.len();
+        let end = match &end_file {
+            Some(end_file) => read_end(end_file, &file)?,
+            None => file_len,
+        };
+        Ok(Self {
+            file,
+            end_file,
+            chunk,
+            end: Some(AtomicU64::new(end)),
+            file_len: AtomicU64::new(file_len),
+        })
+    }
+
+    /// Open the data file in read-only mode.
+    pub fn open_read_only(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let end_file = match File::open(Self::end_path(path)) {
+            Ok(end_file) => Some(end_file),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let file_len = AtomicU64::new(file.metadata()?.len());
+        Ok(Self { file, end_file, chunk: None, end: None, file_len })
+    }
+
+    /// The path of the file of the end of the data file at `path`.
+    pub fn end_path(path: &Path) -> PathBuf {
+        let mut end_path = path.as_os_str().to_owned();
+        end_path.push(".end");
+        PathBuf::from(end_path)
+    }
+}
+
+impl BlockStore for DataFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if self.end_file.is_none() {
+            return self.file.read_at(buf, offset);
+        }
+        let end = self.len()?;
+        if offset >= end {
+            return Ok(0);
+        }
+        let n = buf.len().min((end - offset) as usize);
+        self.file.read_at(&mut buf[..n], offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let end = offset + buf.len() as u64;
+        if let Some(chunk) = self.chunk {
+            let file_len = self.file_len.load(Ordering::SeqCst);
+            if end > file_len {
+                let file_len = end.max(file_len + chunk);
+                self.file.set_len(file_len)?;
+                self.file_len.store(file_len, Ordering::SeqCst);
+            }
+        }
+        let n = self.file.write_at(buf, offset)?;
+        // The end is moved only after the bytes are written, so it never
+        // covers bytes which are not written yet.
+        if let Some(end) = &self.end {
+            end.fetch_max(offset + n as u64, Ordering::SeqCst);
+        }
+        Ok(n)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        match (&self.end, &self.end_file) {
+            (Some(end), _) => Ok(end.load(Ordering::SeqCst)),
+            (None, Some(end_file)) => read_end(end_file, &self.file),
+            (None, None) => self.file.len(),
+        }
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)?;
+        self.file_len.store(len, Ordering::SeqCst);
+        if let Some(end) = &self.end {
+            end.store(len, Ordering::SeqCst);
+        }
+        if let Some(end_file) = &self.end_file {
+            write_end(end_file, len)?;
+        }
+        Ok(())
+    }
+
+    fn publish(&self) -> io::Result<()> {
+        match (&self.end, &self.end_file) {
+            (Some(end), Some(end_file)) => write_end(end_file, end.load(Ordering::SeqCst)),
+            _ => Ok(()),
+        }
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.publish()?;
+        self.file.sync_data()?;
+        if let Some(end_file) = &self.end_file {
+            end_file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// The end is published once the data file is closed too, so the bytes left
+/// by a record failed to be written are still counted as garbage after the
+/// data file is opened again, instead of being written over.
+impl Drop for DataFile {
+    fn drop(&mut self) {
+        let _ = self.publish();
+    }
+}
+
+/// Read the end of the data from its file.
+fn read_end(end_file: &File, file: &File) -> io::Result<u64> {
+    let mut end = [0u8; 8];
+    match end_file.read_exact_at(&mut end, 0) {
+        Ok(()) => Ok(u64::from_le_bytes(end)),
+        // The end file is created but not written yet by a crash, so nothing
+        // is preallocated yet.
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(file.metadata()?.len()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write the end of the data into its file.
+fn write_end(end_file: &File, end: u64) -> io::Result<()> {
+    end_file.write_all_at(&end.to_le_bytes(), 0)
+}
@@ -1,171 +1,4883 @@
 use std::{
-    path::{PathBuf, Path}, fs, io::{Seek, Write, SeekFrom, Read}, num::NonZeroUsize,
+    path::{PathBuf, Path}, fs, io::{self, Seek, Write, Read}, num::NonZeroUsize,
+    marker::PhantomData, borrow::Cow, time::{Duration, Instant},
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex},
+    collections::{BTreeMap, HashMap},
 };
 
 use lru::LruCache;
-use sha256::digest;
 
-use crate::{indexer::Indexer, Error, error::ToInnerResult, offset::Offset};
+use crate::{
+    indexer::{Hashes, Indexer, Records}, names::Names, offset::Offset, Error, error::{ErrorKind, ToInnerResult},
+    labels::{Labels, MAX_LABEL_LEN}, flusher::Flusher,
+    hash::{Hash, HASH_SIZE},
+    btree::{
+        HEAD_NODE_VERSION, DEFAULT_PAGE_SIZE, NEVER_EXPIRES, NodeDump, is_valid_page_size,
+        now_millis,
+    },
+    hasher::{self, WasteHasher, Sha256}, compression::CompressionMode, sync_policy::SyncPolicy,
+    checksum::{ChecksumReader, ChecksumWriter, CHECKSUM_SIZE},
+    varint::{self, VARINT_MAX_LEN}, store::BlockStore, data_file::DataFile, uuid::Uuid,
+};
+
+const VERSION_MAGIC: &str = "skogkatt.org/WasteIsland";
+
+/// The version of archives by `Database::export`. It is bumped only if the
+/// layout of archives changes - never for the format of the database.
+///
+/// - 1: The first version.
+/// - 2: Records have the expiry time after the reference count.
+const ARCHIVE_VERSION: u32 = 2;
+
+/// The max count of shards of the data file - one for each first byte of
+/// hashes.
+const MAX_SHARDS: usize = 256;
+
+/// The diagnostics of a database. See `Database::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatabaseStats {
+    /// The length of the data file.
+    pub data_bytes: u64,
+    /// The length of the index file.
+    pub index_bytes: u64,
+    /// The count of distinct wastes stored.
+    pub keys: usize,
+    /// The height of the index's B+tree - 1 if the root is a leaf.
+    pub index_height: usize,
+    /// The count of pages in the index file.
+    pub index_pages: usize,
+    /// How many times a page of the index was found in the cache, since the
+    /// database is opened.
+    pub index_cache_hits: u64,
+    /// How many times a page of the index was read from the index file.
+    pub index_cache_misses: u64,
+    /// How many pages of the index were evicted from the cache.
+    pub index_cache_evictions: u64,
+    /// How many bytes of the data file are not referenced by the index any
+    /// more - the records of the wastes deleted, expired or put again, and the
+    /// bytes left by a failed `put_reader`. They are reclaimed by `compact`,
+    /// which resets it to 0.
+    pub garbage_bytes: u64,
+}
+
+/// The record of a waste in the data file. See `Database::stat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasteStat {
+    /// The shard of the data file holding the record - always 0 if the data
+    /// file is not sharded.
+    pub shard: usize,
+    /// The offset of the record in its shard of the data file.
+    pub offset: u64,
+    /// The length of the content stored - the compressed one if it is
+    /// compressed.
+    pub len: u64,
+    /// How the content is compressed.
+    pub compression: CompressionMode,
+}
+
+/// The node read in a page of the index by `Database::dump_page`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageDump {
+    /// The head node of the index, with the page ID of the root.
+    Head { root: u32 },
+    /// A leaf, with its records in ascending order of hashes and the page ID
+    /// of the next leaf, which is `None` for the last leaf.
+    Leaf { records: Vec<IndexRecord>, next_leaf: Option<u32> },
+    /// An internal node, with `(separator, child)` in ascending order of
+    /// separators, and the child for the hashes greater than all separators.
+    /// The hashes under a child are at most its separator, and greater than
+    /// the separator before it.
+    Internal { records: Vec<(String, u32)>, rightest_child: u32 },
+}
+
+/// A record of a waste in a leaf of the index. See `PageDump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexRecord {
+    /// The hash of the waste.
+    pub hash: String,
+    /// The shard of the data file where the waste is stored.
+    pub shard: usize,
+    /// The offset of its record in the shard.
+    pub offset: u64,
+    /// How many times it is put and not deleted yet.
+    pub refcount: u32,
+    /// When it expires, in milliseconds since the Unix epoch - `None` if it
+    /// never expires. See `Database::put_with_ttl`.
+    pub expires_at: Option<u64>,
+}
+
+/// What `Database::verify_all` found. All lists are in ascending order of
+/// hashes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// How many wastes are indexed and checked.
+    pub checked: usize,
+    /// The wastes whose content does not hash to their hashes any more.
+    pub mismatched: Vec<String>,
+    /// The wastes whose offsets point past the end of the data file.
+    pub out_of_bounds: Vec<String>,
+    /// The wastes whose records cannot be read - for example, their lengths
+    /// are corrupt or their compressed content cannot be decompressed.
+    pub unreadable: Vec<String>,
+    /// The errors of the pages of the index which cannot be read. The wastes
+    /// indexed in them are never checked.
+    pub unreadable_pages: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Is nothing wrong found?
+    pub fn is_ok(&self) -> bool {
+        self.mismatched.is_empty()
+            && self.out_of_bounds.is_empty()
+            && self.unreadable.is_empty()
+            && self.unreadable_pages.is_empty()
+    }
+}
+
+/// How many records are indexed at once while compacting or importing.
+const COMPACT_BATCH_LEN: usize = 1024;
+
+/// What `Database::compact` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactStats {
+    /// The length of the data file before compacting.
+    pub bytes_before: u64,
+    /// The length of the data file after compacting.
+    pub bytes_after: u64,
+    /// How many records are still referenced, and copied.
+    pub records_kept: u64,
+    /// How many records are not referenced any more, and dropped.
+    pub records_dropped: u64,
+}
+
+/// The iterator through all wastes' hashes of the database lazily, in
+/// ascending order. See `Database::iter`.
+pub struct DatabaseIter<'a> {
+    hashes: Hashes<'a>,
+}
+
+/// The layout of a database, recorded in its `version` file.
+struct Layout {
+    page_size: usize,
+    shards: usize,
+    /// The UUID of the database, which its index has as well.
+    uuid: Uuid,
+    /// The directory of the data file if it is not the directory of the
+    /// database, see `Database::new_split`.
+    data_dir: Option<PathBuf>,
+}
+
+/// The iterator through all wastes' hashes and contents of the database
+/// lazily, in ascending order of hashes. See `Database::iter_values`.
+pub struct DatabaseValues<'a, H: WasteHasher = Sha256> {
+    database: &'a Database<H>,
+    records: Records<'a>,
+}
+
+/// The database of wastes, whose hashes are generated by `H`.
+///
+/// All reads take `&self` and never move the cursor of the data file, so the
+/// database is `Send + Sync` - many threads can read it at once, for example
+/// behind an `RwLock`, while `put` and `delete` still take `&mut self`.
+///
+/// A waste is always appended to the data file before it is indexed - and if
+/// the writes are synced by the `SyncPolicy`, the data file is synced before
+/// the index is written. So the index never references a record which is not
+/// on the disk: a crash between them leaves an orphaned record only, which is
+/// reclaimed by `compact`.
+pub struct Database<H: WasteHasher = Sha256> {
+    hasher: PhantomData<H>,
+    /// The directory of the database, holding its `version` file and the
+    /// index. It is `None` if the database is in stores instead, see `new_in`.
+    path: Option<PathBuf>,
+    /// The directory of the data file if it is not `path`, see `new_split`.
+    data_dir: Option<PathBuf>,
+    /// The shards of the data file - only one if it is not sharded. See
+    /// `with_shards`.
+    data: Vec<Arc<dyn BlockStore>>,
+    indexer: Indexer,
+    /// The keyspace of names. It is `None` if the database is in stores, or
+    /// opened in read-only mode and no name is ever put.
+    names: Option<Names>,
+    /// The labels of wastes, see `set_label`. It is `None` the same way as
+    /// `names`.
+    labels: Option<Labels>,
+    cache: Mutex<LruCache<String, Vec<u8>>>,
+    compression: CompressionMode,
+    sync_policy: SyncPolicy,
+    max_value_size: Option<u64>,
+    /// How many bytes the data file is grown by at least. See
+    /// `with_preallocation`.
+    preallocation: Option<u64>,
+    last_sync: Instant,
+    /// The background thread syncing the database, if the policy is
+    /// `SyncPolicy::Background`.
+    flusher: Option<Flusher>,
+    read_only: bool,
+    /// The memory maps of the data file's shards. See `with_mmap`.
+    #[cfg(feature = "memmap2")]
+    map: Option<Vec<memmap2::Mmap>>,
+}
+
+// Make sure `Database` is always shareable across threads.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Database<Sha256>>();
+    assert_send_sync::<Database<hasher::Blake3>>();
+};
+
+impl Database {
+    /// Gen the waste hash from the content of data.
+    pub fn gen_waste_hash(data: &[u8]) -> String {
+        Sha256::hex_digest(data)
+    }
+
+    /// Create or open a new database at the given path, with the default hash
+    /// algorithm SHA-256. See `with_hasher` as well.
+    /// 
+    /// An error will be raised if the path is a non-empty folder but not a
+    /// database, as attemping to create a new database in it may mess the
+    /// folder up.
+    pub fn new<P>(database_path: P) -> Result<Database, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_hasher(database_path)
+    }
+
+    /// Open an existing database at the given path, with the default hash
+    /// algorithm SHA-256. Unlike `new`, nothing is created: an error of
+    /// `NotFound` is raised if there is no database. See
+    /// `with_hasher_existing` as well.
+    pub fn open<P>(database_path: P) -> Result<Database, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_hasher_existing(database_path)
+    }
+
+    /// Create or open a new database whose data file is in `data_dir` and
+    /// whose index is in `index_dir` - for example, to keep the index on a
+    /// faster disk - with the default hash algorithm SHA-256. See
+    /// `with_hasher_split` as well.
+    pub fn new_split<P, Q>(data_dir: P, index_dir: Q) -> Result<Database, Error>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        Self::with_hasher_split(data_dir, index_dir)
+    }
+
+    /// Open an existing database at the given path in read-only mode, with the
+    /// default hash algorithm SHA-256. See `with_hasher_read_only` as well.
+    pub fn open_read_only<P>(database_path: P) -> Result<Database, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_hasher_read_only(database_path)
+    }
+
+    /// Create or open a new database in the stores instead of a directory,
+    /// with the default hash algorithm SHA-256 - for example, in `MemStore`s
+    /// so that tests never touch the filesystem. See `with_stores` as well.
+    pub fn new_in<D, I>(data: D, index: I) -> Result<Database, Error>
+    where
+        D: BlockStore + 'static,
+        I: BlockStore + 'static,
+    {
+        Self::with_stores(data, index, DEFAULT_PAGE_SIZE)
+    }
+
+    /// Create or open a new database at the given path, with the default hash
+    /// algorithm SHA-256, and memory-map its data file. See `with_mmap` as
+    /// well.
+    #[cfg(feature = "memmap2")]
+    pub fn open_mmap<P>(database_path: P) -> Result<Database, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new(database_path)?.with_mmap()
+    }
+
+    /// Rebuild the index of the database at the given path from its data
+    /// file, with the default hash algorithm SHA-256. See
+    /// `rebuild_index_with_hasher` as well.
+    pub fn rebuild_index<P>(database_path: P) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::rebuild_index_with_hasher(database_path)
+    }
+}
+
+impl<H: WasteHasher> Database<H> {
+    /// Open or create all shards of the data file in write-read mode.
+    /// Each of them is preallocated by `preallocation` bytes at once if it is
+    /// given, see `with_preallocation`.
+    fn open_data(
+        database_path: &Path,
+        shards: usize,
+        preallocation: Option<u64>,
+    ) -> Result<Vec<Arc<dyn BlockStore>>, Error> {
+        (0..shards)
+            .map(|shard| {
+                let file = DataFile::open(
+                    &database_path.join(data_name(shard, shards)),
+                    preallocation,
+                )
+                .to_inner_result("open data file in write-read mode")?;
+                Ok(Arc::new(file) as Arc<dyn BlockStore>)
+            })
+            .collect()
+    }
+
+    /// The content of the `version` file: everything which changes the format
+    /// of the database on the first line, and then the UUID of the database
+    /// and the directory of the data file - if it is not the directory of the
+    /// database - on lines of their own.
+    fn version(layout: &Layout) -> String {
+        let mut version = Self::format_version(layout.page_size, layout.shards);
+        version.push_str(&format!("uuid={}\n", layout.uuid));
+        if let Some(data_dir) = &layout.data_dir {
+            version.push_str(&format!("data_dir={}\n", data_dir.display()));
+        }
+        version
+    }
+
+    /// The first line of the `version` file. See `version`.
+    fn format_version(page_size: usize, shards: usize) -> String {
+        format!(
+            "{} version={} hash={} page_size={} shards={}\n",
+            VERSION_MAGIC, HEAD_NODE_VERSION, H::NAME, page_size, shards,
+        )
+    }
+
+    /// Write the `version` file if the database is just created, or check it
+    /// otherwise. Return the layout of the database, which is by the given
+    /// page size, count of shards and directory of the data file only if it
+    /// is just created - but the data file of an existing database must be
+    /// in `data_dir` if it is given.
+    ///
+    /// A folder without the `version` file is a new database only if it holds
+    /// nothing but the files of a database, see `check_database_dir`.
+    fn check_version(
+        database_path: &Path,
+        data_dir: Option<&Path>,
+        page_size: usize,
+        shards: usize,
+    ) -> Result<Layout, Error> {
+        match Self::read_version(database_path)? {
+            Some(layout) => {
+                if data_dir.is_some_and(|dir| layout.data_dir.as_deref() != Some(dir)) {
+                    return Err(Error::with_kind(
+                        ErrorKind::Unsupported,
+                        format!("the data file of the database is not in {:?}", data_dir.unwrap()),
+                    ));
+                }
+                Ok(layout)
+            }
+            None => {
+                check_database_dir(database_path)?;
+                if let Some(data_dir) = data_dir {
+                    check_database_dir(data_dir)?;
+                }
+                let layout = Layout {
+                    page_size,
+                    shards,
+                    uuid: Uuid::new_v4(),
+                    data_dir: data_dir.map(Path::to_path_buf),
+                };
+                fs::write(database_path.join("version"), Self::version(&layout))
+                    .to_inner_result("write version file")?;
+                Ok(layout)
+            }
+        }
+    }
+
+    /// Check the `version` file and return the layout of the database, or
+    /// `None` if there is no `version` file.
+    fn read_version(database_path: &Path) -> Result<Option<Layout>, Error> {
+        let version = match fs::read_to_string(database_path.join("version")) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).to_inner_result("read version file"),
+        };
+        let unsupported = || Error::with_kind(ErrorKind::Unsupported, "unsupported version");
+        let (format, lines) = version.split_at(version.find('\n').map_or(0, |i| i + 1));
+        let field = |name: &str| -> Option<usize> {
+            format.split_whitespace().find_map(|f| f.strip_prefix(name))?.parse().ok()
+        };
+        let (page_size, shards) = match (field("page_size="), field("shards=")) {
+            (Some(size), Some(shards)) if format == Self::format_version(size, shards) => {
+                (size, shards)
+            }
+            _ => return Err(unsupported()),
+        };
+
+        let (mut uuid, mut data_dir) = (None, None);
+        if !lines.is_empty() && !lines.ends_with('\n') {
+            return Err(unsupported());
+        }
+        for line in lines.lines() {
+            match line.split_once('=') {
+                Some(("uuid", value)) => {
+                    uuid = Some(Uuid::from_str(value).ok_or_else(unsupported)?);
+                }
+                Some(("data_dir", value)) => data_dir = Some(PathBuf::from(value)),
+                _ => return Err(unsupported()),
+            }
+        }
+        let uuid = uuid.ok_or_else(unsupported)?;
+        Ok(Some(Layout { page_size, shards, uuid, data_dir }))
+    }
+
+    /// Check the index belongs to the database of the UUID. A new index - for
+    /// example, the one rebuilt by `rebuild_index` - has the nil UUID, and it
+    /// is taken as the database's own if it is writable.
+    fn check_uuid(indexer: &mut Indexer, uuid: Uuid, writable: bool) -> Result<(), Error> {
+        let index_uuid = indexer.uuid();
+        if index_uuid == uuid {
+            return Ok(());
+        }
+        if !index_uuid.is_nil() {
+            return Err(Error::new("index does not belong to this database"));
+        }
+        if writable {
+            indexer.set_uuid(uuid)?;
+        }
+        Ok(())
+    }
+
+    /// Create or open a new database at the given path, whose hashes are
+    /// generated by `H`.
+    ///
+    /// An error will be raised if the database was created with another hash
+    /// algorithm.
+    pub fn with_hasher<P>(database_path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_page_size(database_path, DEFAULT_PAGE_SIZE)
+    }
+
+    /// Open an existing database at the given path, whose hashes are generated
+    /// by `H`. An error of `NotFound` is raised if there is no `version` file,
+    /// instead of creating a new database - so a mistyped path is caught.
+    pub fn with_hasher_existing<P>(database_path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let database_path = database_path.as_ref();
+        if Self::read_version(database_path)?.is_none() {
+            return Err(Error::with_kind(
+                ErrorKind::NotFound,
+                format!("no database at {:?}", database_path),
+            ));
+        }
+        Self::with_hasher(database_path)
+    }
+
+    /// Create or open a new database whose hashes are generated by `H`, with
+    /// its data file in `data_dir` and everything else - the `version` file,
+    /// the index and the names - in `index_dir`.
+    ///
+    /// The absolute path of `data_dir` is recorded in the `version` file, so
+    /// the database can be opened again by `index_dir` alone, by `with_hasher`
+    /// and the others. An error will be raised if an existing database has its
+    /// data file in another directory.
+    pub fn with_hasher_split<P, Q>(data_dir: P, index_dir: Q) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let data_dir = data_dir.as_ref();
+        fs::create_dir_all(data_dir)
+            .to_inner_result(&format!("create data directory {:?}", data_dir))?;
+        let data_dir = fs::canonicalize(data_dir).to_inner_result("get absolute data directory")?;
+        // It is one line of the `version` file.
+        if data_dir.to_str().is_none_or(|dir| dir.contains('\n')) {
+            return Err(Error::with_kind(
+                ErrorKind::Unsupported,
+                format!("the data directory {:?} is not supported", data_dir),
+            ));
+        }
+        Self::open_dirs(index_dir.as_ref(), Some(&data_dir), DEFAULT_PAGE_SIZE, 1)
+    }
+
+    /// Create a new database at the given path from the archive by `export`,
+    /// whose hashes are generated by `H` - for example, to restore a backup or
+    /// to migrate the database to the current format. See `import`.
+    pub fn with_archive<P, R>(database_path: P, input: R) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+        R: Read,
+    {
+        let mut database = Self::with_hasher(database_path)?;
+        database.import(input)?;
+        Ok(database)
+    }
+
+    /// Create or open a new database at the given path, whose hashes are
+    /// generated by `H`. A new database has index pages of `page_size` bytes -
+    /// a power of two between 1 KB and 64 KB.
+    ///
+    /// An existing database is always opened with its own page size.
+    pub fn with_page_size<P>(database_path: P, page_size: usize) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_shards(database_path, page_size, 1)
+    }
+
+    /// Create or open a new database at the given path as `with_page_size`
+    /// does, but the data file of a new database is split into `shards` files:
+    /// `data.000`, `data.001` and so on, at most 256 ones. Each waste is put
+    /// into the shard chosen by the first byte of its hash, so that every
+    /// file is smaller, for backups and the limits of filesystems.
+    ///
+    /// An existing database is always opened with its own count of shards.
+    pub fn with_shards<P>(database_path: P, page_size: usize, shards: usize) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::check_options(page_size)?;
+        if !(1..=MAX_SHARDS).contains(&shards) {
+            return Err(Error::with_kind(
+                ErrorKind::Unsupported,
+                format!("the count of shards {} is not supported", shards),
+            ));
+        }
+        Self::open_dirs(database_path.as_ref(), None, page_size, shards)
+    }
+
+    /// Create or open a new database at the given path as `with_shards` does,
+    /// with the data file of a new database in `data_dir` if it is given. See
+    /// `with_hasher_split`.
+    fn open_dirs(
+        database_path: &Path,
+        data_dir: Option<&Path>,
+        page_size: usize,
+        shards: usize,
+    ) -> Result<Self, Error> {
+        let database_path = PathBuf::from(database_path);
+
+        fs::create_dir_all(&database_path)
+            .to_inner_result(&format!("create database directory {:?}", database_path))?;
+        let layout = Self::check_version(&database_path, data_dir, page_size, shards)?;
+        let data_path = layout.data_dir.as_deref().unwrap_or(&database_path);
+        Self::recover_compaction(&database_path, data_path, layout.shards)?;
+
+        let data = Self::open_data(data_path, layout.shards, None)
+            .to_inner_result("open data file")?;
+        let mut indexer = Indexer::open(&database_path, H::NAME, layout.page_size)
+            .to_inner_result("open indexer")?;
+        Self::check_uuid(&mut indexer, layout.uuid, true)?;
+        let names = Names::open(&database_path, H::NAME, layout.page_size)?;
+        let labels = Labels::open(&database_path, H::NAME, layout.page_size)?;
+        let mut database =
+            Self::with_parts(Some(database_path), data, indexer, Some(names), false);
+        database.labels = Some(labels);
+        database.data_dir = layout.data_dir;
+        Ok(database)
+    }
+
+    /// Create or open a new database in the stores instead of a directory,
+    /// whose hashes are generated by `H`. A new database has index pages of
+    /// `page_size` bytes.
+    ///
+    /// There is no `version` file to check the stores, and no WAL of the
+    /// index - so the index maybe is left half-changed by a crash. `put_named`,
+    /// `set_label`, `compact`, `destroy` and `with_mmap` are not supported, as they
+    /// need files of their own.
+    pub fn with_stores<D, I>(data: D, index: I, page_size: usize) -> Result<Self, Error>
+    where
+        D: BlockStore + 'static,
+        I: BlockStore + 'static,
+    {
+        Self::check_options(page_size)?;
+        let indexer = Indexer::open_in(index, H::NAME, page_size)
+            .to_inner_result("open indexer")?;
+        Ok(Self::with_parts(None, vec![Arc::new(data)], indexer, None, false))
+    }
+
+    /// Create the database by its opened parts, with the default options.
+    fn with_parts(
+        path: Option<PathBuf>,
+        data: Vec<Arc<dyn BlockStore>>,
+        indexer: Indexer,
+        names: Option<Names>,
+        read_only: bool,
+    ) -> Self {
+        Database {
+            hasher: PhantomData,
+            path,
+            data_dir: None,
+            data,
+            indexer,
+            names,
+            labels: None,
+            cache: Mutex::new(LruCache::new(NonZeroUsize::new(16).unwrap())),
+            compression: CompressionMode::None,
+            sync_policy: SyncPolicy::Never,
+            max_value_size: None,
+            preallocation: None,
+            last_sync: Instant::now(),
+            flusher: None,
+            read_only,
+            #[cfg(feature = "memmap2")]
+            map: None,
+        }
+    }
+
+    /// Check the options to create a new database.
+    fn check_options(page_size: usize) -> Result<(), Error> {
+        if !is_valid_page_size(page_size) {
+            return Err(Error::with_kind(
+                ErrorKind::Unsupported,
+                format!("the page size {} is not supported", page_size),
+            ));
+        }
+        if H::LEN != HASH_SIZE {
+            return Err(Error::with_kind(
+                ErrorKind::Unsupported,
+                "the length of the hash is not supported",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Open an existing database at the given path in read-only mode, whose
+    /// hashes are generated by `H`.
+    ///
+    /// Both the data file and the index file are opened by read-only file
+    /// handles, so many readers can share one database. `put`, `put_reader`
+    /// and `delete` always fail.
+    pub fn with_hasher_read_only<P>(database_path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let database_path = PathBuf::from(database_path.as_ref());
+
+        let layout = match Self::read_version(&database_path)? {
+            Some(layout) => layout,
+            None => return Err(Error::with_kind(
+                ErrorKind::NotFound,
+                format!("no database at {:?}", database_path),
+            )),
+        };
+        let shards = layout.shards;
+        let data_path = layout.data_dir.as_deref().unwrap_or(&database_path);
+        let compacted = (0..shards)
+            .any(|shard| data_path.join(tmp_data_name(shard, shards)).exists());
+        if compacted && !database_path.join("index.tmp").exists() {
+            return Err(Error::new("the compaction needs to be finished in read-write mode"));
+        }
+
+        let data = (0..shards)
+            .map(|shard| {
+                let file = DataFile::open_read_only(&data_path.join(data_name(shard, shards)))
+                    .to_inner_result("open data file in read-only mode")?;
+                Ok(Arc::new(file) as Arc<dyn BlockStore>)
+            })
+            .collect::<Result<_, Error>>()?;
+        let mut indexer = Indexer::open_read_only(&database_path, H::NAME)
+            .to_inner_result("open indexer")?;
+        Self::check_uuid(&mut indexer, layout.uuid, false)?;
+        let names = Names::open_read_only(&database_path, H::NAME)?;
+        let labels = Labels::open_read_only(&database_path, H::NAME)?;
+        let mut database = Self::with_parts(Some(database_path), data, indexer, names, true);
+        database.labels = labels;
+        database.data_dir = layout.data_dir;
+        Ok(database)
+    }
+
+    /// The directory of the database. An error is raised if the database is
+    /// in stores instead, see `with_stores`.
+    fn path(&self) -> Result<&Path, Error> {
+        self.path.as_deref().ok_or_else(|| {
+            Error::with_kind(ErrorKind::Unsupported, "the database is not in a directory")
+        })
+    }
+
+    /// The directory of the data file - the directory of the database unless
+    /// it is split, see `new_split`.
+    fn data_path(&self) -> Result<&Path, Error> {
+        match &self.data_dir {
+            Some(data_dir) => Ok(data_dir),
+            None => self.path(),
+        }
+    }
+
+    /// Compress the wastes put later by `mode`. It is `CompressionMode::None`
+    /// by default.
+    ///
+    /// Every waste records its own mode, so the wastes put before are still
+    /// readable. And the hash is always generated from the origin content.
+    pub fn with_compression(mut self, mode: CompressionMode) -> Self {
+        self.compression = mode;
+        self
+    }
+
+    /// Sync the writes to the disk by `policy`. It is `SyncPolicy::Never` by
+    /// default - the fastest one, but the writes not synced yet maybe are lost
+    /// by a power loss. `SyncPolicy::EveryWrite` loses nothing, while each
+    /// write costs `fsync`s of both the data file and the index.
+    ///
+    /// `SyncPolicy::Background` spawns its thread here, unless the database is
+    /// opened in read-only mode.
+    pub fn with_sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self.restart_flusher();
+        self
+    }
+
+    /// Stop the background thread syncing the database if there is one, and
+    /// spawn a new one for the current data file and index if the policy is
+    /// `SyncPolicy::Background`. It is called once they are replaced.
+    fn restart_flusher(&mut self) {
+        // The pages deferred for the thread before are written after the data
+        // file is synced, as the thread does. It is best effort, and the pages
+        // left deferred are written by the next sync otherwise.
+        if self.flusher.take().is_some() {
+            let _ = self.sync();
+        }
+        let interval = match (self.sync_policy, self.read_only) {
+            (SyncPolicy::Background(interval), false) => Some(interval),
+            _ => None,
+        };
+
+        let mut data = self.data.clone();
+        let mut pagers = vec![self.indexer.pager()];
+        let handles = [
+            self.names.as_ref().map(Names::sync_handles),
+            self.labels.as_ref().map(Labels::sync_handles),
+        ];
+        // The keyspace without handles is not deferred, but written as by the
+        // other policies.
+        for (file, pager) in handles.into_iter().flatten().flatten() {
+            data.push(Arc::new(file));
+            pagers.push(pager);
+        }
+        for pager in &mut pagers {
+            let _ = pager.set_deferred(interval.is_some());
+        }
+        if let Some(interval) = interval {
+            self.flusher = Some(Flusher::spawn(interval, data, pagers));
+        }
+    }
+
+    /// Preallocate the data file: once it runs out, it is grown by at least
+    /// `len` bytes at once - by `set_len` - instead of a write at a time, so
+    /// that it is less fragmented by large sequential loads. It is `None` by
+    /// default.
+    ///
+    /// The end of the data in each shard is kept in a file of its own -
+    /// `data.end` for `data` - so that the appends resume there once the
+    /// database is opened again, even without preallocation. The compacted
+    /// data file is never preallocated until it runs out again.
+    ///
+    /// It is not supported if the database is in stores.
+    pub fn with_preallocation(mut self, len: Option<u64>) -> Result<Self, Error> {
+        self.check_writable()?;
+        let data_path = self.data_path()?.to_path_buf();
+        self.data = Self::open_data(&data_path, self.data.len(), len)
+            .to_inner_result("open data file")?;
+        self.preallocation = len;
+        self.restart_flusher();
+        self.remap()?;
+        Ok(self)
+    }
+
+    /// Reject the wastes larger than `max` bytes - the origin content, before
+    /// it is compressed - by an error of `ErrorKind::TooLarge`. There is no
+    /// limit by default.
+    ///
+    /// `put` checks it before writing anything, and `put_reader` counts the
+    /// bytes streamed, so that a reader which never ends is cut off.
+    pub fn with_max_value_size(mut self, max: Option<u64>) -> Self {
+        self.max_value_size = max;
+        self
+    }
+
+    /// Memory-map the data file, so that `get` and `get_cow` serve the wastes
+    /// by slicing the map without any syscall. The file is remapped after each
+    /// write, so it is best for databases read much more than written.
+    ///
+    /// The data file must not be changed by others while it is mapped. It is
+    /// not supported if the database is in stores.
+    #[cfg(feature = "memmap2")]
+    pub fn with_mmap(mut self) -> Result<Self, Error> {
+        self.map = Some(self.map_data()?);
+        Ok(self)
+    }
+
+    /// Map all shards of the data file by read-only handles of their own.
+    #[cfg(feature = "memmap2")]
+    fn map_data(&self) -> Result<Vec<memmap2::Mmap>, Error> {
+        let data_path = self.data_path()?;
+        let shards = self.data.len();
+        (0..shards)
+            .map(|shard| {
+                let file = fs::File::open(data_path.join(data_name(shard, shards)))
+                    .to_inner_result("open data file to map")?;
+                // The bytes preallocated beyond the end of the data are never
+                // mapped.
+                let len = self.data[shard].len().to_inner_result("get data file's length")?;
+                unsafe { memmap2::MmapOptions::new().len(len as usize).map(&file) }
+                    .to_inner_result("map data file")
+            })
+            .collect()
+    }
+
+    /// Map the data file again after it grows, if it is memory-mapped.
+    fn remap(&mut self) -> Result<(), Error> {
+        #[cfg(feature = "memmap2")]
+        if self.map.is_some() {
+            self.map = Some(self.map_data()?);
+        }
+        Ok(())
+    }
+
+    /// List all wastes' hashes, in ascending order.
+    pub fn list(&self) -> Result<Vec<String>, Error> {
+        self.indexer.list()
+    }
+
+    /// List all wastes' hashes with their labels as `list` does. See
+    /// `set_label`.
+    pub fn list_with_labels(&self) -> Result<Vec<(String, Option<String>)>, Error> {
+        self.list()?
+            .into_iter()
+            .map(|hash| {
+                let label = match &self.labels {
+                    Some(labels) => labels.get(&hash)?,
+                    None => None,
+                };
+                Ok((hash, label))
+            })
+            .collect()
+    }
+
+    /// Walk through all wastes' hashes in ascending order as `list` does, but
+    /// lazily - only a leaf of the index is read at a time, so it is cheap to
+    /// stop early. It is the same as `for hash in &database`.
+    ///
+    /// The database is borrowed until the iterator is dropped, so it can not
+    /// be changed during the walk. An error is yielded if a page of the index
+    /// can not be read, and then the walk ends.
+    pub fn iter(&self) -> DatabaseIter<'_> {
+        DatabaseIter { hashes: self.indexer.hashes() }
+    }
+
+    /// Walk through all wastes as `(hash, content)` lazily, in ascending order
+    /// of hashes as `iter` does - for example, to migrate them into another
+    /// database. The content of each waste is read only when it is yielded,
+    /// and its checksum is checked as `get` does. The expired wastes are
+    /// skipped.
+    ///
+    /// The data file is read in the order of hashes instead of offsets, so it
+    /// is not a sequential read - see `export` as well.
+    pub fn iter_values(&self) -> DatabaseValues<'_, H> {
+        DatabaseValues { database: self, records: self.indexer.records_lazily() }
+    }
+
+    /// List up to `limit` wastes' hashes strictly greater than `after` - or
+    /// from the smallest one if it is `None`, in ascending order. Pass the
+    /// last hash of a page as `after` to get the next page: the pages stay
+    /// stable even if wastes are put in between.
+    pub fn list_paged(&self, after: Option<&str>, limit: usize) -> Result<Vec<String>, Error> {
+        self.indexer.list_paged(after, limit)
+    }
+
+    /// List wastes' hashes in `[start, end)`, in ascending order.
+    pub fn range(&self, start: &str, end: &str) -> Result<Vec<String>, Error> {
+        self.indexer.range(start, end)
+    }
+
+    /// List wastes' hashes starting with the hex prefix - like short hashes of
+    /// git, in ascending order. The prefix can be of any count of nibbles.
+    pub fn find_by_prefix(&self, hex_prefix: &str) -> Result<Vec<String>, Error> {
+        self.indexer.find_by_prefix(hex_prefix)
+    }
+
+    /// The count of distinct wastes stored.
+    pub fn len(&self) -> Result<usize, Error> {
+        Ok(self.indexer.len())
+    }
+
+    /// Get the diagnostics of the database - for example, to alert when the
+    /// index grows unexpectedly. It is cheap: the index is only walked from its
+    /// root to a leaf once.
+    pub fn stats(&self) -> Result<DatabaseStats, Error> {
+        // Read the counters first, so that they do not count the descent for
+        // the height.
+        let pager_stats = self.indexer.pager_stats();
+        Ok(DatabaseStats {
+            data_bytes: self.data_len()?,
+            index_bytes: self.indexer.file_len()?,
+            keys: self.indexer.len(),
+            index_height: self.indexer.height()?,
+            index_pages: self.indexer.page_count(),
+            index_cache_hits: pager_stats.cache_hits,
+            index_cache_misses: pager_stats.cache_misses,
+            index_cache_evictions: pager_stats.evictions,
+            garbage_bytes: self.indexer.garbage_bytes(),
+        })
+    }
+
+    /// The length of the data file - the sum of all its shards.
+    fn data_len(&self) -> Result<u64, Error> {
+        self.data.iter().try_fold(0, |len, shard| {
+            Ok(len + shard.len().to_inner_result("get data file's length")?)
+        })
+    }
+
+    /// The shard of the data file holding the record at the offset.
+    fn data_shard(&self, offset: Offset) -> Result<&dyn BlockStore, Error> {
+        match self.data.get(offset.shard()) {
+            Some(shard) => Ok(&**shard),
+            None => Err(Error::with_kind(ErrorKind::Corrupt, "unknown shard of data file")),
+        }
+    }
+
+    /// The shard of the data file to put the waste of the hash in.
+    fn shard_of(&self, hash: &str) -> usize {
+        let first_byte = u8::from_str_radix(&hash[..2], 16).expect("the hash is in hex");
+        first_byte as usize % self.data.len()
+    }
+
+    /// Is there no waste stored?
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Put the waste and return its hash. Putting the same waste again still
+    /// returns the same hash - and then it needs to be deleted once more.
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(len = data.len()),
+    ))]
+    pub fn put(&mut self, data: &[u8]) -> Result<String, Error> {
+        self.put_status(data).map(|(hash, _)| hash)
+    }
+
+    /// Put the waste as `put` does, and return its hash and whether it is new:
+    /// `false` if the same waste is already existing and not expired yet.
+    ///
+    /// The data is not appended to the data file again if it is already
+    /// existing, so the waste is hashed before it is written. An expired waste
+    /// which is not swept yet is put again at the offset it had.
+    pub fn put_status(&mut self, data: &[u8]) -> Result<(String, bool), Error> {
+        self.check_writable()?;
+        check_value_size(data.len() as u64, self.max_value_size)?;
+        let hash = H::hex_digest(data);
+        let is_new = match self.indexer.get_record(&hash).to_inner_result("get offset by hash")? {
+            Some((offset, _, expires_at)) => {
+                self.indexer.put(&hash, offset)?;
+                expires_at <= now_millis()
+            }
+            None => {
+                let (hash, offset) = self.write_waste(data, &[], Some(hash.clone()))?;
+                self.sync_data_before_index()?;
+                self.index_waste(&hash, offset, NEVER_EXPIRES)?
+            }
+        };
+        self.cache_waste(&hash, data);
+        self.remap()?;
+        self.sync_after_write()?;
+        Ok((hash, is_new))
+    }
+
+    /// Get the waste if it is stored, or put it otherwise - and return its hash
+    /// and content. The content of a waste stored is read from the data file,
+    /// as `get` does - so a broken record fails, instead of returning `data`.
+    /// It is never appended again if it is stored.
+    ///
+    /// Unlike `put`, a waste already stored is not referenced once more. An
+    /// expired waste which is not swept yet is put again at the offset it had.
+    pub fn get_or_put(&mut self, data: &[u8]) -> Result<(String, Vec<u8>), Error> {
+        self.check_writable()?;
+        check_value_size(data.len() as u64, self.max_value_size)?;
+        let hash = H::hex_digest(data);
+        match self.indexer.get_record(&hash).to_inner_result("get offset by hash")? {
+            Some((_, _, expires_at)) if expires_at > now_millis() => {
+                let content = self.get(&hash)?;
+                return Ok((hash, content));
+            }
+            Some((offset, _, _)) => {
+                self.index_waste(&hash, offset, NEVER_EXPIRES)?;
+            }
+            None => {
+                let (hash, offset) = self.write_waste(data, &[], Some(hash.clone()))?;
+                self.sync_data_before_index()?;
+                self.index_waste(&hash, offset, NEVER_EXPIRES)?;
+            }
+        }
+        self.cache_waste(&hash, data);
+        self.remap()?;
+        self.sync_after_write()?;
+        Ok((hash, data.to_vec()))
+    }
+
+    /// Put the waste as `put` does, but it expires after `ttl`. The
+    /// expired waste is never got, and it is removed from the index by
+    /// `sweep_expired` or `compact`. Until then it is still counted by `len`
+    /// and listed by `list`.
+    ///
+    /// If the same waste is already existing, the later expiry time of both
+    /// wins - so a waste put by `put` never expires.
+    pub fn put_with_ttl(&mut self, data: &[u8], ttl: Duration) -> Result<String, Error> {
+        self.check_writable()?;
+        check_value_size(data.len() as u64, self.max_value_size)?;
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let expires_at = now_millis().saturating_add(ttl).min(NEVER_EXPIRES - 1);
+        let hash = H::hex_digest(data);
+        let offset = match self.indexer.get(&hash).to_inner_result("get offset by hash")? {
+            Some(offset) => offset,
+            None => {
+                let offset = self.write_waste(data, &[], Some(hash.clone()))?.1;
+                self.sync_data_before_index()?;
+                offset
+            }
+        };
+        self.index_waste(&hash, offset, expires_at)?;
+        // The cache never checks the expiry time, so the waste is not cached.
+        self.remap()?;
+        self.sync_after_write()?;
+        Ok(hash)
+    }
+
+    /// Remove all expired wastes from the index, and return the count of them.
+    /// Their bytes are left in the data file to be reclaimed by `compact`.
+    pub fn sweep_expired(&mut self) -> Result<usize, Error> {
+        self.check_writable()?;
+        let now = now_millis();
+        let mut expired_bytes = 0;
+        for (hash, offset, _, expires_at) in self.indexer.records()? {
+            if expires_at <= now {
+                expired_bytes += self.record_len(offset).unwrap_or(0);
+                if let Some(labels) = &mut self.labels {
+                    labels.remove(&hash).to_inner_result("remove label")?;
+                }
+            }
+        }
+        let swept = self.indexer.purge_expired()?;
+        if swept > 0 {
+            self.add_garbage(expired_bytes)?;
+            self.sync_after_write()?;
+        }
+        Ok(swept)
+    }
+
+    /// Put the waste with its metadata - for example, its MIME type. The hash
+    /// is generated from `data` only, so the same data with different metadata
+    /// is still the same waste - and the metadata put last wins.
+    ///
+    /// The waste is only appended to the data file again if its metadata is
+    /// changed. Otherwise it is just referenced once more, as `put` does.
+    ///
+    /// See `get_with_meta` as well.
+    pub fn put_with_meta(&mut self, data: &[u8], meta: &[u8]) -> Result<String, Error> {
+        self.check_writable()?;
+        check_value_size(data.len() as u64, self.max_value_size)?;
+        let hash = H::hex_digest(data);
+        let stored = match self.indexer.get(&hash).to_inner_result("get offset by hash")? {
+            // A broken record is just replaced.
+            Some(offset) if self.read_meta(offset).is_ok_and(|stored| stored == meta) => {
+                Some(offset)
+            }
+            _ => None,
+        };
+        match stored {
+            Some(offset) => {
+                self.indexer.put(&hash, offset)?;
+            }
+            None => {
+                let (hash, offset) = self.write_waste(data, meta, Some(hash.clone()))?;
+                self.sync_data_before_index()?;
+                self.index_waste(&hash, offset, NEVER_EXPIRES)?;
+            }
+        }
+        self.cache_waste(&hash, data);
+        self.remap()?;
+        self.sync_after_write()?;
+        Ok(hash)
+    }
+
+    /// Put the waste, and name it `name` - so that it can be got by the name
+    /// later, see `get_named`. Naming another waste by the same name later
+    /// replaces it, and the waste named before loses the reference of the
+    /// name.
+    ///
+    /// It is not supported if the database is in stores.
+    pub fn put_named(&mut self, name: &str, data: &[u8]) -> Result<String, Error> {
+        self.check_writable()?;
+        if self.names.is_none() {
+            return Err(Error::with_kind(
+                ErrorKind::Unsupported,
+                "names are not supported if the database is in stores",
+            ));
+        }
+        let hash = self.put(data)?;
+        let name_hash = H::hex_digest(name.as_bytes());
+        let names = self.names.as_mut().unwrap();
+        if let Some(origin_hash) = names.put(&name_hash, name, &hash)? {
+            self.delete(&origin_hash)?;
+        }
+        Ok(hash)
+    }
+
+    /// Get the waste named `name` by `put_named`.
+    pub fn get_named(&self, name: &str) -> Result<Vec<u8>, Error> {
+        let name_hash = H::hex_digest(name.as_bytes());
+        let hash = match &self.names {
+            Some(names) => names.get(&name_hash, name)?,
+            None => None,
+        };
+        match hash {
+            Some(hash) => self.get(&hash),
+            None => Err(Error::with_kind(ErrorKind::NotFound, "name not found")),
+        }
+    }
+
+    /// Put the waste and label it, see `set_label`.
+    pub fn put_with_label(&mut self, data: &[u8], label: &str) -> Result<String, Error> {
+        self.check_label(label)?;
+        let hash = self.put(data)?;
+        self.set_label(&hash, label)?;
+        Ok(hash)
+    }
+
+    /// Label the waste of the hash by a short UTF-8 text of at most
+    /// `MAX_LABEL_LEN` bytes, instead of the label it had before. The label
+    /// is kept aside from the waste, so it never changes the hash, and it is
+    /// removed once the waste is deleted.
+    ///
+    /// It is not supported if the database is in stores.
+    pub fn set_label(&mut self, hash: &str, label: &str) -> Result<(), Error> {
+        self.check_writable()?;
+        self.check_label(label)?;
+        if !self.contains(hash)? {
+            return Err(Error::with_kind(ErrorKind::NotFound, "waste not found"));
+        }
+        let labels = self.labels.as_mut().unwrap();
+        labels.put(hash, label).to_inner_result("put label")?;
+        self.sync_after_write()
+    }
+
+    /// Get the label of the waste of the hash, or `None` if it has no label.
+    pub fn get_label(&self, hash: &str) -> Result<Option<String>, Error> {
+        if !self.contains(hash)? {
+            return Err(Error::with_kind(ErrorKind::NotFound, "waste not found"));
+        }
+        match &self.labels {
+            Some(labels) => labels.get(hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Return an error if the label cannot be set.
+    fn check_label(&self, label: &str) -> Result<(), Error> {
+        if self.labels.is_none() {
+            return Err(Error::with_kind(
+                ErrorKind::Unsupported,
+                "labels are not supported if the database is in stores",
+            ));
+        }
+        if label.len() > MAX_LABEL_LEN {
+            return Err(Error::with_kind(
+                ErrorKind::TooLarge,
+                format!("the label is longer than {} bytes", MAX_LABEL_LEN),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Put many wastes at once. It is much faster than `put` them one by one,
+    /// as the index is synced only once at the end. The hashes are returned in
+    /// the same order as `items`.
+    ///
+    /// The wastes already existing - or put twice in `items` - are appended to
+    /// the data file only once, as `put_status` does.
+    ///
+    /// If it fails, the wastes put maybe are only partly indexed.
+    pub fn put_batch(&mut self, items: &[&[u8]]) -> Result<Vec<String>, Error> {
+        self.check_writable()?;
+        for data in items {
+            check_value_size(data.len() as u64, self.max_value_size)?;
+        }
+        let mut records = Vec::with_capacity(items.len());
+        let mut offsets = HashMap::new();
+        let mut written = 0;
+        let mut replaced = 0;
+        for data in items {
+            let hash = H::hex_digest(data);
+            let offset = match offsets.get(&hash) {
+                Some(&offset) => offset,
+                None => match self.indexer.get(&hash).to_inner_result("get offset by hash")? {
+                    Some(offset) => offset,
+                    None => {
+                        written += 1;
+                        let offset = self.write_waste(data, &[], Some(hash.clone()))?.1;
+                        replaced += self.replaced_len(&hash, offset)?;
+                        offset
+                    }
+                },
+            };
+            offsets.insert(hash.clone(), offset);
+            records.push((hash, offset));
+        }
+        if written > 0 {
+            self.sync_data_before_index()?;
+        }
+        self.indexer.put_batch(&records)?;
+        self.add_garbage(replaced)?;
+        for (data, (hash, _)) in items.iter().zip(&records) {
+            self.cache_waste(hash, data);
+        }
+        self.remap()?;
+        self.sync_after_write()?;
+        Ok(records.into_iter().map(|(hash, _)| hash).collect())
+    }
+
+    /// Append the record of the waste to the data file, and return its hash
+    /// and offset. It is not indexed yet.
+    ///
+    /// The hash is only generated if it is not given.
+    fn write_waste(
+        &mut self,
+        data: &[u8],
+        meta: &[u8],
+        hash: Option<String>,
+    ) -> Result<(String, Offset), Error> {
+        let compressed = match self.compression {
+            CompressionMode::None => None,
+            CompressionMode::Zstd => Some(
+                zstd::bulk::compress(data, CompressionMode::ZSTD_LEVEL)
+                    .to_inner_result("compress waste's data")?,
+            ),
+        };
+        // Just store the origin content if it can not be compressed smaller.
+        let (mode, stored) = match &compressed {
+            Some(c) if c.len() < data.len() => (self.compression, c.as_slice()),
+            _ => (CompressionMode::None, data),
+        };
+
+        // The origin content stored as it is is hashed chunk by chunk while
+        // it is written, in the same pass as its checksum. But the shard is
+        // chosen by the hash, so it is generated first if there are shards.
+        let mut hash = hash;
+        let mut shard = 0;
+        if self.data.len() > 1 {
+            let h = hash.unwrap_or_else(|| H::hex_digest(data));
+            shard = self.shard_of(&h);
+            hash = Some(h);
+        }
+        let mut hasher = (hash.is_none() && mode == CompressionMode::None).then(H::default);
+
+        let store = &*self.data[shard];
+        let offset = store.len().to_inner_result("get data file's length")?;
+        let mut writer = DataWriter { store, pos: offset };
+        writer.write_all(&[mode.to_flag()])
+            .to_inner_result("write waste's compression flag")?;
+        writer.write_all(&varint::encode(stored.len() as u64))
+            .to_inner_result("write waste's length")?;
+        let mut checksum_writer = ChecksumWriter::new(&mut writer);
+        for chunk in stored.chunks(64 * 1024) { // 64KB.
+            if let Some(hasher) = &mut hasher {
+                hasher.update(chunk);
+            }
+            checksum_writer.write_all(chunk).to_inner_result("write waste's data")?;
+        }
+        let checksum = checksum_writer.checksum();
+        writer.write_all(&checksum.to_le_bytes())
+            .to_inner_result("write waste's checksum")?;
+        write_meta(&mut writer, meta)?;
+
+        let hash = match (hash, hasher) {
+            (Some(hash), _) => hash,
+            (None, Some(hasher)) => hasher::to_hex(&hasher.finalize()),
+            (None, None) => H::hex_digest(data),
+        };
+        store.publish().to_inner_result("publish data file's end")?;
+        Ok((hash, Offset::with_shard(shard as u8, offset)))
+    }
+
+    /// Index the record of the waste just written at the offset, which expires
+    /// at `expires_at`, and return `true` if the waste is new. The record the
+    /// waste was indexed at before - maybe expired - is garbage from now on.
+    fn index_waste(&mut self, hash: &str, offset: Offset, expires_at: u64) -> Result<bool, Error> {
+        let replaced = self.replaced_len(hash, offset)?;
+        let inserted = self.indexer.put_with_expiry(hash, offset, expires_at)?;
+        self.add_garbage(replaced)?;
+        Ok(inserted)
+    }
+
+    /// The length of the record the waste is indexed at, if it is not at the
+    /// offset - or 0 otherwise. It becomes garbage once the waste is indexed
+    /// at the offset instead.
+    fn replaced_len(&self, hash: &str, offset: Offset) -> Result<u64, Error> {
+        match self.indexer.get_record(hash).to_inner_result("get offset by hash")? {
+            // A broken record is not counted, instead of failing the put.
+            Some((origin, _, _)) if origin != offset => Ok(self.record_len(origin).unwrap_or(0)),
+            _ => Ok(0),
+        }
+    }
+
+    /// Count the bytes of the data file as garbage. See
+    /// `DatabaseStats::garbage_bytes`.
+    fn add_garbage(&mut self, bytes: u64) -> Result<(), Error> {
+        if bytes > 0 {
+            self.indexer.add_garbage_bytes(bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Keep the waste just put in the cache if it is small.
+    fn cache_waste(&mut self, hash: &str, data: &[u8]) {
+        if data.len() < 256 * 1024 { // 256KB.
+            let cache = self.cache.get_mut().unwrap();
+            cache.put(hash.to_string(), Vec::from(data));
+            let new_cache_size = NonZeroUsize::new(self.indexer.len() / 4 + 16).unwrap();
+            cache.resize(new_cache_size);
+        }
+    }
+
+    /// Put the waste by streaming it from the reader, so that the waste never
+    /// needs to be held in memory. It is stored as same as `put` does.
+    ///
+    /// If the reader fails or streams more bytes than the max size of values,
+    /// the bytes already streamed (if any) are left in the data file, but the
+    /// waste is not indexed.
+    ///
+    /// The shard is chosen by the hash, which is not known until the reader is
+    /// consumed. So the waste is spooled into a temporary file and hashed first
+    /// if the data file is sharded.
+    pub fn put_reader<R: Read>(&mut self, reader: R) -> Result<String, Error> {
+        self.put_reader_with_meta(reader, &[])
+    }
+
+    /// Put the waste by streaming it from the reader, with its metadata. See
+    /// `put_reader` and `put_with_meta`.
+    pub fn put_reader_with_meta<R: Read>(
+        &mut self,
+        reader: R,
+        meta: &[u8],
+    ) -> Result<String, Error> {
+        self.check_writable()?;
+        let data_len = self.data_len()?;
+        let (hash, offset) = match self.write_reader(reader, meta, None) {
+            Ok(written) => written,
+            Err(e) => {
+                self.add_garbage(self.data_len()?.saturating_sub(data_len))?;
+                return Err(e);
+            }
+        };
+        self.sync_data_before_index()?;
+        self.index_waste(&hash, offset, NEVER_EXPIRES)?;
+        self.remap()?;
+        self.sync_after_write()?;
+        Ok(hash)
+    }
+
+    /// Append the record of the waste streamed from the reader to the data
+    /// file, and return its hash and offset. It is not indexed yet.
+    ///
+    /// The waste is put into the shard of `hash` if it is given, and the caller
+    /// checks it against the hash returned. Otherwise it is spooled and hashed
+    /// first to choose the shard, unless the data file is not sharded.
+    fn write_reader<R: Read>(
+        &mut self,
+        reader: R,
+        meta: &[u8],
+        hash: Option<&str>,
+    ) -> Result<(String, Offset), Error> {
+        let shard = match hash {
+            Some(hash) => self.shard_of(hash),
+            None if self.data.len() == 1 => 0,
+            None => {
+                let mut spool = Spool::create().to_inner_result("create spool file")?;
+                let mut hasher = H::default();
+                copy_and_hash(reader, &mut spool.file, &mut hasher, self.max_value_size)?;
+                spool.file.seek(io::SeekFrom::Start(0)).to_inner_result("rewind spool file")?;
+                let hash = hasher::to_hex(&hasher.finalize());
+                return self.write_reader(&spool.file, meta, Some(&hash));
+            }
+        };
+        let store = &*self.data[shard];
+        let offset = store.len().to_inner_result("get data file's length")?;
+
+        // We do not know the length until the reader is consumed. So reserve
+        // the space of the longest varint first, and patch it later.
+        let mut writer = DataWriter { store, pos: offset };
+        writer.write_all(&[self.compression.to_flag()])
+            .to_inner_result("write waste's compression flag")?;
+        writer.write_all(&varint::encode_padded(0))
+            .to_inner_result("reserve waste's length")?;
+
+        let mut hasher = H::default();
+        let max_len = self.max_value_size;
+        let mut checksum_writer = ChecksumWriter::new(&mut writer);
+        match self.compression {
+            CompressionMode::None => {
+                copy_and_hash(reader, &mut checksum_writer, &mut hasher, max_len)?
+            }
+            CompressionMode::Zstd => {
+                let mut encoder =
+                    zstd::Encoder::new(&mut checksum_writer, CompressionMode::ZSTD_LEVEL)
+                        .to_inner_result("create zstd encoder")?;
+                copy_and_hash(reader, &mut encoder, &mut hasher, max_len)?;
+                encoder.finish().to_inner_result("finish compressing waste's data")?;
+            }
+        }
+        let checksum = checksum_writer.checksum();
+
+        let len = writer.pos - offset - 1 - VARINT_MAX_LEN as u64;
+        writer.write_all(&checksum.to_le_bytes())
+            .to_inner_result("write waste's checksum")?;
+        write_meta(&mut writer, meta)?;
+        store.write_all_at(&varint::encode_padded(len), offset + 1)
+            .to_inner_result("write waste's length")?;
+        store.publish().to_inner_result("publish data file's end")?;
+
+        let hash = hasher::to_hex(&hasher.finalize());
+        Ok((hash, Offset::with_shard(shard as u8, offset)))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub fn get(&self, hash: &str) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![];
+        self.get_into(hash, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Get the waste into `buf`, and return its length. `buf` is cleared first,
+    /// so that a loop of reads can reuse its memory instead of allocating a
+    /// new one for each of them.
+    pub fn get_into(&self, hash: &str, buf: &mut Vec<u8>) -> Result<usize, Error> {
+        buf.clear();
+        #[cfg(feature = "memmap2")]
+        if self.map.is_some() {
+            buf.extend_from_slice(&self.get_cow(hash)?);
+            return Ok(buf.len());
+        }
+
+        if let Some(result) = self.cache.lock().unwrap().get(hash) {
+            buf.extend_from_slice(result);
+            return Ok(buf.len());
+        }
+
+        self.read_waste_into(self.find_offset(hash)?, buf)?;
+        Ok(buf.len())
+    }
+
+    /// Get many wastes at once, in the same order as `hashes` - for example,
+    /// the thumbnails of a page of a gallery. It is `None` for the hash not
+    /// stored, instead of failing all of them.
+    ///
+    /// The cache is locked only once, and the index is looked up in the order
+    /// of hashes, so that the hashes in the same leaf need one descent only.
+    /// Then the wastes are read in the order of the data file.
+    pub fn get_many(&self, hashes: &[&str]) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        let mut wastes: Vec<Option<Vec<u8>>> = vec![None; hashes.len()];
+        let mut missed = vec![];
+        {
+            let mut cache = self.cache.lock().unwrap();
+            for (i, hash) in hashes.iter().enumerate() {
+                match cache.get(*hash) {
+                    Some(waste) => wastes[i] = Some(waste.clone()),
+                    None => missed.push(i),
+                }
+            }
+        }
+
+        let missed_hashes: Vec<&str> = missed.iter().map(|i| hashes[*i]).collect();
+        let offsets = self.indexer
+            .get_many(&missed_hashes)
+            .to_inner_result("get offsets by hashes")?;
+        let mut found: Vec<(Offset, usize)> = offsets
+            .into_iter()
+            .zip(missed)
+            .filter_map(|(offset, i)| Some((offset?, i)))
+            .collect();
+        found.sort_unstable();
+        for (offset, i) in found {
+            wastes[i] = Some(self.read_waste(offset)?);
+        }
+        Ok(wastes)
+    }
+
+    /// Read the content of the record at the offset, and check its checksum.
+    fn read_waste(&self, offset: Offset) -> Result<Vec<u8>, Error> {
+        let mut content = vec![];
+        self.read_waste_into(offset, &mut content)?;
+        Ok(content)
+    }
+
+    /// Read the content of the record at the offset into the empty `buf`, as
+    /// `read_waste` does.
+    fn read_waste_into(&self, offset: Offset, buf: &mut Vec<u8>) -> Result<(), Error> {
+        let (mode, size, mut reader) = self.seek_record(offset)?;
+        let mut compressed = vec![];
+        let content = match mode {
+            CompressionMode::None => &mut *buf,
+            CompressionMode::Zstd => &mut compressed,
+        };
+        content.resize(size as usize, 0);
+        reader.read_exact(content).to_inner_result("read waste")?;
+        if read_checksum(&mut reader)? != crc32fast::hash(content) {
+            buf.clear();
+            return Err(Error::with_kind(ErrorKind::Corrupt, "checksum mismatch"));
+        }
+        if mode == CompressionMode::Zstd {
+            zstd::stream::copy_decode(compressed.as_slice(), &mut *buf)
+                .to_inner_result("decompress waste")?;
+        }
+        Ok(())
+    }
+
+    /// Get the waste without copying it if possible - it is borrowed from the
+    /// memory map if the data file is mapped and the waste is not compressed.
+    /// Otherwise, it is as same as `get`.
+    pub fn get_cow(&self, hash: &str) -> Result<Cow<'_, [u8]>, Error> {
+        #[cfg(feature = "memmap2")]
+        if let Some(maps) = &self.map {
+            let (mode, stored) = self.mapped_waste(maps, hash)?;
+            return match mode {
+                CompressionMode::None => Ok(Cow::Borrowed(stored)),
+                CompressionMode::Zstd => zstd::stream::decode_all(stored)
+                    .map(Cow::Owned)
+                    .to_inner_result("decompress waste"),
+            };
+        }
+
+        self.get(hash).map(Cow::Owned)
+    }
+
+    /// Find the stored content of the waste in the memory maps, and check its
+    /// checksum.
+    #[cfg(feature = "memmap2")]
+    fn mapped_waste<'a>(
+        &self,
+        maps: &'a [memmap2::Mmap],
+        hash: &str,
+    ) -> Result<(CompressionMode, &'a [u8]), Error> {
+        let offset = self.find_offset(hash)?;
+        let map = match maps.get(offset.shard()) {
+            Some(map) => &map[..],
+            None => return Err(Error::with_kind(ErrorKind::Corrupt, "unknown shard of data file")),
+        };
+        if offset.to_u64() >= map.len() as u64 {
+            return Err(past_end_error(offset));
+        }
+        let offset = offset.to_u64() as usize;
+        let corrupt = || Error::with_kind(ErrorKind::Corrupt, "corrupt length");
+
+        let mode = CompressionMode::from_flag(*map.get(offset).ok_or_else(corrupt)?)?;
+        let (size, len) = map.get(offset + 1..).and_then(varint::decode).ok_or_else(corrupt)?;
+
+        // At least the checksum and a one-byte meta's length follow it.
+        let start = offset + 1 + len;
+        if size > map.len().saturating_sub(start + CHECKSUM_SIZE as usize + 1) as u64 {
+            return Err(corrupt());
+        }
+        let end = start + size as usize;
+        let stored = &map[start..end];
+        let checksum = map[end..end + CHECKSUM_SIZE as usize].try_into().unwrap();
+        if u32::from_le_bytes(checksum) != crc32fast::hash(stored) {
+            return Err(Error::with_kind(ErrorKind::Corrupt, "checksum mismatch"));
+        }
+        Ok((mode, stored))
+    }
+
+    /// Get the waste with its metadata. See `put_with_meta` as well.
+    pub fn get_with_meta(&self, hash: &str) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let data = self.get(hash)?;
+        let meta = self.get_meta(hash)?;
+        Ok((data, meta))
+    }
+
+    /// Get only the metadata of the waste. It is empty if the waste is put
+    /// without metadata.
+    pub fn get_meta(&self, hash: &str) -> Result<Vec<u8>, Error> {
+        self.read_meta(self.find_offset(hash)?)
+    }
+
+    /// Read the metadata of the record at the offset.
+    fn read_meta(&self, offset: Offset) -> Result<Vec<u8>, Error> {
+        let (_, size, mut reader) = self.seek_record(offset)?;
+        reader.pos += size + CHECKSUM_SIZE;
+
+        let meta_len = reader.read_varint().to_inner_result("read meta's length")?;
+        let data_len = reader.store.len().to_inner_result("get data file's length")?;
+        let meta_len = match meta_len {
+            Some(len) if len <= data_len.saturating_sub(reader.pos) => len,
+            _ => return Err(Error::with_kind(ErrorKind::Corrupt, "corrupt meta's length")),
+        };
+
+        let mut meta = vec![0u8; meta_len as usize];
+        reader.read_exact(&mut meta).to_inner_result("read meta")?;
+        Ok(meta)
+    }
+
+    /// Get the waste as a reader, which yields exactly the waste's bytes and
+    /// then EOF - so that the waste never needs to be held in memory. The
+    /// checksum is verified at the end, and an error of `InvalidData` is
+    /// raised instead of EOF if it mismatches.
+    ///
+    /// The reader has its own position in the data file, so there can be many
+    /// readers at once.
+    pub fn get_reader(&self, hash: &str) -> Result<Box<dyn Read + '_>, Error> {
+        self.record_reader(self.find_offset(hash)?)
+    }
+
+    /// Get the content of the record at the offset as a reader, as
+    /// `get_reader` does.
+    fn record_reader(&self, offset: Offset) -> Result<Box<dyn Read + '_>, Error> {
+        let (mode, size, reader) = self.seek_record(offset)?;
+        let reader = ChecksumReader::new(reader, size);
+        match mode {
+            CompressionMode::None => Ok(Box::new(reader)),
+            CompressionMode::Zstd => Ok(Box::new(
+                zstd::Decoder::new(reader).to_inner_result("create zstd decoder")?,
+            )),
+        }
+    }
+
+    /// Get at most `len` bytes of the waste from `start` on - fewer if the
+    /// waste ends before - for example, to serve a range of a large video.
+    ///
+    /// The content of an uncompressed waste is read at its position, so the
+    /// bytes out of the slice are never read. Thus the checksum of the whole
+    /// waste is not verified. A compressed waste is still decompressed from
+    /// its start, but only until the end of the slice.
+    pub fn get_slice(&self, hash: &str, start: u64, len: u64) -> Result<Vec<u8>, Error> {
+        let mut slice = vec![];
+        self.get_slice_reader(hash, start, len)?
+            .read_to_end(&mut slice)
+            .to_inner_result("read slice of waste")?;
+        Ok(slice)
+    }
+
+    /// Get the slice of the waste as `get_slice` does, but as a reader - so
+    /// that the slice is never held in memory as a whole either.
+    pub fn get_slice_reader(
+        &self,
+        hash: &str,
+        start: u64,
+        len: u64,
+    ) -> Result<Box<dyn Read + '_>, Error> {
+        let (mode, size, mut reader) = self.seek_record(self.find_offset(hash)?)?;
+        match mode {
+            // All bytes of the content are in the data file, as they are
+            // checked by `seek_record`.
+            CompressionMode::None => {
+                let start = start.min(size);
+                reader.pos += start;
+                Ok(Box::new(reader.take(len.min(size - start))))
+            }
+            CompressionMode::Zstd => {
+                let mut decoder = zstd::Decoder::new(reader.take(size))
+                    .to_inner_result("create zstd decoder")?;
+                io::copy(&mut decoder.by_ref().take(start), &mut io::sink())
+                    .to_inner_result("skip to slice of waste")?;
+                Ok(Box::new(decoder.take(len)))
+            }
+        }
+    }
+
+    /// Read and check the checksum of the waste, without returning its
+    /// content. It is useful to scrub the data file.
+    pub fn verify(&self, hash: &str) -> Result<(), Error> {
+        self.verify_record(self.find_offset(hash)?)
+    }
+
+    /// Check the checksum of the record at the offset, as `verify` does.
+    fn verify_record(&self, offset: Offset) -> Result<(), Error> {
+        let (_, size, mut reader) = self.seek_record(offset)?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buf = vec![0u8; 64 * 1024]; // 64KB.
+        let mut rest = size;
+        while rest > 0 {
+            let n = rest.min(buf.len() as u64) as usize;
+            reader.read_exact(&mut buf[..n]).to_inner_result("read waste")?;
+            hasher.update(&buf[..n]);
+            rest -= n as u64;
+        }
+        if read_checksum(&mut reader)? != hasher.finalize() {
+            return Err(Error::with_kind(ErrorKind::Corrupt, "checksum mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Check every waste indexed: read its record from the data file, hash its
+    /// content again, and compare it with its hash. Everything wrong found is
+    /// collected in the report instead of failing on the first one.
+    ///
+    /// It reads the whole data file, so it is best run while the database is
+    /// idle - for example, by a scrub job after a crash.
+    pub fn verify_all(&self) -> Result<VerifyReport, Error> {
+        let (records, unreadable_pages) = self.indexer.scan();
+        let mut report = VerifyReport {
+            checked: records.len(),
+            unreadable_pages,
+            ..Default::default()
+        };
+        for (hash, offset) in records {
+            let data_len = match self.data.get(offset.shard()) {
+                Some(shard) => shard.len().to_inner_result("get data file's length")?,
+                None => 0,
+            };
+            if offset.to_u64() >= data_len {
+                report.out_of_bounds.push(hash);
+                continue;
+            }
+            match self.hash_record(offset) {
+                Ok(h) if h == hash => {}
+                Ok(_) => report.mismatched.push(hash),
+                Err(_) => report.unreadable.push(hash),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Read the node in the page of the index as it is, for debugging - for
+    /// example, to report a corrupt page precisely, or to check the invariants
+    /// of the index in tests. The head node is in page 0.
+    ///
+    /// The page is read even if it is not referenced by the index any more.
+    /// An error of `ErrorKind::NotFound` is raised if there is no such page.
+    pub fn dump_page(&self, page_id: u32) -> Result<PageDump, Error> {
+        Ok(match self.indexer.dump_page(page_id)? {
+            NodeDump::Head { root } => PageDump::Head { root: root.raw() },
+            NodeDump::Leaf { records, next_leaf } => PageDump::Leaf {
+                records: records
+                    .into_iter()
+                    .map(|(hash, record)| IndexRecord {
+                        hash,
+                        shard: record.value.shard(),
+                        offset: record.value.to_u64(),
+                        refcount: record.refcount,
+                        expires_at: Some(record.expires_at).filter(|at| *at != NEVER_EXPIRES),
+                    })
+                    .collect(),
+                next_leaf: next_leaf.map(|id| id.raw()),
+            },
+            NodeDump::Internal { records, rightest_child } => PageDump::Internal {
+                records: records.into_iter().map(|(hash, child)| (hash, child.raw())).collect(),
+                rightest_child: rightest_child.raw(),
+            },
+        })
+    }
+
+    /// Hash the content of the record at the offset again. The checksum is
+    /// never checked, so that the hash is compared even if it mismatches.
+    fn hash_record(&self, offset: Offset) -> Result<String, Error> {
+        let (mode, size, reader) = self.seek_record(offset)?;
+        let reader = reader.take(size);
+        let mut hasher = H::default();
+        match mode {
+            CompressionMode::None => copy_and_hash(reader, &mut io::sink(), &mut hasher, None)?,
+            CompressionMode::Zstd => {
+                let decoder = zstd::Decoder::new(reader).to_inner_result("create zstd decoder")?;
+                copy_and_hash(decoder, &mut io::sink(), &mut hasher, None)?
+            }
+        }
+        Ok(hasher::to_hex(&hasher.finalize()))
+    }
+
+    /// Get the offset and the stored length of the waste, without reading its
+    /// content - only the head of its record is read.
+    pub fn stat(&self, hash: &str) -> Result<WasteStat, Error> {
+        let offset = self.find_offset(hash)?;
+        let (compression, len, _) = self.seek_record(offset)?;
+        Ok(WasteStat { shard: offset.shard(), offset: offset.to_u64(), len, compression })
+    }
+
+    /// Find the offset of the waste in the data file.
+    fn find_offset(&self, hash: &str) -> Result<Offset, Error> {
+        let offset = self.indexer.get(hash).to_inner_result("get offset by hash")?;
+        offset.ok_or_else(|| Error::with_kind(ErrorKind::NotFound, "hash not found"))
+    }
+
+    /// Read the head of the record at the offset, and return its compression
+    /// mode, stored size and a reader just at the content.
+    fn seek_record(&self, offset: Offset) -> Result<(CompressionMode, u64, DataReader<'_>), Error> {
+        let store = self.data_shard(offset)?;
+        let mut reader = DataReader { store, pos: offset.to_u64() };
+
+        // The index and the data file are out of sync - for example, the data
+        // file is truncated - so there is no record at all.
+        let data_len = store.len().to_inner_result("get data file's length")?;
+        if offset.to_u64() >= data_len {
+            return Err(past_end_error(offset));
+        }
+
+        let mut flag = [0u8; 1];
+        reader.read_exact(&mut flag).to_inner_result("read compression flag")?;
+        let mode = CompressionMode::from_flag(flag[0])?;
+
+        let size = reader.read_varint().to_inner_result("read size")?;
+
+        // The size maybe is broken if the data file is truncated or corrupt.
+        // At least the checksum and a one-byte meta's length follow it.
+        match size {
+            Some(size) if size <= data_len.saturating_sub(reader.pos + CHECKSUM_SIZE + 1) => {
+                Ok((mode, size, reader))
+            }
+            _ => Err(Error::with_kind(ErrorKind::Corrupt, "corrupt length")),
+        }
+    }
+
+    /// Check if the waste is stored. Only the index is consulted - the data
+    /// file is never read.
+    pub fn contains(&self, hash: &str) -> Result<bool, Error> {
+        if self.cache.lock().unwrap().contains(hash) {
+            return Ok(true);
+        }
+
+        let offset = self.indexer.get(hash).to_inner_result("get offset by hash")?;
+        Ok(offset.is_some())
+    }
+
+    /// Check if many wastes are stored at once, in the same order as `hashes` -
+    /// for example, to find which of a manifest of hashes are new before
+    /// uploading them. The same hash can be given many times.
+    ///
+    /// The index is looked up in the order of hashes, as `get_many` does, so
+    /// the hashes in the same leaf need one descent only.
+    pub fn contains_many(&self, hashes: &[&str]) -> Result<Vec<bool>, Error> {
+        let offsets = self.indexer
+            .get_many(hashes)
+            .to_inner_result("get offsets by hashes")?;
+        Ok(offsets.into_iter().map(|offset| offset.is_some()).collect())
+    }
+
+    /// Delete the waste by its hash. Return `true` if the waste was existing.
+    ///
+    /// The same waste maybe is put many times, so it is only gone once it is
+    /// deleted as many times as it is put - and then its bytes are left in
+    /// the data file to be reclaimed.
+    pub fn delete(&mut self, hash: &str) -> Result<bool, Error> {
+        self.check_writable()?;
+        self.cache.get_mut().unwrap().pop(hash);
+
+        let record = self.indexer.get_record(hash).to_inner_result("get offset by hash")?;
+        let deleted = self.indexer.delete(hash).to_inner_result("delete offset by hash")?;
+        if deleted {
+            // The record is not referenced any more once its last reference
+            // is deleted.
+            if let Some((offset, 1, _)) = record {
+                self.add_garbage(self.record_len(offset).unwrap_or(0))?;
+                if let Some(labels) = &mut self.labels {
+                    labels.remove(hash).to_inner_result("remove label")?;
+                }
+            }
+            self.sync_after_write()?;
+        }
+        Ok(deleted)
+    }
+
+    /// Pick up the wastes put and deleted by another process writing the
+    /// database - for example, if it is opened by `open_read_only` - without
+    /// opening it again. Return `true` if anything is changed since it is
+    /// opened or refreshed last time.
+    ///
+    /// It is cheap if nothing is changed: only the head of the index is read.
+    /// Otherwise the cached pages of the index and the cached wastes are all
+    /// dropped. The wastes are appended to the data file before they are
+    /// indexed, so the wastes found are always complete.
+    pub fn refresh(&mut self) -> Result<bool, Error> {
+        let mut changed = self.indexer.refresh()?;
+        if let Some(names) = &mut self.names {
+            changed |= names.refresh()?;
+        }
+        if let Some(labels) = &mut self.labels {
+            changed |= labels.refresh()?;
+        }
+        if changed {
+            self.cache.get_mut().unwrap().clear();
+            self.remap()?;
+        }
+        Ok(changed)
+    }
+
+    /// Is the database opened in read-only mode?
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Return an error if the database is opened in read-only mode.
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::new("database is read-only"));
+        }
+        Ok(())
+    }
+
+    /// Flush all pending writes of the data file and the index.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.indexer.flush().to_inner_result("flush indexer")?;
+        if let Some(names) = &mut self.names {
+            names.flush().to_inner_result("flush names")?;
+        }
+        if let Some(labels) = &mut self.labels {
+            labels.flush().to_inner_result("flush labels")?;
+        }
+        Ok(())
+    }
+
+    /// Flush all pending writes, and make sure they are on the disk by `fsync`
+    /// both the data file and the index. It is slow - see `SyncPolicy` for the
+    /// tradeoff.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        for shard in &self.data {
+            shard.sync().to_inner_result("fsync data file")?;
+        }
+        self.indexer.sync().to_inner_result("sync indexer")?;
+        if let Some(names) = &mut self.names {
+            names.sync().to_inner_result("sync names")?;
+        }
+        if let Some(labels) = &mut self.labels {
+            labels.sync().to_inner_result("sync labels")?;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Sync by the `SyncPolicy` after a write.
+    fn sync_after_write(&mut self) -> Result<(), Error> {
+        if self.should_sync() {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Sync the data file just appended to, before the records in it are
+    /// indexed, if the write is going to be synced by the `SyncPolicy`. Then
+    /// the index synced never references records lost by a power loss.
+    fn sync_data_before_index(&self) -> Result<(), Error> {
+        if self.should_sync() {
+            for shard in &self.data {
+                shard.sync().to_inner_result("fsync data file")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Is the write going to be synced by the `SyncPolicy`?
+    fn should_sync(&self) -> bool {
+        match self.sync_policy {
+            SyncPolicy::Never => false,
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::Periodic(period) => self.last_sync.elapsed() >= period,
+            SyncPolicy::Background(_) => false,
+        }
+    }
+
+    /// Reclaim the space of the records not referenced any more - deleted, or
+    /// put again later. The records still referenced are copied into a fresh
+    /// `data.tmp` one by one, in the order of the data file, and indexed in a
+    /// fresh `index.tmp`. Then both of them replace the origin files. Each
+    /// shard of the data file is copied into its own `.tmp` file, if it is
+    /// sharded.
+    ///
+    /// Only the index is held in memory - never the wastes. It is safe to be
+    /// interrupted: the origin files are untouched until `index.tmp` replaces
+    /// the index, and the left `data.tmp` replaces the data file the next time
+    /// the database is opened.
+    ///
+    /// It is not supported if the database is in stores.
+    pub fn compact(&mut self) -> Result<CompactStats, Error> {
+        self.check_writable()?;
+        // The thread of `SyncPolicy::Background` is stopped first, and the
+        // pages deferred for it are written, as `backup_to` does. Otherwise it
+        // writes the pages of the origin index after it is replaced - and the
+        // WAL, whose path the new index shares. It is restarted once the new
+        // index is open.
+        let compacted = match self.flusher.take() {
+            Some(_) => self.sync(),
+            None => self.flush(),
+        }
+        .and_then(|()| self.compact_files());
+        if compacted.is_err() {
+            self.restart_flusher();
+        }
+        compacted
+    }
+
+    /// Compact the files once nothing else writes them. See `compact`.
+    fn compact_files(&mut self) -> Result<CompactStats, Error> {
+        let path = self.path()?.to_path_buf();
+        let data_path = self.data_path()?.to_path_buf();
+        let bytes_before = self.data_len()?;
+        let shards = self.data.len();
+
+        let tmp_index_path = path.join("index.tmp");
+        Self::remove_compaction(&path, &data_path, shards)?;
+        // The temporary index is created before the temporary data files, as
+        // the data files left without it mean the compaction is done.
+        let mut tmp_indexer = Indexer::open_file(&tmp_index_path, H::NAME, self.indexer.page_size())
+            .to_inner_result("open temporary indexer")?;
+
+        // The expired records are dropped, as if they were swept.
+        let now = now_millis();
+        let mut records = self.indexer.records()?;
+        records.retain(|(_, _, _, expires_at)| *expires_at > now);
+        records.sort_by_key(|(_, offset, _, _)| *offset);
+        let mut stats = CompactStats {
+            bytes_before,
+            bytes_after: 0,
+            records_kept: records.len() as u64,
+            records_dropped: 0,
+        };
+
+        let mut records = records.into_iter().peekable();
+        let mut batch = Vec::with_capacity(COMPACT_BATCH_LEN);
+        for shard in 0..shards {
+            let mut tmp_data = fs::File::options()
+                .write(true)
+                .read(true)
+                .create_new(true)
+                .open(data_path.join(tmp_data_name(shard, shards)))
+                .to_inner_result("create temporary data file")?;
+
+            let mut pos = 0;
+            while let Some((hash, offset, refcount, expires_at)) =
+                records.next_if(|(_, offset, _, _)| offset.shard() == shard)
+            {
+                stats.records_dropped += self.count_records(shard, pos, offset.to_u64());
+                let len = self.record_len(offset)?;
+                let reader = DataReader { store: &*self.data[shard], pos: offset.to_u64() };
+                let new_offset = tmp_data.stream_position().to_inner_result("get offset")?;
+                let copied = io::copy(&mut reader.take(len), &mut tmp_data)
+                    .to_inner_result("copy record")?;
+                if copied != len {
+                    return Err(Error::with_kind(ErrorKind::Corrupt, "truncated record"));
+                }
+                pos = offset.to_u64() + len;
+
+                let new_offset = Offset::with_shard(shard as u8, new_offset);
+                batch.push((hash, new_offset, refcount, expires_at));
+                if batch.len() == COMPACT_BATCH_LEN {
+                    tmp_indexer.put_batch_with_refcounts(&batch)?;
+                    batch.clear();
+                }
+            }
+            let shard_len = self.data[shard].len().to_inner_result("get data file's length")?;
+            stats.records_dropped += self.count_records(shard, pos, shard_len);
+            stats.bytes_after += tmp_data.stream_position().to_inner_result("get offset")?;
+            tmp_data.sync_all().to_inner_result("fsync temporary data file")?;
+        }
+        if records.next().is_some() {
+            return Err(Error::with_kind(ErrorKind::Corrupt, "unknown shard of data file"));
+        }
+        tmp_indexer.put_batch_with_refcounts(&batch)?;
+
+        tmp_indexer.set_uuid(self.indexer.uuid())?;
+        tmp_indexer.sync().to_inner_result("sync temporary indexer")?;
+        drop(tmp_indexer);
+        // The WAL of the temporary index is always empty once it is synced.
+        fs::remove_file(path.join("index.tmp.wal"))
+            .to_inner_result("remove WAL of temporary index")?;
+
+        // Once the index is replaced, the compaction is done - even if the
+        // data file is not replaced yet. See `recover_compaction`.
+        fs::rename(&tmp_index_path, path.join("index"))
+            .to_inner_result("replace index file")?;
+        Self::recover_compaction(&path, &data_path, shards)?;
+
+        self.data = Self::open_data(&data_path, shards, self.preallocation)
+            .to_inner_result("open data file")?;
+        self.indexer = Indexer::open(&path, H::NAME, self.indexer.page_size())
+            .to_inner_result("open indexer")?;
+        self.cache.get_mut().unwrap().clear();
+        self.restart_flusher();
+        self.remap()?;
+        self.sync_after_write()?;
+        Ok(stats)
+    }
+
+    /// Get the length of the whole record at the offset, including its header
+    /// and metadata.
+    fn record_len(&self, offset: Offset) -> Result<u64, Error> {
+        let corrupt = || Error::with_kind(ErrorKind::Corrupt, "corrupt record");
+        let mut reader = DataReader { store: self.data_shard(offset)?, pos: offset.to_u64() + 1 };
+        let size = reader.read_varint().to_inner_result("read size")?.ok_or_else(corrupt)?;
+        reader.pos = reader.pos.checked_add(size + CHECKSUM_SIZE).ok_or_else(corrupt)?;
+        let meta_len = reader.read_varint().to_inner_result("read meta's length")?
+            .ok_or_else(corrupt)?;
+        let end = reader.pos.checked_add(meta_len).ok_or_else(corrupt)?;
+        Ok(end - offset.to_u64())
+    }
+
+    /// Count the records in `[start, end)` of the shard of the data file. The
+    /// bytes which can not be parsed as records - left by a failed
+    /// `put_reader` - are counted as one record.
+    fn count_records(&self, shard: usize, start: u64, end: u64) -> u64 {
+        let mut count = 0;
+        let mut offset = Offset::with_shard(shard as u8, start);
+        while offset.to_u64() < end {
+            count += 1;
+            match self.record_len(offset) {
+                Ok(len) if offset.to_u64() + len <= end => offset = offset.advance(len),
+                _ => break,
+            }
+        }
+        count
+    }
+
+    /// Finish or roll back the compaction interrupted last time. The index
+    /// replaced means it is done except for replacing the shards of the data
+    /// file left.
+    fn recover_compaction(
+        database_path: &Path,
+        data_path: &Path,
+        shards: usize,
+    ) -> Result<(), Error> {
+        if database_path.join("index.tmp").exists() {
+            return Self::remove_compaction(database_path, data_path, shards);
+        }
+        for shard in 0..shards {
+            let tmp_data_path = data_path.join(tmp_data_name(shard, shards));
+            if tmp_data_path.exists() {
+                // The end of the origin data file is removed first, as it is
+                // wrong for the compacted one - which is never preallocated.
+                let shard_path = data_path.join(data_name(shard, shards));
+                match fs::remove_file(DataFile::end_path(&shard_path)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                        return Err(e).to_inner_result("remove end of data file");
+                    }
+                    _ => {}
+                }
+                fs::rename(tmp_data_path, shard_path).to_inner_result("replace data file")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the temporary files of an unfinished compaction.
+    fn remove_compaction(
+        database_path: &Path,
+        data_path: &Path,
+        shards: usize,
+    ) -> Result<(), Error> {
+        let files = (0..shards)
+            .map(|shard| data_path.join(tmp_data_name(shard, shards)))
+            .chain(["index.tmp", "index.tmp.wal"].map(|name| database_path.join(name)));
+        for file in files {
+            match fs::remove_file(&file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e).to_inner_result(&format!("remove {}", file.display()));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild the index of the database at the given path, whose hashes are
+    /// generated by `H`, from its data file - for example, if the index is
+    /// lost or corrupt while the data file is not. The database must not be
+    /// opened by anyone else.
+    ///
+    /// The index is removed first. Then each shard of the data file is scanned
+    /// from its start, and each record whose checksum matches is hashed again
+    /// and indexed - the last one wins if a waste is found many times. A broken
+    /// record ends the scan of its shard, as the records after it can not be
+    /// found: it is the truncated last record after a crash, or the bytes left
+    /// by a failed `put_reader`.
+    ///
+    /// The counts of references, the expiry times and the deletions are only
+    /// in the index, so each waste found is referenced once and never expires.
+    /// And the deleted wastes not compacted yet are back. All bytes of the data
+    /// file not indexed again are counted as garbage.
+    pub fn rebuild_index_with_hasher<P>(database_path: P) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+    {
+        let database_path = database_path.as_ref();
+        if Self::read_version(database_path)?.is_none() {
+            return Err(Error::with_kind(ErrorKind::NotFound, "database not found"));
+        }
+        for name in ["index", "index.wal"] {
+            match fs::remove_file(database_path.join(name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e).to_inner_result(&format!("remove {}", name));
+                }
+                _ => {}
+            }
+        }
+
+        let mut database = Self::with_hasher(database_path)?;
+        let mut records = BTreeMap::new();
+        for shard in 0..database.data.len() {
+            let shard_len = database.data[shard].len().to_inner_result("get data file's length")?;
+            let mut offset = Offset::with_shard(shard as u8, 0);
+            while offset.to_u64() < shard_len {
+                let len = match database.record_len(offset) {
+                    Ok(len) if offset.to_u64() + len <= shard_len => len,
+                    _ => break,
+                };
+                let hash = database.verify_record(offset).and_then(|()| database.hash_record(offset));
+                if let Ok(hash) = hash {
+                    records.insert(hash, (offset, len));
+                }
+                offset = offset.advance(len);
+            }
+        }
+
+        let indexed_bytes: u64 = records.values().map(|(_, len)| len).sum();
+        let records: Vec<(String, Offset, u32, u64)> = records
+            .into_iter()
+            .map(|(hash, (offset, _))| (hash, offset, 1, NEVER_EXPIRES))
+            .collect();
+        database.indexer.bulk_load(&records)?;
+        let garbage_bytes = database.data_len()?.saturating_sub(indexed_bytes);
+        database.add_garbage(garbage_bytes)?;
+        database.sync()
+    }
+
+    /// The header line of archives by `export`.
+    fn archive_header(count: usize) -> String {
+        format!(
+            "{} archive version={} hash={} count={}\n",
+            VERSION_MAGIC, ARCHIVE_VERSION, H::NAME, count,
+        )
+    }
+
+    /// Read and check the header line of the archive, and return the count of
+    /// records in it.
+    fn read_archive_header(input: &mut impl Read) -> Result<usize, Error> {
+        let unsupported = || Error::with_kind(ErrorKind::Unsupported, "unsupported archive");
+        let mut header = vec![];
+        let mut byte = [0u8; 1];
+        while header.last() != Some(&b'\n') {
+            if header.len() == 256 {
+                return Err(unsupported());
+            }
+            match input.read_exact(&mut byte) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(unsupported()),
+                r => r.to_inner_result("read archive's header")?,
+            }
+            header.push(byte[0]);
+        }
+        let header = String::from_utf8(header).map_err(|_| unsupported())?;
+        let count = header
+            .split_whitespace()
+            .find_map(|f| f.strip_prefix("count="))
+            .and_then(|count| count.parse().ok());
+        match count {
+            Some(count) if header == Self::archive_header(count) => Ok(count),
+            _ => Err(unsupported()),
+        }
+    }
+
+    /// Export all wastes to a single archive, which can be imported by
+    /// `import` - for example, to back the database up or to move it. Unlike
+    /// the data file and the index, the archive does not depend on their
+    /// format: the wastes are always uncompressed in it.
+    ///
+    /// The archive is a header line - the magic, the version of archives, the
+    /// hash algorithm and the count of records - and then the records in
+    /// ascending order of hashes. Each of them is the hash, and then the
+    /// reference count, the expiry time, the metadata and the content, each of
+    /// the last two after its length. The numbers are varints. The expired
+    /// wastes are never exported.
+    ///
+    /// It is streamed record by record, so only the index is held in memory -
+    /// never the wastes.
+    pub fn export<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        let now = now_millis();
+        let mut records = self.indexer.records()?;
+        records.retain(|(_, _, _, expires_at)| *expires_at > now);
+        out.write_all(Self::archive_header(records.len()).as_bytes())
+            .to_inner_result("write archive's header")?;
+        for (hash, offset, refcount, expires_at) in records {
+            let meta = self.read_meta(offset)?;
+            let (mode, size, _) = self.seek_record(offset)?;
+            let len = match mode {
+                CompressionMode::None => size,
+                // The length of the origin content is not stored, so it is
+                // decompressed once to get it.
+                CompressionMode::Zstd => io::copy(&mut self.record_reader(offset)?, &mut io::sink())
+                    .to_inner_result("read waste's data")?,
+            };
+
+            out.write_all(Hash::from_str(&hash)?.as_slice())
+                .to_inner_result("write waste's hash")?;
+            out.write_all(&varint::encode(refcount as u64))
+                .to_inner_result("write waste's refcount")?;
+            out.write_all(&varint::encode(expires_at))
+                .to_inner_result("write waste's expiry time")?;
+            out.write_all(&varint::encode(meta.len() as u64))
+                .to_inner_result("write meta's length")?;
+            out.write_all(&meta).to_inner_result("write meta")?;
+            out.write_all(&varint::encode(len)).to_inner_result("write waste's length")?;
+            io::copy(&mut self.record_reader(offset)?, &mut out)
+                .to_inner_result("write waste's data")?;
+        }
+        out.flush().to_inner_result("flush archive")
+    }
+
+    /// Import all wastes from the archive by `export` - usually into an empty
+    /// database. The wastes keep their metadata and reference counts, and are
+    /// compressed as this database is configured.
+    ///
+    /// The wastes are streamed, and their hashes are checked: an error of
+    /// `Corrupt` is raised if the archive is broken. If it fails, the wastes
+    /// imported before are kept - unless the database was empty.
+    ///
+    /// The records of the archive are in ascending order of hashes, so they
+    /// are bulk loaded into the index if the database is empty, instead of
+    /// being put one by one - see `BTree::bulk_load`. Then only the index is
+    /// held in memory, never the wastes.
+    ///
+    /// The wastes already stored are not appended again, but their reference
+    /// counts are added - and they keep their own metadata.
+    pub fn import<R: Read>(&mut self, mut input: R) -> Result<(), Error> {
+        self.check_writable()?;
+        let count = Self::read_archive_header(&mut input)?;
+
+        let corrupt = |msg| Error::with_kind(ErrorKind::Corrupt, msg);
+        let bulk_load = self.indexer.len() == 0;
+        let mut batch = Vec::with_capacity(COMPACT_BATCH_LEN.min(count));
+        let mut last_hash = None;
+        let mut replaced = 0;
+        for i in 0..count {
+            let mut hash = [0u8; HASH_SIZE];
+            match input.read_exact(&mut hash) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(corrupt("truncated archive"));
+                }
+                r => r.to_inner_result("read waste's hash")?,
+            }
+            let hash = Hash::from_bytes(hash);
+            if last_hash.is_some_and(|last_hash| last_hash >= hash) {
+                return Err(corrupt("unordered archive"));
+            }
+            last_hash = Some(hash);
+            let hash = hash.to_string();
+            let refcount = read_archive_varint(&mut input)?;
+            let refcount = match u32::try_from(refcount) {
+                Ok(refcount) if refcount > 0 => refcount,
+                _ => return Err(corrupt("corrupt refcount in archive")),
+            };
+            let expires_at = read_archive_varint(&mut input)?;
+
+            let meta_len = read_archive_varint(&mut input)?;
+            let mut meta = vec![];
+            (&mut input).take(meta_len).read_to_end(&mut meta).to_inner_result("read meta")?;
+            if meta.len() as u64 != meta_len {
+                return Err(corrupt("truncated archive"));
+            }
+
+            let len = read_archive_varint(&mut input)?;
+            check_value_size(len, self.max_value_size)?;
+            let mut data = (&mut input).take(len);
+            let existing = match bulk_load {
+                true => None,
+                false => self.indexer.get(&hash).to_inner_result("get offset by hash")?,
+            };
+            let (data_hash, offset) = match existing {
+                // The waste already stored is not written again, as `put_batch`
+                // does - but its data is still checked.
+                Some(offset) => {
+                    let mut hasher = H::default();
+                    copy_and_hash(&mut data, &mut io::sink(), &mut hasher, self.max_value_size)?;
+                    (hasher::to_hex(&hasher.finalize()), offset)
+                }
+                None => self.write_reader(&mut data, &meta, Some(&hash))?,
+            };
+            if data.limit() != 0 {
+                return Err(corrupt("truncated archive"));
+            }
+            if data_hash != hash {
+                return Err(corrupt("hash mismatch in archive"));
+            }
+
+            if !bulk_load {
+                replaced += self.replaced_len(&hash, offset)?;
+            }
+            batch.push((hash, offset, refcount, expires_at));
+            if !bulk_load && (batch.len() == COMPACT_BATCH_LEN || i == count - 1) {
+                self.sync_data_before_index()?;
+                self.indexer.put_batch_with_refcounts(&batch)?;
+                self.add_garbage(replaced)?;
+                replaced = 0;
+                batch.clear();
+            }
+        }
+        if bulk_load {
+            self.sync_data_before_index()?;
+            self.indexer.bulk_load(&batch)?;
+        }
+        self.remap()?;
+        self.sync_after_write()?;
+        Ok(())
+    }
+
+    /// Back the database up to the directory `dest`, which can be opened as a
+    /// database itself. The pending writes are flushed first.
+    ///
+    /// The data file is only appended, so each of its shards is copied up to
+    /// its length now - and then the index, which only references the records
+    /// below it. The `version` file is copied last, so that an interrupted
+    /// backup is never taken as a database. The backup of a split database is
+    /// not split: all of it is in `dest`.
+    ///
+    /// It is not supported if the database is in stores, or `dest` holds a
+    /// database already.
+    pub fn backup_to<P: AsRef<Path>>(&mut self, dest: P) -> Result<(), Error> {
+        let path = self.path()?.to_path_buf();
+        let dest = dest.as_ref();
+        // The index is only written once the data file is synced by
+        // `SyncPolicy::Background`.
+        match self.sync_policy {
+            SyncPolicy::Background(_) => self.sync()?,
+            _ => self.flush()?,
+        }
+        fs::create_dir_all(dest).to_inner_result("create backup directory")?;
+        if dest.join("version").exists() {
+            return Err(Error::with_kind(
+                ErrorKind::Unsupported,
+                "there is a database in the backup directory already",
+            ));
+        }
+
+        let shards = self.data.len();
+        for (shard, store) in self.data.iter().enumerate() {
+            let len = store.len().to_inner_result("get data file's length")?;
+            let reader = DataReader { store: &**store, pos: 0 };
+            copy_to_new_file(reader.take(len), &dest.join(data_name(shard, shards)))?;
+        }
+        for name in ["index", "names", "names.data", "labels", "labels.data"] {
+            let file = match fs::File::open(path.join(name)) {
+                Ok(file) => file,
+                // There is no name or label if it is opened in read-only mode.
+                Err(e) if e.kind() == io::ErrorKind::NotFound && name != "index" => {
+                    continue;
+                }
+                Err(e) => return Err(e).to_inner_result(&format!("open {}", name)),
+            };
+            copy_to_new_file(file, &dest.join(name))?;
+        }
+        let version = Self::version(&Layout {
+            page_size: self.indexer.page_size(),
+            shards,
+            uuid: self.indexer.uuid(),
+            data_dir: None,
+        });
+        copy_to_new_file(version.as_bytes(), &dest.join("version"))?;
+        Ok(())
+    }
+
+    /// Remove the whole database directory - and the data directory if it is
+    /// split - so all wastes are gone. It is not supported if the database is
+    /// in stores.
+    pub fn destroy(mut self) -> Result<(), Error> {
+        self.flusher = None;
+        let path = self.path()?;
+        if let Some(data_dir) = &self.data_dir {
+            fs::remove_dir_all(data_dir)
+                .to_inner_result(&format!("remove directory {}", data_dir.display()))?;
+        }
+        fs::remove_dir_all(path)
+            .to_inner_result(&format!("remove directory {}", path.display()))?;
+        Ok(())
+    }
+}
+
+impl<H: WasteHasher> Drop for Database<H> {
+    /// Flush all pending writes - and sync them unless the policy is
+    /// `SyncPolicy::Never`. It is best effort: call `flush` or `sync` instead
+    /// if you care about the error.
+    fn drop(&mut self) {
+        // The background thread is joined first, so that nothing is synced
+        // after the last sync here.
+        self.flusher = None;
+        let _ = match self.sync_policy {
+            SyncPolicy::Never => self.flush(),
+            _ => self.sync(),
+        };
+    }
+}
+
+impl<'a, H: WasteHasher> IntoIterator for &'a Database<H> {
+    type Item = Result<String, Error>;
+    type IntoIter = DatabaseIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Iterator for DatabaseIter<'_> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.hashes.next()
+    }
+}
+
+impl<H: WasteHasher> Iterator for DatabaseValues<'_, H> {
+    type Item = Result<(String, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let now = now_millis();
+        loop {
+            let (hash, offset, _, expires_at) = match self.records.next()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+            if expires_at > now {
+                return Some(self.database.read_waste(offset).map(|content| (hash, content)));
+            }
+        }
+    }
+}
+
+/// The name of the shard of the data file - just `data` if it is not sharded.
+fn data_name(shard: usize, shards: usize) -> String {
+    if shards == 1 {
+        "data".to_string()
+    } else {
+        format!("data.{:03}", shard)
+    }
+}
+
+/// The name of the shard of the data file being compacted.
+fn tmp_data_name(shard: usize, shards: usize) -> String {
+    format!("{}.tmp", data_name(shard, shards))
+}
+
+/// Check the directory of a new database holds nothing but the files of a
+/// database - so that a directory of anything else is never written.
+fn check_database_dir(path: &Path) -> Result<(), Error> {
+    let entries = fs::read_dir(path).to_inner_result("read database directory")?;
+    for entry in entries {
+        let entry = entry.to_inner_result("read database directory")?;
+        if !entry.file_name().to_str().is_some_and(is_database_file) {
+            return Err(Error::new(
+                "refusing to initialize over non-empty non-database directory",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Is it the name of a file of a database - the data file, the index, the
+/// names, the labels, or the temporary files and the ends of them?
+fn is_database_file(name: &str) -> bool {
+    let name = name.strip_suffix(".end").unwrap_or(name);
+    let name = name.strip_suffix(".wal").unwrap_or(name);
+    let name = name.strip_suffix(".tmp").unwrap_or(name);
+    match name.strip_prefix("data.") {
+        Some(shard) => shard.len() == 3 && shard.bytes().all(|b| b.is_ascii_digit()),
+        None => {
+            ["data", "index", "names", "names.data", "labels", "labels.data", "version"]
+                .contains(&name)
+        }
+    }
+}
+
+/// A reader of the data file by positional reads, so that many of them can
+/// read at once.
+pub(crate) struct DataReader<'a> {
+    pub(crate) store: &'a dyn BlockStore,
+    pub(crate) pos: u64,
+}
+
+impl DataReader<'_> {
+    /// Read a varint by only one positional read. `None` is returned if the
+    /// varint is broken.
+    pub(crate) fn read_varint(&mut self) -> io::Result<Option<u64>> {
+        let mut buf = [0u8; VARINT_MAX_LEN];
+        let n = self.store.read_at(&mut buf, self.pos)?;
+        Ok(varint::decode(&buf[..n]).map(|(value, len)| {
+            self.pos += len as u64;
+            value
+        }))
+    }
+}
+
+impl Read for DataReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.store.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// A writer of the data file by positional writes from `pos` on - it is
+/// always the end of the data file when a record is appended.
+struct DataWriter<'a> {
+    store: &'a dyn BlockStore,
+    pos: u64,
+}
+
+impl Write for DataWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.store.write_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The temporary file a streamed waste is spooled into to be hashed, before it
+/// is put into the shard of its hash. It is removed once dropped.
+struct Spool {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl Spool {
+    fn create() -> io::Result<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir()
+            .join(format!("waste-island-{}-{}.spool", std::process::id(), id));
+        let file = fs::File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self { path, file })
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Write the metadata as the tail of the record.
+fn write_meta(writer: &mut impl Write, meta: &[u8]) -> Result<(), Error> {
+    writer.write_all(&varint::encode(meta.len() as u64))
+        .to_inner_result("write meta's length")?;
+    writer.write_all(meta).to_inner_result("write meta")?;
+    Ok(())
+}
+
+/// Read the checksum just after the stored content.
+fn read_checksum(reader: &mut impl Read) -> Result<u32, Error> {
+    let mut checksum = [0u8; CHECKSUM_SIZE as usize];
+    reader.read_exact(&mut checksum).to_inner_result("read checksum")?;
+    Ok(u32::from_le_bytes(checksum))
+}
+
+/// The error of the offset in the index which is beyond the end of its data
+/// file.
+fn past_end_error(offset: Offset) -> Error {
+    Error::with_kind(
+        ErrorKind::Corrupt,
+        format!(
+            "index points past end of data: offset {} of shard {}",
+            offset.to_u64(),
+            offset.shard(),
+        ),
+    )
+}
+
+/// Return an error of `ErrorKind::TooLarge` if the length of the waste is
+/// larger than the max one.
+fn check_value_size(len: u64, max_len: Option<u64>) -> Result<(), Error> {
+    match max_len {
+        Some(max_len) if len > max_len => {
+            Err(Error::with_kind(ErrorKind::TooLarge, "value too large"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Copy all bytes from the reader to a new file, and `fsync` it.
+fn copy_to_new_file(mut reader: impl Read, path: &Path) -> Result<(), Error> {
+    let mut file = fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .to_inner_result(&format!("create {}", path.display()))?;
+    io::copy(&mut reader, &mut file).to_inner_result(&format!("copy to {}", path.display()))?;
+    file.sync_all().to_inner_result(&format!("fsync {}", path.display()))
+}
+
+/// Read a varint of the archive by `Database::export`.
+fn read_archive_varint(input: &mut impl Read) -> Result<u64, Error> {
+    varint::read(input)
+        .to_inner_result("read archive")?
+        .ok_or_else(|| Error::with_kind(ErrorKind::Corrupt, "corrupt varint in archive"))
+}
+
+/// Copy all bytes from the reader to the writer, and feed them to the hasher.
+/// An error is raised before writing the bytes beyond `max_len`.
+fn copy_and_hash<R, W, H>(
+    mut reader: R,
+    writer: &mut W,
+    hasher: &mut H,
+    max_len: Option<u64>,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+    H: WasteHasher,
+{
+    let mut buf = vec![0u8; 64 * 1024]; // 64KB.
+    let mut len = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).to_inner_result("read waste's data"),
+        };
+        len += n as u64;
+        check_value_size(len, max_len)?;
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n]).to_inner_result("write waste's data")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use rand::{self, seq::SliceRandom};
+    use benchmark::picture_cache::PictureCache;
+
+    use super::*;
+    use std::time::Duration;
+    use crate::{hasher::Blake3, store::MemStore};
+    use rand::Rng;
+
+    fn clean_up(database_path: &str) {
+        match fs::remove_dir_all(database_path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                panic!("{}", e)
+            }
+            _ => (),
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let waste_hash = database.put(b"hello world").unwrap();
+        let waste2_hash = database.put(b"hello world again").unwrap();
+        assert_eq!(database.get(&waste_hash).unwrap(), b"hello world");
+        assert_eq!(database.get(&waste2_hash).unwrap(), b"hello world again");
+    }
+
+    #[test]
+    fn it_sizes_the_cache_by_the_wastes_indexed() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-sizes-the-cache-by-the-wastes-indexed";
+        clean_up(database_path);
+        let cache_cap = |database: &Database| database.cache.lock().unwrap().cap().get();
+
+        {
+            let mut database = Database::new(database_path).unwrap();
+            for i in 0..200 {
+                database.put(format!("content number {}", i).as_bytes()).unwrap();
+            }
+        }
+        let mut database = Database::new(database_path).unwrap();
+        let hash = database.put(b"content number 0").unwrap();
+        assert_eq!(database.len().unwrap(), 200);
+        assert_eq!(cache_cap(&database), 200 / 4 + 16);
+
+        // The waste referenced twice is still indexed after it is deleted once.
+        assert!(database.delete(&hash).unwrap());
+        database.put(b"content number 1").unwrap();
+        assert_eq!(database.len().unwrap(), 200);
+        assert_eq!(cache_cap(&database), 200 / 4 + 16);
+        assert!(database.delete(&hash).unwrap());
+        assert_eq!(database.len().unwrap(), 199);
+    }
+
+    #[test]
+    fn it_puts_duplicates_once() {
+        let data = MemStore::new();
+        let mut database = Database::new_in(data.clone(), MemStore::new()).unwrap();
+
+        let (hash, inserted) = database.put_status(b"hello world").unwrap();
+        assert!(inserted);
+        let data_len = data.len().unwrap();
+        assert_eq!(database.put_status(b"hello world").unwrap(), (hash.clone(), false));
+        assert_eq!(database.put(b"hello world").unwrap(), hash);
+        assert_eq!(data.len().unwrap(), data_len);
+        assert_eq!(database.len().unwrap(), 1);
+
+        // It is still put three times, and then deleted three times.
+        for _ in 0..3 {
+            assert_eq!(database.get(&hash).unwrap(), b"hello world");
+            assert!(database.delete(&hash).unwrap());
+        }
+        assert!(!database.delete(&hash).unwrap());
+        assert!(database.put_status(b"hello world").unwrap().1);
+
+        // The expired waste is new as well, even if it is not swept yet.
+        let hash = database.put_with_ttl(b"I will be back.", Duration::from_millis(0)).unwrap();
+        let data_len = data.len().unwrap();
+        assert_eq!(database.put_status(b"I will be back.").unwrap(), (hash.clone(), true));
+        assert_eq!(database.put_status(b"I will be back.").unwrap(), (hash.clone(), false));
+        assert_eq!(database.get(&hash).unwrap(), b"I will be back.");
+        assert_eq!(data.len().unwrap(), data_len);
+        assert_eq!(database.len().unwrap(), 2);
+    }
+
+    #[test]
+    fn it_puts_duplicates_in_batch_once() {
+        let data = MemStore::new();
+        let mut database = Database::new_in(data.clone(), MemStore::new()).unwrap();
+        let hash = database.put(b"hello world").unwrap();
+        let data_len = data.len().unwrap();
+
+        let hashes = database.put_batch(&[b"hello", b"hello world", b"hello"]).unwrap();
+        assert_eq!(hashes[1], hash);
+        assert_eq!(hashes[0], hashes[2]);
+        let record_len = data.len().unwrap() - data_len;
+        database.put_batch(&[b"hello"]).unwrap();
+        assert_eq!(data.len().unwrap() - data_len, record_len);
+
+        for _ in 0..3 {
+            assert_eq!(database.get(&hashes[0]).unwrap(), b"hello");
+            assert!(database.delete(&hashes[0]).unwrap());
+        }
+        assert!(!database.delete(&hashes[0]).unwrap());
+    }
+
+    #[test]
+    fn it_exports_and_imports() {
+        let mut database = Database::new_in(MemStore::new(), MemStore::new())
+            .unwrap()
+            .with_compression(CompressionMode::Zstd);
+        let hash1 = database.put(b"hello world").unwrap();
+        let data2 = b"hello world again".repeat(100);
+        let hash2 = database.put_with_meta(&data2, b"text/plain").unwrap();
+        database.put(b"hello world").unwrap();
+        let hash3 = database.put(b"").unwrap();
+        let mut archive = vec![];
+        database.export(&mut archive).unwrap();
+        assert!(archive.starts_with(b"skogkatt.org/WasteIsland archive version=2 hash=sha256 count=3\n"));
+
+        let mut imported = Database::new_in(MemStore::new(), MemStore::new()).unwrap();
+        imported.import(&archive[..]).unwrap();
+        assert_eq!(imported.list().unwrap(), database.list().unwrap());
+        assert_eq!(imported.get(&hash1).unwrap(), b"hello world");
+        assert_eq!(imported.get_with_meta(&hash2).unwrap(), (data2, b"text/plain".to_vec()));
+        assert_eq!(imported.get(&hash3).unwrap(), b"");
+        assert_eq!(imported.stat(&hash2).unwrap().compression, CompressionMode::None);
+        // The reference counts are kept.
+        assert!(imported.delete(&hash1).unwrap());
+        assert!(imported.delete(&hash1).unwrap());
+        assert!(!imported.delete(&hash1).unwrap());
+
+        // The archive is the same if it is exported again.
+        let mut imported = Database::new_in(MemStore::new(), MemStore::new()).unwrap();
+        imported.import(&archive[..]).unwrap();
+        let mut exported = vec![];
+        imported.export(&mut exported).unwrap();
+        assert_eq!(exported, archive);
+
+        // The wastes imported again are referenced again, but never appended.
+        let data_bytes = imported.stats().unwrap().data_bytes;
+        imported.import(&archive[..]).unwrap();
+        assert_eq!(imported.stats().unwrap().data_bytes, data_bytes);
+        assert_eq!(imported.stats().unwrap().garbage_bytes, 0);
+        assert_eq!(imported.len().unwrap(), 3);
+        for _ in 0..4 {
+            assert!(imported.delete(&hash1).unwrap());
+        }
+        assert!(!imported.delete(&hash1).unwrap());
+    }
+
+    #[test]
+    fn it_backs_up_to_new_directories() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-backs-up-to-new-directories";
+        let backup_path = "/tmp/waste-land.skogatt.org/it-backs-up-to-new-directories-2";
+        clean_up(database_path);
+        clean_up(backup_path);
+
+        let mut database = Database::<Sha256>::with_shards(database_path, DEFAULT_PAGE_SIZE, 4)
+            .unwrap()
+            .with_sync_policy(SyncPolicy::Never);
+        let hashes: Vec<String> = (0..1000)
+            .map(|i| database.put(format!("content number {}", i).as_bytes()).unwrap())
+            .collect();
+        database.put_named("latest", b"the latest content").unwrap();
+        database.delete(&hashes[0]).unwrap();
+        database.backup_to(backup_path).unwrap();
+        // It never touches the backup.
+        database.put(b"content put after the backup").unwrap();
+        drop(database);
+
+        let backup = Database::new(backup_path).unwrap();
+        assert_eq!(backup.len().unwrap(), 1000);
+        assert!(backup.get(&hashes[0]).is_err());
+        assert_eq!(backup.get(&hashes[42]).unwrap(), b"content number 42");
+        assert_eq!(backup.get_named("latest").unwrap(), b"the latest content");
+        drop(backup);
+
+        let mut database = Database::new(database_path).unwrap();
+        let err = database.backup_to(backup_path).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
+        let mut database = Database::new_in(MemStore::new(), MemStore::new()).unwrap();
+        let err = database.backup_to(backup_path).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn it_restores_databases_from_archives() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-restores-databases-from-archives";
+        let restored_path = "/tmp/waste-land.skogatt.org/it-restores-databases-from-archives-2";
+        clean_up(database_path);
+        clean_up(restored_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let items: Vec<Vec<u8>> = (0..5000)
+            .map(|i| format!("content number {}", i).into_bytes())
+            .collect();
+        let items: Vec<&[u8]> = items.iter().map(|item| item.as_slice()).collect();
+        let hashes = database.put_batch(&items).unwrap();
+        let mut archive = vec![];
+        database.export(&mut archive).unwrap();
+
+        let restored = Database::<Sha256>::with_archive(restored_path, &archive[..]).unwrap();
+        assert_eq!(restored.list().unwrap(), database.list().unwrap());
+        drop(restored);
+        let mut restored = Database::new(restored_path).unwrap();
+        for (hash, item) in hashes.iter().zip(&items) {
+            assert_eq!(restored.get(hash).unwrap(), *item);
+        }
+
+        // Importing into a database which is not empty adds the references.
+        restored.import(&archive[..]).unwrap();
+        assert_eq!(restored.len().unwrap(), items.len());
+        assert!(restored.delete(&hashes[0]).unwrap());
+        assert_eq!(restored.get(&hashes[0]).unwrap(), items[0]);
+    }
+
+    #[test]
+    fn it_rejects_broken_archives() {
+        let mut database = Database::new_in(MemStore::new(), MemStore::new()).unwrap();
+        database.put(b"hello world").unwrap();
+        let mut archive = vec![];
+        database.export(&mut archive).unwrap();
+
+        let import = |archive: &[u8]| {
+            let mut database = Database::new_in(MemStore::new(), MemStore::new()).unwrap();
+            database.import(archive).err().unwrap().kind()
+        };
+        let mut broken = archive.clone();
+        *broken.last_mut().unwrap() ^= 1;
+        assert_eq!(import(&broken), crate::ErrorKind::Corrupt);
+        assert_eq!(import(&archive[..archive.len() - 1]), crate::ErrorKind::Corrupt);
+        assert_eq!(import(&archive[..archive.len() - 20]), crate::ErrorKind::Corrupt);
+        assert_eq!(import(b"hello world"), crate::ErrorKind::Unsupported);
+
+        let mut blake3_database = Database::<Blake3>::with_stores(
+            MemStore::new(),
+            MemStore::new(),
+            DEFAULT_PAGE_SIZE,
+        ).unwrap();
+        let err = blake3_database.import(&archive[..]).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn it_works_in_memory() {
+        let data = MemStore::new();
+        let index = MemStore::new();
+
+        let mut database = Database::new_in(data.clone(), index.clone()).unwrap();
+        let waste_hash = database.put(b"hello world").unwrap();
+        let waste2_hash = database.put_with_meta(b"hello world again", b"text/plain").unwrap();
+        let waste3_hash = database.put_reader(&b"hello world once more"[..]).unwrap();
+        assert_eq!(database.get(&waste_hash).unwrap(), b"hello world");
+        assert_eq!(database.get_meta(&waste2_hash).unwrap(), b"text/plain");
+        let mut content = vec![];
+        database.get_reader(&waste3_hash).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"hello world once more");
+        assert!(database.delete(&waste_hash).unwrap());
+
+        let stats = database.stats().unwrap();
+        assert_eq!(stats.data_bytes, data.len().unwrap());
+        assert_eq!(stats.index_bytes, index.len().unwrap());
+
+        // Those which need files of their own are not supported.
+        let err = database.put_named("latest", b"hello world").err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
+        let err = database.compact().err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
+        drop(database);
+
+        // It is opened again by the clones of the stores.
+        let database = Database::new_in(data, index).unwrap();
+        assert!(database.get(&waste_hash).is_err());
+        assert_eq!(database.get(&waste2_hash).unwrap(), b"hello world again");
+        assert_eq!(database.list().unwrap().len(), 2);
+        assert!(database.destroy().is_err());
+    }
+
+    #[test]
+    fn it_hashes_wastes_while_writing() {
+        // Across the boundaries of chunks, and of every mode.
+        let mut rng = rand::thread_rng();
+        let mut random = vec![0u8; 200 * 1024 + 7];
+        rng.fill(&mut random[..]);
+        let repeated = vec![42u8; 200 * 1024 + 7];
+        let mut database = Database::new_in(MemStore::new(), MemStore::new()).unwrap();
+        let mut blake3_database = Database::<Blake3>::with_stores(
+            MemStore::new(),
+            MemStore::new(),
+            DEFAULT_PAGE_SIZE,
+        ).unwrap();
+        for data in [&random[..], &repeated[..], b"", b"hello world"] {
+            assert_eq!(database.put(data).unwrap(), Sha256::hex_digest(data));
+            assert_eq!(blake3_database.put(data).unwrap(), Blake3::hex_digest(data));
+        }
+
+        let mut database = Database::new_in(MemStore::new(), MemStore::new())
+            .unwrap()
+            .with_compression(CompressionMode::Zstd);
+        for data in [&random[..], &repeated[..]] {
+            let hash = database.put(data).unwrap();
+            assert_eq!(hash, Database::gen_waste_hash(data));
+            assert_eq!(database.get(&hash).unwrap(), data);
+        }
+        let stat = database.stat(&Sha256::hex_digest(&repeated)).unwrap();
+        assert_eq!(stat.compression, CompressionMode::Zstd);
+    }
+
+    #[test]
+    fn it_works_with_empty_wastes() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-empty-wastes";
+        clean_up(database_path);
+
+        let empty_hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let mut database = Database::new(database_path)
+            .unwrap()
+            .with_compression(CompressionMode::Zstd);
+        assert_eq!(database.put(b"").unwrap(), empty_hash);
+        assert_eq!(database.put_reader(&b""[..]).unwrap(), empty_hash);
+        assert_eq!(database.get(empty_hash).unwrap(), b"");
+        drop(database);
+
+        // Read it from the data file - it is not cached anymore.
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.get(empty_hash).unwrap(), Vec::<u8>::new());
+        let mut buf = b"something else".to_vec();
+        assert_eq!(database.get_into(empty_hash, &mut buf).unwrap(), 0);
+        assert!(buf.is_empty());
+        let mut content = vec![];
+        database.get_reader(empty_hash).unwrap().read_to_end(&mut content).unwrap();
+        assert!(content.is_empty());
+        database.verify(empty_hash).unwrap();
+        assert_eq!(database.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn it_gets_wastes_into_buffers() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-gets-wastes-into-buffers";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path)
+            .unwrap()
+            .with_compression(CompressionMode::Zstd);
+        let data1 = b"hello world again".repeat(1000);
+        let hash1 = database.put(&data1).unwrap();
+        let hash2 = database.put(b"hello world").unwrap();
+        drop(database);
+
+        // Read them from the data file - they are not cached anymore.
+        let database = Database::new(database_path).unwrap();
+        let mut buf = b"something else".to_vec();
+        assert_eq!(database.get_into(&hash1, &mut buf).unwrap(), data1.len());
+        assert_eq!(buf, data1);
+        let ptr = buf.as_ptr();
+        assert_eq!(database.get_into(&hash2, &mut buf).unwrap(), 11);
+        assert_eq!(buf, b"hello world");
+        assert_eq!(buf.as_ptr(), ptr);
+
+        let absent_hash = Database::gen_waste_hash(b"I am not here.");
+        let err = database.get_into(&absent_hash, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn it_gets_many_wastes() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-gets-many-wastes";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hashes: Vec<String> = (0..100)
+            .map(|i| database.put(format!("content number {}", i).as_bytes()).unwrap())
+            .collect();
+        drop(database);
+
+        // Out of order, with a duplicated and an absent hash - and some of them
+        // are cached.
+        let mut database = Database::new(database_path).unwrap();
+        database.put(b"content number 7").unwrap();
+        let absent_hash = Database::gen_waste_hash(b"absent");
+        let requested = [&hashes[42], &hashes[7], &absent_hash, &hashes[99], &hashes[42], &hashes[0]];
+        let requested: Vec<&str> = requested.iter().map(|s| s.as_str()).collect();
+        let wastes = database.get_many(&requested).unwrap();
+        let expected: Vec<Option<Vec<u8>>> = requested
+            .iter()
+            .map(|hash| database.get(hash).ok())
+            .collect();
+        assert_eq!(wastes, expected);
+        assert_eq!(wastes[1].as_deref(), Some(&b"content number 7"[..]));
+        assert_eq!(wastes[2], None);
+
+        assert!(database.get_many(&[]).unwrap().is_empty());
+        assert!(database.get_many(&["not a hash"]).is_err());
+    }
+
+    #[test]
+    fn it_dumps_pages_of_the_index() {
+        /// Check the hashes under the page are in `(lower, upper]`, and return
+        /// the records in the leaves under it.
+        fn check(
+            database: &Database,
+            page_id: u32,
+            lower: Option<&str>,
+            upper: Option<&str>,
+        ) -> Vec<IndexRecord> {
+            let in_bounds = |hash: &str| {
+                lower.is_none_or(|lower| hash > lower) && upper.is_none_or(|upper| hash <= upper)
+            };
+            match database.dump_page(page_id).unwrap() {
+                PageDump::Head { .. } => panic!("the head node is referenced"),
+                PageDump::Leaf { records, .. } => {
+                    assert!(records.iter().all(|r| in_bounds(&r.hash)));
+                    records
+                }
+                PageDump::Internal { records, rightest_child } => {
+                    let mut leaf_records = vec![];
+                    let mut lower = lower;
+                    for (separator, child) in &records {
+                        assert!(in_bounds(separator));
+                        leaf_records.extend(check(database, *child, lower, Some(separator)));
+                        lower = Some(separator);
+                    }
+                    leaf_records.extend(check(database, rightest_child, lower, upper));
+                    leaf_records
+                }
+            }
+        }
+
+        let mut database = Database::with_stores(MemStore::new(), MemStore::new(), 1024).unwrap();
+        let mut hashes = vec![];
+        for i in 0..1000 {
+            hashes.push(database.put(format!("content number {}", i).as_bytes()).unwrap());
+        }
+        database.put(b"content number 0").unwrap();
+        database.put_with_ttl(b"expiring", Duration::from_secs(3600)).unwrap();
+        hashes.push(Database::gen_waste_hash(b"expiring"));
+        hashes.sort();
+
+        let root = match database.dump_page(0).unwrap() {
+            PageDump::Head { root } => root,
+            dump => panic!("unexpected page 0: {:?}", dump),
+        };
+        assert!(matches!(database.dump_page(root).unwrap(), PageDump::Internal { .. }));
+        let records = check(&database, root, None, None);
+        let listed: Vec<String> = records.iter().map(|r| r.hash.clone()).collect();
+        assert_eq!(listed, hashes);
+
+        let record = |data: &[u8]| {
+            let hash = Database::gen_waste_hash(data);
+            records.iter().find(|r| r.hash == hash).unwrap().clone()
+        };
+        let first = record(b"content number 0");
+        assert_eq!((first.shard, first.offset, first.refcount, first.expires_at), (0, 0, 2, None));
+        assert!(record(b"expiring").expires_at.is_some());
+
+        let err = database.dump_page(1_000_000).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn it_gets_or_puts_wastes() {
+        let mut database = Database::new_in(MemStore::new(), MemStore::new()).unwrap();
+        let (hash, content) = database.get_or_put(b"this is a content.").unwrap();
+        assert_eq!(hash, Database::gen_waste_hash(b"this is a content."));
+        assert_eq!(content, b"this is a content.");
+        let data_bytes = database.stats().unwrap().data_bytes;
+
+        // It is got, so it is neither appended nor referenced again.
+        assert_eq!(database.get_or_put(b"this is a content.").unwrap(), (hash.clone(), content));
+        assert_eq!(database.stats().unwrap().data_bytes, data_bytes);
+        assert_eq!(database.len().unwrap(), 1);
+        assert!(database.delete(&hash).unwrap());
+        assert!(!database.contains(&hash).unwrap());
+
+        // The expired waste is put again, and never expires any more.
+        let hash = database.put_with_ttl(b"I will be back.", Duration::from_millis(0)).unwrap();
+        let data_bytes = database.stats().unwrap().data_bytes;
+        let (_, content) = database.get_or_put(b"I will be back.").unwrap();
+        assert_eq!(content, b"I will be back.");
+        assert_eq!(database.get(&hash).unwrap(), b"I will be back.");
+        assert_eq!(database.stats().unwrap().data_bytes, data_bytes);
+    }
+
+    #[test]
+    fn it_checks_many_wastes() {
+        let mut database = Database::new_in(MemStore::new(), MemStore::new()).unwrap();
+        let hashes: Vec<String> = (0..1000)
+            .map(|i| database.put(format!("content number {}", i).as_bytes()).unwrap())
+            .collect();
+        let deleted_hash = database.put(b"deleted").unwrap();
+        database.delete(&deleted_hash).unwrap();
+
+        let absent_hash = Database::gen_waste_hash(b"absent");
+        let mut requested: Vec<&str> = vec![&absent_hash, &deleted_hash, &hashes[500]];
+        requested.extend(hashes.iter().rev().map(|s| s.as_str()));
+        requested.push(&hashes[500]);
+        let found = database.contains_many(&requested).unwrap();
+        let expected: Vec<bool> = requested.iter().map(|h| database.contains(h).unwrap()).collect();
+        assert_eq!(found, expected);
+        assert_eq!(&found[..3], [false, false, true]);
+        assert!(found[3..].iter().all(|found| *found));
+
+        assert!(database.contains_many(&[]).unwrap().is_empty());
+        assert!(database.contains_many(&["not a hash"]).is_err());
+    }
+
+    #[test]
+    fn it_works_on_large_data() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-on-large-data";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let size = 30000;
+        let cache = PictureCache::new(size);
+
+        let mut step = 0;
+        let mut bef_step = 0;
+        for p in &cache.data_pathes {
+            step += 1;
+            if step * 100 / cache.data_hashes.len() != bef_step {
+                eprintln!("{} / 100", step * 100 / cache.data_hashes.len());
+                bef_step += 1;
+            }
+
+            let content = fs::read(p).unwrap();
+            database.put(&content).unwrap();
+        }
+
+        for step in 0..100 {
+            eprintln!("{} / 100", step);
+            for h in &cache.data_hashes[size / 100 * step .. size / 100 * (step + 1)] {
+                let mut database = Database::new(database_path).unwrap();
+                database.get(h).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn it_works_with_delete() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-delete";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash1 = database.put(b"this is a content number 1.").unwrap();
+        let hash2 = database.put(b"this is a content number 2.").unwrap();
+        assert!(database.delete(&hash1).unwrap());
+        assert!(!database.delete(&hash1).unwrap());
+        assert!(database.get(&hash1).is_err());
+
+        let database = Database::new(database_path).unwrap();
+        assert!(database.get(&hash1).is_err());
+        assert_eq!(database.get(&hash2).unwrap(), b"this is a content number 2.");
+        assert_eq!(database.list().unwrap(), vec![hash2]);
+    }
+
+    #[test]
+    fn it_keeps_shared_content_until_deleted_by_all() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-keeps-shared-content-until-deleted-by-all";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash = database.put(b"the same content.").unwrap();
+        assert_eq!(database.put(b"the same content.").unwrap(), hash);
+        assert_eq!(database.put_reader(&b"the same content."[..]).unwrap(), hash);
+        assert_eq!(database.put_batch(&[b"the same content."]).unwrap(), [hash.as_str()]);
+
+        for _ in 0..3 {
+            assert!(database.delete(&hash).unwrap());
+            assert_eq!(database.get(&hash).unwrap(), b"the same content.");
+        }
+        let mut database = Database::new(database_path).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"the same content.");
+        assert!(database.delete(&hash).unwrap());
+        assert!(database.get(&hash).is_err());
+        assert!(!database.delete(&hash).unwrap());
+    }
+
+    /// Put some wastes, and delete or put again some of them: 2 of 4 records
+    /// are not referenced any more.
+    fn put_wastes_to_compact(database_path: &str) -> Vec<String> {
+        clean_up(database_path);
+        let mut database = Database::new(database_path).unwrap();
+        let hash1 = database.put(b"this is a content number 1.").unwrap();
+        let hash2 = database.put_with_meta(b"this is a content number 2.", b"text/plain").unwrap();
+        let hash3 = database.put(&b"this is a content number 3.".repeat(1000)).unwrap();
+        // It is streamed, so that its record is appended to the data file again.
+        database.put_reader(&b"this is a content number 1."[..]).unwrap();
+        database.delete(&hash3).unwrap();
+        vec![hash1, hash2, hash3]
+    }
+
+    #[test]
+    fn it_works_with_compact() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-compact";
+        let hashes = put_wastes_to_compact(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let stats = database.compact().unwrap();
+        assert_eq!(stats.records_kept, 2);
+        assert_eq!(stats.records_dropped, 2);
+        assert!(stats.bytes_after < stats.bytes_before);
+        let data_len = fs::metadata(Path::new(database_path).join("data")).unwrap().len();
+        assert_eq!(data_len, stats.bytes_after);
+        assert!(!Path::new(database_path).join("data.tmp").exists());
+        assert!(!Path::new(database_path).join("index.tmp").exists());
+
+        assert_eq!(database.get(&hashes[0]).unwrap(), b"this is a content number 1.");
+        assert_eq!(
+            database.get_with_meta(&hashes[1]).unwrap(),
+            (b"this is a content number 2.".to_vec(), b"text/plain".to_vec()),
+        );
+        assert!(database.get(&hashes[2]).is_err());
+        let hash4 = database.put(b"this is a content number 4.").unwrap();
+
+        // The reference counts are kept: the content put twice still needs to
+        // be deleted twice.
+        let mut database = Database::new(database_path).unwrap();
+        assert_eq!(database.list().unwrap().len(), 3);
+        assert_eq!(database.get(&hash4).unwrap(), b"this is a content number 4.");
+        database.delete(&hashes[0]).unwrap();
+        assert_eq!(database.get(&hashes[0]).unwrap(), b"this is a content number 1.");
+        database.delete(&hashes[0]).unwrap();
+        assert!(database.get(&hashes[0]).is_err());
+
+        // Nothing is dropped the second time.
+        let stats = database.compact().unwrap();
+        assert_eq!(stats.records_dropped, 1);
+        let stats = database.compact().unwrap();
+        assert_eq!((stats.records_kept, stats.records_dropped), (2, 0));
+        assert_eq!(stats.bytes_after, stats.bytes_before);
+    }
+
+    #[test]
+    fn it_compacts_with_background_sync() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-compacts-with-background-sync";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path)
+            .unwrap()
+            .with_sync_policy(SyncPolicy::Background(Duration::from_millis(1)));
+        let mut kept = vec![];
+        for round in 0..3 {
+            let hashes: Vec<String> = (0..100)
+                .map(|i| database.put(format!("content {} of {}", i, round).as_bytes()).unwrap())
+                .collect();
+            for hash in &hashes[..50] {
+                database.delete(hash).unwrap();
+            }
+            kept.extend(hashes.into_iter().enumerate().skip(50).map(|(i, hash)| (round, i, hash)));
+            // The thread never writes the pages of the index replaced, and it
+            // syncs the new index from now on.
+            let stats = database.compact().unwrap();
+            assert_eq!(stats.records_kept, kept.len() as u64);
+        }
+        drop(database);
+
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.len().unwrap(), kept.len());
+        assert!(database.verify_all().unwrap().is_ok());
+        for (round, i, hash) in &kept {
+            let content = format!("content {} of {}", i, round);
+            assert_eq!(database.get(hash).unwrap(), content.as_bytes());
+        }
+    }
+
+    #[test]
+    fn it_recovers_from_interrupted_compact() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-recovers-from-interrupted-compact";
+        let compacted_path = "/tmp/waste-land.skogatt.org/it-recovers-from-interrupted-compact-2";
+        let hashes = put_wastes_to_compact(database_path);
+        put_wastes_to_compact(compacted_path);
+        Database::new(compacted_path).unwrap().compact().unwrap();
+        let (path, compacted_path) = (Path::new(database_path), Path::new(compacted_path));
+
+        // Interrupted before the index is replaced: the temporary files are
+        // just removed.
+        fs::write(path.join("data.tmp"), b"half-written data").unwrap();
+        fs::write(path.join("index.tmp"), b"half-written index").unwrap();
+        assert!(Database::open_read_only(database_path).is_ok());
+        let database = Database::new(database_path).unwrap();
+        assert!(!path.join("data.tmp").exists());
+        assert!(!path.join("index.tmp").exists());
+        assert_eq!(database.get(&hashes[0]).unwrap(), b"this is a content number 1.");
+        drop(database);
+
+        // Interrupted after the index is replaced: the data file is replaced
+        // as well when opening it. The index is of another database, so its
+        // `version` file is taken as well.
+        fs::copy(compacted_path.join("index"), path.join("index")).unwrap();
+        fs::copy(compacted_path.join("version"), path.join("version")).unwrap();
+        fs::copy(compacted_path.join("data"), path.join("data.tmp")).unwrap();
+        assert!(Database::open_read_only(database_path).is_err());
+        let database = Database::new(database_path).unwrap();
+        assert!(!path.join("data.tmp").exists());
+        assert_eq!(
+            fs::read(path.join("data")).unwrap(),
+            fs::read(compacted_path.join("data")).unwrap(),
+        );
+        assert_eq!(database.get(&hashes[0]).unwrap(), b"this is a content number 1.");
+        assert_eq!(database.get_meta(&hashes[1]).unwrap(), b"text/plain");
+    }
+
+    #[test]
+    fn it_works_with_shards() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-shards";
+        clean_up(database_path);
+        let path = Path::new(database_path);
+
+        let mut database = Database::<Sha256>::with_shards(database_path, DEFAULT_PAGE_SIZE, 4).unwrap();
+        let mut hashes = vec![];
+        for i in 0..64 {
+            hashes.push(database.put(format!("content number {}", i).as_bytes()).unwrap());
+        }
+        let streamed_hash = database.put_reader(&b"a streamed content"[..]).unwrap();
+        let first_byte = u8::from_str_radix(&streamed_hash[..2], 16).unwrap() as usize;
+        assert_eq!(database.stat(&streamed_hash).unwrap().shard, first_byte % 4);
+        for (i, hash) in hashes.iter().enumerate() {
+            assert_eq!(database.get(hash).unwrap(), format!("content number {}", i).as_bytes());
+            let first_byte = u8::from_str_radix(&hash[..2], 16).unwrap() as usize;
+            assert_eq!(database.stat(hash).unwrap().shard, first_byte % 4);
+        }
+        assert_eq!(database.get(&streamed_hash).unwrap(), b"a streamed content");
+        assert!(!path.join("data").exists());
+        let shard_bytes: u64 = (0..4)
+            .map(|i| fs::metadata(path.join(format!("data.{:03}", i))).unwrap().len())
+            .sum();
+        assert_eq!(database.stats().unwrap().data_bytes, shard_bytes);
+        drop(database);
+
+        // The count of shards is recorded, and the one given is ignored.
+        let version = fs::read_to_string(path.join("version")).unwrap();
+        assert!(version.contains("shards=4"));
+        let mut database = Database::new(database_path).unwrap();
+        assert_eq!(database.get(&hashes[42]).unwrap(), b"content number 42");
+        for hash in &hashes[..32] {
+            database.delete(hash).unwrap();
+        }
+        let stats = database.compact().unwrap();
+        assert_eq!(stats.records_kept, 33);
+        assert_eq!(stats.records_dropped, 32);
+        assert_eq!(database.stats().unwrap().data_bytes, stats.bytes_after);
+        assert!(database.get(&hashes[0]).is_err());
+        assert_eq!(database.get(&hashes[42]).unwrap(), b"content number 42");
+        assert_eq!(database.get(&streamed_hash).unwrap(), b"a streamed content");
+        drop(database);
+
+        let database = Database::open_read_only(database_path).unwrap();
+        assert_eq!(database.get(&hashes[63]).unwrap(), b"content number 63");
+
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-shards-2";
+        clean_up(database_path);
+        for shards in [0, 257] {
+            let err = Database::<Sha256>::with_shards(database_path, DEFAULT_PAGE_SIZE, shards).err().unwrap();
+            assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
+        }
+    }
+
+    #[test]
+    fn it_lists_hashes_in_ascending_order() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-lists-hashes-in-ascending-order";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let mut hashes = vec![];
+        for i in 0..1000 {
+            hashes.push(database.put(format!("content number {}", i).as_bytes()).unwrap());
+        }
+        hashes.sort();
+        assert_eq!(database.list().unwrap(), hashes);
+    }
+
+    #[test]
+    fn it_lists_hashes_by_pages() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-lists-hashes-by-pages";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let mut hashes = vec![];
+        for i in 0..1000 {
+            hashes.push(database.put(format!("content number {}", i).as_bytes()).unwrap());
+        }
+        hashes.sort();
+
+        let first = database.list_paged(None, 100).unwrap();
+        assert_eq!(first, hashes[..100]);
+        let second = database.list_paged(Some(&hashes[99]), 100).unwrap();
+        assert_eq!(second, hashes[100..200]);
+        assert_eq!(database.list_paged(Some(&hashes[990]), 100).unwrap(), hashes[991..]);
+        assert_eq!(database.list_paged(Some(&hashes[999]), 100).unwrap(), Vec::<String>::new());
+        let err = database.list_paged(Some("not a hash"), 100).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::InvalidHash);
+    }
+
+    #[test]
+    fn it_iterates_hashes_lazily() {
+        let mut database = Database::new_in(MemStore::new(), MemStore::new()).unwrap();
+        assert_eq!(database.iter().count(), 0);
+        let mut hashes = vec![];
+        for i in 0..1000 {
+            hashes.push(database.put(format!("content number {}", i).as_bytes()).unwrap());
+        }
+        hashes.sort();
+
+        let mut iterated = vec![];
+        for hash in &database {
+            iterated.push(hash.unwrap());
+        }
+        assert_eq!(iterated, hashes);
+        let first: Vec<String> = database.iter().take(3).map(Result::unwrap).collect();
+        assert_eq!(first, hashes[..3]);
+    }
+
+    #[test]
+    fn it_iterates_values() {
+        let mut database = Database::new_in(MemStore::new(), MemStore::new())
+            .unwrap()
+            .with_compression(CompressionMode::Zstd);
+        assert_eq!(database.iter_values().count(), 0);
+        let mut wastes = vec![];
+        for i in 0..100 {
+            let content = format!("content number {}", i).into_bytes();
+            wastes.push((database.put(&content).unwrap(), content));
+        }
+        wastes.sort();
+        database.put_with_ttl(b"I will be gone.", Duration::from_millis(0)).unwrap();
+
+        let iterated: Vec<(String, Vec<u8>)> =
+            database.iter_values().map(Result::unwrap).collect();
+        assert_eq!(iterated, wastes);
+    }
+
+    #[test]
+    fn it_lists_hashes_in_range() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-lists-hashes-in-range";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let mut hashes = vec![];
+        for i in 0..1000 {
+            hashes.push(database.put(format!("content number {}", i).as_bytes()).unwrap());
+        }
+        hashes.sort();
+
+        let (start, end) = (&hashes[100], &hashes[900]);
+        assert_eq!(database.range(start, end).unwrap(), hashes[100..900]);
+        assert_eq!(database.range(end, start).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn it_works_with_names() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-names";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash1 = database.put_named("latest", b"version 1").unwrap();
+        assert_eq!(database.get_named("latest").unwrap(), b"version 1");
+        assert_eq!(database.get(&hash1).unwrap(), b"version 1");
+        let long_name = "a much longer name than a hash ".repeat(10);
+        database.put_named(&long_name, b"version 1").unwrap();
+        database.put_named("", b"the empty name").unwrap();
+        assert_eq!(database.get_named("").unwrap(), b"the empty name");
+        let err = database.get_named("absent").err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::NotFound);
+
+        // Overwriting the name updates the mapping, and the waste named before
+        // loses its reference.
+        let hash2 = database.put_named("latest", b"version 2").unwrap();
+        assert_eq!(database.get_named("latest").unwrap(), b"version 2");
+        assert_eq!(database.get_named(&long_name).unwrap(), b"version 1");
+        database.delete(&hash1).unwrap();
+        assert!(database.get(&hash1).is_err());
+        database.put_named("latest", b"version 2").unwrap();
+        assert_eq!(database.get(&hash2).unwrap(), b"version 2");
+
+        let database = Database::open_read_only(database_path).unwrap();
+        assert_eq!(database.get_named("latest").unwrap(), b"version 2");
+        assert!(database.get_named(&long_name).is_err());
+        assert!(database.get_named("absent").is_err());
+
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-names-2";
+        clean_up(database_path);
+        Database::new(database_path).unwrap();
+        let database = Database::open_read_only(database_path).unwrap();
+        assert!(database.get_named("latest").is_err());
+    }
+
+    #[test]
+    fn it_works_with_labels() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-labels";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash1 = database.put_with_label(b"hello world", "greeting").unwrap();
+        let hash2 = database.put(b"unlabeled").unwrap();
+        assert_eq!(database.get_label(&hash1).unwrap().as_deref(), Some("greeting"));
+        assert_eq!(database.get_label(&hash2).unwrap(), None);
+        let err = database.get_label(&Sha256::hex_digest(b"absent")).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::NotFound);
+        let err = database.set_label(&Sha256::hex_digest(b"absent"), "absent").err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::NotFound);
+        let err = database.set_label(&hash2, &"x".repeat(MAX_LABEL_LEN + 1)).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::TooLarge);
+
+        // Labeling again replaces the label, and the hash stays the same.
+        database.set_label(&hash1, "salutation").unwrap();
+        database.set_label(&hash2, "").unwrap();
+        let mut expected = vec![
+            (hash1.clone(), Some("salutation".to_string())),
+            (hash2.clone(), Some("".to_string())),
+        ];
+        expected.sort();
+        assert_eq!(database.list_with_labels().unwrap(), expected);
+
+        // The label is removed with the waste.
+        database.delete(&hash2).unwrap();
+        database.put(b"unlabeled").unwrap();
+        assert_eq!(database.get_label(&hash2).unwrap(), None);
+        drop(database);
+
+        let database = Database::open_read_only(database_path).unwrap();
+        assert_eq!(database.get_label(&hash1).unwrap().as_deref(), Some("salutation"));
+
+        let mut database = Database::new_in(MemStore::new(), MemStore::new()).unwrap();
+        let err = database.put_with_label(b"hello world", "greeting").err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
+        let hash = database.put(b"hello world").unwrap();
+        assert_eq!(database.list_with_labels().unwrap(), vec![(hash, None)]);
+    }
+
+    #[test]
+    fn it_stats_wastes() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-stats-wastes";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash1 = database.put(b"hello world").unwrap();
+        let content = b"waste ".repeat(100_000);
+        let hash2 = database.put_reader(content.as_slice()).unwrap();
+        let mut database = database.with_compression(CompressionMode::Zstd);
+        let hash3 = database.put(&b"trash ".repeat(100_000)).unwrap();
+
+        assert_eq!(
+            database.stat(&hash1).unwrap(),
+            WasteStat { shard: 0, offset: 0, len: 11, compression: CompressionMode::None },
+        );
+        let stat2 = database.stat(&hash2).unwrap();
+        assert_eq!((stat2.len, stat2.compression), (content.len() as u64, CompressionMode::None));
+        let stat3 = database.stat(&hash3).unwrap();
+        assert!(stat3.offset > stat2.offset + stat2.len);
+        assert_eq!(stat3.compression, CompressionMode::Zstd);
+        assert!(stat3.len < content.len() as u64);
+
+        let err = database.stat(&Database::gen_waste_hash(b"absent")).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn it_finds_hashes_by_prefix() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-finds-hashes-by-prefix";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let mut hashes = vec![];
+        for i in 0..1000 {
+            hashes.push(database.put(format!("content number {}", i).as_bytes()).unwrap());
+        }
+        hashes.sort();
+
+        for prefix_len in [0, 1, 2, 3, 8, 64] {
+            let prefix = &hashes[500][..prefix_len];
+            let expected: Vec<String> =
+                hashes.iter().filter(|h| h.starts_with(prefix)).cloned().collect();
+            assert_eq!(database.find_by_prefix(prefix).unwrap(), expected, "{:?}", prefix);
+        }
+        assert_eq!(database.find_by_prefix("").unwrap().len(), 1000);
+        assert_eq!(database.find_by_prefix(&hashes[0][..32].to_uppercase()).unwrap(), [hashes[0].clone()]);
+        assert!(database.find_by_prefix("fffffffffffffffffffffff").unwrap().is_empty());
+        assert!(database.find_by_prefix("not hex").is_err());
+        assert!(database.find_by_prefix(&format!("{}0", hashes[0])).is_err());
+    }
+
+    #[test]
+    fn it_knows_its_length() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-knows-its-length";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        assert!(database.is_empty().unwrap());
+        let hash = database.put(b"this is a content number 1.").unwrap();
+        database.put(b"this is a content number 2.").unwrap();
+        database.put(b"this is a content number 1.").unwrap();
+        assert_eq!(database.len().unwrap(), 2);
+
+        // The content put twice needs to be deleted twice.
+        let mut database = Database::new(database_path).unwrap();
+        assert_eq!(database.len().unwrap(), 2);
+        database.delete(&hash).unwrap();
+        assert_eq!(database.len().unwrap(), 2);
+        database.delete(&hash).unwrap();
+        assert_eq!(database.len().unwrap(), 1);
+        assert!(!database.is_empty().unwrap());
+    }
+
+    #[test]
+    fn it_counts_garbage_bytes() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-counts-garbage-bytes";
+        clean_up(database_path);
+        let garbage_bytes = |database: &Database| database.stats().unwrap().garbage_bytes;
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash = database.put(b"content number 1").unwrap();
+        database.put(b"content number 1").unwrap();
+        database.put(b"content number 2").unwrap();
+        let record_len = database.stats().unwrap().data_bytes / 2;
+        assert_eq!(garbage_bytes(&database), 0);
+
+        // It is garbage only once its last reference is deleted.
+        database.delete(&hash).unwrap();
+        assert_eq!(garbage_bytes(&database), 0);
+        database.delete(&hash).unwrap();
+        assert_eq!(garbage_bytes(&database), record_len);
+
+        // The record put again with the same metadata is just referenced once
+        // more, and the one with another metadata replaces the origin one - so
+        // it is longer by the byte of the metadata.
+        database.put_with_meta(b"content number 2", b"").unwrap();
+        assert_eq!(garbage_bytes(&database), record_len);
+        database.put_with_meta(b"content number 2", b"!").unwrap();
+        assert_eq!(garbage_bytes(&database), 2 * record_len);
+        database.put_reader(&b"content number 2"[..]).unwrap();
+        assert_eq!(garbage_bytes(&database), 3 * record_len + 1);
+
+        database.put_with_ttl(b"content number 3", Duration::from_millis(0)).unwrap();
+        database.sweep_expired().unwrap();
+        assert_eq!(garbage_bytes(&database), 4 * record_len + 1);
+        drop(database);
+
+        // It survives reopen, and it is reset by `compact`.
+        let mut database = Database::new(database_path).unwrap();
+        assert_eq!(garbage_bytes(&database), 4 * record_len + 1);
+        let stats = database.compact().unwrap();
+        assert_eq!(stats.bytes_before - stats.bytes_after, 4 * record_len + 1);
+        assert_eq!(garbage_bytes(&database), 0);
+    }
+
+    #[test]
+    fn it_knows_its_stats() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-knows-its-stats";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let stats = database.stats().unwrap();
+        assert_eq!(stats, DatabaseStats {
+            data_bytes: 0,
+            index_bytes: 2 * DEFAULT_PAGE_SIZE as u64,
+            keys: 0,
+            index_height: 1,
+            index_pages: 2,
+            index_cache_hits: 1, // The head node just appended.
+            index_cache_misses: 0,
+            index_cache_evictions: 0,
+            garbage_bytes: 0,
+        });
+
+        let contents: Vec<Vec<u8>> = (0..1000u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let items: Vec<&[u8]> = contents.iter().map(|c| c.as_slice()).collect();
+        database.put_batch(&items).unwrap();
+        let stats = database.stats().unwrap();
+        assert_eq!(stats.keys, 1000);
+        assert_eq!(stats.index_height, 2);
+        assert_eq!(stats.index_bytes, (stats.index_pages * DEFAULT_PAGE_SIZE) as u64);
+        let data_len = fs::metadata(Path::new(database_path).join("data")).unwrap().len();
+        assert_eq!(stats.data_bytes, data_len);
+
+        // The pages just read are found in the cache later.
+        let database = Database::open_read_only(database_path).unwrap();
+        let read_only_stats = database.stats().unwrap();
+        assert_eq!(read_only_stats.keys, stats.keys);
+        assert_eq!(read_only_stats.index_height, stats.index_height);
+        assert_eq!(read_only_stats.index_pages, stats.index_pages);
+        database.get(&Database::gen_waste_hash(&contents[0])).unwrap();
+        let misses = database.stats().unwrap().index_cache_misses;
+        database.get(&Database::gen_waste_hash(&contents[0])).unwrap();
+        let new_stats = database.stats().unwrap();
+        assert_eq!(new_stats.index_cache_misses, misses);
+        assert!(new_stats.index_cache_hits > read_only_stats.index_cache_hits);
+    }
+
+    #[test]
+    fn it_skips_the_index_for_absent_hashes() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-skips-the-index-for-absent-hashes";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let contents: Vec<Vec<u8>> = (0..1000u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let items: Vec<&[u8]> = contents.iter().map(|c| c.as_slice()).collect();
+        let hashes = database.put_batch(&items).unwrap();
+        let hash = database.put(b"put after the batch").unwrap();
+
+        let page_reads = |database: &Database| {
+            let stats = database.stats().unwrap();
+            stats.index_cache_hits + stats.index_cache_misses
+        };
+        let absent_hash = |i: u32| Database::gen_waste_hash(format!("absent {}", i).as_bytes());
+        assert!(!database.contains(&absent_hash(0)).unwrap());
+
+        // Without the Bloom filter, each lookup reads at least the root.
+        let reads = page_reads(&database);
+        for i in 1..=100 {
+            assert!(!database.contains(&absent_hash(i)).unwrap());
+            assert!(database.get(&absent_hash(i)).is_err());
+        }
+        assert!(page_reads(&database) - reads < 20);
+
+        // The hashes put later are still found, even after it is rebuilt.
+        let more: Vec<Vec<u8>> = (1000..5000u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let more_items: Vec<&[u8]> = more.iter().map(|c| c.as_slice()).collect();
+        let more_hashes = database.put_batch(&more_items).unwrap();
+        for hash in hashes.iter().chain(&more_hashes).chain([&hash]) {
+            assert!(database.contains(hash).unwrap());
+        }
+        database.delete(&hash).unwrap();
+        assert!(!database.contains(&hash).unwrap());
+    }
+
+    #[test]
+    fn it_knows_what_it_contains() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-knows-what-it-contains";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash = database.put(b"I am here.").unwrap();
+        let absent_hash = Database::gen_waste_hash(b"I am not here.");
+        assert!(database.contains(&hash).unwrap());
+        assert!(!database.contains(&absent_hash).unwrap());
+        assert!(database.contains("not a hash").is_err());
+
+        let database = Database::new(database_path).unwrap();
+        assert!(database.contains(&hash).unwrap());
+        assert!(!database.contains(&absent_hash).unwrap());
+    }
+
+    #[test]
+    fn it_works_with_put_reader() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-put-reader";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let large_content: Vec<u8> = (0..1_000_000).map(|i| (i % 251) as u8).collect();
+        let large_hash = database.put_reader(large_content.as_slice()).unwrap();
+        assert_eq!(large_hash, Database::gen_waste_hash(&large_content));
+        let small_hash = database.put_reader(&b"this is a small content."[..]).unwrap();
+        let empty_hash = database.put_reader(&b""[..]).unwrap();
+        let hash = database.put(b"this is the last content.").unwrap();
+        let meta_hash = database
+            .put_reader_with_meta(&b"this is a content with meta."[..], b"text/plain")
+            .unwrap();
+
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.get_meta(&meta_hash).unwrap(), b"text/plain");
+        assert_eq!(database.get(&meta_hash).unwrap(), b"this is a content with meta.");
+        assert_eq!(database.get(&large_hash).unwrap(), large_content);
+        assert_eq!(database.get(&small_hash).unwrap(), b"this is a small content.");
+        assert_eq!(database.get(&empty_hash).unwrap(), b"");
+        assert_eq!(database.get(&hash).unwrap(), b"this is the last content.");
+    }
+
+    #[test]
+    fn it_works_with_get_reader() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-get-reader";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash1 = database.put(b"this is a content number 1.").unwrap();
+        let hash2 = database.put(b"this is a content number 2.").unwrap();
+
+        // It never reads past the waste, even if we ask for more.
+        let mut content = vec![];
+        database.get_reader(&hash1).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"this is a content number 1.");
+        let mut buf = [0u8; 1024];
+        let mut reader = database.get_reader(&hash2).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), b"this is a content number 2.".len());
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        drop(reader);
+
+        let absent_hash = Database::gen_waste_hash(b"I am not here.");
+        assert!(database.get_reader(&absent_hash).is_err());
+    }
+
+    #[test]
+    fn it_gets_slices() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-gets-slices";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash1 = database.put(b"this is a content.").unwrap();
+        let mut database = database.with_compression(CompressionMode::Zstd);
+        let hash2 = database.put(b"this is another content.").unwrap();
+
+        assert_eq!(database.get_slice(&hash1, 5, 2).unwrap(), b"is");
+        assert_eq!(database.get_slice(&hash2, 8, 7).unwrap(), b"another");
+        // The slice is cut at the end of the waste.
+        assert_eq!(database.get_slice(&hash1, 10, u64::MAX).unwrap(), b"content.");
+        assert_eq!(database.get_slice(&hash2, 16, u64::MAX).unwrap(), b"content.");
+        assert!(database.get_slice(&hash1, u64::MAX, 1).unwrap().is_empty());
+        assert!(database.get_slice(&hash2, 100, 1).unwrap().is_empty());
+        let mut slice = vec![];
+        database.get_slice_reader(&hash2, 8, 7).unwrap().read_to_end(&mut slice).unwrap();
+        assert_eq!(slice, b"another");
+
+        let absent_hash = Database::gen_waste_hash(b"I am not here.");
+        let err = database.get_slice(&absent_hash, 0, 1).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn it_rejects_corrupt_length() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-rejects-corrupt-length";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash = database.put(b"this is a content.").unwrap();
+
+        // Break the length of the only waste.
+        let data_path = Path::new(database_path).join("data");
+        let mut content = fs::read(&data_path).unwrap();
+        content[1] = 0x7f;
+        fs::write(&data_path, content).unwrap();
+
+        let database = Database::new(database_path).unwrap();
+        assert!(database.get(&hash).is_err());
+    }
+
+    #[test]
+    fn it_rejects_offsets_past_the_end_of_data() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-rejects-offsets-past-the-end-of-data";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let first = database.put(b"this is a content.").unwrap();
+        let data_path = Path::new(database_path).join("data");
+        let data_len = fs::metadata(&data_path).unwrap().len();
+        let second = database.put(b"this is another content.").unwrap();
+        drop(database);
+
+        // The data file loses the second waste, but the index still has it.
+        fs::OpenOptions::new().write(true).open(&data_path).unwrap().set_len(data_len).unwrap();
+
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.get(&first).unwrap(), b"this is a content.");
+        let err = database.get(&second).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::Corrupt);
+        assert!(err.to_string().contains(&format!("offset {}", data_len)));
+    }
+
+    #[test]
+    fn it_rebuilds_the_index_from_data() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-rebuilds-the-index-from-data";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hashes: Vec<String> = (0..100)
+            .map(|i| database.put(format!("content number {}", i).as_bytes()).unwrap())
+            .collect();
+        let mut database = database.with_compression(CompressionMode::Zstd);
+        let compressed_hash = database.put(&b"waste ".repeat(1000)).unwrap();
+        let meta_hash = database.put_with_meta(b"<p>hello</p>", b"text/plain").unwrap();
+        database.put_with_meta(b"<p>hello</p>", b"text/html").unwrap();
+        drop(database);
+
+        // The index is lost, and the last record is truncated by a crash.
+        fs::write(Path::new(database_path).join("index"), b"garbage").unwrap();
+        let data_path = Path::new(database_path).join("data");
+        let mut content = fs::read(&data_path).unwrap();
+        content.extend_from_slice(&[0, 100, 1, 2, 3]);
+        fs::write(&data_path, content).unwrap();
+        assert!(Database::new(database_path).is_err());
+
+        Database::rebuild_index(database_path).unwrap();
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.len().unwrap(), 102);
+        for (i, hash) in hashes.iter().enumerate() {
+            assert_eq!(database.get(hash).unwrap(), format!("content number {}", i).as_bytes());
+        }
+        assert_eq!(database.get(&compressed_hash).unwrap(), b"waste ".repeat(1000));
+        assert_eq!(database.get_meta(&meta_hash).unwrap(), b"text/html");
+        assert!(database.verify_all().unwrap().is_ok());
+
+        clean_up(database_path);
+        assert_eq!(
+            Database::rebuild_index(database_path).unwrap_err().kind(),
+            crate::ErrorKind::NotFound,
+        );
+    }
+
+    #[test]
+    fn it_stores_lengths_as_varints() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-stores-lengths-as-varints";
+        clean_up(database_path);
+
+        let data_path = Path::new(database_path).join("data");
+        let mut database = Database::new(database_path).unwrap();
+        let mut hashes = vec![];
+        let mut data_len = 0;
+        for (len, varint_len) in [(127, 1), (128, 2), (16383, 2), (16384, 3)] {
+            hashes.push((database.put(&vec![len as u8; len]).unwrap(), len));
 
-pub struct Database {
-    path: PathBuf,
-    data: fs::File,
-    indexer: Indexer,
-    len: usize,
-    cache: LruCache<String, Vec<u8>>,
-}
+            // The flag, the length, the content, the checksum and the empty meta.
+            let new_data_len = fs::metadata(&data_path).unwrap().len();
+            assert_eq!(new_data_len - data_len, (1 + varint_len + len + 4 + 1) as u64);
+            data_len = new_data_len;
+        }
 
-impl Database {
-    /// Gen the waste hash from the content of data.
-    pub fn gen_waste_hash(data: &[u8]) -> String {
-        digest(data)
+        let database = Database::new(database_path).unwrap();
+        for (hash, len) in hashes {
+            assert_eq!(database.get(&hash).unwrap(), vec![len as u8; len]);
+        }
     }
-    
-    fn open_data(database_path: &PathBuf) -> Result<fs::File, Error> {
-        let file = fs::File::options()
-            .write(true)
-            .read(true)
-            .create(true)
-            .open(database_path.join("data"))
-            .to_inner_result("open data file in write-read mode")?;
-        Ok(file)
+
+    #[test]
+    fn it_works_with_blake3() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-blake3";
+        clean_up(database_path);
+
+        let mut database = Database::<Blake3>::with_hasher(database_path).unwrap();
+        let hash = database.put(b"hello world").unwrap();
+        assert_eq!(hash, Blake3::hex_digest(b"hello world"));
+        let streamed_hash = database.put_reader(&b"hello world again"[..]).unwrap();
+        assert_eq!(streamed_hash, Blake3::hex_digest(b"hello world again"));
+
+        // It must be opened by the same hash algorithm.
+        assert!(Database::new(database_path).is_err());
+        let database = Database::<Blake3>::with_hasher(database_path).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"hello world");
     }
 
-    /// Create or open a new database at the given path.
-    /// 
-    /// An error will be raised if the path is not an empty folder, as
-    /// attemping to create a new database in a non-empty folder may mess the
-    /// folder up.
-    pub fn new<P>(database_path: P) -> Result<Database, Error>
-    where
-        P: AsRef<Path>,
-    {
-        let database_path = PathBuf::from(database_path.as_ref());
+    #[test]
+    fn it_works_with_compression() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-compression";
+        clean_up(database_path);
 
-        fs::create_dir_all(&database_path)
-            .to_inner_result(&format!("create database directory {:?}", database_path))?;
+        let random_content: Vec<u8> = (0..100_000).map(|_| rand::thread_rng().gen()).collect();
+        let repeated_content = b"waste ".repeat(100_000);
 
-        Ok(Database {
-            data: Self::open_data(&database_path).to_inner_result("open data file")?,
-            indexer: Indexer::open(&database_path).to_inner_result("open indexer")?,
-            path: database_path,
-            len: 0,
-            cache: LruCache::new(NonZeroUsize::new(16).unwrap()),
-        })
+        let mut database = Database::new(database_path).unwrap();
+        let raw_hash = database.put(b"this is a raw content.").unwrap();
+        let mut database = database.with_compression(CompressionMode::Zstd);
+        let random_hash = database.put(&random_content).unwrap();
+        let repeated_hash = database.put(&repeated_content).unwrap();
+        let streamed_hash = database.put_reader(repeated_content.as_slice()).unwrap();
+        assert_eq!(random_hash, Database::gen_waste_hash(&random_content));
+        assert_eq!(repeated_hash, Database::gen_waste_hash(&repeated_content));
+        assert_eq!(streamed_hash, repeated_hash);
+
+        // The highly compressible content takes much less space.
+        let data_len = fs::metadata(Path::new(database_path).join("data")).unwrap().len();
+        assert!(data_len < (random_content.len() + repeated_content.len()) as u64);
+
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.get(&raw_hash).unwrap(), b"this is a raw content.");
+        assert_eq!(database.get(&random_hash).unwrap(), random_content);
+        assert_eq!(database.get(&repeated_hash).unwrap(), repeated_content);
+        let mut content = vec![];
+        database.get_reader(&repeated_hash).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(content, repeated_content);
     }
 
-    pub fn list(&mut self) -> Result<Vec<String>, Error> {
-        self.indexer.list()
+    #[test]
+    fn it_detects_corruption_by_checksum() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-detects-corruption-by-checksum";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash1 = database.put(b"this is a content number 1.").unwrap();
+        let hash2 = database.put(b"this is a content number 2.").unwrap();
+        database.verify(&hash1).unwrap();
+        database.verify(&hash2).unwrap();
+
+        // Flip a bit of the first waste's content, just after its flag and its
+        // one-byte length.
+        let data_path = Path::new(database_path).join("data");
+        let mut content = fs::read(&data_path).unwrap();
+        content[2] ^= 1;
+        fs::write(&data_path, content).unwrap();
+
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.get(&hash1).unwrap_err().to_string(), "checksum mismatch");
+        assert_eq!(database.verify(&hash1).unwrap_err().to_string(), "checksum mismatch");
+        let err = database.get_reader(&hash1).unwrap().read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(database.get(&hash2).unwrap(), b"this is a content number 2.");
+        database.verify(&hash2).unwrap();
     }
 
-    pub fn put(&mut self, data: &[u8]) -> Result<String, Error> {
-        let hash = Self::gen_waste_hash(data);
+    #[test]
+    fn it_verifies_all_wastes() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-verifies-all-wastes";
+        clean_up(database_path);
 
-        let offset = self.data.seek(SeekFrom::End(0)).to_inner_result("set offset")?;
-        self.data.write(&Offset::new(data.len() as u64).to_bytes())
-            .to_inner_result("write waste's length")?;
-        self.data.write_all(data).to_inner_result("write waste's data")?;
+        let mut database = Database::new(database_path).unwrap();
+        let hash1 = database.put(b"this is a content number 1.").unwrap();
+        let hash2 = database.put(b"this is a content number 2.").unwrap();
+        let hash3 = database.put(b"this is a content number 3.").unwrap();
+        let report = database.verify_all().unwrap();
+        assert_eq!(report, VerifyReport { checked: 3, ..Default::default() });
+        assert!(report.is_ok());
+        let offset3 = database.stat(&hash3).unwrap().offset;
+        drop(database);
 
-        self.indexer.put(&hash, offset)?;
-        self.len += 1;
+        // Flip a bit of the first waste's content, and cut the last waste's
+        // record just after its flag.
+        let data_path = Path::new(database_path).join("data");
+        let mut content = fs::read(&data_path).unwrap();
+        content[2] ^= 1;
+        content.truncate(offset3 as usize + 1);
+        fs::write(&data_path, &content).unwrap();
+        let report = Database::new(database_path).unwrap().verify_all().unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.mismatched, vec![hash1.clone()]);
+        assert_eq!(report.unreadable, vec![hash3.clone()]);
+        assert!(report.out_of_bounds.is_empty());
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.get(&hash2).unwrap(), b"this is a content number 2.");
+        drop(database);
 
-        if data.len() < 256 * 1024 { // 256KB.
-            self.cache.put(hash.clone(), Vec::from(data));
-            let new_cache_size = NonZeroUsize::new(self.len / 4 + 16).unwrap();
-            self.cache.resize(new_cache_size);
+        // Now the last waste is beyond the end.
+        content.truncate(offset3 as usize);
+        fs::write(&data_path, &content).unwrap();
+        let report = Database::new(database_path).unwrap().verify_all().unwrap();
+        assert_eq!(report.out_of_bounds, vec![hash3]);
+        assert!(report.unreadable.is_empty());
+
+        // The index is a single leaf: break its node type, so that no waste
+        // can be found.
+        let index_path = Path::new(database_path).join("index");
+        let mut content = fs::read(&index_path).unwrap();
+        content[DEFAULT_PAGE_SIZE] = 0xff;
+        fs::write(&index_path, content).unwrap();
+        let report = Database::new(database_path).unwrap().verify_all().unwrap();
+        assert_eq!(report.checked, 0);
+        assert_eq!(report.unreadable_pages.len(), 1);
+    }
+
+    #[test]
+    fn it_expires_wastes() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-expires-wastes";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash1 = database.put_with_ttl(b"I will be gone.", Duration::from_millis(50)).unwrap();
+        let hash2 = database.put(b"I will be here.").unwrap();
+        let hash2_again = database.put_with_ttl(b"I will be here.", Duration::from_millis(50)).unwrap();
+        assert_eq!(hash2_again, hash2);
+        let hash3 =
+            database.put_with_ttl(b"I will be here for long.", Duration::from_secs(3600)).unwrap();
+        assert_eq!(database.get(&hash1).unwrap(), b"I will be gone.");
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(database.get(&hash1).unwrap_err().kind(), crate::ErrorKind::NotFound);
+        assert!(!database.contains(&hash1).unwrap());
+        assert_eq!(database.get(&hash2).unwrap(), b"I will be here.");
+        assert_eq!(database.get(&hash3).unwrap(), b"I will be here for long.");
+        // The expired waste is still counted until it is swept.
+        assert_eq!(database.len().unwrap(), 3);
+        assert_eq!(database.sweep_expired().unwrap(), 1);
+        assert_eq!(database.sweep_expired().unwrap(), 0);
+        assert_eq!(database.len().unwrap(), 2);
+        drop(database);
+
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.list().unwrap().len(), 2);
+        assert!(!database.contains(&hash1).unwrap());
+        assert!(database.contains(&hash3).unwrap());
+    }
+
+    #[test]
+    fn it_tells_kinds_of_errors() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-tells-kinds-of-errors";
+        clean_up(database_path);
+
+        let database = Database::new(database_path).unwrap();
+        let absent_hash = Database::gen_waste_hash(b"I am not here.");
+        assert_eq!(database.get(&absent_hash).unwrap_err().kind(), crate::ErrorKind::NotFound);
+        assert_eq!(database.get("not a hash").unwrap_err().kind(), crate::ErrorKind::InvalidHash);
+        let not_hex = "z".repeat(64);
+        assert_eq!(database.get(&not_hex).unwrap_err().kind(), crate::ErrorKind::InvalidHash);
+        let err = Database::<Blake3>::with_hasher(database_path).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn it_works_with_meta() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-meta";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash = database.put_with_meta(b"<p>hello</p>", b"text/html").unwrap();
+        assert_eq!(hash, Database::gen_waste_hash(b"<p>hello</p>"));
+        let plain_hash = database.put(b"hello").unwrap();
+        let streamed_hash = database.put_reader(&b"hello again"[..]).unwrap();
+
+        let mut database = Database::new(database_path).unwrap();
+        assert_eq!(
+            database.get_with_meta(&hash).unwrap(),
+            (b"<p>hello</p>".to_vec(), b"text/html".to_vec()),
+        );
+        assert_eq!(database.get_meta(&plain_hash).unwrap(), b"");
+        assert_eq!(database.get_meta(&streamed_hash).unwrap(), b"");
+        assert_eq!(database.get(&streamed_hash).unwrap(), b"hello again");
+
+        // The same data is the same waste, whatever its metadata is.
+        let data_bytes = database.stats().unwrap().data_bytes;
+        let same_hash = database.put_with_meta(b"<p>hello</p>", b"text/plain").unwrap();
+        assert_eq!(same_hash, hash);
+        assert_eq!(database.get_meta(&hash).unwrap(), b"text/plain");
+        assert_eq!(database.len().unwrap(), 3);
+        assert!(database.stats().unwrap().data_bytes > data_bytes);
+
+        // It is only referenced again if its metadata is the same.
+        let data_bytes = database.stats().unwrap().data_bytes;
+        assert_eq!(database.put_with_meta(b"<p>hello</p>", b"text/plain").unwrap(), hash);
+        assert_eq!(database.stats().unwrap().data_bytes, data_bytes);
+        assert_eq!(database.len().unwrap(), 3);
+        for _ in 0..3 {
+            assert!(database.delete(&hash).unwrap());
         }
-        Ok(hash)
+        assert!(!database.contains(&hash).unwrap());
+    }
+
+    #[test]
+    fn it_checks_version() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-checks-version";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        let hash = database.put(b"hello world").unwrap();
+        let version_path = Path::new(database_path).join("version");
+        let version = fs::read_to_string(&version_path).unwrap();
+        assert!(version.contains("hash=sha256"));
+        assert!(version.contains(&format!("page_size={}", DEFAULT_PAGE_SIZE)));
+
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"hello world");
+
+        // A folder written by a future format.
+        fs::write(&version_path, version.replace("version=", "version=1")).unwrap();
+        let err = Database::new(database_path).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
+        assert_eq!(err.to_string(), "unsupported version");
+    }
+
+    #[test]
+    fn it_opens_existing_databases_only() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-opens-existing-databases-only";
+        clean_up(database_path);
+
+        let err = Database::open(database_path).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::NotFound);
+        assert!(!Path::new(database_path).exists());
+
+        let hash = Database::new(database_path).unwrap().put(b"hello world").unwrap();
+        let database = Database::open(database_path).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn it_checks_uuid_of_index() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-checks-uuid-of-index";
+        let other_path = "/tmp/waste-land.skogatt.org/it-checks-uuid-of-index-2";
+        clean_up(database_path);
+        clean_up(other_path);
+        let path = Path::new(database_path);
+
+        let hash = Database::new(database_path).unwrap().put(b"hello world").unwrap();
+        Database::new(other_path).unwrap().put(b"hello wasteland").unwrap();
+        let version = fs::read_to_string(path.join("version")).unwrap();
+        let other_version = fs::read_to_string(Path::new(other_path).join("version")).unwrap();
+        assert!(version.lines().nth(1).unwrap().starts_with("uuid="));
+        assert_ne!(version, other_version);
+
+        // The index of another database is never taken.
+        fs::copy(Path::new(other_path).join("index"), path.join("index")).unwrap();
+        let err = Database::new(database_path).err().unwrap();
+        assert_eq!(err.to_string(), "index does not belong to this database");
+        let err = Database::open_read_only(database_path).err().unwrap();
+        assert_eq!(err.to_string(), "index does not belong to this database");
+
+        // A rebuilt index belongs to the database.
+        Database::rebuild_index(database_path).unwrap();
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"hello world");
     }
 
-    pub fn get(&mut self, hash: &str) -> Result<Vec<u8>, Error> {
-        if let Some(result) = self.cache.get(hash) {
-            return Ok(result.clone());
+    #[test]
+    fn it_refuses_non_database_folders() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-refuses-non-database-folders";
+        clean_up(database_path);
+        let path = Path::new(database_path);
+
+        fs::create_dir_all(path).unwrap();
+        fs::write(path.join("notes.txt"), b"my notes").unwrap();
+        let err = Database::new(database_path).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "refusing to initialize over non-empty non-database directory",
+        );
+        assert!(!path.join("version").exists());
+        assert!(!path.join("data").exists());
+
+        // A database with other files in it is still opened.
+        fs::remove_file(path.join("notes.txt")).unwrap();
+        let mut database = Database::new(database_path).unwrap();
+        let hash = database.put(b"hello world").unwrap();
+        drop(database);
+        fs::write(path.join("notes.txt"), b"my notes").unwrap();
+        let database = Database::new(database_path).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn it_works_with_split_dirs() {
+        let data_path = "/tmp/waste-land.skogatt.org/it-works-with-split-dirs/data";
+        let index_path = "/tmp/waste-land.skogatt.org/it-works-with-split-dirs/index";
+        clean_up("/tmp/waste-land.skogatt.org/it-works-with-split-dirs");
+        let (data_dir, index_dir) = (Path::new(data_path), Path::new(index_path));
+
+        let mut database = Database::new_split(data_path, index_path).unwrap();
+        let hash = database.put(b"hello world").unwrap();
+        database.put_named("greeting", b"hello wasteland").unwrap();
+        database.delete(&hash).unwrap();
+        database.compact().unwrap();
+        let hash = database.put(b"hello world").unwrap();
+        drop(database);
+        assert!(data_dir.join("data").exists());
+        assert!(!index_dir.join("data").exists());
+        for name in ["index", "names", "version"] {
+            assert!(index_dir.join(name).exists());
+            assert!(!data_dir.join(name).exists());
         }
 
-        let offset = self.indexer.get(hash).to_inner_result("get offset by hash")?;
-        let offset = match offset {
-            None => return Err(Error::new("hash not found")),
-            Some(o) => o,
-        };
+        // It is opened by the directory of the index alone, in any mode.
+        let database = Database::new(index_path).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"hello world");
+        assert_eq!(database.get_named("greeting").unwrap(), b"hello wasteland");
+        drop(database);
+        let database = Database::open_read_only(index_path).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"hello world");
+        drop(database);
+        let database = Database::new_split(data_path, index_path).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"hello world");
+        drop(database);
 
-        self.data.seek(SeekFrom::Start(offset.to_u64()))
-            .to_inner_result("set offset")?;
+        let other_data_path = "/tmp/waste-land.skogatt.org/it-works-with-split-dirs/other";
+        let err = Database::new_split(other_data_path, index_path).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
 
-        let mut size = [0u8; 8];
-        self.data.read_exact(&mut size).to_inner_result("read size")?;
-        let size = Offset::from_bytes(size).to_u64() as usize;
+        Database::new(index_path).unwrap().destroy().unwrap();
+        assert!(!data_dir.exists());
+        assert!(!index_dir.exists());
+    }
 
-        let mut content = Vec::with_capacity(size);
-        unsafe { content.set_len(size) };
-        self.data.read_exact(&mut content).to_inner_result("read waste")?;
-        Ok(content)
+    #[test]
+    fn it_works_with_page_size() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-page-size";
+        clean_up(database_path);
+
+        let err = Database::<Sha256>::with_page_size(database_path, 3000).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Unsupported);
+
+        let mut database = Database::<Sha256>::with_page_size(database_path, 16 << 10).unwrap();
+        let hashes: Vec<String> = (0..1000u32)
+            .map(|i| database.put(&i.to_le_bytes()).unwrap())
+            .collect();
+        drop(database);
+        let version = fs::read_to_string(Path::new(database_path).join("version")).unwrap();
+        assert!(version.contains("page_size=16384"));
+
+        // It is opened with its own page size.
+        let database = Database::new(database_path).unwrap();
+        for (i, hash) in hashes.iter().enumerate() {
+            assert_eq!(database.get(hash).unwrap(), (i as u32).to_le_bytes());
+        }
     }
 
-    pub fn drop(self) -> Result<(), Error> {
-        fs::remove_dir_all(&self.path)
-            .to_inner_result(&format!("remove directory {}", &self.path.display()))?;
-        Ok(())
+    #[test]
+    fn it_works_with_put_batch() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-put-batch";
+        clean_up(database_path);
+
+        let contents: Vec<Vec<u8>> = (0..1000u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let items: Vec<&[u8]> = contents.iter().map(|c| c.as_slice()).collect();
+        let mut database = Database::new(database_path).unwrap();
+        let hashes = database.put_batch(&items).unwrap();
+        assert_eq!(database.len().unwrap(), 1000);
+        for (hash, content) in hashes.iter().zip(&contents) {
+            assert_eq!(hash, &Database::gen_waste_hash(content));
+        }
+
+        let database = Database::new(database_path).unwrap();
+        for (hash, content) in hashes.iter().zip(&contents) {
+            assert_eq!(&database.get(hash).unwrap(), content);
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::io::ErrorKind;
-    use rand::{self, seq::SliceRandom};
-    use benchmark::picture_cache::PictureCache;
+    #[test]
+    fn it_rejects_too_large_values() {
+        let mut database = Database::new_in(MemStore::new(), MemStore::new())
+            .unwrap()
+            .with_max_value_size(Some(16));
+        let hash = database.put(b"exactly 16 bytes").unwrap();
+        let data_bytes = database.stats().unwrap().data_bytes;
 
-    use super::*;
+        let err = database.put(b"more than 16 bytes").err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::TooLarge);
+        let err = database.put_batch(&[b"small", b"more than 16 bytes"]).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::TooLarge);
+        assert_eq!(database.stats().unwrap().data_bytes, data_bytes);
+        assert_eq!(database.len().unwrap(), 1);
 
-    fn clean_up(database_path: &str) {
-        match fs::remove_dir_all(database_path) {
-            Err(e) if e.kind() != ErrorKind::NotFound => {
-                panic!("{}", e)
+        // A reader never ending is cut off.
+        let err = database.put_reader(io::repeat(42)).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::TooLarge);
+        database.put_reader(&b"small"[..]).unwrap();
+        assert_eq!(database.len().unwrap(), 2);
+
+        let mut database = database.with_max_value_size(None);
+        database.put(b"more than 16 bytes").unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"exactly 16 bytes");
+    }
+
+    #[test]
+    fn it_syncs_data_before_indexing() {
+        use std::sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc};
+
+        /// The data store which remembers its length when it is synced last.
+        #[derive(Clone, Default)]
+        struct DataStore {
+            store: MemStore,
+            synced_len: Arc<AtomicU64>,
+        }
+
+        /// The index store which fails all writes once `failing` is set, and
+        /// remembers the length of the data synced when it is written last.
+        #[derive(Clone, Default)]
+        struct IndexStore {
+            store: MemStore,
+            data: DataStore,
+            failing: Arc<AtomicBool>,
+            data_synced_len: Arc<AtomicU64>,
+        }
+
+        impl BlockStore for DataStore {
+            fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+                self.store.read_at(buf, offset)
+            }
+            fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+                self.store.write_at(buf, offset)
+            }
+            fn len(&self) -> io::Result<u64> {
+                self.store.len()
+            }
+            fn set_len(&self, len: u64) -> io::Result<()> {
+                self.store.set_len(len)
+            }
+            fn sync(&self) -> io::Result<()> {
+                self.synced_len.store(self.store.len()?, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        impl BlockStore for IndexStore {
+            fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+                self.store.read_at(buf, offset)
+            }
+            fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+                let data_synced_len = self.data.synced_len.load(Ordering::SeqCst);
+                self.data_synced_len.store(data_synced_len, Ordering::SeqCst);
+                if self.failing.load(Ordering::SeqCst) {
+                    return Err(io::Error::other("index write failed"));
+                }
+                self.store.write_at(buf, offset)
+            }
+            fn len(&self) -> io::Result<u64> {
+                self.store.len()
+            }
+            fn set_len(&self, len: u64) -> io::Result<()> {
+                self.store.set_len(len)
+            }
+        }
+
+        let data = DataStore::default();
+        let index = IndexStore { data: data.clone(), ..Default::default() };
+        let mut database = Database::new_in(data.clone(), index.clone())
+            .unwrap()
+            .with_sync_policy(SyncPolicy::EveryWrite);
+        let hash = database.put(b"this is a content.").unwrap();
+
+        // The index fails to be written, but the record is already on the disk.
+        index.failing.store(true, Ordering::SeqCst);
+        assert!(database.put(b"this is another content.").is_err());
+        let data_len = data.len().unwrap();
+        assert_eq!(index.data_synced_len.load(Ordering::SeqCst), data_len);
+        assert_eq!(data.synced_len.load(Ordering::SeqCst), data_len);
+
+        index.failing.store(false, Ordering::SeqCst);
+        drop(database);
+        let database = Database::new_in(data, index).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"this is a content.");
+    }
+
+    #[test]
+    fn it_syncs_in_background() {
+        use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+
+        /// The data store which remembers its length when it is synced last.
+        #[derive(Clone, Default)]
+        struct DataStore {
+            store: MemStore,
+            synced_len: Arc<AtomicU64>,
+        }
+
+        impl BlockStore for DataStore {
+            fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+                self.store.read_at(buf, offset)
+            }
+            fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+                self.store.write_at(buf, offset)
+            }
+            fn len(&self) -> io::Result<u64> {
+                self.store.len()
+            }
+            fn set_len(&self, len: u64) -> io::Result<()> {
+                self.store.set_len(len)
+            }
+            fn sync(&self) -> io::Result<()> {
+                self.synced_len.store(self.store.len()?, Ordering::SeqCst);
+                Ok(())
             }
-            _ => (),
         }
+
+        let data = DataStore::default();
+        let index = MemStore::new();
+        let index_bytes = || {
+            let mut buf = vec![0; index.len().unwrap() as usize];
+            index.read_exact_at(&mut buf, 0).unwrap();
+            buf
+        };
+        let mut database = Database::new_in(data.clone(), index.clone())
+            .unwrap()
+            .with_sync_policy(SyncPolicy::Background(Duration::from_millis(10)));
+        let indexed = index_bytes();
+        database.put(b"this is a content.").unwrap();
+        // The write never waits for a sync, but the thread syncs it soon - and
+        // the index is only written once the data file is synced.
+        let data_len = data.len().unwrap();
+        let start = Instant::now();
+        while index_bytes() == indexed {
+            assert!(start.elapsed() < Duration::from_secs(10), "never synced in background");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(data.synced_len.load(Ordering::SeqCst), data_len);
+
+        // The thread stops promptly, and the last writes are synced by the
+        // database itself.
+        let hour = Duration::from_secs(3600);
+        let mut database = database.with_sync_policy(SyncPolicy::Background(hour));
+        database.put(b"this is another content.").unwrap();
+        let start = Instant::now();
+        drop(database);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(data.synced_len.load(Ordering::SeqCst), data.len().unwrap());
     }
 
     #[test]
-    fn it_works() {
-        let database_path = "/tmp/waste-land.skogatt.org/it-works";
+    fn it_preallocates_data_files() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-preallocates-data-files";
         clean_up(database_path);
+        let data_path = Path::new(database_path).join("data");
+        let file_len = || fs::metadata(&data_path).unwrap().len();
+
+        let mut database = Database::new(database_path)
+            .unwrap()
+            .with_preallocation(Some(4096))
+            .unwrap();
+        let mut hashes = vec![];
+        for i in 0..10 {
+            hashes.push(database.put(&vec![i as u8; 1000]).unwrap());
+        }
+        let data_bytes = database.stats().unwrap().data_bytes;
+        assert!(data_bytes < 11000);
+        assert_eq!(file_len(), 3 * 4096);
+        // The end is published once a record is written, even if not synced.
+        let end = fs::read(Path::new(database_path).join("data.end")).unwrap();
+        assert_eq!(u64::from_le_bytes(end.try_into().unwrap()), data_bytes);
+        drop(database);
 
+        // The appends resume at the end of the data, even without preallocation.
         let mut database = Database::new(database_path).unwrap();
-        let waste_hash = database.put(b"hello world").unwrap();
-        let waste2_hash = database.put(b"hello world again").unwrap();
-        assert_eq!(database.get(&waste_hash).unwrap(), b"hello world");
-        assert_eq!(database.get(&waste2_hash).unwrap(), b"hello world again");
+        assert_eq!(database.stats().unwrap().data_bytes, data_bytes);
+        let hash = database.put(b"this is a content.").unwrap();
+        assert_eq!(database.stat(&hash).unwrap().offset, data_bytes);
+        assert_eq!(file_len(), 3 * 4096);
+        for (i, hash) in hashes.iter().enumerate() {
+            assert_eq!(database.get(hash).unwrap(), vec![i as u8; 1000]);
+        }
+        drop(database);
+
+        let database = Database::open_read_only(database_path).unwrap();
+        assert_eq!(database.get(&hash).unwrap(), b"this is a content.");
+        assert!(database.verify_all().unwrap().is_ok());
+        drop(database);
+
+        // The compacted data file is just as long as the data.
+        let mut database = Database::new(database_path).unwrap();
+        database.delete(&hashes[0]).unwrap();
+        database.compact().unwrap();
+        assert_eq!(file_len(), database.stats().unwrap().data_bytes);
+        assert!(!Path::new(database_path).join("data.end").exists());
+        assert_eq!(database.get(&hashes[9]).unwrap(), vec![9u8; 1000]);
+
+        assert!(Database::new_in(MemStore::new(), MemStore::new())
+            .unwrap()
+            .with_preallocation(Some(4096))
+            .is_err());
     }
 
     #[test]
-    fn it_works_on_large_data() {
-        let database_path = "/tmp/waste-land.skogatt.org/it-works-on-large-data";
+    fn it_works_with_sync_policies() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-sync-policies";
+        clean_up(database_path);
+
+        let policies = [
+            SyncPolicy::Never,
+            SyncPolicy::EveryWrite,
+            SyncPolicy::Periodic(Duration::ZERO),
+            SyncPolicy::Periodic(Duration::from_secs(3600)),
+        ];
+        let mut hashes = vec![];
+        for (i, policy) in policies.into_iter().enumerate() {
+            let mut database = Database::new(database_path).unwrap().with_sync_policy(policy);
+            let last_sync = database.last_sync;
+            let content = format!("content synced by {:?}", policy);
+            let hash = database.put(content.as_bytes()).unwrap();
+            database.put_batch(&[format!("batch number {}", i).as_bytes()]).unwrap();
+            assert_eq!(database.get(&hash).unwrap(), content.as_bytes());
+            let synced = database.last_sync != last_sync;
+            assert_eq!(synced, matches!(policy, SyncPolicy::EveryWrite | SyncPolicy::Periodic(Duration::ZERO)));
+            hashes.push((hash, content));
+        }
+
+        let mut database = Database::new(database_path).unwrap();
+        assert_eq!(database.len().unwrap(), policies.len() * 2);
+        for (hash, content) in &hashes {
+            assert_eq!(database.get(hash).unwrap(), content.as_bytes());
+        }
+        database.sync().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "memmap2")]
+    fn it_works_with_mmap() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-mmap";
+        clean_up(database_path);
+
+        let mut database = Database::open_mmap(database_path).unwrap();
+        let hash1 = database.put(b"hello world").unwrap();
+        let hash2 = database.put_reader(&b"hello again"[..]).unwrap();
+        let hashes = database.put_batch(&[b"one", b"two"]).unwrap();
+        assert!(matches!(database.get_cow(&hash1).unwrap(), Cow::Borrowed(b"hello world")));
+        assert_eq!(database.get(&hash2).unwrap(), b"hello again");
+        assert_eq!(database.get(&hashes[1]).unwrap(), b"two");
+        drop(database);
+
+        let database = Database::open_mmap(database_path)
+            .unwrap()
+            .with_compression(CompressionMode::Zstd);
+        assert_eq!(&*database.get_cow(&hash1).unwrap(), b"hello world");
+        let mut database = database;
+        let compressed = vec![b'a'; 4096];
+        let hash3 = database.put(&compressed).unwrap();
+        assert!(matches!(database.get_cow(&hash3).unwrap(), Cow::Owned(_)));
+        assert_eq!(database.get(&hash3).unwrap(), compressed);
+
+        // Just like `get`, for the database not mapped.
+        let database = Database::open_read_only(database_path).unwrap();
+        assert!(matches!(database.get_cow(&hash1).unwrap(), Cow::Owned(_)));
+        assert_eq!(database.get_cow(&hash3).unwrap(), compressed);
+    }
+
+    #[test]
+    fn it_works_with_concurrent_readers() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-concurrent-readers";
         clean_up(database_path);
 
         let mut database = Database::new(database_path).unwrap();
-        let size = 30000;
-        let cache = PictureCache::new(size);
+        let wastes: Vec<(String, Vec<u8>)> = (0..64u32)
+            .map(|i| {
+                // Larger than the cache, so that the data file is really read.
+                let data = vec![i as u8; 300 * 1024];
+                (database.put(&data).unwrap(), data)
+            })
+            .collect();
 
-        let mut step = 0;
-        let mut bef_step = 0;
-        for p in &cache.data_pathes {
-            step += 1;
-            if step * 100 / cache.data_hashes.len() != bef_step {
-                eprintln!("{} / 100", step * 100 / cache.data_hashes.len());
-                bef_step += 1;
-            }
+        let database = std::sync::Arc::new(database);
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let database = database.clone();
+                let wastes = wastes.clone();
+                std::thread::spawn(move || {
+                    for (hash, data) in wastes.iter().cycle().skip(t).take(64) {
+                        assert_eq!(&database.get(hash).unwrap(), data);
+                        let mut content = vec![];
+                        database.get_reader(hash).unwrap().read_to_end(&mut content).unwrap();
+                        assert_eq!(&content, data);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
 
-            let content = fs::read(p).unwrap();
-            database.put(&content).unwrap();
+    #[test]
+    fn it_works_in_read_only_mode() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-in-read-only-mode";
+        clean_up(database_path);
+
+        assert!(Database::open_read_only(database_path).is_err());
+        assert!(!Path::new(database_path).exists());
+
+        let hash = Database::new(database_path).unwrap().put(b"hello world").unwrap();
+
+        // Many readers can share one database.
+        let mut reader1 = Database::open_read_only(database_path).unwrap();
+        let reader2 = Database::open_read_only(database_path).unwrap();
+        assert!(reader1.is_read_only());
+        assert_eq!(reader1.get(&hash).unwrap(), b"hello world");
+        assert_eq!(reader2.list().unwrap(), vec![hash.as_str()]);
+
+        let err = reader1.put(b"hello").err().unwrap();
+        assert_eq!(err.to_string(), "database is read-only");
+        assert!(reader1.put_reader(&b"hello"[..]).is_err());
+        assert!(reader1.delete(&hash).is_err());
+        drop(reader1);
+
+        assert_eq!(reader2.len().unwrap(), 1);
+        assert!(reader2.contains(&hash).unwrap());
+    }
+
+    #[test]
+    fn it_refreshes_wastes_put_by_another_writer() {
+        let database_path =
+            "/tmp/waste-land.skogatt.org/it-refreshes-wastes-put-by-another-writer";
+        clean_up(database_path);
+
+        let mut writer = Database::new(database_path).unwrap();
+        let hash1 = writer.put(b"hello world").unwrap();
+        let mut reader = Database::open_read_only(database_path).unwrap();
+        assert_eq!(reader.get(&hash1).unwrap(), b"hello world");
+        assert!(!reader.refresh().unwrap());
+
+        let hashes: Vec<String> = (0..1000)
+            .map(|i| writer.put(format!("content number {}", i).as_bytes()).unwrap())
+            .collect();
+        assert!(!reader.contains(&hashes[0]).unwrap());
+        assert!(reader.refresh().unwrap());
+        assert!(!reader.refresh().unwrap());
+        assert_eq!(reader.len().unwrap(), 1001);
+        for (i, hash) in hashes.iter().enumerate() {
+            assert_eq!(reader.get(hash).unwrap(), format!("content number {}", i).as_bytes());
         }
 
-        for step in 0..100 {
-            eprintln!("{} / 100", step);
-            for h in &cache.data_hashes[size / 100 * step .. size / 100 * (step + 1)] {
-                let mut database = Database::new(database_path).unwrap();
-                database.get(h).unwrap();
-            }
+        assert!(writer.delete(&hash1).unwrap());
+        assert_eq!(reader.get(&hash1).unwrap(), b"hello world");
+        assert!(reader.refresh().unwrap());
+        assert_eq!(reader.get(&hash1).unwrap_err().kind(), crate::ErrorKind::NotFound);
+        assert_eq!(reader.list().unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn it_never_rebuilds_the_bloom_filter_on_lookups_after_refreshes() {
+        let database_path =
+            "/tmp/waste-land.skogatt.org/it-never-rebuilds-the-bloom-filter-on-lookups";
+        clean_up(database_path);
+
+        let mut writer = Database::new(database_path).unwrap();
+        for i in 0..1000 {
+            writer.put(format!("content number {}", i).as_bytes()).unwrap();
         }
+        let mut reader = Database::open_read_only(database_path).unwrap();
+        let absent_hash = Sha256::hex_digest(b"an absent content");
+        assert!(!reader.contains(&absent_hash).unwrap());
+
+        let hashes: Vec<String> = (1000..2000)
+            .map(|i| writer.put(format!("content number {}", i).as_bytes()).unwrap())
+            .collect();
+        assert!(reader.refresh().unwrap());
+        let page_reads = |reader: &Database| {
+            let stats = reader.indexer.pager_stats();
+            stats.cache_hits + stats.cache_misses
+        };
+
+        // The hashes are maybe present, so they are looked up by the B+tree
+        // instead of scanning all of them to rebuild the filter.
+        let before = page_reads(&reader);
+        assert!(!reader.contains(&absent_hash).unwrap());
+        assert!(reader.contains(&hashes[0]).unwrap());
+        let height = reader.indexer.height().unwrap() as u64;
+        assert!(page_reads(&reader) - before <= 2 * (height + 1));
+
+        // The filter is rebuilt by the refresh finding nothing changed.
+        assert!(!reader.refresh().unwrap());
+        let before = page_reads(&reader);
+        assert!(!reader.contains(&absent_hash).unwrap());
+        assert_eq!(page_reads(&reader), before);
+        assert!(reader.contains(&hashes[999]).unwrap());
+    }
+
+    #[test]
+    fn it_can_be_destroyed() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-can-be-destroyed";
+        clean_up(database_path);
+
+        let mut database = Database::new(database_path).unwrap();
+        database.put(b"hello world").unwrap();
+        database.destroy().unwrap();
+        assert!(!Path::new(database_path).exists());
     }
 
     #[test]
@@ -177,7 +4889,7 @@ mod tests {
         let hash1 = database.put(b"this is a content number 1.").unwrap();
         let hash2 = database.put(b"this is a content number 2.").unwrap();
 
-        let mut database = Database::new(database_path).unwrap();
+        let database = Database::new(database_path).unwrap();
         assert_eq!(
             database.get(&hash1).unwrap(),
             b"this is a content number 1."
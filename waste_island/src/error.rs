@@ -1,15 +1,49 @@
 use std::fmt::{Debug, Display};
 
+/// What kind of error it is - so that the caller can handle them without
+/// matching the message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The waste is not stored.
+    NotFound,
+    /// The data file or the index file is broken.
+    Corrupt,
+    /// The IO failed.
+    Io,
+    /// The hash is not a valid hex string of `HASH_SIZE` bytes.
+    InvalidHash,
+    /// The database is in a format or configuration we do not support.
+    Unsupported,
+    /// The waste is larger than the max size of values. See
+    /// `Database::with_max_value_size`.
+    TooLarge,
+}
+
 pub struct Error {
+    kind: ErrorKind,
     message: String,
+    /// The error from outside which causes it - for example, the `io::Error`
+    /// from the OS. See `to_inner_result`.
+    source: Option<Box<dyn std::error::Error + Send + Sync>>,
 }
 
 impl Error {
-    pub fn new(message: &str) -> Self {
+    /// Create an error of `ErrorKind::Io`. See `with_kind` as well.
+    pub fn new(message: impl Into<String>) -> Self {
+        Self::with_kind(ErrorKind::Io, message)
+    }
+
+    pub fn with_kind(kind: ErrorKind, message: impl Into<String>) -> Self {
         Error {
-            message: message.to_string(),
+            kind,
+            message: message.into(),
+            source: None,
         }
     }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
 }
 
 impl Debug for Error {
@@ -24,15 +58,78 @@ impl Display for Error {
     }
 }
 
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_ref().map(|source| &**source as _)
+    }
+}
+
 pub trait ToInnerResult<T> {
+    /// Wrap the error with the prefix telling what was being done. The error
+    /// from outside is kept as the source of our error.
     fn to_inner_result(self, prefix: &str) -> Result<T, Error>;
 }
 
-impl<T, E> ToInnerResult<T> for Result<T, E> where E: std::fmt::Display {
+impl<T, E> ToInnerResult<T> for Result<T, E>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
     fn to_inner_result(self, prefix: &str) -> Result<T, Error> {
         match self {
             Ok(v) => Ok(v),
-            Err(err) => Err(Error::new(&format!("{}: {}", prefix, err.to_string())))
+            Err(err) => {
+                let message = format!("{}: {}", prefix, err);
+                // Keep the kind and the source if it is our error. Otherwise
+                // it is from IO, and it is the source itself.
+                let err: Box<dyn std::error::Error + Send + Sync> = Box::new(err);
+                let (kind, source) = match err.downcast::<Error>() {
+                    Ok(err) => (err.kind, err.source),
+                    Err(err) => (ErrorKind::Io, Some(err)),
+                };
+                Err(Error { kind, message, source })
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works_as_std_error() {
+        fn fail() -> Result<(), Box<dyn std::error::Error>> {
+            Err(Error::new(String::from("hash not found")))?;
+            Ok(())
+        }
+        assert_eq!(fail().unwrap_err().to_string(), "hash not found");
+        assert_eq!(Error::new("hash not found").to_string(), "hash not found");
+    }
+
+    #[test]
+    fn it_keeps_the_kind_when_wrapped() {
+        let err: Result<(), Error> = Err(Error::with_kind(ErrorKind::NotFound, "hash not found"));
+        let err = err.to_inner_result("get offset by hash").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "get offset by hash: hash not found");
+
+        let err: Result<(), std::io::Error> = Err(std::io::ErrorKind::Other.into());
+        assert_eq!(err.to_inner_result("read size").unwrap_err().kind(), ErrorKind::Io);
+    }
+
+    #[test]
+    fn it_keeps_the_source_when_wrapped() {
+        use std::error::Error as _;
+
+        let err = Error::with_kind(ErrorKind::NotFound, "hash not found");
+        assert!(err.source().is_none());
+
+        let err: Result<(), std::io::Error> = Err(std::io::ErrorKind::PermissionDenied.into());
+        let err = err.to_inner_result("open data file").unwrap_err();
+        let err = Err::<(), _>(err).to_inner_result("open database").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Io);
+        assert_eq!(err.to_string(), "open database: open data file: permission denied");
+        let source = err.source().unwrap().downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(source.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    btree::Pager, error::{Error, ToInnerResult}, store::BlockStore,
+};
+
+/// The background thread syncing the data files and the indexes of a database
+/// every interval, see `SyncPolicy::Background`. It is stopped and joined once
+/// it is dropped - promptly, without waiting for the interval to pass.
+///
+/// The pagers of the indexes defer their writes, see `Pager::set_deferred`.
+/// So each time the pages deferred are staged first, then the data files are
+/// synced, and only then the staged pages are written - the indexes on the
+/// disk never reference records which are not.
+pub struct Flusher {
+    /// Is the thread asked to stop? It is notified by the condvar.
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Flusher {
+    /// Spawn the thread syncing the data files, and then the indexes by their
+    /// deferred pagers, every `interval`.
+    pub fn spawn(
+        interval: Duration,
+        data: Vec<Arc<dyn BlockStore>>,
+        mut pagers: Vec<Pager>,
+    ) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let handle = thread::spawn({
+            let stop = stop.clone();
+            move || loop {
+                let (stopped, condvar) = &*stop;
+                let stopped = stopped.lock().unwrap();
+                let (stopped, _) =
+                    condvar.wait_timeout_while(stopped, interval, |stopped| !*stopped).unwrap();
+                if *stopped {
+                    return;
+                }
+                drop(stopped);
+
+                let result = sync(&data, &mut pagers);
+                // It is best effort: the database syncs again by itself once
+                // it is dropped, and reports the error of `Database::sync`.
+                #[cfg(feature = "tracing")]
+                if let Err(e) = &result {
+                    tracing::warn!(error = ?e, "background sync failed");
+                }
+                let _ = result;
+            }
+        });
+        Self { stop, handle: Some(handle) }
+    }
+}
+
+/// Sync the data files, and then the pages of the indexes staged before. The
+/// pages are staged first, so they only reference the records written to the
+/// data files before they are synced. The pages deferred meanwhile are left
+/// for the next time.
+fn sync(data: &[Arc<dyn BlockStore>], pagers: &mut [Pager]) -> Result<(), Error> {
+    for pager in pagers.iter_mut() {
+        pager.stage_deferred();
+    }
+    for store in data {
+        store.sync().to_inner_result("fsync data file")?;
+    }
+    for pager in pagers.iter_mut() {
+        pager.sync_staged()?;
+    }
+    Ok(())
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        let (stopped, condvar) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        condvar.notify_one();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
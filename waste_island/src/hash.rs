@@ -1,21 +1,35 @@
 use std::fmt::{Display, Debug};
 
-use crate::error::Error;
+use crate::error::{Error, ErrorKind};
 
 pub const HASH_SIZE: usize = 32;
 
-#[derive(Eq, PartialEq, Hash, PartialOrd, Clone, Copy)]
+/// The hash of a waste, as the key of the index.
+///
+/// Hashes are ordered as their bytes are - lexicographically, from the first
+/// byte - which is the same as the order of their hex strings. The keys of the
+/// index and all lists of hashes are in this order.
+#[derive(Eq, PartialEq, Hash, PartialOrd, Ord, Clone, Copy)]
 pub struct Hash([u8; HASH_SIZE]);
 
 impl Hash {
     pub fn from_str(str: &str) -> Result<Self, Error> {
         if str.len() != HASH_SIZE * 2 {
-            return Err(Error::new("the length of str is not equal to HASH_SIZE * 2"));
+            return Err(Error::with_kind(
+                ErrorKind::InvalidHash,
+                "the length of str is not equal to HASH_SIZE * 2",
+            ));
+        }
+        // `from_str_radix` takes a leading `+` as well, so check the digits
+        // first. It also makes sure that `str` is sliced at char boundaries.
+        if !str.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::with_kind(ErrorKind::InvalidHash, "invalid hex in hash"));
         }
 
         let mut result = [0u8; HASH_SIZE];
         for i in 0..HASH_SIZE {
-            let byte = u8::from_str_radix(&str[2 * i..2 * i + 2], 16).unwrap();
+            let byte = u8::from_str_radix(&str[2 * i..2 * i + 2], 16)
+                .map_err(|_| Error::with_kind(ErrorKind::InvalidHash, "invalid hex in hash"))?;
             result[i] = byte;
         }
         Ok(Self(result))
@@ -24,6 +38,20 @@ impl Hash {
     pub fn from_bytes(bytes: [u8; HASH_SIZE]) -> Self {
         Self(bytes)
     }
+
+    pub fn as_bytes(&self) -> &[u8; HASH_SIZE] {
+        &self.0
+    }
+
+    /// Copy the bytes out, as `from_bytes` takes them.
+    pub fn to_bytes(self) -> [u8; HASH_SIZE] {
+        self.0
+    }
+
+    /// The bytes as a slice - for APIs taking bytes of any length.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 impl Display for Hash {
@@ -40,4 +68,60 @@ impl Debug for Hash {
         write!(f, "{}", self)?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_gives_its_bytes_back() {
+        let mut bytes = [0u8; HASH_SIZE];
+        bytes.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        let hash = Hash::from_bytes(bytes);
+        assert_eq!(hash.to_bytes(), bytes);
+        assert_eq!(hash.as_slice(), &bytes[..]);
+        assert_eq!(Hash::from_str(&hash.to_string()).unwrap().to_bytes(), bytes);
+    }
+
+    #[test]
+    fn it_rejects_invalid_hex() {
+        let valid = "0123456789abcdefABCDEF".repeat(3)[..HASH_SIZE * 2].to_string();
+        assert_eq!(Hash::from_str(&valid).unwrap().to_string(), valid.to_lowercase());
+
+        let mut invalids = vec![
+            valid[1..].to_string(),
+            format!("{}0", valid),
+            String::new(),
+            "0g".repeat(HASH_SIZE),
+            "+f".repeat(HASH_SIZE),
+            " f".repeat(HASH_SIZE),
+        ];
+        // A char of two bytes, which is not at the boundary of a byte.
+        invalids.push(format!("0é{}", &valid[3..]));
+        for invalid in invalids {
+            let err = Hash::from_str(&invalid).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidHash, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn it_is_ordered_by_bytes() {
+        let hash = |s: &str| Hash::from_str(&s.repeat(HASH_SIZE)).unwrap();
+        assert!(hash("00") < hash("ff"));
+        assert!(hash("00") < hash("01"));
+
+        // The first byte dominates the second one.
+        let mut bytes = [0u8; HASH_SIZE];
+        bytes[0] = 0x01;
+        let first = Hash::from_bytes(bytes);
+        bytes = [0xff; HASH_SIZE];
+        bytes[0] = 0x00;
+        let second = Hash::from_bytes(bytes);
+        assert!(second < first);
+        assert_eq!(first.cmp(&second), first.to_string().cmp(&second.to_string()));
+
+        let mut hashes = vec![first, hash("ff"), second, hash("00")];
+        hashes.sort();
+        assert_eq!(hashes, [hash("00"), second, first, hash("ff")]);
+    }
+}
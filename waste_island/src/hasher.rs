@@ -0,0 +1,95 @@
+use sha2::Digest;
+
+/// The algorithm to generate the waste hash from the content of data.
+///
+/// A database is always opened with the algorithm it was created with -
+/// `NAME` is recorded in the index to check it.
+pub trait WasteHasher: Default {
+    /// The name recorded in the index. It should be shorter than 16 bytes.
+    const NAME: &'static str;
+
+    /// The length of the digest in bytes.
+    const LEN: usize;
+
+    /// Feed more data.
+    fn update(&mut self, data: &[u8]);
+
+    /// Get the digest of all data fed.
+    fn finalize(self) -> Vec<u8>;
+
+    /// Get the digest of the data.
+    fn digest(data: &[u8]) -> Vec<u8> {
+        let mut hasher = Self::default();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Get the digest of the data, in lowercase hex.
+    fn hex_digest(data: &[u8]) -> String {
+        to_hex(&Self::digest(data))
+    }
+}
+
+/// Turn the digest to lowercase hex.
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SHA-256, the default algorithm.
+#[derive(Default)]
+pub struct Sha256(sha2::Sha256);
+
+impl WasteHasher for Sha256 {
+    const NAME: &'static str = "sha256";
+    const LEN: usize = 32;
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data)
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+}
+
+/// BLAKE3, which is much faster than SHA-256.
+#[derive(Default)]
+pub struct Blake3(blake3::Hasher);
+
+impl WasteHasher for Blake3 {
+    const NAME: &'static str = "blake3";
+    const LEN: usize = 32;
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        self.0.finalize().as_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(
+            Sha256::hex_digest(b"hello world"),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+        );
+        assert_eq!(
+            Blake3::hex_digest(b"hello world"),
+            "d74981efa70a0c880b8d8c1985d075dbcbf679b99a5f9914e5aaf96b831a9e24",
+        );
+    }
+
+    #[test]
+    fn it_works_with_streaming() {
+        let mut hasher = Blake3::default();
+        hasher.update(b"hello ");
+        hasher.update(b"world");
+        assert_eq!(hasher.finalize(), Blake3::digest(b"hello world"));
+    }
+}
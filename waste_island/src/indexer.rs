@@ -1,14 +1,28 @@
-use std::{path::PathBuf};
+use std::{path::{Path, PathBuf}, sync::RwLock, vec};
 
-use crate::btree::BTree;
-use crate::error::{Error, ToInnerResult};
-use crate::hash::Hash;
+use crate::bloom::BloomFilter;
+use crate::btree::{BTree, LeafValue, Leaves, NodeDump, PageId, Pager, PagerStats};
+use crate::error::{Error, ErrorKind, ToInnerResult};
+use crate::hash::{Hash, HASH_SIZE};
 use crate::offset::Offset;
+use crate::store::BlockStore;
+use crate::uuid::Uuid;
+
+/// The least capacity of the Bloom filter.
+const MIN_BLOOM_CAPACITY: usize = 1024;
 
 /// Indexer is a struct representing the object storage's index, which maps the
 /// object hash to the object's offset.
 pub struct Indexer {
     b_tree: BTree,
+    /// The Bloom filter of all hashes, so that absent hashes are found without
+    /// touching the B-Tree. It is `None` until it is built by the first lookup,
+    /// and it is dropped to be rebuilt larger once it is full.
+    bloom: RwLock<Option<BloomFilter>>,
+    /// Is the Bloom filter dropped by `refresh`? Then all hashes are maybe
+    /// present until it is rebuilt by `refresh` as well, instead of by a
+    /// lookup.
+    bloom_stale: bool,
 }
 
 impl Indexer {
@@ -17,33 +31,452 @@ impl Indexer {
     /// 
     /// It will:
     ///
-    ///   - Open or create a new index file in the path.
+    ///   - Open or create a new index file in the path, whose hashes are
+    ///     generated by `hash_algorithm`. A new file has pages of
+    ///     `page_size` bytes.
     ///   - Return `Indexer` itself.
-    pub fn open(path: &PathBuf) -> Result<Self, Error> {
-        let b_tree = BTree::new(&path.join("index"))
+    pub fn open(path: &PathBuf, hash_algorithm: &str, page_size: usize) -> Result<Self, Error> {
+        Self::open_file(&path.join("index"), hash_algorithm, page_size)
+    }
+
+    /// Open or create a new `Indexer` by the index file itself, instead of the
+    /// directory holding it. See `open` as well.
+    pub fn open_file(file: &Path, hash_algorithm: &str, page_size: usize) -> Result<Self, Error> {
+        let b_tree = BTree::new(file, hash_algorithm, page_size)
             .to_inner_result("open index file by B-Tree format")?;
-        let result = Self { b_tree };
-        Ok(result)
+        Ok(Self { b_tree, bloom: RwLock::new(None), bloom_stale: false })
+    }
+
+    /// Open or create a new `Indexer` in the store instead of a file. See
+    /// `open` as well.
+    pub fn open_in<S>(store: S, hash_algorithm: &str, page_size: usize) -> Result<Self, Error>
+    where
+        S: BlockStore + 'static,
+    {
+        let b_tree = BTree::new_in(store, hash_algorithm, page_size)
+            .to_inner_result("open index store by B-Tree format")?;
+        Ok(Self { b_tree, bloom: RwLock::new(None), bloom_stale: false })
+    }
+
+    /// Open an existing `Indexer` by path in read-only mode. The index file is
+    /// never written.
+    pub fn open_read_only(path: &Path, hash_algorithm: &str) -> Result<Self, Error> {
+        Self::open_file_read_only(&path.join("index"), hash_algorithm)
     }
 
-    /// Put a new record: a mapping from hash to the offset in data file.
+    /// Open an existing `Indexer` by the index file itself in read-only mode.
+    pub fn open_file_read_only(file: &Path, hash_algorithm: &str) -> Result<Self, Error> {
+        let b_tree = BTree::open_read_only(file, hash_algorithm)
+            .to_inner_result("open index file by B-Tree format")?;
+        Ok(Self { b_tree, bloom: RwLock::new(None), bloom_stale: false })
+    }
+
+    /// Put a new record: a mapping from hash to the offset in data file. The
+    /// reference count of an existing record is increased.
     /// 
     /// See method `get` as well.
-    pub fn put(&mut self, hash: &str, offset: u64) -> Result<(), Error> {
+    pub fn put(&mut self, hash: &str, offset: Offset) -> Result<bool, Error> {
+        let hash = Hash::from_str(hash).to_inner_result("turn to valid hash")?;
+
+        let inserted = self.b_tree.put(&hash, &offset)?;
+        self.insert_bloom(&hash);
+        Ok(inserted)
+    }
+
+    /// Put a new record which expires at `expires_at`, in milliseconds since
+    /// the Unix epoch. See `BTree::put_with_expiry`.
+    pub fn put_with_expiry(
+        &mut self,
+        hash: &str,
+        offset: Offset,
+        expires_at: u64,
+    ) -> Result<bool, Error> {
         let hash = Hash::from_str(hash).to_inner_result("turn to valid hash")?;
-        let offset = Offset::new(offset);
 
-        self.b_tree.put(&hash, &offset)
+        let inserted = self.b_tree.put_with_expiry(&hash, &offset, expires_at)?;
+        self.insert_bloom(&hash);
+        Ok(inserted)
+    }
+
+    /// Put many records at once, and sync the index file only once.
+    pub fn put_batch(&mut self, records: &[(String, Offset)]) -> Result<(), Error> {
+        let records = records
+            .iter()
+            .map(|(hash, offset)| {
+                let hash = Hash::from_str(hash).to_inner_result("turn to valid hash")?;
+                Ok((hash, *offset))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        self.b_tree.put_batch(&records)?;
+        records.iter().for_each(|(hash, _)| self.insert_bloom(hash));
+        Ok(())
+    }
+
+    /// Put many records at once as `put_batch` does, but each of them is
+    /// `(hash, offset, refcount, expires_at)` and adds its own count of
+    /// references.
+    pub fn put_batch_with_refcounts(
+        &mut self,
+        records: &[(String, Offset, u32, u64)],
+    ) -> Result<(), Error> {
+        let records = records
+            .iter()
+            .map(|(hash, offset, refcount, expires_at)| {
+                let hash = Hash::from_str(hash).to_inner_result("turn to valid hash")?;
+                Ok((hash, LeafValue::new(*offset, *refcount, *expires_at)))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        self.b_tree.put_batch_with_refcounts(&records)?;
+        records.iter().for_each(|(hash, _)| self.insert_bloom(hash));
+        Ok(())
     }
 
-    /// Get the offset in the data file by the hash.
-    pub fn get(&mut self, hash: &str) -> Result<Option<Offset>, Error> {
+    /// Load the records `(hash, offset, refcount, expires_at)` into the empty
+    /// index, in strictly ascending order of hashes. It is much faster than
+    /// putting them, see `BTree::bulk_load`.
+    pub fn bulk_load(&mut self, records: &[(String, Offset, u32, u64)]) -> Result<(), Error> {
+        let records = records
+            .iter()
+            .map(|(hash, offset, refcount, expires_at)| {
+                let hash = Hash::from_str(hash).to_inner_result("turn to valid hash")?;
+                Ok((hash, LeafValue::new(*offset, *refcount, *expires_at)))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        self.b_tree.bulk_load(&records)?;
+        // It is rebuilt by the first lookup, as large as the records need.
+        *self.bloom.get_mut().unwrap() = None;
+        Ok(())
+    }
+
+    /// Get the offset in the data file by the hash. The B-Tree is not touched
+    /// if the Bloom filter tells the hash is absent.
+    pub fn get(&self, hash: &str) -> Result<Option<Offset>, Error> {
         let hash = Hash::from_str(hash).to_inner_result("turn to valid hash")?;
 
+        if !self.may_contain(&hash)? {
+            return Ok(None);
+        }
         self.b_tree.get(&hash)
     }
 
-    pub fn list(&mut self) -> Result<Vec<String>, Error> {
+    /// Get the record `(offset, refcount, expires_at)` by the hash, even if it
+    /// is expired. See `BTree::get_record`.
+    pub fn get_record(&self, hash: &str) -> Result<Option<(Offset, u32, u64)>, Error> {
+        let hash = Hash::from_str(hash).to_inner_result("turn to valid hash")?;
+
+        if !self.may_contain(&hash)? {
+            return Ok(None);
+        }
+        let record = self.b_tree.get_record(&hash)?;
+        Ok(record.map(|record| (record.value, record.refcount, record.expires_at)))
+    }
+
+    /// Get the offsets of many hashes at once, in the same order as `hashes`.
+    /// They are looked up in ascending order, so that the B-Tree is descended
+    /// only once for the hashes in the same leaf.
+    pub fn get_many(&self, hashes: &[&str]) -> Result<Vec<Option<Offset>>, Error> {
+        let mut keys = Vec::with_capacity(hashes.len());
+        for (i, hash) in hashes.iter().enumerate() {
+            let hash = Hash::from_str(hash).to_inner_result("turn to valid hash")?;
+            if self.may_contain(&hash)? {
+                keys.push((hash, i));
+            }
+        }
+        keys.sort_unstable();
+
+        let sorted: Vec<Hash> = keys.iter().map(|(hash, _)| *hash).collect();
+        let mut offsets = vec![None; hashes.len()];
+        for ((_, i), offset) in keys.iter().zip(self.b_tree.get_many(&sorted)?) {
+            offsets[*i] = offset;
+        }
+        Ok(offsets)
+    }
+
+    /// Check the hash by the Bloom filter, which is built by scanning all
+    /// hashes if it is not yet - unless it is dropped by `refresh`.
+    fn may_contain(&self, hash: &Hash) -> Result<bool, Error> {
+        if let Some(bloom) = &*self.bloom.read().unwrap() {
+            return Ok(bloom.contains(hash));
+        }
+        if self.bloom_stale {
+            return Ok(true);
+        }
+
+        let bloom = self.build_bloom()?;
+        let contains = bloom.contains(hash);
+        *self.bloom.write().unwrap() = Some(bloom);
+        Ok(contains)
+    }
+
+    /// Build the Bloom filter of all hashes by scanning them.
+    fn build_bloom(&self) -> Result<BloomFilter, Error> {
+        let hashes = self.b_tree.list()?;
+        // Leave room to grow, so that it is not rebuilt again soon.
+        let mut bloom = BloomFilter::with_capacity((hashes.len() * 2).max(MIN_BLOOM_CAPACITY));
+        hashes.iter().for_each(|hash| bloom.insert(hash));
+        Ok(bloom)
+    }
+
+    /// Insert the hash just put into the Bloom filter - or drop the filter if
+    /// it is full, to be rebuilt larger lazily.
+    fn insert_bloom(&mut self, hash: &Hash) {
+        let bloom = self.bloom.get_mut().unwrap();
+        match bloom {
+            Some(b) if b.is_full() => *bloom = None,
+            Some(b) => b.insert(hash),
+            None => {}
+        }
+    }
+
+    /// Delete the record by the hash. Return `true` if the record was existing.
+    /// The record is only removed once its reference count hits zero.
+    pub fn delete(&mut self, hash: &str) -> Result<bool, Error> {
+        let hash = Hash::from_str(hash).to_inner_result("turn to valid hash")?;
+
+        self.b_tree.delete(&hash)
+    }
+
+    /// Remove all expired records, and return the count of them. See
+    /// `BTree::purge_expired`.
+    pub fn purge_expired(&mut self) -> Result<usize, Error> {
+        self.b_tree.purge_expired()
+    }
+
+    /// Pick up the changes by another writer of the index file. Return `true`
+    /// if it is changed. See `BTree::refresh`.
+    ///
+    /// The hashes put by the other writer are not in the Bloom filter, and
+    /// they are only found by scanning all hashes. So the filter is dropped,
+    /// and all hashes are maybe present until the index is found unchanged by
+    /// a refresh - which rebuilds the filter, so that a lookup never does.
+    pub fn refresh(&mut self) -> Result<bool, Error> {
+        let changed = self.b_tree.refresh()?;
+        if changed {
+            *self.bloom.get_mut().unwrap() = None;
+            self.bloom_stale = true;
+        } else if self.bloom_stale {
+            *self.bloom.get_mut().unwrap() = Some(self.build_bloom()?);
+            self.bloom_stale = false;
+        }
+        Ok(changed)
+    }
+
+    /// Sync all pending index writes to the index file.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.b_tree.flush()
+    }
+
+    /// Sync all pending index writes to the index file, and `fsync` it.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.b_tree.sync()
+    }
+
+    /// How many bytes of the data file are garbage. See
+    /// `BTree::garbage_bytes`.
+    pub fn garbage_bytes(&self) -> u64 {
+        self.b_tree.garbage_bytes()
+    }
+
+    /// Count more bytes of the data file as garbage.
+    pub fn add_garbage_bytes(&mut self, bytes: u64) -> Result<(), Error> {
+        self.b_tree.add_garbage_bytes(bytes)
+    }
+
+    /// The UUID of the database owning the index. See `BTree::uuid`.
+    pub fn uuid(&self) -> Uuid {
+        Uuid::from_bytes(self.b_tree.uuid())
+    }
+
+    /// Set the UUID of the database owning the index.
+    pub fn set_uuid(&mut self, uuid: Uuid) -> Result<(), Error> {
+        self.b_tree.set_uuid(uuid.to_bytes())
+    }
+
+    /// The count of records.
+    pub fn len(&self) -> usize {
+        self.b_tree.len()
+    }
+
+    /// The height of the B+tree.
+    pub fn height(&self) -> Result<usize, Error> {
+        self.b_tree.height()
+    }
+
+    /// The count of pages in the index file.
+    pub fn page_count(&self) -> usize {
+        self.b_tree.page_count()
+    }
+
+    /// The length of the index file in bytes.
+    pub fn file_len(&self) -> Result<u64, Error> {
+        self.b_tree.file_len()
+    }
+
+    /// Another handle of the pager of the index file, see `BTree::pager`.
+    pub fn pager(&self) -> Pager {
+        self.b_tree.pager()
+    }
+
+    /// The counters of the cache of the index file's pages.
+    pub fn pager_stats(&self) -> PagerStats {
+        self.b_tree.pager_stats()
+    }
+
+    /// The size of the index file's pages.
+    pub fn page_size(&self) -> usize {
+        self.b_tree.page_size()
+    }
+
+    /// List all records as `(hash, offset, refcount, expires_at)`, in
+    /// ascending order of hashes - the expired ones not purged yet as well.
+    pub fn records(&self) -> Result<Vec<(String, Offset, u32, u64)>, Error> {
+        Ok(self.b_tree
+            .records()?
+            .iter()
+            .map(|(hs, record)| {
+                (hs.to_string(), record.value, record.refcount, record.expires_at)
+            })
+            .collect())
+    }
+
+    /// Read the node in the page of the B+tree as it is, with the hashes in
+    /// hex. See `BTree::dump_page`.
+    pub fn dump_page(&self, page_id: u32) -> Result<NodeDump<String, Offset>, Error> {
+        fn hex<T>(records: Vec<(Hash, T)>) -> Vec<(String, T)> {
+            records.into_iter().map(|(hash, value)| (hash.to_string(), value)).collect()
+        }
+        Ok(match self.b_tree.dump_page(PageId::new(page_id as usize))? {
+            NodeDump::Head { root } => NodeDump::Head { root },
+            NodeDump::Leaf { records, next_leaf } => {
+                NodeDump::Leaf { records: hex(records), next_leaf }
+            }
+            NodeDump::Internal { records, rightest_child } => {
+                NodeDump::Internal { records: hex(records), rightest_child }
+            }
+        })
+    }
+
+    /// List all records as `(hash, offset)`, in ascending order of hashes, and
+    /// the errors of the unreadable pages of the B+tree - whose records are
+    /// skipped instead of failing the whole listing.
+    pub fn scan(&self) -> (Vec<(String, Offset)>, Vec<String>) {
+        let mut errors = vec![];
+        let records = self.b_tree.scan(&mut errors);
+        let records = records
+            .iter()
+            .map(|(hs, record)| (hs.to_string(), record.value))
+            .collect();
+        let errors = errors
+            .iter()
+            .map(|(page_id, err)| format!("page {}: {}", page_id.raw(), err))
+            .collect();
+        (records, errors)
+    }
+
+    /// List all hashes in `[start, end)`, in ascending order.
+    pub fn range(&self, start: &str, end: &str) -> Result<Vec<String>, Error> {
+        let start = Hash::from_str(start).to_inner_result("turn start to valid hash")?;
+        let end = Hash::from_str(end).to_inner_result("turn end to valid hash")?;
+
+        Ok(self.b_tree.range(&start, &end)?.iter().map(|(hs, _)| hs.to_string()).collect())
+    }
+
+    /// List all hashes starting with the hex prefix, in ascending order. The
+    /// prefix can be of any length, even an odd count of nibbles.
+    pub fn find_by_prefix(&self, hex_prefix: &str) -> Result<Vec<String>, Error> {
+        if hex_prefix.len() > HASH_SIZE * 2 {
+            return Err(Error::with_kind(ErrorKind::InvalidHash, "the prefix is longer than a hash"));
+        }
+        // All hashes with the prefix are between the prefix padded by `0` and
+        // the one padded by `f`.
+        let padding = HASH_SIZE * 2 - hex_prefix.len();
+        let start = Hash::from_str(&format!("{}{}", hex_prefix, "0".repeat(padding)))
+            .to_inner_result("turn prefix to valid hash")?;
+        let last = Hash::from_str(&format!("{}{}", hex_prefix, "f".repeat(padding)))
+            .to_inner_result("turn prefix to valid hash")?;
+
+        Ok(self.b_tree.range_inclusive(&start, &last)?.iter().map(|(hs, _)| hs.to_string()).collect())
+    }
+
+    /// List up to `limit` hashes strictly greater than `after`, in ascending
+    /// order.
+    pub fn list_paged(&self, after: Option<&str>, limit: usize) -> Result<Vec<String>, Error> {
+        let after = after
+            .map(Hash::from_str)
+            .transpose()
+            .to_inner_result("turn after to valid hash")?;
+        Ok(self.b_tree
+            .list_after(after.as_ref(), limit)?
+            .iter()
+            .map(|hs| hs.to_string())
+            .collect())
+    }
+
+    /// List all hashes, in ascending order.
+    pub fn list(&self) -> Result<Vec<String>, Error> {
         Ok(self.b_tree.list()?.iter().map(|hs| hs.to_string()).collect())
     }
+
+    /// Walk through all hashes in ascending order as `list` does, but lazily:
+    /// only one leaf of the B+tree is read at a time.
+    pub fn hashes(&self) -> Hashes<'_> {
+        Hashes { records: self.records_lazily() }
+    }
+
+    /// Walk through all records as `records` does, but lazily as `hashes`
+    /// does.
+    pub fn records_lazily(&self) -> Records<'_> {
+        Records { leaves: self.b_tree.leaves().map_err(Some), records: vec![].into_iter() }
+    }
+}
+
+/// The iterator through all hashes of the index. See `Indexer::hashes`.
+pub struct Hashes<'a> {
+    records: Records<'a>,
+}
+
+impl Iterator for Hashes<'_> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.records.next()?.map(|(hash, _, _, _)| hash))
+    }
+}
+
+/// The iterator through all records `(hash, offset, refcount, expires_at)`
+/// of the index, in ascending order of hashes. See `Indexer::records_lazily`.
+pub struct Records<'a> {
+    /// The leaves not walked yet - or the error to find the first one, which
+    /// is taken by the first `next`.
+    leaves: Result<Leaves<'a, Hash, Offset>, Option<Error>>,
+    /// The records of the leaf walked last, not yielded yet.
+    records: vec::IntoIter<(Hash, LeafValue)>,
+}
+
+impl Iterator for Records<'_> {
+    type Item = Result<(String, Offset, u32, u64), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((hash, record)) = self.records.next() {
+                let record = (hash.to_string(), record.value, record.refcount, record.expires_at);
+                return Some(Ok(record));
+            }
+            let leaves = match &mut self.leaves {
+                Ok(leaves) => leaves,
+                Err(e) => return e.take().map(Err),
+            };
+            match leaves.next()? {
+                Ok(leaf) => {
+                    let records: Vec<(Hash, LeafValue)> =
+                        leaf.into_iter().map(|r| (r.key, r.value)).collect();
+                    self.records = records.into_iter();
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
 }
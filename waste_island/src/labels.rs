@@ -0,0 +1,117 @@
+use std::{
+    fs, io::{Read, Seek, SeekFrom, Write}, path::Path,
+};
+
+use crate::{
+    btree::Pager, database::DataReader, error::{Error, ErrorKind, ToInnerResult},
+    indexer::Indexer, offset::Offset, varint,
+};
+
+/// The max length of a label in bytes.
+pub const MAX_LABEL_LEN: usize = 256;
+
+/// The labels of wastes: short human-readable texts to list them by, which
+/// never change their hashes. See `Database::set_label`.
+///
+/// Labels are of any length up to `MAX_LABEL_LEN`, so the index `labels` maps
+/// the waste's hash to the offset of a record in `labels.data` instead: the
+/// label itself, as a varint length and its bytes. Setting a label again
+/// appends a new record, and the old one is left behind.
+pub struct Labels {
+    indexer: Indexer,
+    data: fs::File,
+}
+
+impl Labels {
+    /// Open or create the labels in the database directory. A new index has
+    /// pages of `page_size` bytes.
+    pub fn open(path: &Path, hash_algorithm: &str, page_size: usize) -> Result<Self, Error> {
+        let data = fs::File::options()
+            .write(true)
+            .read(true)
+            .create(true)
+            .truncate(false)
+            .open(path.join("labels.data"))
+            .to_inner_result("open labels data file in write-read mode")?;
+        let indexer = Indexer::open_file(&path.join("labels"), hash_algorithm, page_size)
+            .to_inner_result("open labels indexer")?;
+        Ok(Self { indexer, data })
+    }
+
+    /// Open the labels in the database directory in read-only mode. Return
+    /// `None` if there is no label at all.
+    pub fn open_read_only(path: &Path, hash_algorithm: &str) -> Result<Option<Self>, Error> {
+        let data = match fs::File::open(path.join("labels.data")) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).to_inner_result("open labels data file in read-only mode"),
+        };
+        let indexer = Indexer::open_file_read_only(&path.join("labels"), hash_algorithm)
+            .to_inner_result("open labels indexer")?;
+        Ok(Some(Self { indexer, data }))
+    }
+
+    /// Label the waste of the hash, instead of the label it had before.
+    pub fn put(&mut self, hash: &str, label: &str) -> Result<(), Error> {
+        let offset = self.data.seek(SeekFrom::End(0)).to_inner_result("set offset")?;
+        self.data.write_all(&varint::encode(label.len() as u64))
+            .to_inner_result("write label's length")?;
+        self.data.write_all(label.as_bytes()).to_inner_result("write label")?;
+        // The record of the label before is removed first, so that the record
+        // is always referenced once - and removed by one `remove`.
+        self.remove(hash)?;
+        self.indexer.put(hash, Offset::new(offset))?;
+        Ok(())
+    }
+
+    /// Remove the label of the waste of the hash. Return `true` if it had one.
+    pub fn remove(&mut self, hash: &str) -> Result<bool, Error> {
+        self.indexer.delete(hash)
+    }
+
+    /// Get the label of the waste of the hash.
+    pub fn get(&self, hash: &str) -> Result<Option<String>, Error> {
+        let offset = match self.indexer.get(hash)? {
+            None => return Ok(None),
+            Some(o) => o.to_u64(),
+        };
+        let corrupt = || Error::with_kind(ErrorKind::Corrupt, "corrupt label record");
+
+        let mut reader = DataReader { store: &self.data, pos: offset };
+        let label_len = reader.read_varint().to_inner_result("read label's length")?
+            .ok_or_else(corrupt)?;
+        let data_len = self.data.metadata().to_inner_result("get labels data file's metadata")?.len();
+        if label_len > MAX_LABEL_LEN as u64 || label_len > data_len.saturating_sub(reader.pos) {
+            return Err(corrupt());
+        }
+
+        let mut label = vec![0u8; label_len as usize];
+        reader.read_exact(&mut label).to_inner_result("read label")?;
+        Ok(Some(String::from_utf8(label).map_err(|_| corrupt())?))
+    }
+
+    /// Pick up the labels set by another writer. The data file is always read
+    /// at offsets, so only the index needs to be refreshed.
+    pub fn refresh(&mut self) -> Result<bool, Error> {
+        self.indexer.refresh()
+    }
+
+    /// Sync all pending writes of the labels.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.data.flush().to_inner_result("flush labels data file")?;
+        self.indexer.flush()
+    }
+
+    /// Sync all pending writes of the labels, and `fsync` them.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.data.sync_data().to_inner_result("fsync labels data file")?;
+        self.indexer.sync()
+    }
+
+    /// Another handle of the data file of the labels, and of the pager of
+    /// their index - so that both are synced by another thread, see `Flusher`.
+    pub fn sync_handles(&self) -> Result<(fs::File, Pager), Error> {
+        let data = self.data.try_clone().to_inner_result("clone labels data file")?;
+        Ok((data, self.indexer.pager()))
+    }
+}
@@ -5,6 +5,26 @@ mod btree;
 mod offset;
 mod utils;
 mod database;
+mod hasher;
+mod compression;
+mod checksum;
+mod varint;
+mod sync_policy;
+mod flusher;
+mod bloom;
+mod names;
+mod labels;
+mod store;
+mod data_file;
+mod uuid;
 
-pub use error::Error;
-pub use database::Database;
+pub use error::{Error, ErrorKind};
+pub use database::{
+    Database, CompactStats, DatabaseIter, DatabaseStats, DatabaseValues, IndexRecord, PageDump,
+    VerifyReport, WasteStat,
+};
+pub use hasher::{WasteHasher, Sha256, Blake3};
+pub use compression::CompressionMode;
+pub use sync_policy::SyncPolicy;
+pub use store::{BlockStore, MemStore};
+pub use btree::{BPlusTree, LeafValue, Pod};
@@ -0,0 +1,128 @@
+use std::{
+    fs, io::{Read, Seek, SeekFrom, Write}, path::Path,
+};
+
+use crate::{
+    btree::Pager, database::DataReader, error::{Error, ErrorKind, ToInnerResult},
+    hash::{Hash, HASH_SIZE}, indexer::Indexer, offset::Offset, varint,
+};
+
+/// The keyspace of names, each of which maps to the hash of a waste. See
+/// `Database::put_named`.
+///
+/// Names are of any length, so the index `names` is keyed by their hashes
+/// instead, and maps to the offset of a record in `names.data`: the name
+/// itself, as a varint length and its bytes, and the waste's hash. The name is
+/// read back to make sure it is not another name of the same hash.
+pub struct Names {
+    indexer: Indexer,
+    data: fs::File,
+}
+
+impl Names {
+    /// Open or create the names in the database directory. A new index has
+    /// pages of `page_size` bytes.
+    pub fn open(path: &Path, hash_algorithm: &str, page_size: usize) -> Result<Self, Error> {
+        let data = fs::File::options()
+            .write(true)
+            .read(true)
+            .create(true)
+            .truncate(false)
+            .open(path.join("names.data"))
+            .to_inner_result("open names data file in write-read mode")?;
+        let indexer = Indexer::open_file(&path.join("names"), hash_algorithm, page_size)
+            .to_inner_result("open names indexer")?;
+        Ok(Self { indexer, data })
+    }
+
+    /// Open the names in the database directory in read-only mode. Return
+    /// `None` if there is no name at all.
+    pub fn open_read_only(path: &Path, hash_algorithm: &str) -> Result<Option<Self>, Error> {
+        let data = match fs::File::open(path.join("names.data")) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).to_inner_result("open names data file in read-only mode"),
+        };
+        let indexer = Indexer::open_file_read_only(&path.join("names"), hash_algorithm)
+            .to_inner_result("open names indexer")?;
+        Ok(Some(Self { indexer, data }))
+    }
+
+    /// Map the name, whose hash is `name_hash`, to the waste's hash. Return the
+    /// hash it mapped to before.
+    pub fn put(&mut self, name_hash: &str, name: &str, hash: &str) -> Result<Option<String>, Error> {
+        let hash = Hash::from_str(hash).to_inner_result("turn to valid hash")?;
+        let origin_hash = match self.read_record(name_hash)? {
+            Some((origin_name, _)) if origin_name != name => {
+                return Err(Error::with_kind(ErrorKind::Unsupported, "the hash of the name collides"));
+            }
+            Some((_, origin_hash)) => Some(origin_hash),
+            None => None,
+        };
+
+        let offset = self.data.seek(SeekFrom::End(0)).to_inner_result("set offset")?;
+        self.data.write_all(&varint::encode(name.len() as u64))
+            .to_inner_result("write name's length")?;
+        self.data.write_all(name.as_bytes()).to_inner_result("write name")?;
+        self.data.write_all(hash.as_bytes()).to_inner_result("write name's hash")?;
+        self.indexer.put(name_hash, Offset::new(offset))?;
+        Ok(origin_hash)
+    }
+
+    /// Get the waste's hash which the name maps to.
+    pub fn get(&self, name_hash: &str, name: &str) -> Result<Option<String>, Error> {
+        match self.read_record(name_hash)? {
+            Some((origin_name, hash)) if origin_name == name => Ok(Some(hash)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Read the name and the waste's hash of the record by the name's hash.
+    fn read_record(&self, name_hash: &str) -> Result<Option<(String, String)>, Error> {
+        let offset = match self.indexer.get(name_hash)? {
+            None => return Ok(None),
+            Some(o) => o.to_u64(),
+        };
+        let corrupt = || Error::with_kind(ErrorKind::Corrupt, "corrupt name record");
+
+        let mut reader = DataReader { store: &self.data, pos: offset };
+        let name_len = reader.read_varint().to_inner_result("read name's length")?
+            .ok_or_else(corrupt)?;
+        let data_len = self.data.metadata().to_inner_result("get names data file's metadata")?.len();
+        if name_len > data_len.saturating_sub(reader.pos + HASH_SIZE as u64) {
+            return Err(corrupt());
+        }
+
+        let mut name = vec![0u8; name_len as usize];
+        reader.read_exact(&mut name).to_inner_result("read name")?;
+        let mut hash = [0u8; HASH_SIZE];
+        reader.read_exact(&mut hash).to_inner_result("read name's hash")?;
+        let name = String::from_utf8(name).map_err(|_| corrupt())?;
+        Ok(Some((name, Hash::from_bytes(hash).to_string())))
+    }
+
+    /// Pick up the names put by another writer. The data file is always read
+    /// at offsets, so only the index needs to be refreshed.
+    pub fn refresh(&mut self) -> Result<bool, Error> {
+        self.indexer.refresh()
+    }
+
+    /// Sync all pending writes of the names.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.data.flush().to_inner_result("flush names data file")?;
+        self.indexer.flush()
+    }
+
+    /// Sync all pending writes of the names, and `fsync` them.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.data.sync_data().to_inner_result("fsync names data file")?;
+        self.indexer.sync()
+    }
+
+    /// Another handle of the data file of the names, and of the pager of
+    /// their index - so that both are synced by another thread, see `Flusher`.
+    pub fn sync_handles(&self) -> Result<(fs::File, Pager), Error> {
+        let data = self.data.try_clone().to_inner_result("clone names data file")?;
+        Ok((data, self.indexer.pager()))
+    }
+}
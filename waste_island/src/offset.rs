@@ -1,40 +1,74 @@
 use std::fmt::Debug;
 
-pub const OFFSET_SIZE: usize = 8;
-
-/// The data struct representing the offset in data file.
-#[derive(Eq, PartialEq, Clone, Copy)]
-pub struct Offset(u64);
+/// The data struct representing the offset in data file: the shard of the
+/// data file, and the position in it. The shard is always 0 if the data file
+/// is not sharded.
+///
+/// Offsets are ordered by their shards first, and then by their positions -
+/// so sorting offsets sorts reads in the order of the data file.
+///
+/// Offsets are stored in index pages as they are, so the layout is `repr(C)`
+/// and the padding after the shard is an explicit field which is always zero.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+#[repr(C)]
+pub struct Offset {
+    shard: u8,
+    _pad: [u8; 7],
+    pos: u64,
+}
 
 impl Offset {
     pub fn new(n: u64) -> Self {
-        Self(n)
+        Self::with_shard(0, n)
     }
 
-    pub fn to_bytes(&self) -> [u8; OFFSET_SIZE] {
-        let mut bytes = [0u8; 8];
-        for i in 0..8 {
-            bytes[i] = (self.0 >> (i * 8)) as u8;
-        }
-        bytes
+    pub fn with_shard(shard: u8, n: u64) -> Self {
+        Self { shard, _pad: [0; 7], pos: n }
     }
 
-    pub fn from_bytes(bytes: [u8; OFFSET_SIZE]) -> Self {
-        let mut n = 0;
-        for i in 0..OFFSET_SIZE {
-            n |= (bytes[i] as u64) << (i * 8);
-        }
-        Self(n)
+    pub fn shard(&self) -> usize {
+        self.shard as usize
     }
 
     pub fn to_u64(&self) -> u64 {
-        self.0
+        self.pos
+    }
+
+    /// The offset `n` bytes after me, in the same shard.
+    pub fn advance(self, n: u64) -> Offset {
+        Self::with_shard(self.shard, self.pos + n)
     }
 }
 
 impl Debug for Offset {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Offset(0x{:02x} = {})", self.0, self.0)?;
+        write!(f, "Offset({}: 0x{:02x} = {})", self.shard, self.pos, self.pos)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_is_ordered_by_shards_and_positions() {
+        let positions = [42u64, 0, u64::MAX, 7, 0x100, 1];
+        let mut offsets: Vec<Offset> = positions.iter().map(|&pos| Offset::new(pos)).collect();
+        offsets.sort();
+        let mut positions = positions.to_vec();
+        positions.sort();
+        assert_eq!(offsets.iter().map(|o| o.to_u64()).collect::<Vec<_>>(), positions);
+
+        assert!(Offset::with_shard(0, u64::MAX) < Offset::with_shard(1, 0));
+        assert_eq!(Offset::with_shard(3, 40).advance(2), Offset::with_shard(3, 42));
+    }
+
+    #[test]
+    fn it_pins_the_layout_of_offsets() {
+        assert_eq!(std::mem::offset_of!(Offset, shard), 0);
+        assert_eq!(std::mem::offset_of!(Offset, _pad), 1);
+        assert_eq!(std::mem::offset_of!(Offset, pos), 8);
+        assert_eq!(std::mem::size_of::<Offset>(), 16);
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind},
+    sync::{Arc, RwLock},
+};
+
+/// The storage of bytes under the data file and the index - a file by
+/// default. See `Database::new_in`.
+///
+/// All methods take `&self` and read or write at the given offset, so many
+/// readers can share one store.
+pub trait BlockStore: Send + Sync {
+    /// Read bytes at the offset into the buffer, and return how many bytes
+    /// are read. It is 0 at the end of the store.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Write bytes of the buffer at the offset, and return how many bytes are
+    /// written. The store grows if it is written beyond its end.
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize>;
+
+    /// The length of the store in bytes.
+    fn len(&self) -> io::Result<u64>;
+
+    /// Truncate or extend the store to the length. The bytes extended are 0.
+    fn set_len(&self, len: u64) -> io::Result<()>;
+
+    /// Make sure the bytes written so far are read once the store is opened
+    /// again, even if they are not synced yet. It is called once a record is
+    /// written to the data file. Nothing to do by default.
+    fn publish(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Make sure all bytes written are on the disk. Nothing to do by default.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Is the store empty?
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Read exactly the length of the buffer at the offset. An error of
+    /// `ErrorKind::UnexpectedEof` is raised if the store ends before it.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "store ends")),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the whole buffer at the offset.
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write_at(buf, offset) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "store is full")),
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The file is read and written by positional reads and writes, so its cursor
+/// is never used.
+impl BlockStore for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        #[cfg(unix)]
+        return std::os::unix::fs::FileExt::read_at(self, buf, offset);
+        #[cfg(windows)]
+        return std::os::windows::fs::FileExt::seek_read(self, buf, offset);
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        #[cfg(unix)]
+        return std::os::unix::fs::FileExt::write_at(self, buf, offset);
+        #[cfg(windows)]
+        return std::os::windows::fs::FileExt::seek_write(self, buf, offset);
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.sync_data()
+    }
+}
+
+/// The store in memory, which is gone once all its clones are dropped. It is
+/// mostly for tests, so that they never touch the filesystem.
+///
+/// Its clones share the same bytes, so a database can be opened again by the
+/// clones of its stores.
+#[derive(Debug, Clone, Default)]
+pub struct MemStore {
+    buf: Arc<RwLock<Vec<u8>>>,
+}
+
+impl MemStore {
+    /// Create a new empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BlockStore for MemStore {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let bytes = self.buf.read().unwrap();
+        let start = (offset as usize).min(bytes.len());
+        let n = buf.len().min(bytes.len() - start);
+        buf[..n].copy_from_slice(&bytes[start..start + n]);
+        Ok(n)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let mut bytes = self.buf.write().unwrap();
+        let end = offset as usize + buf.len();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[offset as usize..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.buf.read().unwrap().len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        self.buf.write().unwrap().resize(len as usize, 0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works_in_memory() {
+        let store = MemStore::new();
+        assert!(store.is_empty().unwrap());
+
+        store.write_all_at(b"world", 6).unwrap();
+        store.write_all_at(b"hello", 0).unwrap();
+        assert_eq!(store.len().unwrap(), 11);
+
+        let mut buf = [1u8; 11];
+        store.read_exact_at(&mut buf, 0).unwrap();
+        assert_eq!(&buf, b"hello\0world");
+        assert_eq!(store.read_at(&mut buf, 8).unwrap(), 3);
+        assert_eq!(store.read_at(&mut buf, 42).unwrap(), 0);
+        let err = store.read_exact_at(&mut buf, 1).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        // The clones share the same bytes.
+        let clone = store.clone();
+        clone.set_len(5).unwrap();
+        assert_eq!(store.len().unwrap(), 5);
+    }
+
+    #[test]
+    fn it_works_on_files() {
+        let directory_path = std::path::Path::new("/tmp/waste-land/");
+        std::fs::create_dir_all(directory_path).unwrap();
+        let file_path = directory_path.join("it-works-on-files.store");
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&file_path)
+            .unwrap();
+
+        file.write_all_at(b"world", 6).unwrap();
+        file.write_all_at(b"hello", 0).unwrap();
+        assert_eq!(BlockStore::len(&file).unwrap(), 11);
+        let mut buf = [1u8; 11];
+        file.read_exact_at(&mut buf, 0).unwrap();
+        assert_eq!(&buf, b"hello\0world");
+
+        BlockStore::set_len(&file, 5).unwrap();
+        assert_eq!(std::fs::read(&file_path).unwrap(), b"hello");
+    }
+}
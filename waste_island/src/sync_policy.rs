@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+/// When the database asks the OS to write the data file and the index to the
+/// disk - by `fsync`. See `Database::with_sync_policy`.
+///
+/// Writes accepted by the OS survive a crash of the process, but maybe not a
+/// power loss until they are synced. Syncing is slow, so it is a tradeoff
+/// between durability and throughput.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Never sync unless `Database::sync` is called. The fastest one.
+    #[default]
+    Never,
+    /// Sync after each write. Nothing written is lost after the write returns,
+    /// but each write costs at least one `fsync`.
+    EveryWrite,
+    /// Sync after a write if the last sync is older than the duration. At most
+    /// the writes of the duration are lost.
+    ///
+    /// It is checked only when writing, so the last writes are left unsynced
+    /// until the next write, `Database::sync` or the database is dropped.
+    Periodic(Duration),
+    /// Sync every duration by a background thread, so that no write waits for
+    /// a sync. At most the writes of the duration are lost, and the thread is
+    /// stopped before the last sync once the database is dropped.
+    ///
+    /// The index is only written to the OS once the data file is synced, so
+    /// the writes of the duration are lost by a crash of the process as well,
+    /// and other processes find them only then.
+    Background(Duration),
+}
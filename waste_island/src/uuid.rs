@@ -0,0 +1,98 @@
+use std::fmt::{Debug, Display};
+
+/// The length of a UUID in bytes.
+pub const UUID_SIZE: usize = 16;
+
+/// The UUID of a database, kept in both its `version` file and the head node
+/// of its index - so that the index of another database is never taken as
+/// its own.
+#[derive(Eq, PartialEq, Clone, Copy)]
+pub struct Uuid([u8; UUID_SIZE]);
+
+impl Uuid {
+    /// Generate a new random UUID of version 4. It is never nil.
+    pub fn new_v4() -> Self {
+        let mut bytes: [u8; UUID_SIZE] = rand::random();
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Self(bytes)
+    }
+
+    pub fn from_bytes(bytes: [u8; UUID_SIZE]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn to_bytes(self) -> [u8; UUID_SIZE] {
+        self.0
+    }
+
+    /// Is it the nil UUID, whose bytes are all zero? It is the UUID of a new
+    /// index, which belongs to no database yet.
+    pub fn is_nil(&self) -> bool {
+        self.0 == [0; UUID_SIZE]
+    }
+
+    /// Parse the UUID in the hyphenated form of `Display`, or return `None`
+    /// if it is not.
+    pub fn from_str(str: &str) -> Option<Self> {
+        let groups: Vec<&str> = str.split('-').collect();
+        let lens: Vec<usize> = groups.iter().map(|g| g.len()).collect();
+        if lens != [8, 4, 4, 4, 12] {
+            return None;
+        }
+        // `from_str_radix` takes a leading `+` as well, so check the digits
+        // first. It also makes sure that the groups are sliced at char
+        // boundaries.
+        let hex = groups.concat();
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let mut bytes = [0u8; UUID_SIZE];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        }
+        Some(Self(bytes))
+    }
+}
+
+impl Display for Uuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if [4, 6, 8, 10].contains(&i) {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for Uuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_by_str() {
+        let uuid = Uuid::new_v4();
+        assert!(!uuid.is_nil());
+        assert_ne!(uuid, Uuid::new_v4());
+        let str = uuid.to_string();
+        assert_eq!(str.len(), 36);
+        assert_eq!(&str[14..15], "4");
+        assert_eq!(Uuid::from_str(&str), Some(uuid));
+
+        let nil = Uuid::from_str("00000000-0000-0000-0000-000000000000").unwrap();
+        assert!(nil.is_nil());
+        assert_eq!(Uuid::from_str("00000000-0000-0000-0000-00000000000"), None);
+        assert_eq!(Uuid::from_str("00000000-0000-0000-0000-00000000000g"), None);
+        assert_eq!(Uuid::from_str("000000000000-0000-0000-000000000000"), None);
+        assert_eq!(Uuid::from_str("+0000000-0000-0000-0000-000000000000"), None);
+    }
+}
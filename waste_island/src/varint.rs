@@ -0,0 +1,127 @@
+use std::io::{self, Read};
+
+/// The max length of a varint - a `u64` needs 10 groups of 7 bits.
+pub const VARINT_MAX_LEN: usize = 10;
+
+/// Encode the value in LEB128: 7 bits in each byte, from the lowest, and the
+/// highest bit tells if there are more bytes.
+pub fn encode(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(VARINT_MAX_LEN);
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Encode the value in exactly `VARINT_MAX_LEN` bytes, padded by redundant
+/// groups of zeros. It is useful to patch a length reserved before.
+pub fn encode_padded(mut value: u64) -> [u8; VARINT_MAX_LEN] {
+    let mut bytes = [0u8; VARINT_MAX_LEN];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if i != VARINT_MAX_LEN - 1 {
+            *byte |= 0x80;
+        }
+    }
+    bytes
+}
+
+/// Decode the varint at the beginning of the bytes, and return the value and
+/// how many bytes it takes. Return `None` if it is truncated or overflows.
+pub fn decode(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().take(VARINT_MAX_LEN).enumerate() {
+        let group = (byte & 0x7f) as u64;
+        if i == VARINT_MAX_LEN - 1 && group > 1 {
+            return None;
+        }
+        value |= group << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Read a varint from the reader byte by byte, so that no byte after it is
+/// consumed. Return `None` if it is broken, or the reader ends before it.
+pub fn read(reader: &mut impl Read) -> io::Result<Option<u64>> {
+    let mut bytes = Vec::with_capacity(VARINT_MAX_LEN);
+    let mut byte = [0u8; 1];
+    while bytes.len() < VARINT_MAX_LEN {
+        match reader.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => bytes.push(byte[0]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(decode(&bytes).map(|(value, _)| value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works_at_boundaries() {
+        for (value, len) in [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (16383, 2),
+            (16384, 3),
+            (u32::MAX as u64, 5),
+            (u64::MAX, 10),
+        ] {
+            let bytes = encode(value);
+            assert_eq!(bytes.len(), len, "the length of {}", value);
+            assert_eq!(decode(&bytes), Some((value, len)));
+        }
+        assert_eq!(encode(127), [0x7f]);
+        assert_eq!(encode(128), [0x80, 0x01]);
+        assert_eq!(encode(16383), [0xff, 0x7f]);
+        assert_eq!(encode(16384), [0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn it_works_with_padding() {
+        for value in [0, 127, 128, 16384, u64::MAX] {
+            assert_eq!(decode(&encode_padded(value)), Some((value, VARINT_MAX_LEN)));
+        }
+    }
+
+    #[test]
+    fn it_rejects_broken_varints() {
+        assert_eq!(decode(&[]), None);
+        assert_eq!(decode(&[0x80, 0x80]), None);
+        assert_eq!(decode(&[0xff; VARINT_MAX_LEN]), None);
+        let mut overflow = [0xff; VARINT_MAX_LEN];
+        overflow[VARINT_MAX_LEN - 1] = 0x02;
+        assert_eq!(decode(&overflow), None);
+
+        // The bytes after the varint are ignored.
+        assert_eq!(decode(&[0x05, 0xff, 0xff]), Some((5, 1)));
+    }
+
+    #[test]
+    fn it_reads_from_readers() {
+        let mut bytes = encode(16384);
+        bytes.extend(encode_padded(42));
+        bytes.push(0x80);
+        let mut reader = &bytes[..];
+        assert_eq!(read(&mut reader).unwrap(), Some(16384));
+        assert_eq!(read(&mut reader).unwrap(), Some(42));
+        assert_eq!(read(&mut reader).unwrap(), None);
+        assert_eq!(read(&mut reader).unwrap(), None);
+    }
+}
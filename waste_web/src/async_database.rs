@@ -0,0 +1,182 @@
+use std::{
+    env, fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use axum::http::StatusCode;
+use futures_util::stream;
+use hyper::body::{Body, Bytes, HttpBody};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use waste_island::CompressionMode;
+
+use crate::error::Error;
+
+/// How many bytes of a waste are read at once by `AsyncDatabase::stream`.
+const CHUNK_LEN: u64 = 64 * 1024; // 64KB.
+/// How many chunks of a waste are read ahead of the client at most.
+const CHUNKS_AHEAD: usize = 4;
+
+/// The database shared by the handlers. `Database` does blocking IO, so every
+/// access runs on the blocking threads of tokio, and the workers are never
+/// stalled by the disk.
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    database: Arc<RwLock<waste_island::Database>>,
+}
+
+impl AsyncDatabase {
+    pub fn new(database: waste_island::Database) -> Self {
+        Self { database: Arc::new(RwLock::new(database)) }
+    }
+
+    /// Run `f` with the database locked for reading.
+    pub async fn read<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&waste_island::Database) -> Result<T, Error> + Send + 'static,
+    {
+        let database = self.database.clone();
+        tokio::task::spawn_blocking(move || f(&database.read().unwrap())).await?
+    }
+
+    /// Run `f` with the database locked for writing.
+    pub async fn write<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut waste_island::Database) -> Result<T, Error> + Send + 'static,
+    {
+        let database = self.database.clone();
+        tokio::task::spawn_blocking(move || f(&mut database.write().unwrap())).await?
+    }
+
+    /// Stream at most `len` bytes of the waste from `start` on as a body,
+    /// without holding them in memory as a whole. They are read chunk by
+    /// chunk on the blocking threads, at most `CHUNKS_AHEAD` chunks ahead of
+    /// the client.
+    ///
+    /// The database is only locked while a chunk is read, so a slow client
+    /// never blocks others - unless the waste is compressed, which is only
+    /// decompressed from its start, so the database is kept locked until it
+    /// is streamed out. The body is aborted if the waste is gone before.
+    pub fn stream(&self, hash: String, start: u64, len: u64) -> Body {
+        let database = self.database.clone();
+        let (sender, mut receiver) = mpsc::channel(CHUNKS_AHEAD);
+        tokio::task::spawn_blocking(move || {
+            let stat = database.read().unwrap().stat(&hash);
+            match stat {
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(io::Error::other(e)));
+                }
+                Ok(stat) if stat.compression != CompressionMode::None => {
+                    let database = database.read().unwrap();
+                    let mut reader = match database.get_slice_reader(&hash, start, len) {
+                        Ok(reader) => reader,
+                        Err(e) => {
+                            let _ = sender.blocking_send(Err(io::Error::other(e)));
+                            return;
+                        }
+                    };
+                    send_chunks(&sender, || {
+                        let mut chunk = vec![];
+                        (&mut reader).take(CHUNK_LEN).read_to_end(&mut chunk)?;
+                        Ok(chunk)
+                    });
+                }
+                Ok(_) => {
+                    let end = start.saturating_add(len);
+                    let mut pos = start;
+                    send_chunks(&sender, || {
+                        let len = (end - pos).min(CHUNK_LEN);
+                        let chunk = database.read().unwrap().get_slice(&hash, pos, len);
+                        let chunk = chunk.map_err(io::Error::other)?;
+                        pos += chunk.len() as u64;
+                        Ok(chunk)
+                    });
+                }
+            }
+        });
+        Body::wrap_stream(stream::poll_fn(move |cx| receiver.poll_recv(cx)))
+    }
+
+    /// Put the waste streamed from the body, with its metadata. The body is
+    /// spooled into a temporary file first, and the database is only locked
+    /// once it is received as a whole - so a slow client never blocks others.
+    /// It is rejected at once if it streams more than `max_len` bytes.
+    pub async fn put(&self, mut body: Body, meta: Vec<u8>, max_len: u64) -> Result<String, Error> {
+        let spool = Spool::create()?;
+        let mut file = tokio::fs::File::from_std(spool.file.try_clone()?);
+        let mut len = 0;
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            len += chunk.len() as u64;
+            if len > max_len {
+                return Err(Error::with_status(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("the waste is larger than {} bytes", max_len),
+                ));
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+
+        self.write(move |database| {
+            let mut file = &spool.file;
+            file.seek(SeekFrom::Start(0))?;
+            Ok(database.put_reader_with_meta(file, &meta)?)
+        })
+        .await
+    }
+}
+
+/// Send the chunks read by `read` into the body until the waste ends - by an
+/// empty chunk - or the body is dropped once the client is gone.
+fn send_chunks<F>(sender: &mpsc::Sender<io::Result<Bytes>>, mut read: F)
+where
+    F: FnMut() -> io::Result<Vec<u8>>,
+{
+    loop {
+        let chunk = match read() {
+            Ok(chunk) if chunk.is_empty() => return,
+            Ok(chunk) => Ok(Bytes::from(chunk)),
+            Err(e) => Err(e),
+        };
+        let failed = chunk.is_err();
+        if sender.blocking_send(chunk).is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// The temporary file a request body is spooled into by `AsyncDatabase::put`.
+/// It is removed once dropped.
+struct Spool {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl Spool {
+    fn create() -> io::Result<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir()
+            .join(format!("waste-web-{}-{}.spool", process::id(), id));
+        let file = fs::File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self { path, file })
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
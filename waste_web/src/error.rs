@@ -1,30 +1,94 @@
 use std::io;
 
+use axum::http::StatusCode;
+use waste_island::ErrorKind;
+
 #[derive(Debug)]
 pub struct Error {
+    status: StatusCode,
+    /// The stable machine-readable code of the error, see `code`.
+    code: &'static str,
     msg: String,
+    /// The headers responsed with the error - for example, `Content-Range` of
+    /// the status 416.
+    headers: Vec<(&'static str, String)>,
 }
 
 impl Error {
+    /// Create an error of the status 500.
     pub fn new(msg: String) -> Self {
-        return Self { msg }
+        return Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            code: "internal",
+            msg,
+            headers: vec![],
+        }
+    }
+
+    /// Create an error of the status, whose code is derived from the status.
+    pub fn with_status(status: StatusCode, msg: String) -> Self {
+        let code = match status {
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::BAD_REQUEST => "bad_request",
+            StatusCode::PAYLOAD_TOO_LARGE => "too_large",
+            StatusCode::RANGE_NOT_SATISFIABLE => "range_not_satisfiable",
+            _ => "internal",
+        };
+        Self { status, code, msg, headers: vec![] }
+    }
+
+    /// Response the header with the error as well.
+    pub fn with_header(mut self, name: &'static str, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// The status code responsed to the client.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The code responsed to the client - for example, `not_found`. Unlike the
+    /// message, it never changes, so clients can match it.
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn headers(&self) -> &[(&'static str, String)] {
+        &self.headers
     }
 }
 
 impl From<waste_island::Error> for Error {
     fn from(value: waste_island::Error) -> Self {
-        Self { msg: value.to_string() }
+        let (status, code) = match value.kind() {
+            ErrorKind::NotFound => (StatusCode::NOT_FOUND, "not_found"),
+            ErrorKind::InvalidHash => (StatusCode::BAD_REQUEST, "invalid_hash"),
+            ErrorKind::TooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "too_large"),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        };
+        Self { status, code, msg: value.to_string(), headers: vec![] }
     }
 }
 
 impl From<io::Error> for Error {
     fn from(value: io::Error) -> Self {
-        Self { msg: value.to_string() }
+        Self::new(value.to_string())
     }
 }
 
 impl From<hyper::Error> for Error {
     fn from(value: hyper::Error) -> Self {
-        Self { msg: value.to_string() }
+        Self::new(value.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(value: tokio::task::JoinError) -> Self {
+        Self::new(value.to_string())
     }
 }
@@ -1,18 +1,20 @@
+mod async_database;
 mod error;
 mod server;
 
-use std::{net::SocketAddr};
+use std::{collections::HashMap, net::SocketAddr};
 
 use axum::{
     TypedHeader,
     headers::ContentType,
-    extract::{Path, State, Extension, RawBody},
-    http::{StatusCode, HeaderMap, HeaderValue},
-    response::IntoResponse,
+    extract::{Path, Query, State, Extension, RawBody},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{AppendHeaders, IntoResponse},
     routing::{get, post},
     Router,
 };
-use hyper::Method;
+use hyper::{body::HttpBody, Body, Method};
+use serde_json::json;
 use tower_http::{services::ServeDir, cors::{CorsLayer, Any}};
 
 use server::{Server, ServerResponse};
@@ -25,12 +27,12 @@ async fn main() -> Result<(), Error> {
     let addr = SocketAddr::from(([127, 0, 0, 1], 3514));
 
     let cors = CorsLayer::new()
-        .allow_methods(vec![Method::GET, Method::POST])
+        .allow_methods(vec![Method::GET, Method::HEAD, Method::POST, Method::DELETE])
         .allow_headers(Any)
         .allow_origin(Any);
 
     let router = Router::new()
-        .route("/api/v1/wastes/:waste_key", get(get_waste))
+        .route("/api/v1/wastes/:waste_key", get(get_waste).head(head_waste).delete(delete_waste))
         .route("/api/v1/wastes", post(put_waste).get(list_wastes))
         .nest_service("/", ServeDir::new("./frontend_ui/dist/"))
         .with_state(server)
@@ -45,31 +47,61 @@ async fn main() -> Result<(), Error> {
 }
 
 fn handle_result(r: Result<ServerResponse, Error>) -> impl IntoResponse {
-    match r {
-        Ok(v) => (
-            v.status,
-            [
-                ("Content-Type", v.content_type),
-                ("Access-Control-Allow-Origin", "*".to_string()),
-            ],
-            v.body
-        ),
-        Err(e) => (
-            StatusCode::BAD_REQUEST,
-            [
-                ("Content-Type", "application/json".to_string()),
-                ("Access-Control-Allow-Origin", "*".to_string()),
-            ],
-            "{}".as_bytes().to_vec(),
-        ),
+    let mut v = r.unwrap_or_else(|e| ServerResponse {
+        status: e.status(),
+        content_type: "application/json".to_string(),
+        headers: e.headers().to_vec(),
+        body: Body::from(json!({ "error": { "code": e.code(), "message": e.msg() } }).to_string()),
+    });
+    // The response of `HEAD` gives the length of the content it leaves out, and
+    // the streamed one gives the length of the content to come.
+    let content_length = match v.headers.iter().position(|(k, _)| *k == "Content-Length") {
+        Some(i) => Some(v.headers.remove(i).1),
+        None => v.body.size_hint().exact().map(|len| len.to_string()),
+    };
+    // The responses without a body have no length: it is forbidden by 204, and
+    // misleading by 304.
+    match content_length {
+        Some(len) if !matches!(v.status, StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED) => {
+            v.headers.push(("Content-Length", len));
+        }
+        _ => {}
     }
+    (
+        v.status,
+        [
+            ("Content-Type", v.content_type),
+            ("Access-Control-Allow-Origin", "*".to_string()),
+        ],
+        AppendHeaders(v.headers),
+        axum::body::boxed(v.body),
+    )
 }
 
 async fn get_waste(
     State(mut state): State<Server>,
     Path(waste_key): Path<String>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    let result = state.get_waste(waste_key);
+    let range = headers.get("Range").and_then(|v| v.to_str().ok());
+    let if_none_match = headers.get("If-None-Match").and_then(|v| v.to_str().ok());
+    let result = state.get_waste(waste_key, range, if_none_match).await;
+    handle_result(result)
+}
+
+async fn head_waste(
+    State(mut state): State<Server>,
+    Path(waste_key): Path<String>,
+) -> impl IntoResponse {
+    let result = state.head_waste(waste_key).await;
+    handle_result(result)
+}
+
+async fn delete_waste(
+    State(mut state): State<Server>,
+    Path(waste_key): Path<String>,
+) -> impl IntoResponse {
+    let result = state.delete_waste(waste_key).await;
     handle_result(result)
 }
 
@@ -78,17 +110,137 @@ async fn put_waste(
     TypedHeader(type_content): TypedHeader<ContentType>,
     RawBody(body): RawBody,
 ) -> impl IntoResponse {
-    let body = match hyper::body::to_bytes(body).await {
-        Ok(b) => b,
-        Err(e) => return handle_result(Err(e.into())),
-    };
-    let result = state.put_waste(type_content.to_string().as_bytes(), &body[..]);
+    let result = state.put_waste(type_content.to_string().as_bytes(), body).await;
     handle_result(result)
 }
 
 async fn list_wastes(
     State(mut state): State<Server>,
+    Query(mut params): Query<HashMap<String, String>>,
 ) -> impl IntoResponse {
-    let result = state.list_wastes();
+    let limit = params.remove("limit");
+    let result = state.list_wastes(params.remove("after"), limit.as_deref()).await;
     handle_result(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Get the waste by the handler with the headers, and return the status,
+    /// the headers and the JSON body.
+    async fn get_json(
+        server: &Server,
+        waste_key: &str,
+        headers: HeaderMap,
+    ) -> (StatusCode, HeaderMap, serde_json::Value) {
+        let response = get_waste(State(server.clone()), Path(waste_key.to_string()), headers)
+            .await
+            .into_response();
+        let (status, headers) = (response.status(), response.headers().clone());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, headers, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn it_responses_errors_in_json() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-responses-errors-in-json";
+        let _ = fs::remove_dir_all(database_path);
+        let server = Server::new(database_path).unwrap();
+
+        let hash = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        let (status, _, body) = get_json(&server, hash, HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "not_found");
+        assert!(body["error"]["message"].is_string());
+
+        let (status, _, body) = get_json(&server, "not a hash", HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_hash");
+
+        // The headers of the error are responsed as well.
+        server.clone().put_waste(b"text/plain", hyper::Body::from("hello world")).await.unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("Range", HeaderValue::from_static("bytes=11-"));
+        let (status, headers, body) = get_json(&server, hash, headers).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(headers["Content-Range"], "bytes */11");
+        assert_eq!(body["error"]["code"], "range_not_satisfiable");
+    }
+
+    #[tokio::test]
+    async fn it_streams_wastes_in_chunks() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-streams-wastes-in-chunks";
+        let _ = fs::remove_dir_all(database_path);
+        let content: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let compressed: Vec<u8> = content.iter().map(|byte| byte % 2).collect();
+        let mut database = waste_island::Database::new(database_path)
+            .unwrap()
+            .with_compression(waste_island::CompressionMode::Zstd);
+        let compressed_hash =
+            database.put_with_meta(&compressed, b"application/octet-stream").unwrap();
+        drop(database);
+
+        let server = Server::new(database_path).unwrap();
+        let mut response = server.clone()
+            .put_waste(b"application/octet-stream", hyper::Body::from(content.clone()))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(&mut response.body).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let hash = body["name"].as_str().unwrap().to_string();
+
+        for (hash, content) in [(hash, content), (compressed_hash, compressed)] {
+            let response = get_waste(State(server.clone()), Path(hash.clone()), HeaderMap::new())
+                .await
+                .into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["Content-Length"], "200000");
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, content);
+
+            // The slice is streamed by the same way.
+            let mut headers = HeaderMap::new();
+            headers.insert("Range", HeaderValue::from_static("bytes=100000-"));
+            let response = get_waste(State(server.clone()), Path(hash), headers)
+                .await
+                .into_response();
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(response.headers()["Content-Length"], "100000");
+            assert_eq!(response.headers()["Content-Range"], "bytes 100000-199999/200000");
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, content[100_000..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_responses_no_length_without_bodies() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-responses-no-length-without-bodies";
+        let _ = fs::remove_dir_all(database_path);
+        let server = Server::new(database_path).unwrap();
+        let hash = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        server.clone().put_waste(b"text/plain", hyper::Body::from("hello world")).await.unwrap();
+
+        let response = get_waste(State(server.clone()), Path(hash.to_string()), HeaderMap::new())
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-Length"], "11");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("If-None-Match", HeaderValue::from_str(&format!("{:?}", hash)).unwrap());
+        let response = get_waste(State(server.clone()), Path(hash.to_string()), headers)
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(!response.headers().contains_key("Content-Length"));
+
+        let response = delete_waste(State(server.clone()), Path(hash.to_string()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!response.headers().contains_key("Content-Length"));
+    }
+}
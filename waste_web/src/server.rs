@@ -1,81 +1,296 @@
 use std::{
     convert::Infallible,
     future::Future,
+    io,
     pin::Pin,
-    sync::{Arc, Mutex},
 };
 
 use axum::{extract::Path, http::StatusCode, response::IntoResponse, http::status::InvalidStatusCode};
 use serde_json::json;
 
-use crate::error::Error;
+use hyper::{body::HttpBody, Body};
+use waste_island::CompressionMode;
+
+use crate::{async_database::AsyncDatabase, error::Error};
+
+/// The max size of a waste put, in bytes.
+const MAX_WASTE_SIZE: u64 = 64 * 1024 * 1024; // 64MB.
+/// The count of wastes listed in a page if the limit is not given.
+const DEFAULT_LIST_LIMIT: usize = 100;
+/// The max count of wastes listed in a page.
+const MAX_LIST_LIMIT: usize = 1000;
+/// The max length of a slice responsed for a range. A longer range - for
+/// example, `bytes=0-` - is cut, and the client asks for the rest later.
+const MAX_RANGE_LEN: u64 = 8 * 1024 * 1024; // 8MB.
 
 #[derive(Clone)]
 pub struct Server {
-    database: Arc<Mutex<waste_island::Database>>,
+    database: AsyncDatabase,
 }
 
 pub struct ServerResponse {
     pub status: StatusCode,
     pub content_type: String,
-    pub body: Vec<u8>,
+    /// Headers other than `Content-Type`. `Content-Length` is only given if
+    /// the body does not know its length - for example, it is left out by
+    /// `HEAD`, or streamed.
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Body,
+}
+
+/// The range of `Range: bytes=...`, both ends inclusive.
+enum ByteRange {
+    /// `bytes=start-` or `bytes=start-end`.
+    From(u64, Option<u64>),
+    /// `bytes=-len`: the last `len` bytes.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Parse the value of the `Range` header. Return `None` if it is not a
+    /// single range of bytes, so that it can be ignored.
+    fn parse(value: &str) -> Option<Self> {
+        let spec = value.trim().strip_prefix("bytes=")?;
+        if spec.contains(',') {
+            return None;
+        }
+        let (start, end) = spec.split_once('-')?;
+        match (start.trim(), end.trim()) {
+            ("", "") => None,
+            ("", len) => Some(ByteRange::Suffix(len.parse().ok()?)),
+            (start, "") => Some(ByteRange::From(start.parse().ok()?, None)),
+            (start, end) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                if end < start {
+                    return None;
+                }
+                Some(ByteRange::From(start, Some(end)))
+            }
+        }
+    }
+
+    /// Resolve the range against the total length of the waste. Return both
+    /// ends of the slice, inclusive and cut by `MAX_RANGE_LEN` - or `None` if
+    /// the range is not satisfiable.
+    fn resolve(&self, total: u64) -> Option<(u64, u64)> {
+        let (start, end) = match *self {
+            ByteRange::From(start, _) if start >= total => return None,
+            ByteRange::From(start, end) => (start, end.map_or(total - 1, |end| end.min(total - 1))),
+            ByteRange::Suffix(0) => return None,
+            ByteRange::Suffix(len) => (total.saturating_sub(len), total.checked_sub(1)?),
+        };
+        Some((start, end.min(start + MAX_RANGE_LEN - 1)))
+    }
 }
 
 impl Server {
     pub fn new(database_path: &str) -> Result<Self, Error> {
-        let database = waste_island::Database::new(database_path)?;
+        let database = waste_island::Database::new(database_path)?
+            .with_max_value_size(Some(MAX_WASTE_SIZE));
         Ok(Self {
-            database: Arc::new(Mutex::new(database)),
+            database: AsyncDatabase::new(database),
         })
     }
 
-    pub fn list_wastes(&mut self) -> Result<ServerResponse, Error> {
-        let mut database = self.database.lock().unwrap();
-        let result = database.list()?;
+    /// List the wastes. All of them are listed if neither `after` nor `limit` is
+    /// given. Otherwise up to `limit` wastes after `after` are listed, and the
+    /// hash to list the next page after is responsed as `next` - or `null` if
+    /// it is the last page.
+    pub async fn list_wastes(
+        &mut self,
+        after: Option<String>,
+        limit: Option<&str>,
+    ) -> Result<ServerResponse, Error> {
+        if after.is_none() && limit.is_none() {
+            let result = self.database.read(|database| Ok(database.list()?)).await?;
+            return Ok(ServerResponse {
+                status: StatusCode::OK,
+                content_type: "application/json".to_string(),
+                headers: vec![],
+                body: Body::from(json!({ "data": result }).to_string()),
+            });
+        }
+
+        let limit = match limit {
+            None => DEFAULT_LIST_LIMIT,
+            Some(limit) => match limit.parse::<usize>() {
+                Ok(limit) if limit > 0 && limit <= MAX_LIST_LIMIT => limit,
+                _ => {
+                    return Err(Error::with_status(
+                        StatusCode::BAD_REQUEST,
+                        format!("limit should be in 1..={}, but it is {}", MAX_LIST_LIMIT, limit),
+                    ))
+                }
+            },
+        };
+        let result = self.database
+            .read(move |database| Ok(database.list_paged(after.as_deref(), limit)?))
+            .await?;
+        let next = match result.len() {
+            len if len == limit => result.last().cloned(),
+            _ => None,
+        };
         Ok(ServerResponse {
             status: StatusCode::OK,
             content_type: "application/json".to_string(),
-            body: json!({ "data": result }).to_string().as_bytes().to_vec(),
+            headers: vec![],
+            body: Body::from(json!({ "data": result, "next": next }).to_string()),
         })
     }
 
-    pub fn get_waste(&mut self, waste_key: String) -> Result<ServerResponse, Error> {
-        let mut database = self.database.lock().unwrap();
-        let res = database.get(&waste_key)?;
-        if res.len() == 0 {
-            return Err(Error::new(format!("length = 0, when key = {}", waste_key)));
+    /// Get the waste. Only the slice is responsed if `range` - the value of
+    /// the `Range` header - is given.
+    ///
+    /// The waste never changes, so if `if_none_match` - the value of the
+    /// `If-None-Match` header - matches its `ETag`, only the status 304 is
+    /// responsed.
+    pub async fn get_waste(
+        &mut self,
+        waste_key: String,
+        range: Option<&str>,
+        if_none_match: Option<&str>,
+    ) -> Result<ServerResponse, Error> {
+        if if_none_match.is_some_and(|tags| etag_matches(tags, &waste_key)) {
+            let key = waste_key.clone();
+            if self.database.read(move |database| Ok(database.contains(&key)?)).await? {
+                return Ok(ServerResponse {
+                    status: StatusCode::NOT_MODIFIED,
+                    content_type: "application/json".to_string(),
+                    headers: vec![("ETag", format!("{:?}", waste_key))],
+                    body: Body::empty(),
+                });
+            }
         }
-        let content_type_len = &res[0];
-        let content_type = &res[1..1 + *content_type_len as usize];
-        let body = &res[1 + *content_type_len as usize..];
+
+        // Only the slice is streamed if the range is given, and the whole
+        // waste otherwise.
+        let key = waste_key.clone();
+        let range = range.and_then(ByteRange::parse);
+        let (content_type, total) = self.database.read(move |database| {
+            let content_type = parse_content_type(database.get_meta(&key)?, &key)?;
+            Ok((content_type, waste_len(database, &key)?))
+        })
+        .await?;
+        let mut headers = vec![
+            ("Accept-Ranges", "bytes".to_string()),
+            ("ETag", format!("{:?}", waste_key)),
+        ];
+        let (status, start, len) = match range {
+            None => (StatusCode::OK, 0, total),
+            Some(range) => {
+                let Some((start, end)) = range.resolve(total) else {
+                    return Err(Error::with_status(
+                        StatusCode::RANGE_NOT_SATISFIABLE,
+                        format!("the range is out of the {} bytes of the waste", total),
+                    )
+                    .with_header("Content-Range", format!("bytes */{}", total)));
+                };
+                headers.push(("Content-Range", format!("bytes {}-{}/{}", start, end, total)));
+                (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+            }
+        };
+        headers.push(("Content-Length", len.to_string()));
+        Ok(ServerResponse {
+            status,
+            content_type,
+            headers,
+            body: self.database.stream(waste_key, start, len),
+        })
+    }
+
+    /// Get the content type and the length of the waste, without its content.
+    /// The hash of the waste is responsed as its `ETag`.
+    pub async fn head_waste(&mut self, waste_key: String) -> Result<ServerResponse, Error> {
+        let key = waste_key.clone();
+        let (content_type, len) = self.database.read(move |database| {
+            let content_type = parse_content_type(database.get_meta(&key)?, &key)?;
+            Ok((content_type, waste_len(database, &key)?))
+        })
+        .await?;
         Ok(ServerResponse {
             status: StatusCode::OK,
-            content_type: unsafe { String::from_utf8_unchecked(content_type.to_vec()) },
-            body: body.to_vec(),
+            content_type,
+            headers: vec![
+                ("Accept-Ranges", "bytes".to_string()),
+                ("ETag", format!("{:?}", waste_key)),
+                ("Content-Length", len.to_string()),
+            ],
+            body: Body::empty(),
         })
     }
 
-    pub fn put_waste(
+    pub async fn delete_waste(&mut self, waste_key: String) -> Result<ServerResponse, Error> {
+        self.database.write(move |database| {
+            if !database.delete(&waste_key)? {
+                return Err(Error::with_status(
+                    StatusCode::NOT_FOUND,
+                    format!("not found, when key = {}", waste_key),
+                ));
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(ServerResponse {
+            status: StatusCode::NO_CONTENT,
+            content_type: "application/json".to_string(),
+            headers: vec![],
+            body: Body::empty(),
+        })
+    }
+
+    /// Put the waste streamed from the request body. It is rejected by the
+    /// status 413 if it is larger than `MAX_WASTE_SIZE` - at once if the
+    /// `Content-Length` says so, or as soon as too many bytes are streamed.
+    pub async fn put_waste(
         &mut self,
         content_type: &[u8],
-        body_data: &[u8],
+        body: Body,
     ) -> Result<ServerResponse, Error> {
-        let mut database = self.database.lock().unwrap();
-        let mut data = vec![];
-        data.push(content_type.len() as u8);
-        data.extend_from_slice(content_type);
-        data.extend_from_slice(body_data);
-        let name = database.put(&data).unwrap();
+        if body.size_hint().lower() > MAX_WASTE_SIZE {
+            return Err(Error::with_status(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("the waste is larger than {} bytes", MAX_WASTE_SIZE),
+            ));
+        }
+        let name = self.database.put(body, content_type.to_vec(), MAX_WASTE_SIZE).await?;
         Ok(ServerResponse {
             status: StatusCode::OK,
             content_type: "application/json".to_string(),
-            body: format!(r#"{{"type":"OK","name":{:?}}}"#, name)
-                .as_bytes()
-                .to_vec(),
+            headers: vec![],
+            body: Body::from(format!(r#"{{"type":"OK","name":{:?}}}"#, name)),
         })
     }
 }
 
+/// Does the value of `If-None-Match` match the `ETag` of the waste - its
+/// quoted hash? Weak tags are compared as same as strong ones.
+fn etag_matches(if_none_match: &str, waste_key: &str) -> bool {
+    let etag = format!("{:?}", waste_key);
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Get the origin length of the waste - without reading it, unless it is
+/// compressed.
+fn waste_len(database: &waste_island::Database, waste_key: &str) -> Result<u64, Error> {
+    let stat = database.stat(waste_key)?;
+    Ok(match stat.compression {
+        CompressionMode::None => stat.len,
+        // The origin length is not stored, so count it by decompressing.
+        _ => io::copy(&mut database.get_reader(waste_key)?, &mut io::sink())?,
+    })
+}
+
+/// Turn the metadata of the waste to its content type.
+fn parse_content_type(meta: Vec<u8>, waste_key: &str) -> Result<String, Error> {
+    // The content type is stored as the waste's metadata.
+    String::from_utf8(meta).map_err(|_| {
+        Error::new(format!("content type is not valid UTF-8, when key = {}", waste_key))
+    })
+}
+
 // impl Service<Request<Incoming>> for Server {
 //     type Response = Response<Full<Bytes>>;
 //     type Error = Infallible;
@@ -129,4 +344,148 @@ impl Server {
 //         Box::pin(response)
 //     }
 // }
-// 
\ No newline at end of file
+// 
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use hyper::body::Bytes;
+
+    use super::*;
+
+    /// Read the whole body of the response.
+    async fn body_of(response: &mut ServerResponse) -> Bytes {
+        hyper::body::to_bytes(&mut response.body).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_responses_not_modified_for_matched_etags() {
+        let database_path =
+            "/tmp/waste-land.skogatt.org/it-responses-not-modified-for-matched-etags";
+        let _ = fs::remove_dir_all(database_path);
+
+        let mut server = Server::new(database_path).unwrap();
+        let mut response =
+            server.put_waste(b"text/plain", Body::from("hello world")).await.unwrap();
+        let body = body_of(&mut response).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let key = body["name"].as_str().unwrap().to_string();
+
+        let response = server.get_waste(key.clone(), None, None).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        let (_, etag) = response.headers.iter().find(|(k, _)| *k == "ETag").unwrap().clone();
+        assert_eq!(etag, format!("{:?}", key));
+
+        let mut response = server.get_waste(key.clone(), None, Some(&etag)).await.unwrap();
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert!(body_of(&mut response).await.is_empty());
+        let tags = format!(r#""another", W/{}"#, etag);
+        let response = server.get_waste(key.clone(), None, Some(&tags)).await.unwrap();
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        let mut response = server.get_waste(key.clone(), None, Some(r#""another""#)).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(body_of(&mut response).await, &b"hello world"[..]);
+
+        // A deleted waste is not cached any more.
+        server.delete_waste(key.clone()).await.unwrap();
+        let err = server.get_waste(key, None, Some(&etag)).await.err().unwrap();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_works_with_empty_wastes() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-works-with-empty-wastes-on-web";
+        let _ = fs::remove_dir_all(database_path);
+
+        let mut server = Server::new(database_path).unwrap();
+        let mut response = server.put_waste(b"text/plain", Body::empty()).await.unwrap();
+        let body = body_of(&mut response).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let key = body["name"].as_str().unwrap().to_string();
+        assert_eq!(key, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+
+        // An empty waste is still found.
+        let mut response = server.get_waste(key.clone(), None, None).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.content_type, "text/plain");
+        assert!(body_of(&mut response).await.is_empty());
+        let response = server.head_waste(key.clone()).await.unwrap();
+        assert!(response.headers.contains(&("Content-Length", "0".to_string())));
+
+        server.delete_waste(key.clone()).await.unwrap();
+        let err = server.get_waste(key, None, None).await.err().unwrap();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_serves_others_while_wastes_are_uploaded() {
+        let database_path =
+            "/tmp/waste-land.skogatt.org/it-serves-others-while-wastes-are-uploaded";
+        let _ = fs::remove_dir_all(database_path);
+
+        let mut server = Server::new(database_path).unwrap();
+        let mut response =
+            server.put_waste(b"text/plain", Body::from("hello world")).await.unwrap();
+        let body = body_of(&mut response).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let key = body["name"].as_str().unwrap().to_string();
+
+        let (mut sender, body) = Body::channel();
+        let mut uploader = server.clone();
+        let upload = tokio::spawn(async move { uploader.put_waste(b"text/plain", body).await });
+        sender.send_data(Bytes::from("hello ")).await.unwrap();
+
+        // The upload is not finished yet, but the database is not locked by it.
+        let get = server.get_waste(key, None, None);
+        let mut response =
+            tokio::time::timeout(Duration::from_secs(5), get).await.unwrap().unwrap();
+        assert_eq!(body_of(&mut response).await, &b"hello world"[..]);
+
+        sender.send_data(Bytes::from("again")).await.unwrap();
+        drop(sender);
+        let mut response = upload.await.unwrap().unwrap();
+        let body = body_of(&mut response).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let key = body["name"].as_str().unwrap().to_string();
+        let mut response = server.get_waste(key, None, None).await.unwrap();
+        assert_eq!(body_of(&mut response).await, &b"hello again"[..]);
+    }
+
+    #[tokio::test]
+    async fn it_responses_slices_of_ranges() {
+        let database_path = "/tmp/waste-land.skogatt.org/it-responses-slices-of-ranges";
+        let _ = fs::remove_dir_all(database_path);
+
+        let mut server = Server::new(database_path).unwrap();
+        let mut response =
+            server.put_waste(b"text/plain", Body::from("hello world")).await.unwrap();
+        let body = body_of(&mut response).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let key = body["name"].as_str().unwrap().to_string();
+        let content_range = |response: &ServerResponse| {
+            let (_, range) = response.headers.iter().find(|(k, _)| *k == "Content-Range").unwrap();
+            range.clone()
+        };
+
+        for (range, slice, expected) in [
+            ("bytes=0-4", "hello", "bytes 0-4/11"),
+            ("bytes=6-", "world", "bytes 6-10/11"),
+            ("bytes=-5", "world", "bytes 6-10/11"),
+            ("bytes=-100", "hello world", "bytes 0-10/11"),
+            ("bytes=6-18446744073709551615", "world", "bytes 6-10/11"),
+        ] {
+            let mut response = server.get_waste(key.clone(), Some(range), None).await.unwrap();
+            assert_eq!(response.status, StatusCode::PARTIAL_CONTENT, "{}", range);
+            assert_eq!(body_of(&mut response).await, slice.as_bytes());
+            assert_eq!(content_range(&response), expected);
+        }
+
+        for range in ["bytes=11-", "bytes=-0"] {
+            let err = server.get_waste(key.clone(), Some(range), None).await.err().unwrap();
+            assert_eq!(err.status(), StatusCode::RANGE_NOT_SATISFIABLE, "{}", range);
+            assert_eq!(err.code(), "range_not_satisfiable");
+            assert_eq!(err.headers(), [("Content-Range", "bytes */11".to_string())]);
+        }
+    }
+}